@@ -0,0 +1,223 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{Result, anyhow};
+
+use crate::cancel::CancelToken;
+use crate::compress::{Choice, Codec, Compression, ZstdOptions};
+use crate::device::{self, ConfirmMode, Device};
+use crate::events::{self, Observer};
+use crate::i18n::{Msg, tr};
+use crate::output;
+use crate::read::{self, FillPattern};
+use crate::slow::SlowWrite;
+use crate::wear;
+use crate::write::{self, Failure};
+
+impl Device {
+    /// The devices that are safe to write, as the command offers them:
+    /// removable and USB disks, never one holding the running system or
+    /// excluded by the administrator.
+    pub fn enumerate() -> Result<Vec<Device>> {
+        device::get_removable_devices(false)
+    }
+}
+
+/// A running job's hold on the observer and output, given up when the job
+/// ends.
+struct Observed {
+    _running: MutexGuard<'static, ()>,
+}
+
+impl Observed {
+    /// Sets up a job's run: nothing is printed and no progress bars are
+    /// drawn, and `observer` is told of its events. Jobs share this, so a
+    /// job started while another runs waits for it to end.
+    fn start(observer: Option<Arc<dyn Observer>>) -> Self {
+        static RUNNING: Mutex<()> = Mutex::new(());
+
+        // A job that panicked left nothing behind that matters
+        let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        output::init(true, false, false, false);
+        output::silence(true);
+        events::observe(observer);
+        Observed { _running: running }
+    }
+}
+
+impl Drop for Observed {
+    fn drop(&mut self) {
+        events::observe(None);
+        output::silence(false);
+    }
+}
+
+/// Writes an image file, compressed or not, to one or more devices and
+/// verifies it, as `etchr write` does once the devices are chosen.
+pub struct WriteJob {
+    image: PathBuf,
+    devices: Vec<Device>,
+    verify: bool,
+    parallel: bool,
+    observer: Option<Arc<dyn Observer>>,
+    cancel: CancelToken,
+}
+
+impl WriteJob {
+    pub fn new(image: impl Into<PathBuf>) -> Self {
+        WriteJob {
+            image: image.into(),
+            devices: Vec::new(),
+            verify: true,
+            parallel: false,
+            observer: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Adds a device to write, one of those [`Device::enumerate`] found.
+    pub fn device(mut self, device: &Device) -> Self {
+        self.devices.push(device.clone());
+        self
+    }
+
+    /// Whether to compare each device against the image once it's written.
+    /// On by default.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Writes every device at once, each on its own thread, rather than one
+    /// after another.
+    pub fn parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// A token that stops the job from another thread once cancelled.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Writes the image. Written one after another, the first device to
+    /// fail stops the job with its error; written in parallel, every device
+    /// is seen through and those that failed are returned. Fails if no
+    /// device was added.
+    pub fn run(self) -> Result<Vec<Failure>> {
+        if self.devices.is_empty() {
+            return Err(anyhow!(tr(Msg::NoDevicesToWrite)));
+        }
+        let _observed = Observed::start(self.observer);
+        let options = write::Options {
+            verify: self.verify,
+            sandbox: false,
+            queue_depth: 4,
+            drop_privileges: false,
+            dictionary: None,
+            confirm: ConfirmMode::AssumeYes,
+            partition: None,
+            to_partition: None,
+            stream: false,
+            max_temp_size: None,
+            slow_write: SlowWrite::default(),
+            parallel: self.parallel,
+            report: None,
+            checksum: None,
+            truncate: false,
+        };
+        for device in &self.devices {
+            device::release(&device.path)?;
+        }
+        let paths: Vec<PathBuf> = self.devices.iter().map(|d| d.path.clone()).collect();
+        let failures = write::run(&self.image, &paths, &options, &self.cancel)?;
+        for device in &self.devices {
+            if !failures.iter().any(|f| f.device == device.path) {
+                wear::record(&device.name).ok();
+            }
+        }
+        Ok(failures)
+    }
+}
+
+/// Reads a device into an image file, as `etchr read` does. The image is
+/// compressed if its name ends in `.gz`, `.xz` or `.zst`.
+pub struct ReadJob {
+    device: PathBuf,
+    image: PathBuf,
+    compression: Option<Compression>,
+    observer: Option<Arc<dyn Observer>>,
+    cancel: CancelToken,
+}
+
+impl ReadJob {
+    /// Reads `device`, one of those [`Device::enumerate`] found, into
+    /// `image`.
+    pub fn new(device: &Device, image: impl Into<PathBuf>) -> Self {
+        let image = image.into();
+        let compression = image
+            .extension()
+            .and_then(|ext| Codec::from_extension(&ext.to_string_lossy()))
+            .map(Compression::at_default_level);
+        ReadJob {
+            device: device.path.clone(),
+            image,
+            compression,
+            observer: None,
+            cancel: CancelToken::default(),
+        }
+    }
+
+    /// Compresses the image with `compression`, whatever its name, or not
+    /// at all with `None`.
+    pub fn compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn observer(mut self, observer: impl Observer + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// A token that stops the job from another thread once cancelled.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    /// Reads the device. Unreadable sectors are filled with zeros.
+    pub fn run(self) -> Result<()> {
+        let _observed = Observed::start(self.observer);
+        let options = read::Options {
+            sandbox: false,
+            keep_partial: false,
+            resume: false,
+            fill: FillPattern::default(),
+            mapfile: None,
+            compress: self.compression.map(Choice::Fixed),
+            zstd: ZstdOptions::default(),
+            train_dict: None,
+            drop_privileges: false,
+            sparse: true,
+        };
+        read::run(&self.device, &self.image, None, &options, &self.cancel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_job_needs_a_device() {
+        let Err(error) = WriteJob::new("image.img").run() else {
+            panic!("a job without devices ran");
+        };
+        assert_eq!(error.to_string(), tr(Msg::NoDevicesToWrite));
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::device::{self, Device};
+use crate::i18n::Msg;
+use crate::json;
+use crate::output::info;
+use crate::progress;
+use crate::status::StatusFile;
+
+const BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// The image a card was deployed with, identified by the SHA-256 of its
+/// first `size` bytes, i.e. of the uncompressed image.
+pub struct Assignment {
+    pub serial: String,
+    pub sha256: String,
+    pub size: u64,
+    pub name: String,
+}
+
+/// Parses a manifest of the form `{"devices": [{"serial": "...", "sha256":
+/// "...", "size": 123, "name": "..."}]}`, where `name` is optional.
+pub fn parse(text: &str) -> Result<Vec<Assignment>> {
+    let doc = json::parse(text)?;
+    let devices = doc
+        .get("devices")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("expected a \"devices\" array"))?;
+
+    devices
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let field = |key: &str| entry.get(key).and_then(|v| v.as_str());
+            let serial = field("serial")
+                .filter(|s| !s.is_empty())
+                .ok_or_else(|| anyhow!("device {i}: missing \"serial\""))?;
+            let sha256 = field("sha256")
+                .filter(|h| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(|| anyhow!("device {i}: missing or invalid \"sha256\""))?;
+            let size = match entry.get("size") {
+                Some(json::Value::Number(n)) if *n > 0.0 && n.fract() == 0.0 => *n as u64,
+                _ => return Err(anyhow!("device {i}: missing or invalid \"size\"")),
+            };
+            Ok(Assignment {
+                serial: serial.to_string(),
+                sha256: sha256.to_lowercase(),
+                size,
+                name: field("name").unwrap_or("").to_string(),
+            })
+        })
+        .collect()
+}
+
+fn make_progress_bar(len: u64, prefix: &str) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
+    pb.set_prefix(format!("{prefix:<12}"));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} [{elapsed_precise}] [{bar:40.cyan/black}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}")
+            .unwrap()
+            .progress_chars("■ "),
+    );
+    progress::add(pb)
+}
+
+/// Computes the SHA-256 of the first `len` bytes of `path`, as lowercase
+/// hex. `manifest` is shown as the image in `etchr watch`.
+fn hash_prefix(path: &Path, len: u64, manifest: &Path, cancel: &CancelToken) -> Result<String> {
+    let file = File::open(path)?;
+    let pb = make_progress_bar(len, "Hashing");
+    let mut status = StatusFile::create("audit", path, manifest);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    let mut offset = 0;
+    while offset < len {
+        cancel.check(&pb, Msg::PhaseHashing)?;
+        let n = std::cmp::min(BUFFER_SIZE as u64, len - offset) as usize;
+        file.read_exact_at(&mut buf[..n], offset)?;
+        hasher.update(&buf[..n]);
+        offset += n as u64;
+        pb.set_position(offset);
+        status.update("Hashing", &pb);
+    }
+    pb.finish_and_clear();
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/// Checks each of `devices` whose serial number is in the manifest `file`
+/// against the image assigned to it, reading only as much of the device as
+/// the image is long. Fails if any card doesn't match, so that audits can
+/// be scripted.
+pub fn run(file: &Path, devices: &[Device], cancel: &CancelToken) -> Result<()> {
+    let text = fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+    let manifest = parse(&text).with_context(|| format!("parsing {}", file.display()))?;
+
+    let mut audited = 0;
+    let mut mismatched = 0;
+    for device in devices {
+        let path = style(device.path.display()).cyan();
+        let Some(serial) = device::serial(&device.name) else {
+            info!("{path}: no serial number, skipped");
+            continue;
+        };
+        let Some(assignment) = manifest
+            .iter()
+            .find(|a| a.serial.eq_ignore_ascii_case(&serial))
+        else {
+            info!("{path} (serial {serial}): not in the manifest, skipped");
+            continue;
+        };
+        let label = if assignment.name.is_empty() {
+            &assignment.sha256[..12]
+        } else {
+            &assignment.name
+        };
+
+        audited += 1;
+        if assignment.size > device.size_bytes {
+            mismatched += 1;
+            println!(
+                "{path} (serial {serial}): ❌ too small for {label} ({} > {})",
+                HumanBytes(assignment.size),
+                HumanBytes(device.size_bytes)
+            );
+            continue;
+        }
+        let hash = hash_prefix(&device.path, assignment.size, file, cancel)
+            .with_context(|| format!("reading {}", device.path.display()))?;
+        if hash == assignment.sha256 {
+            println!("{path} (serial {serial}): ✅ matches {label}");
+        } else {
+            mismatched += 1;
+            println!("{path} (serial {serial}): ❌ differs from {label}");
+        }
+    }
+
+    if audited == 0 {
+        return Err(anyhow!("none of the devices are in {}", file.display()));
+    }
+    if mismatched > 0 {
+        return Err(anyhow!(
+            "{mismatched} of {audited} audited devices don't match their image"
+        ));
+    }
+    info!("\n✨ All {audited} audited devices match their image.");
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use std::sync::Mutex;
+
+use crate::output::verbose;
+
+/// Memory handed out for I/O buffers, and the most that may be, if
+/// `memory_limit` is set in the config file.
+static POOL: Mutex<Pool> = Mutex::new(Pool {
+    limit: None,
+    in_use: 0,
+});
+
+struct Pool {
+    limit: Option<u64>,
+    in_use: u64,
+}
+
+impl Pool {
+    /// How many of `wanted` buffers of `size` bytes fit under the limit,
+    /// but at least one (see [`reserve`]).
+    fn grant(&self, wanted: usize, size: usize) -> usize {
+        match self.limit {
+            Some(limit) => {
+                let available = limit.saturating_sub(self.in_use) / size.max(1) as u64;
+                available.clamp(1, wanted.max(1) as u64) as usize
+            }
+            None => wanted,
+        }
+    }
+}
+
+/// Caps the memory of all I/O buffers at `limit` bytes, for the rest of
+/// the run.
+pub fn set_limit(limit: Option<u64>) {
+    POOL.lock().unwrap().limit = limit;
+}
+
+/// Reserves memory for up to `wanted` buffers of `size` bytes, for `what`.
+///
+/// Every read-ahead ring, compressor queue and scratch buffer takes its
+/// memory from here, so that together they stay under the limit. Fewer
+/// buffers are granted once the limit is near, but always at least one, so
+/// that an operation can go on, only slower; it may then overshoot the
+/// limit by that buffer.
+pub fn reserve(what: &str, wanted: usize, size: usize) -> Reservation {
+    let mut pool = POOL.lock().unwrap();
+    let count = pool.grant(wanted, size);
+    if count < wanted {
+        verbose!(
+            "Memory limit reached: {what} gets {count} of {wanted} buffers of {} KiB",
+            size / 1024
+        );
+    }
+    let bytes = count as u64 * size as u64;
+    pool.in_use += bytes;
+    Reservation { count, size, bytes }
+}
+
+/// Memory reserved with [`reserve`], given back when dropped.
+pub struct Reservation {
+    count: usize,
+    size: usize,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// Number of buffers granted.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Allocates one of the buffers, aligned to `align` bytes.
+    pub fn buffer(&self, align: usize) -> AlignedBuffer {
+        AlignedBuffer::new(self.size, align)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut pool = POOL.lock().unwrap();
+        pool.in_use = pool.in_use.saturating_sub(self.bytes);
+    }
+}
+
+/// A fixed-size buffer aligned to `align` bytes, as O_DIRECT requires.
+pub struct AlignedBuffer {
+    buf: Vec<u8>,
+    offset: usize,
+    size: usize,
+}
+
+impl AlignedBuffer {
+    fn new(size: usize, align: usize) -> Self {
+        // Allocate extra capacity and use an aligned slice of it
+        let buf = vec![0u8; size + align];
+        let offset = buf.as_ptr().align_offset(align);
+        Self { buf, offset, size }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.buf[self.offset..self.offset + self.size]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: usize = 1024 * 1024;
+
+    #[test]
+    fn grants_everything_without_a_limit() {
+        let pool = Pool {
+            limit: None,
+            in_use: 1 << 40,
+        };
+        assert_eq!(pool.grant(64, MIB), 64);
+    }
+
+    #[test]
+    fn grants_what_fits_under_the_limit() {
+        let mut pool = Pool {
+            limit: Some(8 * MIB as u64),
+            in_use: 0,
+        };
+        assert_eq!(pool.grant(4, MIB), 4);
+        pool.in_use = 5 * MIB as u64;
+        assert_eq!(pool.grant(4, MIB), 3);
+        // A buffer that only partly fits isn't granted
+        pool.in_use += 1;
+        assert_eq!(pool.grant(4, MIB), 2);
+    }
+
+    #[test]
+    fn grants_one_buffer_past_the_limit() {
+        let pool = Pool {
+            limit: Some(MIB as u64),
+            in_use: 4 * MIB as u64,
+        };
+        assert_eq!(pool.grant(4, MIB), 1);
+        assert_eq!(pool.grant(0, 0), 1);
+    }
+
+    #[test]
+    fn aligns_buffers() {
+        for align in [1, 512, 4096] {
+            let mut buffer = AlignedBuffer::new(3 * 512, align);
+            let slice = buffer.as_mut_slice();
+            assert_eq!(slice.len(), 3 * 512);
+            assert_eq!(slice.as_ptr().align_offset(align), 0);
+        }
+    }
+}
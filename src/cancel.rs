@@ -0,0 +1,58 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use indicatif::ProgressBar;
+
+use crate::i18n::{Msg, tr, trf};
+
+/// Set once the user asks to stop. Every long operation gets a clone and
+/// checks it at least once per buffer, so that it stops promptly and
+/// cleans up after itself.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+/// The error of an operation stopped through its [`CancelToken`].
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(tr(Msg::CancelledByUser))
+    }
+}
+
+impl Error for Cancelled {}
+
+impl CancelToken {
+    /// A token that is cancelled by Ctrl+C.
+    pub fn on_ctrl_c() -> Result<Self> {
+        let token = Self::default();
+        let handler = token.clone();
+        ctrlc::set_handler(move || handler.cancel())?;
+        Ok(token)
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Returns [`Cancelled`] once cancelled, after marking `pb` as
+    /// cancelled. `phase` names the operation in the bar, e.g.
+    /// [`Msg::PhaseWrite`]. The caller does its cleanup before passing the
+    /// error on.
+    pub(crate) fn check(&self, pb: &ProgressBar, phase: Msg) -> Result<(), Cancelled> {
+        if !self.is_cancelled() {
+            return Ok(());
+        }
+        pb.println(tr(Msg::CleaningUp));
+        pb.finish_with_message(trf(Msg::PhaseCancelled, &[&tr(phase)]));
+        Err(Cancelled)
+    }
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::i18n::{Msg, trf};
+
+/// Identification of an SD card or eMMC chip, decoded by the kernel from
+/// its CID register. Useful for spotting counterfeit or aged cards, which
+/// often carry unknown manufacturer IDs or implausible dates.
+#[derive(Clone)]
+pub struct CardInfo {
+    /// `SD` or `MMC`.
+    pub kind: String,
+    pub manufacturer_id: u32,
+    /// Two-character OEM/application ID, or its hex value if not printable.
+    pub oem: String,
+    /// Product name, e.g. `SC32G`.
+    pub product: String,
+    pub serial: String,
+    /// Manufacturing date as `MM/YYYY`.
+    pub date: String,
+}
+
+/// SD Association manufacturer IDs of well-known vendors.
+const SD_MANUFACTURERS: &[(u32, &str)] = &[
+    (0x01, "Panasonic"),
+    (0x02, "Toshiba"),
+    (0x03, "SanDisk"),
+    (0x1b, "Samsung"),
+    (0x1d, "ADATA"),
+    (0x27, "Phison"),
+    (0x28, "Lexar"),
+    (0x31, "Silicon Power"),
+    (0x41, "Kingston"),
+    (0x74, "Transcend"),
+    (0x76, "Patriot"),
+    (0x82, "Sony"),
+    (0x9f, "Kingston"),
+];
+
+/// JEDEC manufacturer IDs of well-known eMMC vendors.
+const MMC_MANUFACTURERS: &[(u32, &str)] = &[
+    (0x11, "Toshiba"),
+    (0x13, "Micron"),
+    (0x15, "Samsung"),
+    (0x45, "SanDisk"),
+    (0x70, "Kingston"),
+    (0x90, "SK Hynix"),
+    (0xfe, "Micron"),
+];
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+impl CardInfo {
+    /// Reads the CID fields of the block device `device_name` (e.g.
+    /// `mmcblk0`). Returns `None` for anything that isn't an SD/MMC card,
+    /// including cards behind USB readers, which hide the CID.
+    pub fn read(device_name: &str) -> Option<Self> {
+        let dir = PathBuf::from("/sys/block").join(device_name).join("device");
+        let read = |file: &str| {
+            fs::read_to_string(dir.join(file))
+                .map(|s| s.trim().to_string())
+                .ok()
+        };
+
+        let kind = read("type").filter(|t| t == "SD" || t == "MMC")?;
+        let oem = read("oemid").unwrap_or_default();
+        let oem = match parse_hex(&oem).map(|id| [(id >> 8) as u8, id as u8]) {
+            Some(bytes) if bytes.iter().all(u8::is_ascii_graphic) => {
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+            _ => oem,
+        };
+
+        Some(CardInfo {
+            kind,
+            manufacturer_id: read("manfid").and_then(|id| parse_hex(&id))?,
+            oem,
+            product: read("name").unwrap_or_default(),
+            serial: read("serial").unwrap_or_default(),
+            date: read("date").unwrap_or_default(),
+        })
+    }
+
+    /// The manufacturer's name, if the ID is a known one.
+    pub fn manufacturer(&self) -> Option<&'static str> {
+        let table = if self.kind == "SD" {
+            SD_MANUFACTURERS
+        } else {
+            MMC_MANUFACTURERS
+        };
+        table
+            .iter()
+            .find(|(id, _)| *id == self.manufacturer_id)
+            .map(|(_, name)| *name)
+    }
+}
+
+impl fmt::Display for CardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let manufacturer = match self.manufacturer() {
+            Some(name) => name.to_string(),
+            None => trf(
+                Msg::UnknownManufacturer,
+                &[&format!("{:#04x}", self.manufacturer_id)],
+            ),
+        };
+        write!(
+            f,
+            "{}",
+            trf(
+                Msg::CardDetails,
+                &[
+                    &manufacturer,
+                    &self.product,
+                    &self.oem,
+                    &self.serial,
+                    &self.date
+                ]
+            )
+        )
+    }
+}
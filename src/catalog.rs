@@ -0,0 +1,140 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::history;
+use crate::json;
+use crate::signature::{self, Signed};
+
+/// A known-good image, identified by the SHA-256 of the file as published
+/// (i.e., before decompression).
+#[derive(Clone)]
+pub struct Entry {
+    pub sha256: String,
+    pub name: String,
+    pub version: String,
+}
+
+impl Entry {
+    /// The name and version for display, e.g. `OpenWrt 23.05.3`.
+    pub fn label(&self) -> String {
+        if self.version.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} {}", self.name, self.version)
+        }
+    }
+}
+
+/// The local catalog, one `sha256\tname\tversion` line per image.
+fn catalog_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("catalog"))
+}
+
+/// Reads the local catalog. A missing or unreadable catalog is empty.
+pub fn load() -> Vec<Entry> {
+    let Some(contents) = catalog_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(Entry {
+                sha256: fields.next()?.to_string(),
+                name: fields.next()?.to_string(),
+                version: fields.next().unwrap_or("").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Finds the catalog entry for an image hash, given as full hex.
+pub fn lookup<'a>(entries: &'a [Entry], sha256: &str) -> Option<&'a Entry> {
+    entries
+        .iter()
+        .find(|e| e.sha256.eq_ignore_ascii_case(sha256))
+}
+
+fn save(entries: &[Entry]) -> Result<()> {
+    let path = catalog_path().ok_or_else(|| anyhow!("cannot locate the state directory"))?;
+    let mut contents = String::new();
+    for entry in entries {
+        contents.push_str(&format!(
+            "{}\t{}\t{}\n",
+            entry.sha256, entry.name, entry.version
+        ));
+    }
+    history::save(&path, &contents).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Parses a catalog file of the form
+/// `{"images": [{"sha256": "...", "name": "...", "version": "..."}]}`.
+fn parse(text: &str) -> Result<Vec<Entry>> {
+    let doc = json::parse(text)?;
+    let images = doc
+        .get("images")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| anyhow!("expected an \"images\" array"))?;
+
+    images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let field = |key: &str| image.get(key).and_then(|v| v.as_str());
+            let sha256 = field("sha256")
+                .filter(|h| h.len() == 64 && h.chars().all(|c| c.is_ascii_hexdigit()))
+                .ok_or_else(|| anyhow!("image {i}: missing or invalid \"sha256\""))?;
+            let name = field("name").ok_or_else(|| anyhow!("image {i}: missing \"name\""))?;
+            // Tabs and newlines would break the catalog's line format
+            let clean = |s: &str| s.replace(['\t', '\n', '\r'], " ");
+            Ok(Entry {
+                sha256: sha256.to_lowercase(),
+                name: clean(name),
+                version: clean(field("version").unwrap_or("")),
+            })
+        })
+        .collect()
+}
+
+/// Merges the images from a JSON catalog file into the local catalog,
+/// replacing entries with the same hash. Unless `unsigned`, the file must
+/// carry a valid detached signature. Returns the number of images imported.
+pub fn import(
+    file: &Path,
+    signature: Option<&Path>,
+    unsigned: bool,
+    keyring: Option<&Path>,
+) -> Result<usize> {
+    let text = fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+
+    if !unsigned {
+        let signature = signature
+            .map(Path::to_path_buf)
+            .or_else(|| signature::find_detached(file))
+            .ok_or_else(|| {
+                anyhow!(
+                    "no signature found for {} (expected {0}.asc or {0}.sig; use --signature)",
+                    file.display()
+                )
+            })?;
+        signature::verify(
+            Signed::Data {
+                file,
+                data: text.as_bytes(),
+            },
+            &signature,
+            keyring,
+        )?;
+    }
+
+    let imported = parse(&text).with_context(|| format!("parsing {}", file.display()))?;
+    let mut entries = load();
+    entries.retain(|e| lookup(&imported, &e.sha256).is_none());
+    entries.extend(imported.iter().cloned());
+    save(&entries)?;
+
+    Ok(imported.len())
+}
@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Result, anyhow};
+use console::style;
+
+use crate::cancel::CancelToken;
+use crate::output::{info, verbose};
+use crate::write;
+
+/// The SHA-256 an image is expected to have, as published beside it.
+pub struct Checksum {
+    /// Lowercase hex.
+    pub sha256: String,
+    /// Where it was found: `--checksum`, or the sidecar file or URL.
+    pub from: String,
+}
+
+/// Parses a SHA-256 given in hex.
+pub fn parse_sha256(hash: &str) -> Result<String, String> {
+    if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("'{hash}' is not a SHA-256 hash (64 hex digits)"));
+    }
+    Ok(hash.to_ascii_lowercase())
+}
+
+/// The checksum `image` must match: `given` on the command line, or else
+/// the one published beside it, in `IMAGE.sha256` or in a `SHA256SUMS`
+/// file of its directory. For an `http(s)://` URL, `URL.sha256` is
+/// fetched, if the server has it.
+pub fn expected(image: &Path, given: Option<String>) -> Result<Option<Checksum>> {
+    if let Some(sha256) = given {
+        return Ok(Some(Checksum {
+            sha256,
+            from: "--checksum".to_string(),
+        }));
+    }
+    let name = image.to_string_lossy();
+    if name.starts_with("http://") || name.starts_with("https://") {
+        let url = format!("{name}.sha256");
+        return Ok(download(&url).and_then(|contents| {
+            let sha256 = find(&contents, file_name(&name))?;
+            Some(Checksum { sha256, from: url })
+        }));
+    }
+    if name == "-" || name.contains("://") {
+        return Ok(None);
+    }
+
+    let file = image.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar = image.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    let sums = image
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .join("SHA256SUMS");
+    for path in [PathBuf::from(sidecar), sums] {
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        match find(&contents, &file) {
+            Some(sha256) => {
+                return Ok(Some(Checksum {
+                    sha256,
+                    from: path.display().to_string(),
+                }));
+            }
+            None => verbose!("{} has no checksum for {file}", path.display()),
+        }
+    }
+    Ok(None)
+}
+
+/// Checks a local image file against `checksum` before anything is
+/// written, so a corrupt download is never flashed.
+pub fn check_file(image: &Path, checksum: &Checksum, cancel: &CancelToken) -> Result<()> {
+    let actual = write::image_hash(image, cancel)?;
+    if actual != checksum.sha256 {
+        return Err(anyhow!(
+            "{}'s SHA-256 is {actual}, but {} gives {}; the image is corrupt or not the one published",
+            image.display(),
+            checksum.from,
+            checksum.sha256
+        ));
+    }
+    info!(
+        "✅ {} matches its published SHA-256 ({})",
+        style(image.display()).cyan(),
+        checksum.from
+    );
+    Ok(())
+}
+
+/// The hash for `file` in the contents of a checksum file: either a lone
+/// hash, or `sha256sum` output (`HASH  NAME` or `HASH *NAME` lines), or
+/// BSD style `SHA256 (NAME) = HASH` lines.
+fn find(contents: &str, file: &str) -> Option<String> {
+    let mut lone = None;
+    for line in contents.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("SHA256 (")
+            && let Some((name, hash)) = rest.split_once(") = ")
+        {
+            if name == file {
+                return parse_sha256(hash.trim()).ok();
+            }
+            continue;
+        }
+        let mut fields = line.splitn(2, char::is_whitespace);
+        let Some(hash) = fields.next().and_then(|h| parse_sha256(h).ok()) else {
+            continue;
+        };
+        match fields
+            .next()
+            .map(|name| name.trim().trim_start_matches('*'))
+        {
+            Some(name) if file_name(name) == file => return Some(hash),
+            Some(_) => {}
+            None => lone = Some(hash),
+        }
+    }
+    lone
+}
+
+/// The last component of a path or URL.
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Fetches a small file over HTTP(S), if it's there.
+fn download(url: &str) -> Option<String> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--location", "--max-time", "30"])
+        .arg(url)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,1623 @@
+use crate::{
+    audit, buffers, cancel, catalog, checksum, clone, compress, config, customize, device, events,
+    exclude, history, i18n, label, launcher, list, man, notify, output, pause, picker, policy,
+    probe, prompt, read, safety, setup, shared, signature, sink, source, spinner, stats, status,
+    testimage, title, units, unlock, verify, wear, write,
+};
+use anyhow::{Context, Result, anyhow};
+use cancel::CancelToken;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use console::style;
+use device::ConfirmMode;
+use i18n::{Msg, tr, trf};
+use indicatif::HumanBytes;
+use libc::ECHOCTL;
+use output::info;
+use std::io::{IsTerminal, Write, stdin, stdout};
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use termios::{TCSANOW, Termios, tcsetattr};
+
+#[derive(Parser)]
+#[command(name = "etchr")]
+#[command(about = "A safe, interactive disk imaging tool", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+
+    /// Safety profile (overrides `safety` in the config file)
+    #[arg(long, global = true, value_enum)]
+    safety: Option<safety::SafetyProfile>,
+
+    /// Use the settings of the [profile.NAME] section of the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Language for messages (defaults to LANG)
+    #[arg(long, global = true, value_enum)]
+    lang: Option<i18n::Lang>,
+
+    /// Answer yes to all confirmation prompts
+    #[arg(short = 'y', long, visible_alias = "force", global = true)]
+    yes: bool,
+
+    /// Write to devices even if they're shared over NFS or Samba, or attached to a virtual machine
+    #[arg(long, global = true)]
+    force_shared: bool,
+
+    /// Only print errors (and prompts, unless --yes is given)
+    #[arg(short = 'q', long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print additional diagnostic information
+    #[arg(short = 'v', long, global = true)]
+    verbose: bool,
+
+    /// Use numbered prompts and single-line progress, for serial consoles
+    #[arg(long, global = true)]
+    serial_console: bool,
+
+    /// Print byte counts as plain numbers, for scripts parsing the output
+    #[arg(long, global = true)]
+    raw_sizes: bool,
+
+    /// Write progress and result records to this inherited file descriptor
+    #[arg(long, global = true, value_name = "N")]
+    progress_fd: Option<i32>,
+
+    /// For GUI frontends: print JSON events on stdout instead of progress bars, and never prompt
+    #[arg(
+        long,
+        visible_alias = "machine-readable",
+        global = true,
+        conflicts_with = "progress_fd"
+    )]
+    json: bool,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Write an image to a device interactively
+    Write {
+        /// Image file to write, '-' for stdin, an http(s)://, s3://, scp:// or sftp:// URL, or a magnet link or .torrent file (opens an interactive picker if omitted)
+        image: Option<PathBuf>,
+
+        /// Skip write verification
+        #[arg(short = 'n', long = "no-verify")]
+        no_verify: bool,
+
+        /// Probe the device speed and show an estimated duration before confirming
+        #[arg(long)]
+        probe: bool,
+
+        /// Mirror progress into the terminal (and tmux window) title
+        #[arg(long)]
+        title: bool,
+
+        /// Show progress on the terminal's dock or taskbar icon (over D-Bus)
+        #[arg(long)]
+        dock_progress: bool,
+
+        /// Allow the image to be a block device (device-to-device copy)
+        #[arg(long)]
+        from_device: bool,
+
+        /// Restrict filesystem access and syscalls once the device and image are open
+        #[arg(long)]
+        sandbox: bool,
+
+        /// Install a script on the flashed system that runs once on first boot
+        #[arg(long, value_name = "SCRIPT")]
+        firstboot: Option<PathBuf>,
+
+        /// Set the hostname of the flashed system
+        #[arg(long, value_parser = customize::parse_hostname)]
+        hostname: Option<String>,
+
+        /// Give the flashed system a static address, e.g. 192.168.1.10/24
+        #[arg(long, value_name = "ADDRESS/PREFIX")]
+        static_ip: Option<customize::StaticAddress>,
+
+        /// Default gateway for --static-ip
+        #[arg(long, requires = "static_ip")]
+        gateway: Option<IpAddr>,
+
+        /// DNS server for --static-ip (can be repeated)
+        #[arg(long, requires = "static_ip")]
+        dns: Vec<IpAddr>,
+
+        /// Interface to configure with --static-ip [default: eth0]
+        #[arg(long, requires = "static_ip")]
+        interface: Option<String>,
+
+        /// Authorize an SSH key file, or github:<user>'s keys, for the default user (can be repeated)
+        #[arg(long, value_name = "FILE|github:USER")]
+        ssh_key: Vec<customize::SshKeySource>,
+
+        /// Write to this device (e.g. sdb) instead of choosing interactively; repeat to write several devices in turn
+        #[arg(long = "device", value_name = "NAME")]
+        devices: Vec<String>,
+
+        /// Write to every removable device attached, e.g. a hub full of card readers
+        #[arg(long, conflicts_with = "devices")]
+        all_removable: bool,
+
+        /// Write and verify several devices at once rather than one after another
+        #[arg(long)]
+        parallel: bool,
+
+        /// Save a JSON report of the image and device hashes and any mismatches to FILE
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+
+        /// The image's published SHA-256, instead of one from an IMAGE.sha256 or SHA256SUMS file beside it
+        #[arg(long, alias = "sha256", value_name = "HEX", value_parser = checksum::parse_sha256)]
+        checksum: Option<String>,
+
+        /// Number of 1 MiB buffers read ahead of the device
+        #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..=256))]
+        queue_depth: u16,
+
+        /// zstd dictionary the image was compressed with (see 'read --train-dict')
+        #[arg(long, value_name = "FILE")]
+        dict: Option<PathBuf>,
+
+        /// Write only partition N of the image (e.g. the rootfs of a full-disk image)
+        #[arg(long, value_name = "N")]
+        partition: Option<u32>,
+
+        /// Write into partition N of the device, leaving the rest of the device as is
+        #[arg(long, value_name = "N")]
+        to_partition: Option<u32>,
+
+        /// Write a compressed image as it's decompressed, without a temp file
+        #[arg(long, conflicts_with = "partition")]
+        stream: bool,
+
+        /// Eject each device once it's written, so it can be unplugged
+        #[arg(long)]
+        eject: bool,
+
+        /// Write an image larger than the device, cutting off what doesn't fit
+        #[arg(long)]
+        force_oversize: bool,
+    },
+    /// Read a device to an image file interactively
+    Read {
+        /// Output image file, '-' for stdout, tcp://HOST:PORT, unix:PATH, an http(s):// URL to PUT to, or s3://BUCKET/KEY
+        #[arg(required = true)]
+        image: PathBuf,
+
+        /// Read this device (e.g. sdb) instead of choosing interactively
+        #[arg(long, value_name = "NAME")]
+        device: Option<String>,
+
+        /// Probe the device speed and show an estimated duration before confirming
+        #[arg(long)]
+        probe: bool,
+
+        /// Mirror progress into the terminal (and tmux window) title
+        #[arg(long)]
+        title: bool,
+
+        /// Show progress on the terminal's dock or taskbar icon (over D-Bus)
+        #[arg(long)]
+        dock_progress: bool,
+
+        /// Restrict filesystem access and syscalls once the device and image are open
+        #[arg(long)]
+        sandbox: bool,
+
+        /// Read into IMAGE.partial and keep it if the read is cancelled or fails
+        #[arg(long)]
+        keep_partial: bool,
+
+        /// Continue a read kept with --keep-partial where it stopped
+        #[arg(long)]
+        resume: bool,
+
+        /// Hex bytes written in place of sectors that can't be read
+        #[arg(long, value_name = "HEX", default_value = "00")]
+        bad_sector_fill: read::FillPattern,
+
+        /// Save a GNU ddrescue mapfile of the read, including unreadable sectors
+        #[arg(long, value_name = "FILE")]
+        mapfile: Option<PathBuf>,
+
+        /// Compress the image with gzip, xz or zstd (e.g. 'zstd:19'), or 'auto' to pick the strongest that keeps up with the device [default: the codec the image's extension names, if any]
+        #[arg(long, value_name = "CODEC[:LEVEL]", conflicts_with_all = ["keep_partial", "resume"])]
+        compress: Option<compress::Choice>,
+
+        /// Let zstd find repeats up to 2^WINDOW_LOG bytes apart [default: 27]; large windows need as much memory to decompress
+        #[arg(
+            long,
+            value_name = "WINDOW_LOG",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "27",
+            value_parser = clap::value_parser!(u32).range(10..=i64::from(compress::MAX_WINDOW_LOG))
+        )]
+        long: Option<u32>,
+
+        /// Compress with a zstd dictionary, e.g. one trained on a similar device with --train-dict
+        #[arg(long, value_name = "FILE", conflicts_with = "train_dict")]
+        dict: Option<PathBuf>,
+
+        /// Train a zstd dictionary on the device, save it to FILE and compress with it
+        #[arg(long, value_name = "FILE")]
+        train_dict: Option<PathBuf>,
+
+        /// End each zstd frame with a checksum of its contents
+        #[arg(long)]
+        checksum: bool,
+
+        /// Sign the image with the key in the [signing] section of the config file
+        #[arg(long)]
+        sign: bool,
+
+        /// Write blocks of zeros to an uncompressed image, instead of leaving holes in it
+        #[arg(long)]
+        no_sparse: bool,
+    },
+    /// Copy selected partitions of a device or image to another device
+    Clone {
+        /// Device or image to copy the partitions from (chosen interactively if omitted)
+        source: Option<PathBuf>,
+
+        /// Partitions to copy, e.g. '1,3'
+        #[arg(long, required = true, value_name = "N,...", value_delimiter = ',')]
+        partitions: Vec<u32>,
+
+        /// Also copy the partition table, and anything before the first partition
+        #[arg(long)]
+        table: bool,
+
+        /// Copy to this device (e.g. sdb) instead of choosing interactively
+        #[arg(long, value_name = "NAME")]
+        device: Option<String>,
+
+        /// Skip comparing the copied partitions with the source
+        #[arg(short = 'n', long = "no-verify")]
+        no_verify: bool,
+
+        /// Mirror progress into the terminal (and tmux window) title
+        #[arg(long)]
+        title: bool,
+
+        /// Show progress on the terminal's dock or taskbar icon (over D-Bus)
+        #[arg(long)]
+        dock_progress: bool,
+    },
+    /// Unlock a device for writing for a few minutes, when the config sets require_unlock
+    Unlock {
+        /// Device to unlock (e.g. sdb)
+        device: String,
+    },
+    /// Compare a device with an earlier dump of it, and show which partitions changed
+    Verify {
+        /// Image the device was read into earlier, compressed or not
+        #[arg(long, required = true, value_name = "IMAGE")]
+        baseline: PathBuf,
+
+        /// Compare this device (e.g. sdb) instead of choosing interactively
+        #[arg(long, value_name = "NAME", conflicts_with = "image")]
+        device: Option<String>,
+
+        /// Compare an image file instead of a device
+        #[arg(long, value_name = "FILE")]
+        image: Option<PathBuf>,
+
+        /// Save a JSON report of both hashes and the changed ranges to FILE
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+    /// Check that deployed cards still hold the image assigned to their serial number
+    Audit {
+        /// Manifest, e.g. {"devices": [{"serial": "...", "sha256": "...", "size": 123}]}
+        manifest: PathBuf,
+
+        /// Audit only this device (e.g. sdb); can be repeated [default: all devices offered]
+        #[arg(long = "device", value_name = "NAME")]
+        devices: Vec<String>,
+    },
+    /// List available removable devices
+    List {
+        /// Output format
+        #[arg(long, value_enum, default_value_t = list::Format::Table)]
+        format: list::Format,
+    },
+    /// Manage the catalog of known-good images
+    Catalog {
+        #[command(subcommand)]
+        action: CatalogAction,
+    },
+    /// Show a live dashboard of running read/write operations
+    Watch,
+    /// Show how many devices were written each day, how many failed, and with which images
+    Stats {
+        /// Cover the last N days
+        #[arg(long, value_name = "N", default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+        days: u32,
+    },
+    /// Choose default settings (image directory, verification, ...) and save them to the config file
+    Setup,
+    /// Generate a synthetic image, for testing and benchmarking
+    GenTestImage {
+        /// Output image file
+        image: PathBuf,
+
+        /// Size of the (uncompressed) image, e.g. 256M
+        #[arg(long, value_parser = testimage::parse_size)]
+        size: u64,
+
+        /// Sizes of MBR partitions to lay out from 1 MiB on, e.g. '16M,rest'
+        #[arg(long, value_name = "SIZE,...", value_delimiter = ',')]
+        partitions: Vec<testimage::PartitionSize>,
+
+        /// What to fill the image with: zero, random, sectors, mixed, or hex bytes
+        #[arg(long, default_value = "mixed")]
+        fill: testimage::Fill,
+
+        /// Seed for the random data, to make the same image again
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+
+        /// Compress the image with gzip, xz or zstd (e.g. 'zstd:19')
+        #[arg(long, value_name = "CODEC[:LEVEL]")]
+        compress: Option<compress::Choice>,
+    },
+    /// Manage settings that apply to every user of this machine
+    Admin {
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+    /// Print or install man pages generated from this CLI
+    Man {
+        /// Subcommand whose page to print (e.g. 'write'); prints the main page if omitted
+        page: Option<String>,
+
+        /// Install all pages into this directory (e.g. /usr/local/share/man/man1)
+        #[arg(long, conflicts_with = "page")]
+        install: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CatalogAction {
+    /// Import images from a signed JSON catalog file
+    Import {
+        /// Catalog file, e.g. {"images": [{"sha256": "...", "name": "...", "version": "..."}]}
+        file: PathBuf,
+
+        /// Detached signature (defaults to FILE.asc or FILE.sig)
+        #[arg(long, conflicts_with = "unsigned")]
+        signature: Option<PathBuf>,
+
+        /// Import without checking a signature
+        #[arg(long)]
+        unsigned: bool,
+    },
+    /// List the images in the catalog
+    List,
+}
+
+#[derive(Subcommand)]
+enum AdminAction {
+    /// Never offer a device again, whatever the safety profile (needs root)
+    Exclude {
+        /// Device to exclude (e.g. sda)
+        device: String,
+    },
+}
+
+struct TermRestorer {
+    original_termios: Option<Termios>,
+}
+
+impl TermRestorer {
+    fn new() -> Self {
+        let fd = stdout().as_raw_fd();
+        if !stdout().is_terminal() {
+            return Self {
+                original_termios: None,
+            };
+        }
+
+        if let Ok(original_termios) = Termios::from_fd(fd) {
+            let mut new_termios = original_termios;
+            new_termios.c_lflag &= !ECHOCTL;
+
+            if tcsetattr(fd, TCSANOW, &new_termios).is_ok() {
+                Self {
+                    original_termios: Some(original_termios),
+                }
+            } else {
+                Self {
+                    original_termios: None,
+                }
+            }
+        } else {
+            Self {
+                original_termios: None,
+            }
+        }
+    }
+}
+
+impl Drop for TermRestorer {
+    fn drop(&mut self) {
+        if let Some(ref original_termios) = self.original_termios {
+            let fd = stdout().as_raw_fd();
+            tcsetattr(fd, TCSANOW, original_termios).ok();
+        }
+    }
+}
+
+/// Shows the config profile in effect, if `--profile` selected one, as it
+/// changes how the operation runs.
+fn print_profile(profile: &Option<String>) {
+    if let Some(name) = profile {
+        println!("{}{}", tr(Msg::ProfileLabel), style(name).cyan());
+    }
+}
+
+/// Prints what's known about the card in `device` and its connection,
+/// warning if the connection slows it down or the card has been written
+/// more than `wear_warning` times.
+fn print_device_details(device: &device::Device, wear_warning: u32) {
+    if let Some(card) = &device.card {
+        println!("{}{card}", tr(Msg::CardLabel));
+    }
+    if let Some(link) = &device.link {
+        println!("{}{link}", tr(Msg::LinkLabel));
+        if let Some(warning) = link.warning(&device.name) {
+            eprintln!("{} {warning}", style(tr(Msg::Warning)).yellow().bold());
+        }
+    }
+    if let Some(burns) = device.burns.filter(|b| *b > wear_warning) {
+        eprintln!(
+            "{} {}",
+            style(tr(Msg::Warning)).yellow().bold(),
+            trf(Msg::WornCard, &[&device.name, &burns, &wear_warning])
+        );
+    }
+}
+
+/// Refuses to overwrite `device` while something else depends on it (see
+/// [`shared::users`]), unless `force` is given.
+fn check_shared(device: &device::Device, force: bool) -> Result<()> {
+    let users = shared::users(device);
+    if users.is_empty() {
+        return Ok(());
+    }
+    let users = users.join(", ");
+    if force {
+        eprintln!(
+            "{} {}",
+            style(tr(Msg::Warning)).yellow().bold(),
+            trf(Msg::SharedForced, &[&device.path.display(), &users])
+        );
+        return Ok(());
+    }
+    Err(anyhow!(trf(
+        Msg::SharedRefused,
+        &[&device.path.display(), &users]
+    )))
+}
+
+/// Reads the zstd dictionary given with `--dict`, if any.
+fn read_dictionary(path: Option<PathBuf>) -> Result<Option<Vec<u8>>> {
+    path.map(|path| {
+        std::fs::read(&path).with_context(|| trf(Msg::ReadingDictionary, &[&path.display()]))
+    })
+    .transpose()
+}
+
+/// Returns true if `path` is `-`, meaning stdin or stdout.
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// The codec the extension of `image` names, e.g. zstd for `backup.img.zst`.
+fn codec_of(image: &Path) -> Option<compress::Codec> {
+    compress::Codec::from_extension(&image.extension()?.to_string_lossy())
+}
+
+/// Compresses a read's image with the codec its extension names, unless
+/// `--compress` picks one, at the level `levels` (from the config file)
+/// gives for it, or else the codec's default.
+fn infer_compression(cli: &mut Cli, levels: &[compress::Compression]) {
+    if let Commands::Read {
+        image,
+        compress: compress @ None,
+        keep_partial: false,
+        resume: false,
+        ..
+    } = &mut cli.command
+    {
+        *compress = codec_of(image).map(|codec| {
+            let compression = levels
+                .iter()
+                .find(|c| c.codec == codec)
+                .copied()
+                .unwrap_or_else(|| compress::Compression::at_default_level(codec));
+            compress::Choice::Fixed(compression)
+        });
+    }
+}
+
+/// Refuses an image larger than any of `devices` before anything is
+/// written, from its size or the size its compressed format records,
+/// decompressing it to measure if it records none, unless `force` is
+/// given. Writing it would fail partway, with the device already
+/// overwritten. A stream can't be measured beforehand, so it's let through
+/// and the write fails once the device is full, unless `force` is given.
+fn check_fits(
+    image: &Path,
+    devices: &[device::Device],
+    dictionary: Option<&[u8]>,
+    force: bool,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let size = match write::uncompressed_size(image) {
+        Some(size) => Some(size),
+        None if source::is_stream(image) => None,
+        None => write::decompressed_size(image, dictionary, cancel)?,
+    };
+    let Some(size) = size else {
+        if force {
+            for device in devices {
+                let args: [&dyn std::fmt::Display; 3] = [
+                    if is_stdio(image) {
+                        &"stdin"
+                    } else {
+                        &image.display()
+                    },
+                    &device.path.display(),
+                    &HumanBytes(device.size_bytes),
+                ];
+                eprintln!(
+                    "{} {}",
+                    style(tr(Msg::Warning)).yellow().bold(),
+                    trf(Msg::ImageSizeUnchecked, &args)
+                );
+            }
+        }
+        return Ok(());
+    };
+    for device in devices.iter().filter(|d| d.size_bytes < size) {
+        if !force {
+            return Err(anyhow!(trf(
+                Msg::ImageTooLarge,
+                &[
+                    &HumanBytes(size),
+                    &device.path.display(),
+                    &HumanBytes(device.size_bytes)
+                ]
+            )));
+        }
+        eprintln!(
+            "{} {}",
+            style(tr(Msg::Warning)).yellow().bold(),
+            trf(
+                Msg::ImageWillBeCutOff,
+                &[
+                    &HumanBytes(size),
+                    &device.path.display(),
+                    &HumanBytes(device.size_bytes),
+                    &HumanBytes(size - device.size_bytes)
+                ]
+            )
+        );
+    }
+    Ok(())
+}
+
+/// Rejects argument combinations that can't work, with a clap-style
+/// usage error, before any device is touched.
+fn validate(cli: &Cli) {
+    let error = match &cli.command {
+        Commands::Write {
+            image: Some(image),
+            from_device,
+            ..
+        } if is_stdio(image) => {
+            if *from_device {
+                Some((
+                    ErrorKind::ArgumentConflict,
+                    "--from-device cannot be used when the image is read from stdin ('-')",
+                ))
+            } else if stdin().is_terminal() {
+                Some((
+                    ErrorKind::InvalidValue,
+                    "the image '-' means stdin, but stdin is a terminal; pipe an image in instead",
+                ))
+            } else {
+                None
+            }
+        }
+        Commands::Read { image, .. } if is_stdio(image) && stdout().is_terminal() => Some((
+            ErrorKind::InvalidValue,
+            "refusing to write image data to a terminal; redirect stdout or give an output file",
+        )),
+        Commands::Read {
+            image,
+            keep_partial,
+            resume,
+            ..
+        } if sink::is_stream(image) && (*keep_partial || *resume) => Some((
+            ErrorKind::ArgumentConflict,
+            "--keep-partial and --resume need an output file, not stdout ('-') or a URL",
+        )),
+        Commands::Read {
+            image,
+            compress: None,
+            keep_partial,
+            resume,
+            ..
+        } if (*keep_partial || *resume) && codec_of(image).is_some() => Some((
+            ErrorKind::ArgumentConflict,
+            "the image's extension asks for compression, which --keep-partial and --resume can't do",
+        )),
+        Commands::Read {
+            image, sign: true, ..
+        } if sink::is_stream(image) => Some((
+            ErrorKind::ArgumentConflict,
+            "--sign needs an output file, not stdout ('-') or a URL",
+        )),
+        Commands::Read {
+            compress,
+            long,
+            dict,
+            train_dict,
+            checksum,
+            ..
+        } if (long.is_some() || dict.is_some() || train_dict.is_some() || *checksum)
+            && !matches!(
+                compress,
+                Some(compress::Choice::Fixed(compress::Compression {
+                    codec: compress::Codec::Zstd,
+                    ..
+                }))
+            ) =>
+        {
+            Some((
+                ErrorKind::MissingRequiredArgument,
+                "--long, --dict, --train-dict and --checksum need --compress zstd",
+            ))
+        }
+        _ => None,
+    };
+
+    if let Some((kind, message)) = error {
+        Cli::command().error(kind, message).exit();
+    }
+}
+
+/// Runs the `etchr` command, for `main.rs`.
+pub fn main() -> Result<()> {
+    let result = run();
+    events::result(&result);
+    match &result {
+        Ok(()) => notify::status(tr(Msg::NotifyFinished)),
+        Err(e) => notify::status(&trf(Msg::NotifyFailed, &[&format!("{e:#}")])),
+    }
+    result
+}
+
+fn run() -> Result<()> {
+    // This guard will be dropped when main() exits, restoring the terminal
+    let _term_restorer = TermRestorer::new();
+
+    // Ctrl+C stops the running operation, which cleans up after itself
+    let cancel = CancelToken::on_ctrl_c()?;
+
+    // SIGUSR1 toggles pause/resume of a running operation
+    pause::install_signal_handler();
+
+    // Settled now, while still root, for the state read before the drop and
+    // written after it
+    history::state_dir();
+
+    let mut cli = Cli::parse();
+    if let Some(fd) = cli.progress_fd {
+        events::open(fd)?;
+    }
+    if cli.json {
+        events::open_json()?;
+    }
+    i18n::init(cli.lang);
+    output::init(cli.quiet, cli.verbose, cli.serial_console, cli.json);
+    units::set_raw(cli.raw_sizes);
+    // Only offered to someone at the terminal, about to answer prompts anyway
+    let interactive = matches!(
+        cli.command,
+        Commands::Write { .. } | Commands::Read { .. } | Commands::Clone { .. }
+    ) && !cli.yes
+        && !cli.quiet
+        && !cli.json
+        && cli.profile.is_none()
+        && stdin().is_terminal()
+        && stdout().is_terminal();
+    if interactive {
+        setup::offer()?;
+    }
+    let config = config::load(cli.profile.as_deref())?;
+    infer_compression(&mut cli, &config.compress);
+    validate(&cli);
+    notify::init();
+    notify::ready();
+    spinner::set_theme(config.spinner.unwrap_or_default());
+    buffers::set_limit(config.memory_limit);
+    let safety = cli.safety.or(config.safety).unwrap_or_default();
+    let wear_warning = config.wear_warning.unwrap_or(wear::DEFAULT_WARNING);
+
+    let confirm_mode = if cli.yes {
+        safety.check_override("--yes")?;
+        ConfirmMode::AssumeYes
+    } else if safety.requires_typed_confirmation() {
+        ConfirmMode::Typed
+    } else {
+        ConfirmMode::Prompt
+    };
+    if cli.force_shared {
+        safety.check_override("--force-shared")?;
+    }
+    // The summary is what the user confirms, so it's only optional with --yes
+    let show_summary = !cli.yes || !output::is_quiet();
+
+    match cli.command {
+        Commands::Write {
+            image,
+            no_verify,
+            probe,
+            title,
+            dock_progress,
+            from_device,
+            sandbox,
+            queue_depth,
+            firstboot,
+            hostname,
+            static_ip,
+            gateway,
+            dns,
+            interface,
+            ssh_key,
+            devices: device_names,
+            all_removable,
+            parallel,
+            report,
+            checksum: given_checksum,
+            dict,
+            partition,
+            to_partition,
+            stream,
+            eject,
+            force_oversize,
+        } => {
+            if title {
+                title::enable();
+            }
+            if dock_progress {
+                launcher::enable(config.launcher_app.clone());
+            }
+            let verify = !no_verify && config.verify.unwrap_or(true);
+            if !verify {
+                safety.check_override(if no_verify {
+                    "--no-verify"
+                } else {
+                    "verify = false"
+                })?;
+            }
+            let parallel = parallel || config.parallel.unwrap_or(false);
+            let eject = eject || config.eject.unwrap_or(false);
+            let network = static_ip.map(|address| customize::Network {
+                interface: interface
+                    .or(config.network_interface)
+                    .unwrap_or_else(|| "eth0".to_string()),
+                address,
+                gateway: gateway.or(config.network_gateway),
+                dns: if dns.is_empty() {
+                    config.network_dns
+                } else {
+                    dns
+                },
+            });
+            // Fetch keys now, so a typo fails before the device is written
+            let ssh_keys = if ssh_key.is_empty() {
+                None
+            } else {
+                Some(
+                    ssh_key
+                        .iter()
+                        .map(customize::SshKeySource::fetch)
+                        .collect::<Result<String>>()?,
+                )
+            };
+            let customizations = customize::Customizations {
+                firstboot,
+                hostname,
+                network,
+                ssh_keys,
+            };
+            let sandbox = sandbox || safety.always_sandboxes();
+            if sandbox && !customizations.is_empty() {
+                return Err(anyhow!(tr(Msg::CustomizeSandboxed)));
+            }
+
+            let image = match image {
+                Some(image) => image,
+                None => {
+                    let start_dir = config.image_dir.unwrap_or_else(|| PathBuf::from("."));
+                    picker::choose_image(&start_dir)?
+                }
+            };
+
+            // Downloaded up front, so it's a local file from here on, and
+            // kept until the devices are written
+            #[cfg(feature = "torrent")]
+            let mut _torrent = None;
+            let image = if source::is_torrent(&image) {
+                #[cfg(feature = "torrent")]
+                {
+                    let download = crate::torrent::fetch(&image, &cancel)?;
+                    let path = download.image().to_path_buf();
+                    _torrent = Some(download);
+                    path
+                }
+                #[cfg(not(feature = "torrent"))]
+                return Err(anyhow!(tr(Msg::TorrentNotBuilt)));
+            } else {
+                image
+            };
+
+            // Catch swapped arguments like `etchr write /dev/sdb`
+            let image_is_device = device::is_block_device(&image);
+            if image_is_device && !from_device {
+                return Err(anyhow!(trf(Msg::ImageIsBlockDevice, &[&image.display()])));
+            }
+
+            policy::load(config.policy)?.check(
+                &image,
+                !source::is_stream(&image) && !image_is_device,
+                &cancel,
+            )?;
+            // A corrupt download is caught before anything is written,
+            // or for a stream, as soon as it has arrived
+            let published = if image_is_device {
+                None
+            } else {
+                checksum::expected(&image, given_checksum)?
+            };
+            if let Some(published) = &published
+                && !source::is_stream(&image)
+            {
+                checksum::check_file(&image, published, &cancel)?;
+            }
+
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            let targets = if all_removable {
+                // Only what's removable, whatever the safety profile allows
+                let removable = device::get_removable_devices(false)?;
+                if removable.is_empty() {
+                    return Err(anyhow!(tr(Msg::NoRemovableDevices)));
+                }
+                removable
+            } else if device_names.is_empty() {
+                vec![device::select_device(
+                    &devices,
+                    safety.allows_internal_disks(),
+                    tr(Msg::SelectWriteTarget),
+                )?]
+            } else {
+                let mut targets: Vec<device::Device> = Vec::new();
+                for name in &device_names {
+                    let target = device::find_device(&devices, name)?;
+                    if targets.iter().any(|t| t.path == target.path) {
+                        return Err(anyhow!(trf(Msg::DuplicateDevice, &[name])));
+                    }
+                    targets.push(target);
+                }
+                targets
+            };
+            if image_is_device {
+                let image = image.canonicalize()?;
+                if targets.iter().any(|t| t.path == image) {
+                    return Err(anyhow!(tr(Msg::SourceIsTarget)));
+                }
+            }
+            if config.require_unlock {
+                let window = config.unlock_window.unwrap_or(unlock::DEFAULT_WINDOW);
+                for device in &targets {
+                    unlock::check(device, window)?;
+                }
+            }
+            for device in &targets {
+                check_shared(device, cli.force_shared)?;
+            }
+            if force_oversize {
+                safety.check_override("--force-oversize")?;
+            }
+            let dictionary = read_dictionary(dict)?;
+            // Partitions are checked once the image and device are open
+            if partition.is_none() && to_partition.is_none() {
+                check_fits(
+                    &image,
+                    &targets,
+                    dictionary.as_deref(),
+                    force_oversize,
+                    &cancel,
+                )?;
+            }
+
+            // Print the warning and operation details manually
+            if show_summary {
+                for device in &targets {
+                    println!(
+                        "{} {}",
+                        style(tr(Msg::Warning)).red().bold(),
+                        trf(
+                            Msg::EraseWarning,
+                            &[&device.name, &format!("{:.1}", device.size_gb)]
+                        ),
+                    );
+                    for usage in &device.usage {
+                        println!("  {usage}");
+                    }
+                }
+                for device in &targets {
+                    println!(
+                        "{}{}",
+                        tr(Msg::DeviceLabel),
+                        style(device.path.display()).cyan()
+                    );
+                    print_device_details(device, wear_warning);
+                }
+                println!("{}{}", tr(Msg::ImageLabel), style(image.display()).cyan());
+                print_profile(&config.profile);
+            }
+            if probe && show_summary {
+                for device in &targets {
+                    let estimate = probe::measure_write_speed(device)
+                        .map(|rate| {
+                            probe::describe_estimate(write::uncompressed_size(&image), rate)
+                        })
+                        .unwrap_or_else(|e| trf(Msg::Unavailable, &[&e]));
+                    println!("{}{estimate}", tr(Msg::EstimatedTime));
+                }
+            }
+
+            // Create a simple prompt string for the confirmation
+            let prompt = tr(Msg::ConfirmProceed);
+
+            if !device::confirm_operation(
+                prompt,
+                &targets,
+                // A streamed image can't be inspected without consuming it
+                (!source::is_stream(&image)).then_some(image.as_path()),
+                confirm_mode,
+                &cancel,
+            )? {
+                println!("{}", tr(Msg::WriteCancelled));
+                return Ok(());
+            }
+            if let Some(seconds) = config.countdown
+                && !device::countdown(&targets, seconds, &cancel)
+            {
+                println!("{}", tr(Msg::WriteCancelled));
+                return Ok(());
+            }
+
+            info!();
+            let _keys = pause::KeyListener::start();
+            let options = write::Options {
+                verify,
+                sandbox,
+                queue_depth: queue_depth.into(),
+                // Mounting the flashed system afterwards needs root, as
+                // does ejecting the devices
+                drop_privileges: customizations.is_empty() && !eject,
+                dictionary,
+                confirm: confirm_mode,
+                partition,
+                to_partition,
+                stream,
+                max_temp_size: config.max_temp_size,
+                slow_write: config.slow_write,
+                parallel,
+                report,
+                checksum: published.filter(|_| source::is_stream(&image)),
+                truncate: force_oversize,
+            };
+            for device in &targets {
+                device::release(&device.path)?;
+            }
+            let paths: Vec<PathBuf> = targets.iter().map(|t| t.path.clone()).collect();
+            let failures = write::run(&image, &paths, &options, &cancel)?;
+            let failed = |device: &device::Device| failures.iter().any(|f| f.device == device.path);
+            // Failing to update the history must not fail a successful flash
+            if failures.len() < targets.len() {
+                history::record_write(&image).ok();
+            }
+            let mut labeler = config
+                .label_command
+                .map(|command| label::Labeler::new(command, sandbox));
+            // Hashed before confirming or while streamed, unless --quiet --yes
+            let hash = labeler
+                .as_ref()
+                .filter(|_| !image_is_device)
+                .and_then(|_| write::image_hash(&image, &cancel).ok());
+            for device in targets.iter().filter(|device| !failed(device)) {
+                wear::record(&device.name).ok();
+                if !customizations.is_empty() {
+                    customize::apply(&device.path, &customizations)?;
+                }
+                if let Some(labeler) = &mut labeler {
+                    labeler.submit(label::payload(&device.name, &image, hash.as_deref()));
+                }
+                info!(
+                    "\n{}",
+                    trf(
+                        Msg::FlashSuccess,
+                        &[
+                            &style(device.path.display()).cyan(),
+                            &style(image.display()).cyan()
+                        ]
+                    )
+                );
+                if eject && sandbox {
+                    // The sandbox lets no programs run
+                    eprintln!(
+                        "{} {}",
+                        style(tr(Msg::Warning)).yellow().bold(),
+                        trf(Msg::EjectSandboxed, &[&device.path.display()])
+                    );
+                } else if eject {
+                    match device::eject(&device.path) {
+                        Ok(()) => info!(
+                            "{}",
+                            trf(Msg::CanBeUnplugged, &[&style(device.path.display()).cyan()])
+                        ),
+                        Err(e) => eprintln!("{} {e:#}", style(tr(Msg::Warning)).yellow().bold()),
+                    }
+                }
+            }
+            if let Some(labeler) = labeler {
+                labeler.finish();
+            }
+            if !failures.is_empty() {
+                for failure in &failures {
+                    eprintln!(
+                        "❌ {}: {:#}",
+                        style(failure.device.display()).cyan(),
+                        failure.error
+                    );
+                }
+                return Err(anyhow!(trf(
+                    Msg::DevicesFailed,
+                    &[&failures.len(), &targets.len()]
+                )));
+            }
+        }
+        Commands::Read {
+            image,
+            device: device_name,
+            probe,
+            title,
+            dock_progress,
+            sandbox,
+            keep_partial,
+            resume,
+            bad_sector_fill,
+            mapfile,
+            compress,
+            long,
+            dict,
+            train_dict,
+            checksum,
+            sign,
+            no_sparse,
+        } => {
+            if title {
+                title::enable();
+            }
+            if dock_progress {
+                launcher::enable(config.launcher_app.clone());
+            }
+            let sandbox = sandbox || safety.always_sandboxes();
+            // Check the key now, so a missing one fails before the read
+            let signing_key = if sign {
+                if sandbox {
+                    return Err(anyhow!(tr(Msg::SignSandboxed)));
+                }
+                Some(
+                    config
+                        .signing_key
+                        .ok_or_else(|| anyhow!(tr(Msg::SignNeedsKey)))?,
+                )
+            } else {
+                None
+            };
+            // Claim stdout for the image before anything else is printed
+            let stdout = sink::claim_stdout(&image)?;
+            sink::check(&image, sandbox)?;
+            // A kept read that crashed or was killed can be picked up again
+            let resume = match read::interrupted(&image).filter(|_| !sink::is_stream(&image)) {
+                Some(offset)
+                    if !resume && compress.is_none() && confirm_mode != ConfirmMode::AssumeYes =>
+                {
+                    let question = trf(
+                        Msg::ResumeReadQuestion,
+                        &[&image.display(), &HumanBytes(offset)],
+                    );
+                    let resume = prompt::confirm(&question, true)?;
+                    if !resume {
+                        read::discard_partial(&image)?;
+                    }
+                    resume
+                }
+                Some(offset) if !resume => {
+                    info!(
+                        "{}",
+                        trf(
+                            Msg::ResumeReadHint,
+                            &[&image.display(), &HumanBytes(offset)]
+                        )
+                    );
+                    false
+                }
+                _ => resume,
+            };
+
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            let device = match device_name {
+                Some(name) => device::find_device(&devices, &name)?,
+                None => device::select_device(
+                    &devices,
+                    safety.allows_internal_disks(),
+                    tr(Msg::SelectReadSource),
+                )?,
+            };
+
+            // Print the operation details manually
+            if show_summary {
+                println!(
+                    "{}",
+                    trf(
+                        Msg::ReadSummary,
+                        &[&format!("{:.1}", device.size_gb), &device.name]
+                    )
+                );
+                println!(
+                    "{}{}",
+                    tr(Msg::DeviceLabel),
+                    style(device.path.display()).cyan()
+                );
+                print_device_details(&device, wear_warning);
+                println!("{}{}", tr(Msg::OutputLabel), style(image.display()).cyan());
+                print_profile(&config.profile);
+            }
+            if probe && show_summary {
+                let estimate = probe::measure_read_speed(&device.path, device.size_bytes)
+                    .map(|rate| probe::describe_estimate(Some(device.size_bytes), rate))
+                    .unwrap_or_else(|e| trf(Msg::Unavailable, &[&e]));
+                println!("{}{estimate}", tr(Msg::EstimatedTime));
+            }
+
+            // Create a simple prompt string for the confirmation
+            let prompt = tr(Msg::ConfirmProceed);
+
+            if !device::confirm_operation(
+                prompt,
+                std::slice::from_ref(&device),
+                None,
+                confirm_mode,
+                &cancel,
+            )? {
+                println!("{}", tr(Msg::ReadCancelled));
+                return Ok(());
+            }
+
+            // Only now that the read is confirmed start an upload, which
+            // replaces what's stored at its URL, and connect to a socket
+            // while the sandbox still allows it
+            let (stream, sink) = sink::open(&image, stdout, sandbox)?.unzip();
+            info!();
+            let _keys = pause::KeyListener::start();
+            let options = read::Options {
+                sandbox,
+                // A resumed read can be cancelled and resumed again
+                keep_partial: keep_partial || resume,
+                resume,
+                fill: bad_sector_fill,
+                mapfile,
+                compress,
+                zstd: compress::ZstdOptions {
+                    long,
+                    dictionary: read_dictionary(dict)?,
+                    checksum,
+                },
+                train_dict,
+                drop_privileges: true,
+                sparse: !no_sparse,
+            };
+            let result = read::run(&device.path, &image, stream, &options, &cancel);
+            match (result, sink) {
+                (Ok(()), Some(sink)) => sink.finish()?,
+                (Ok(()), None) => {}
+                (Err(e), Some(sink)) => return Err(sink.abandon(e)),
+                (Err(e), None) => return Err(e),
+            }
+            info!(
+                "\n{}",
+                trf(
+                    Msg::ReadSuccess,
+                    &[
+                        &style(device.path.display()).cyan(),
+                        &style(image.display()).cyan()
+                    ]
+                )
+            );
+            if let Some(key) = signing_key {
+                let signature = signature::sign(&image, &key)?;
+                info!(
+                    "{}",
+                    trf(Msg::SignedImage, &[&style(signature.display()).cyan()])
+                );
+            }
+        }
+        Commands::Clone {
+            source,
+            mut partitions,
+            table,
+            device: device_name,
+            no_verify,
+            title,
+            dock_progress,
+        } => {
+            let verify = !no_verify && config.verify.unwrap_or(true);
+            if !verify {
+                safety.check_override(if no_verify {
+                    "--no-verify"
+                } else {
+                    "verify = false"
+                })?;
+            }
+            if title {
+                title::enable();
+            }
+            if dock_progress {
+                launcher::enable(config.launcher_app.clone());
+            }
+            partitions.sort_unstable();
+            partitions.dedup();
+
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            // Source and target are chosen in two distinct steps, and the
+            // source isn't offered again as the target
+            let source = match source {
+                Some(source) => source,
+                None => {
+                    device::select_device(
+                        &devices,
+                        safety.allows_internal_disks(),
+                        tr(Msg::SelectCloneSource),
+                    )?
+                    .path
+                }
+            };
+            let target = match device_name {
+                Some(name) => device::find_device(&devices, &name)?,
+                None => {
+                    let source = source.canonicalize()?;
+                    let candidates: Vec<device::Device> = devices
+                        .iter()
+                        .filter(|device| device.path != source)
+                        .cloned()
+                        .collect();
+                    device::select_device(
+                        &candidates,
+                        safety.allows_internal_disks(),
+                        tr(Msg::SelectCloneTarget),
+                    )?
+                }
+            };
+            if source.canonicalize()? == target.path {
+                return Err(anyhow!(tr(Msg::SourceIsTarget)));
+            }
+            if config.require_unlock {
+                unlock::check(
+                    &target,
+                    config.unlock_window.unwrap_or(unlock::DEFAULT_WINDOW),
+                )?;
+            }
+            check_shared(&target, cli.force_shared)?;
+            let plan = clone::plan(&source, &target.path, &partitions, table)?;
+
+            if show_summary {
+                let numbers: Vec<String> = partitions.iter().map(u32::to_string).collect();
+                println!(
+                    "{} {}",
+                    style(tr(Msg::Warning)).red().bold(),
+                    trf(
+                        if table {
+                            Msg::OverwritePartitionsAndTable
+                        } else {
+                            Msg::OverwritePartitions
+                        },
+                        &[
+                            &numbers.join(", "),
+                            &target.name,
+                            &format!("{:.1}", target.size_gb)
+                        ]
+                    )
+                );
+                let (from, to) = plan.sides()?;
+                clone::print_sides(&from, &to);
+                print_device_details(&target, wear_warning);
+                for line in plan.describe() {
+                    println!("  {line}");
+                }
+                print_profile(&config.profile);
+            }
+            // A swapped source and target is easy to miss and destroys the
+            // data that was to be copied, so the direction is asked for too
+            if confirm_mode != ConfirmMode::AssumeYes {
+                let (from, to) = plan.sides()?;
+                if !clone::confirm_direction(&from, &to)? {
+                    return Err(anyhow!(tr(Msg::OppositeDirection)));
+                }
+            }
+
+            if !device::confirm_operation(
+                tr(Msg::ConfirmProceed),
+                std::slice::from_ref(&target),
+                None,
+                confirm_mode,
+                &cancel,
+            )? {
+                println!("{}", tr(Msg::WriteCancelled));
+                return Ok(());
+            }
+            if let Some(seconds) = config.countdown
+                && !device::countdown(std::slice::from_ref(&target), seconds, &cancel)
+            {
+                println!("{}", tr(Msg::WriteCancelled));
+                return Ok(());
+            }
+
+            info!();
+            let _keys = pause::KeyListener::start();
+            device::release(&target.path)?;
+            clone::run(plan, verify, &cancel)?;
+            wear::record(&target.name).ok();
+            info!(
+                "{}",
+                trf(
+                    Msg::CopiedPartitions,
+                    &[
+                        &style(source.display()).cyan(),
+                        &style(target.path.display()).cyan()
+                    ]
+                )
+            );
+        }
+        Commands::Unlock { device: name } => {
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            let target = device::find_device(&devices, &name)?;
+            let window = config.unlock_window.unwrap_or(unlock::DEFAULT_WINDOW);
+            unlock::unlock(&target, window)?;
+            info!(
+                "{}",
+                trf(
+                    Msg::Unlocked,
+                    &[
+                        &style(target.path.display()).cyan(),
+                        &(window.as_secs() / 60)
+                    ]
+                )
+            );
+            if !config.require_unlock {
+                info!("{}", tr(Msg::UnlockNotRequired));
+            }
+        }
+        Commands::Verify {
+            baseline,
+            device: device_name,
+            image,
+            report,
+        } => {
+            let target = match image {
+                Some(image) => image,
+                None => {
+                    let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+                    match device_name {
+                        Some(name) => device::find_device(&devices, &name)?,
+                        None => device::select_device(
+                            &devices,
+                            safety.allows_internal_disks(),
+                            tr(Msg::SelectVerifyTarget),
+                        )?,
+                    }
+                    .path
+                }
+            };
+            verify::run(&target, &baseline, report.as_deref(), &cancel)?;
+        }
+        Commands::Audit {
+            manifest,
+            devices: device_names,
+        } => {
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            let devices = if device_names.is_empty() {
+                devices
+            } else {
+                device_names
+                    .iter()
+                    .map(|name| device::find_device(&devices, name))
+                    .collect::<Result<_>>()?
+            };
+            audit::run(&manifest, &devices, &cancel)?;
+        }
+        Commands::List { format } => {
+            let devices = device::get_removable_devices(safety.allows_internal_disks())?;
+            list::print(&devices, format);
+        }
+        Commands::Catalog { action } => match action {
+            CatalogAction::Import {
+                file,
+                signature,
+                unsigned,
+            } => {
+                if unsigned {
+                    safety.check_override("--unsigned")?;
+                }
+                let count = catalog::import(
+                    &file,
+                    signature.as_deref(),
+                    unsigned,
+                    config.catalog_keyring.as_deref(),
+                )?;
+                info!("{}", trf(Msg::ImportedImages, &[&count]));
+            }
+            CatalogAction::List => {
+                for entry in catalog::load() {
+                    println!("{}  {}", entry.sha256, entry.label());
+                }
+            }
+        },
+        Commands::GenTestImage {
+            image,
+            size,
+            partitions,
+            fill,
+            seed,
+            compress,
+        } => {
+            let compression = match compress {
+                None => None,
+                Some(compress::Choice::Fixed(compression)) => Some(compression),
+                Some(compress::Choice::Auto) => {
+                    return Err(anyhow!(tr(Msg::TestImageNeedsCodec)));
+                }
+            };
+            let spec = testimage::Spec {
+                size,
+                partitions,
+                fill,
+                seed,
+                compression,
+            };
+            testimage::generate(&image, &spec, &cancel)?;
+            info!(
+                "{}",
+                trf(
+                    Msg::GeneratedImage,
+                    &[&style(image.display()).cyan(), &units::bytes(size)]
+                )
+            );
+        }
+        Commands::Admin { action } => match action {
+            AdminAction::Exclude { device } => {
+                let entry = exclude::add(&device)?;
+                info!("{}", trf(Msg::ExcludedDevice, &[&device, &entry]));
+            }
+        },
+        Commands::Stats { days } => {
+            stats::show(days)?;
+        }
+        Commands::Setup => {
+            setup::run()?;
+        }
+        Commands::Watch => {
+            status::watch(&cancel)?;
+        }
+        Commands::Man { page, install } => {
+            if let Some(dir) = install {
+                man::install(Cli::command(), &dir)?;
+                return Ok(());
+            }
+            stdout().write_all(&man::render(Cli::command(), page.as_deref())?)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn device(size_bytes: u64) -> device::Device {
+        device::Device {
+            path: PathBuf::from("/dev/sdz"),
+            name: "sdz".to_string(),
+            size_gb: size_bytes as f64 / 1e9,
+            size_bytes,
+            mount_point: String::new(),
+            removable: true,
+            usb: true,
+            card: None,
+            link: None,
+            burns: None,
+            usage: Vec::new(),
+        }
+    }
+
+    fn fits(image: &Path, size_bytes: u64, force: bool) -> Result<()> {
+        check_fits(
+            image,
+            &[device(size_bytes)],
+            None,
+            force,
+            &CancelToken::default(),
+        )
+    }
+
+    #[test]
+    fn refuses_images_larger_than_the_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("os.img");
+        fs::File::create(&image)
+            .unwrap()
+            .set_len(2 * MIB + 1)
+            .unwrap();
+
+        fits(&image, 4 * MIB, false).unwrap();
+        fits(&image, 2 * MIB + 1, false).unwrap();
+        let error = fits(&image, 2 * MIB, false).unwrap_err();
+        assert!(error.to_string().contains("/dev/sdz"), "{error}");
+        // Cut off on request
+        fits(&image, 2 * MIB, true).unwrap();
+    }
+
+    #[test]
+    fn measures_gzip_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("os.img.gz");
+        let mut gz = GzEncoder::new(fs::File::create(&image).unwrap(), Compression::fast());
+        gz.write_all(&vec![0u8; 3 * MIB as usize]).unwrap();
+        gz.finish().unwrap();
+
+        // The compressed file is far smaller than what it writes
+        assert!(fits(&image, 2 * MIB, false).is_err());
+        fits(&image, 3 * MIB, false).unwrap();
+    }
+
+    #[test]
+    fn lets_streams_of_unknown_size_through() {
+        for image in ["-", "https://example.com/os.img.xz"] {
+            fits(Path::new(image), MIB, false).unwrap();
+            fits(Path::new(image), MIB, true).unwrap();
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+use std::thread;
+
+use anyhow::{Result, anyhow};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use sha2::{Digest, Sha256};
+
+use crate::buffers;
+use crate::cancel::CancelToken;
+use crate::device;
+use crate::i18n::{Msg, tr};
+use crate::menu;
+use crate::output::info;
+use crate::partition::{self, Partition, Table};
+use crate::pause;
+use crate::progress;
+use crate::report;
+use crate::status::{self, StatusFile};
+use crate::title::TitleMirror;
+
+const BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// Devices are read in sectors of up to this size with O_DIRECT.
+const SECTOR_SIZE: usize = 4096;
+
+fn make_progress_bar(len: u64, prefix: &str, color: &str) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
+    pb.set_prefix(format!("{prefix:<12}"));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(&format!("{{prefix}} [{{elapsed_precise}}] [{{bar:40.{color}/black}}] {{bytes}}/{{total_bytes}} ({{bytes_per_sec}}, {{eta}}) {{msg}}"))
+            .unwrap()
+            .progress_chars("■ "),
+    );
+    progress::add(pb)
+}
+
+/// What [`run`] copies, checked against both partition tables before
+/// anything is written.
+pub struct Plan<'a> {
+    source_path: &'a Path,
+    target_path: &'a Path,
+    source: File,
+    target: File,
+    /// The source's table, if it replaces the target's.
+    table: Option<Table>,
+    /// Each source partition and the target partition it's copied to.
+    copies: Vec<(Partition, Partition)>,
+}
+
+/// Opens `source` (a device or image) and `target`, and checks that
+/// partitions `numbers` of the source fit in the same partitions of the
+/// target: those of the target's own table, or with `copy_table`, those of
+/// the source's table once copied over.
+pub fn plan<'a>(
+    source_path: &'a Path,
+    target_path: &'a Path,
+    numbers: &[u32],
+    copy_table: bool,
+) -> Result<Plan<'a>> {
+    let source = File::open(source_path)?;
+    let target = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(target_path)?;
+    let source_name = source_path.display().to_string();
+    let target_name = target_path.display().to_string();
+
+    let source_table = partition::read_table(&source)
+        .map_err(|e| anyhow!("reading the partition table of {source_name}: {e}"))?;
+    let target_size = device::file_size(&target)?;
+    if copy_table && source_table.min_disk_size() > target_size {
+        return Err(anyhow!(
+            "the partition table of {source_name} needs {}, but {target_name} has {}",
+            HumanBytes(source_table.min_disk_size()),
+            HumanBytes(target_size)
+        ));
+    }
+    let target_table = if copy_table {
+        None
+    } else {
+        Some(
+            partition::read_table(&target)
+                .map_err(|e| anyhow!("reading the partition table of {target_name}: {e} (--table copies it from {source_name})"))?,
+        )
+    };
+
+    let mut copies = Vec::new();
+    for &number in numbers {
+        let from = source_table.get(number).ok_or_else(|| {
+            anyhow!(
+                "{source_name} has no partition {number}; its partitions are: {}",
+                source_table.describe()
+            )
+        })?;
+        let to = match &target_table {
+            None => from,
+            Some(table) => {
+                let to = table.get(number).ok_or_else(|| {
+                    anyhow!(
+                        "{target_name} has no partition {number}; its partitions are: {}",
+                        table.describe()
+                    )
+                })?;
+                if from.len > to.len {
+                    return Err(anyhow!(
+                        "partition {number} of {source_name} ({}) doesn't fit in partition {number} of {target_name} ({})",
+                        HumanBytes(from.len),
+                        HumanBytes(to.len)
+                    ));
+                }
+                to
+            }
+        };
+        copies.push((from, to));
+    }
+
+    Ok(Plan {
+        source_path,
+        target_path,
+        source,
+        target,
+        table: copy_table.then_some(source_table),
+        copies,
+    })
+}
+
+impl Plan<'_> {
+    /// The source and target, as told apart in the summary.
+    pub fn sides(&self) -> Result<(Side, Side)> {
+        Ok((
+            Side::of(self.source_path, &self.source)?,
+            Side::of(self.target_path, &self.target)?,
+        ))
+    }
+
+    /// Lists what goes where, for the summary shown before confirming.
+    pub fn describe(&self) -> Vec<String> {
+        self.copies
+            .iter()
+            .map(|(from, to)| {
+                format!(
+                    "partition {} ({}) → {} ({})",
+                    from.number,
+                    HumanBytes(from.len),
+                    self.target_path.display(),
+                    HumanBytes(to.len)
+                )
+            })
+            .collect()
+    }
+}
+
+/// One side of a clone, with what tells it apart from the other.
+pub struct Side {
+    path: String,
+    size: u64,
+    model: Option<String>,
+    serial: Option<String>,
+}
+
+impl Side {
+    fn of(path: &Path, file: &File) -> Result<Self> {
+        let disk = device::is_block_device(path)
+            .then(|| path.canonicalize().ok())
+            .flatten()
+            .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()));
+        Ok(Side {
+            path: path.display().to_string(),
+            size: device::file_size(file)?,
+            model: disk.as_deref().and_then(device::model),
+            serial: disk.as_deref().and_then(device::serial),
+        })
+    }
+}
+
+/// Prints the source and target side by side, so a swapped pair stands
+/// out before anything is written.
+pub fn print_sides(source: &Side, target: &Side) {
+    let unknown = || "-".to_string();
+    let rows = [
+        (
+            "",
+            tr(Msg::SourceSide).to_string(),
+            tr(Msg::TargetSide).to_string(),
+        ),
+        (
+            tr(Msg::SideDevice),
+            source.path.clone(),
+            target.path.clone(),
+        ),
+        (
+            tr(Msg::SideSize),
+            HumanBytes(source.size).to_string(),
+            HumanBytes(target.size).to_string(),
+        ),
+        (
+            tr(Msg::SideModel),
+            source.model.clone().unwrap_or_else(unknown),
+            target.model.clone().unwrap_or_else(unknown),
+        ),
+        (
+            tr(Msg::SideSerial),
+            source.serial.clone().unwrap_or_else(unknown),
+            target.serial.clone().unwrap_or_else(unknown),
+        ),
+    ];
+    let width = rows
+        .iter()
+        .map(|(_, from, _)| from.len())
+        .max()
+        .unwrap_or(0)
+        + 3;
+    for (i, (label, from, to)) in rows.iter().enumerate() {
+        // Pad before styling, as the escape codes take no room
+        let from = format!("{from:<width$}");
+        if i == 0 {
+            println!(
+                "  {label:<9}{}{}",
+                style(from).green().bold(),
+                style(to).red().bold()
+            );
+        } else {
+            println!("  {label:<9}{}{}", style(from).green(), style(to).red());
+        }
+    }
+}
+
+/// Asks which way the data is to flow, with both ways listed in an order
+/// that changes from run to run, so that it takes a look rather than
+/// habit. Returns whether the answer matches `source` and `target`.
+pub fn confirm_direction(source: &Side, target: &Side) -> Result<bool> {
+    let right = format!("{} → {}", source.path, target.path);
+    let wrong = format!("{} → {}", target.path, source.path);
+    let right_first = status::unix_now().is_multiple_of(2);
+    let items = if right_first {
+        [right, wrong]
+    } else {
+        [wrong, right]
+    };
+    let choice = menu::select(tr(Msg::CopyDirection), &items)?;
+    Ok((choice == 0) == right_first)
+}
+
+/// Copies the planned partitions, after the partition table if it's
+/// copied too, and with `verify`, checks each copy against the source.
+/// The kernel is asked to re-read a replaced table.
+pub fn run(plan: Plan, verify: bool, cancel: &CancelToken) -> Result<()> {
+    let Plan {
+        source_path,
+        target_path,
+        source,
+        target,
+        table,
+        copies,
+    } = plan;
+    info!(
+        "Copying partitions from \"{}\" to \"{}\"",
+        source_path.display(),
+        target_path.display()
+    );
+
+    if let Some(table) = &table {
+        table.copy(&source, &target, device::file_size(&target)?)?;
+        target.sync_all()?;
+        info!("Copied the partition table");
+    }
+
+    let mut status = StatusFile::create("clone", target_path, source_path);
+    let mut title = TitleMirror::new();
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    for (from, to) in copies {
+        let pb = make_progress_bar(from.len, &format!("Partition {}", from.number), "green");
+        let mut copied = 0;
+        while copied < from.len {
+            pause::wait_while_paused(&pb, cancel);
+            if let Err(cancelled) = cancel.check(&pb, Msg::PhaseCopy) {
+                target.sync_all()?;
+                return Err(anyhow::Error::new(cancelled).context(format!(
+                    "partition {} of {} is only partially copied ({} of {})",
+                    to.number,
+                    target_path.display(),
+                    HumanBytes(copied),
+                    HumanBytes(from.len)
+                )));
+            }
+
+            let len = std::cmp::min(BUFFER_SIZE as u64, from.len - copied) as usize;
+            source.read_exact_at(&mut buf[..len], from.start + copied)?;
+            target.write_all_at(&buf[..len], to.start + copied)?;
+            copied += len as u64;
+            pb.set_position(copied);
+            status.update("Copying", &pb);
+            title.update("Copying", &pb);
+        }
+        target.sync_all()?;
+        pb.finish_with_message("✅ Copied.");
+        drop(pb);
+
+        if verify {
+            verify_copy(
+                source_path,
+                target_path,
+                &from,
+                &to,
+                &mut status,
+                &mut title,
+                cancel,
+            )?;
+        }
+    }
+
+    if table.is_some() && device::is_block_device(target_path) {
+        device::reread_partitions(target_path)?;
+    }
+    Ok(())
+}
+
+/// Checks that partition `to` of the target holds what partition `from` of
+/// the source does, by hashing both at once: the source is read again and
+/// the target read back, each on its own thread.
+fn verify_copy(
+    source_path: &Path,
+    target_path: &Path,
+    from: &Partition,
+    to: &Partition,
+    status: &mut StatusFile,
+    title: &mut TitleMirror,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let source_pb = make_progress_bar(from.len, &format!("Source {}", from.number), "magenta");
+    let target_pb = make_progress_bar(from.len, &format!("Verifying {}", to.number), "magenta");
+    let (source_hash, target_hash) = thread::scope(|scope| {
+        let source = scope.spawn(|| {
+            hash_range(
+                source_path,
+                from.start,
+                from.len,
+                &source_pb,
+                cancel,
+                |_| {},
+            )
+        });
+        let target = hash_range(target_path, to.start, from.len, &target_pb, cancel, |pb| {
+            status.update("Verifying", pb);
+            title.update("Verifying", pb);
+        });
+        let source = source.join().expect("the source hashing thread panicked");
+        (source, target)
+    });
+    let (source_hash, target_hash) = (source_hash?, target_hash?);
+    source_pb.finish_and_clear();
+    if source_hash != target_hash {
+        target_pb.finish_with_message("❌ Verification failed.");
+        return Err(anyhow!(
+            "❌ Verification failed: partition {} of {} doesn't match partition {} of {} (SHA-256 {target_hash}, but {source_hash} on the source)",
+            to.number,
+            target_path.display(),
+            from.number,
+            source_path.display()
+        ));
+    }
+    target_pb.finish_with_message("✅ Verification successful.");
+    Ok(())
+}
+
+/// Hashes `len` bytes of `path` from `start`, calling `progress` after each
+/// chunk. Devices are read with O_DIRECT, so that what's hashed is what
+/// the medium holds rather than what the page cache kept from the copy,
+/// which went through it.
+fn hash_range(
+    path: &Path,
+    start: u64,
+    len: u64,
+    pb: &ProgressBar,
+    cancel: &CancelToken,
+    mut progress: impl FnMut(&ProgressBar),
+) -> Result<String> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    if device::is_block_device(path) {
+        options.custom_flags(device::O_DIRECT);
+    }
+    let file = options.open(path)?;
+    let memory = buffers::reserve("verification", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(SECTOR_SIZE);
+    let buf = buffer.as_mut_slice();
+    let mut hasher = Sha256::new();
+    let mut hashed = 0;
+    while hashed < len {
+        pause::wait_while_paused(pb, cancel);
+        cancel.check(pb, Msg::PhaseVerification)?;
+
+        let chunk = std::cmp::min(BUFFER_SIZE as u64, len - hashed) as usize;
+        file.read_exact_at(&mut buf[..chunk], start + hashed)?;
+        hasher.update(&buf[..chunk]);
+        hashed += chunk as u64;
+        pb.set_position(hashed);
+        progress(pb);
+    }
+    Ok(report::hex(hasher))
+}
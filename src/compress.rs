@@ -0,0 +1,363 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use flate2::write::GzEncoder;
+use xz2::write::XzEncoder;
+
+use crate::buffers::{self, Reservation};
+
+/// Chunks queued for the compressor thread before reading waits for it,
+/// fewer if the memory limit doesn't allow as many.
+const QUEUE_DEPTH: usize = 4;
+
+/// The largest zstd window, 2 GiB. Decompressing needs as much memory.
+pub const MAX_WINDOW_LOG: u32 = 31;
+
+/// Size of the dictionaries trained by [`train_dictionary`], as zstd's own.
+const DICTIONARY_SIZE: usize = 112_640;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Codec {
+    fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Xz => "xz",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// The codec that `write` recognizes by the file extension `ext`.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "gz" | "gzip" => Some(Codec::Gzip),
+            "xz" => Some(Codec::Xz),
+            "zst" | "zstd" => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The file extension `write` recognizes the codec by.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Xz => "xz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    fn levels(self) -> std::ops::RangeInclusive<u32> {
+        match self {
+            Codec::Gzip | Codec::Xz => 0..=9,
+            Codec::Zstd => 1..=22,
+        }
+    }
+
+    fn default_level(self) -> u32 {
+        match self {
+            Codec::Gzip | Codec::Xz => 6,
+            Codec::Zstd => 3,
+        }
+    }
+}
+
+/// A codec and the level to compress at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Compression {
+    pub codec: Codec,
+    pub level: u32,
+}
+
+impl Compression {
+    /// `codec` at its default level, as when `--compress` names no level.
+    pub fn at_default_level(codec: Codec) -> Self {
+        Compression {
+            codec,
+            level: codec.default_level(),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.codec, self.level)
+    }
+}
+
+/// The `--compress` setting of `read`: a given compression, or `auto` to
+/// pick one by benchmarking (see [`choose`]).
+#[derive(Clone, Copy)]
+pub enum Choice {
+    Auto,
+    Fixed(Compression),
+}
+
+impl FromStr for Choice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "auto" {
+            return Ok(Choice::Auto);
+        }
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let codec = Codec::from_extension(name)
+            .ok_or_else(|| format!("unknown codec '{name}' (expected auto, gzip, xz or zstd)"))?;
+        let level = match level {
+            None => codec.default_level(),
+            Some(level) => level
+                .parse()
+                .ok()
+                .filter(|level| codec.levels().contains(level))
+                .ok_or_else(|| {
+                    let levels = codec.levels();
+                    format!(
+                        "{} levels go from {} to {}, not '{level}'",
+                        codec.name(),
+                        levels.start(),
+                        levels.end()
+                    )
+                })?,
+        };
+        Ok(Choice::Fixed(Compression { codec, level }))
+    }
+}
+
+/// zstd settings beyond the level.
+#[derive(Clone, Default)]
+pub struct ZstdOptions {
+    /// Match over a window of 2^`long` bytes, which finds repeats far
+    /// apart in large images.
+    pub long: Option<u32>,
+    /// Compress with this dictionary, which is needed to decompress too.
+    pub dictionary: Option<Vec<u8>>,
+    /// End each frame with a checksum of its contents.
+    pub checksum: bool,
+}
+
+/// Trains a zstd dictionary on `samples`, the concatenation of pieces of
+/// `piece_size` bytes.
+pub fn train_dictionary(samples: &[u8], piece_size: usize) -> io::Result<Vec<u8>> {
+    let sizes: Vec<usize> = samples.chunks(piece_size).map(<[u8]>::len).collect();
+    zstd::dict::from_continuous(samples, &sizes, DICTIONARY_SIZE)
+}
+
+/// A stream compressor for one of the codecs.
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Xz(XzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(compression: Compression, zstd: &ZstdOptions, out: W) -> io::Result<Self> {
+        let level = compression.level;
+        Ok(match compression.codec {
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(out, flate2::Compression::new(level))),
+            Codec::Xz => Encoder::Xz(XzEncoder::new(out, level)),
+            Codec::Zstd => {
+                let dictionary = zstd.dictionary.as_deref().unwrap_or_default();
+                let mut encoder = zstd::Encoder::with_dictionary(out, level as i32, dictionary)?;
+                if let Some(window_log) = zstd.long {
+                    encoder.long_distance_matching(true)?;
+                    encoder.window_log(window_log)?;
+                }
+                encoder.include_checksum(zstd.checksum)?;
+                Encoder::Zstd(encoder)
+            }
+        })
+    }
+
+    /// Writes the end of the stream and returns the output.
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(e) => e.finish(),
+            Encoder::Xz(e) => e.finish(),
+            Encoder::Zstd(e) => e.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(e) => e.write(buf),
+            Encoder::Xz(e) => e.write(buf),
+            Encoder::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(e) => e.flush(),
+            Encoder::Xz(e) => e.flush(),
+            Encoder::Zstd(e) => e.flush(),
+        }
+    }
+}
+
+/// Compresses into a file on a separate thread, so that compressing a
+/// chunk overlaps with reading the next one from the device.
+pub struct Compressor {
+    chunks: SyncSender<Vec<u8>>,
+    thread: Option<JoinHandle<io::Result<File>>>,
+    /// Compressed bytes written to the file so far.
+    written: Arc<AtomicU64>,
+    _memory: Reservation,
+}
+
+/// The compressor's output file, counting what's written to it.
+struct Counted {
+    file: File,
+    written: Arc<AtomicU64>,
+}
+
+impl Write for Counted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn join(thread: JoinHandle<io::Result<File>>) -> io::Result<File> {
+    thread
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("compressor thread panicked")))
+}
+
+impl Compressor {
+    /// Starts compressing chunks of up to `chunk_size` bytes into `out`.
+    pub fn spawn(
+        compression: Compression,
+        zstd: &ZstdOptions,
+        chunk_size: usize,
+        out: File,
+    ) -> io::Result<Self> {
+        let written = Arc::new(AtomicU64::new(0));
+        let out = Counted {
+            file: out,
+            written: written.clone(),
+        };
+        let mut encoder = Encoder::new(compression, zstd, out)?;
+        let memory = buffers::reserve("compression", QUEUE_DEPTH, chunk_size);
+        let (chunks, chunks_rx) = sync_channel::<Vec<u8>>(memory.count());
+        let thread = thread::spawn(move || {
+            // Ends once the reader is done and drops the sender
+            for chunk in chunks_rx {
+                encoder.write_all(&chunk)?;
+            }
+            encoder.finish().map(|out| out.file)
+        });
+        Ok(Self {
+            chunks,
+            thread: Some(thread),
+            written,
+            _memory: memory,
+        })
+    }
+
+    /// Compressed bytes written so far, behind what was queued.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+
+    /// Queues `data` to be compressed, waiting if the compressor is behind.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.chunks.send(data.to_vec()).is_ok() {
+            return Ok(());
+        }
+        // The thread only stops early on an error, returned here
+        match self.thread.take().map(join) {
+            Some(Err(e)) => Err(e),
+            _ => Err(io::Error::other("compressor stopped")),
+        }
+    }
+
+    /// Waits for everything queued to be compressed and the stream to be
+    /// ended, and returns the output file.
+    pub fn finish(self) -> io::Result<File> {
+        let Self { chunks, thread, .. } = self;
+        drop(chunks);
+        thread
+            .map(join)
+            .unwrap_or_else(|| Err(io::Error::other("compressor stopped")))
+    }
+}
+
+/// Levels tried by [`choose`], fastest first within each codec.
+const CANDIDATES: &[(Codec, &[u32])] = &[
+    (Codec::Zstd, &[1, 3, 9, 15, 19]),
+    (Codec::Gzip, &[1, 6, 9]),
+    (Codec::Xz, &[1, 6, 9]),
+];
+
+/// The result of compressing a sample with one candidate.
+#[derive(Clone, Copy)]
+pub struct Benchmark {
+    pub compression: Compression,
+    /// Uncompressed bytes per second.
+    pub speed: f64,
+    pub ratio: f64,
+}
+
+/// Compresses `sample` with each candidate and returns the one with the
+/// smallest output among those at least as fast as `read_speed` (bytes per
+/// second), or the fastest if none keeps up. Higher levels of a codec are
+/// skipped once a level is too slow, as they'd only be slower.
+pub fn choose(sample: &[u8], read_speed: f64) -> io::Result<Benchmark> {
+    let mut best: Option<Benchmark> = None;
+    let mut fastest: Option<Benchmark> = None;
+
+    for &(codec, levels) in CANDIDATES {
+        for &level in levels {
+            let compression = Compression { codec, level };
+            let start = Instant::now();
+            let mut encoder = Encoder::new(compression, &ZstdOptions::default(), Vec::new())?;
+            encoder.write_all(sample)?;
+            let size = encoder.finish()?.len();
+            let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+            let result = Benchmark {
+                compression,
+                speed: sample.len() as f64 / elapsed,
+                ratio: size as f64 / sample.len().max(1) as f64,
+            };
+
+            let keeps_up = result.speed >= read_speed;
+            if fastest.as_ref().is_none_or(|f| result.speed > f.speed) {
+                fastest = Some(result);
+            }
+            if !keeps_up {
+                break;
+            }
+            if best.as_ref().is_none_or(|b| result.ratio < b.ratio) {
+                best = Some(result);
+            }
+        }
+    }
+    Ok(best.or(fastest).expect("there are candidates"))
+}
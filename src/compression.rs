@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::zip;
+
+/// Reads a little-endian integer of `bytes.len()` bytes.
+pub fn le(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |acc, &b| (acc << 8) | u64::from(b))
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(buf)
+}
+
+/// Reads an xz multibyte integer (7 bits per byte, least significant first).
+fn xz_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Returns the uncompressed size of an xz file, from the index at the end
+/// of each stream. Concatenated streams and stream padding are handled.
+/// `None` if the file is too short or damaged to have an index.
+fn xz_size<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let mut end = reader.seek(SeekFrom::End(0)).ok()?;
+    let mut total = 0u64;
+
+    // The file is untrusted, so every step back through it is checked:
+    // one that would go past its start means it's damaged
+    while end > 0 {
+        let mut footer = [0u8; 12];
+        let footer_start = end.checked_sub(12)?;
+        read_at(reader, footer_start, &mut footer).ok()?;
+        if footer == [0; 12] {
+            // Stream padding comes in multiples of four zero bytes
+            end = end.checked_sub(4)?;
+            continue;
+        }
+        if &footer[10..] != b"YZ" {
+            return None;
+        }
+
+        let index_size = (le(&footer[4..8]) + 1) * 4;
+        let index_start = footer_start.checked_sub(index_size)?;
+        let mut index = vec![0u8; usize::try_from(index_size).ok()?];
+        read_at(reader, index_start, &mut index).ok()?;
+        if index[0] != 0 {
+            return None;
+        }
+
+        let mut pos = 1;
+        let records = xz_varint(&index, &mut pos)?;
+        let mut blocks_size = 0u64;
+        for _ in 0..records {
+            let unpadded = xz_varint(&index, &mut pos)?;
+            blocks_size = blocks_size.checked_add(unpadded.div_ceil(4).checked_mul(4)?)?;
+            total = total.checked_add(xz_varint(&index, &mut pos)?)?;
+        }
+
+        // Step over the blocks and the stream header to the previous stream
+        end = index_start.checked_sub(blocks_size.checked_add(12)?)?;
+    }
+    Some(total)
+}
+
+const ZSTD_MAGIC: u32 = 0xfd2f_b528;
+
+/// Returns the uncompressed size of a zstd file, summing the content size
+/// of every frame. `None` if any frame doesn't declare its size, as with
+/// data compressed from a pipe.
+fn zstd_size<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    let len = reader.seek(SeekFrom::End(0)).ok()?;
+    let mut pos = 0u64;
+    let mut total = 0u64;
+
+    while pos < len {
+        let mut magic = [0u8; 4];
+        read_at(reader, pos, &mut magic).ok()?;
+        let magic = le(&magic) as u32;
+
+        // Skippable frames carry metadata, not content
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            let mut size = [0u8; 4];
+            read_at(reader, pos + 4, &mut size).ok()?;
+            pos += 8 + le(&size);
+            continue;
+        }
+        if magic != ZSTD_MAGIC {
+            return None;
+        }
+
+        let mut descriptor = [0u8; 1];
+        read_at(reader, pos + 4, &mut descriptor).ok()?;
+        let descriptor = descriptor[0];
+        let single_segment = descriptor & 0x20 != 0;
+        let has_checksum = descriptor & 0x04 != 0;
+        let dict_id_len = [0, 1, 2, 4][usize::from(descriptor & 0x03)];
+        let size_len = match descriptor >> 6 {
+            0 if single_segment => 1,
+            0 => return None,
+            1 => 2,
+            2 => 4,
+            _ => 8,
+        };
+
+        let size_pos = pos + 5 + u64::from(!single_segment) + dict_id_len;
+        let mut size = [0u8; 8];
+        read_at(reader, size_pos, &mut size[..size_len]).ok()?;
+        let mut content_size = le(&size[..size_len]);
+        if size_len == 2 {
+            content_size += 256;
+        }
+        total = total.checked_add(content_size)?;
+
+        // Walk the blocks to find where the next frame starts
+        pos = size_pos + size_len as u64;
+        loop {
+            let mut header = [0u8; 3];
+            read_at(reader, pos, &mut header).ok()?;
+            let header = le(&header);
+            let last = header & 1 != 0;
+            let block_type = (header >> 1) & 3;
+            let block_size = header >> 3;
+            // RLE blocks store a single byte, repeated `block_size` times
+            pos += 3 + if block_type == 1 { 1 } else { block_size };
+            if last {
+                break;
+            }
+        }
+        if has_checksum {
+            pos += 4;
+        }
+    }
+    Some(total)
+}
+
+/// Returns the uncompressed size recorded in an xz or zstd file, or of the
+/// image in a ZIP archive, reading only its headers and index. Other
+/// formats (and gzip, whose size field wraps at 4 GiB) return `None`.
+pub fn uncompressed_size(path: &Path, ext: &str) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    match ext {
+        "xz" => xz_size(&mut file),
+        "zst" | "zstd" => zstd_size(&mut file),
+        "zip" => zip::find_image(&mut file, path)
+            .ok()
+            .map(|entry| entry.uncompressed_len),
+        _ => None,
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::compress;
+use crate::policy::Policy;
+use crate::safety::SafetyProfile;
+use crate::signature::SigningKey;
+use crate::slow::SlowWrite;
+use crate::spinner;
+
+/// User configuration, loaded from `~/.config/etchr/config.toml`.
+///
+/// Only a small subset of TOML is understood: `[section]` headers and
+/// `key = value` pairs, where values are quoted strings, integers or booleans.
+/// A `[profile.NAME]` section holds settings that replace the others when
+/// selected with `--profile NAME`.
+#[derive(Default)]
+pub struct Config {
+    /// The profile selected with `--profile`.
+    pub profile: Option<String>,
+    /// Directory the interactive image picker starts in.
+    pub image_dir: Option<PathBuf>,
+    /// Default safety profile, overridden by `--safety`.
+    pub safety: Option<SafetyProfile>,
+    /// Animation for progress of unknown length.
+    pub spinner: Option<spinner::Theme>,
+    /// Keyring trusted for catalog signatures, instead of the default one.
+    pub catalog_keyring: Option<PathBuf>,
+    /// Restrictions on which images may be written (`[policy]` section).
+    pub policy: Policy,
+    /// Interface configured by `--static-ip`, if not given.
+    pub network_interface: Option<String>,
+    /// Gateway for `--static-ip`, if not given.
+    pub network_gateway: Option<IpAddr>,
+    /// DNS servers for `--static-ip`, if none are given.
+    pub network_dns: Vec<IpAddr>,
+    /// Desktop file of the terminal whose icon `--dock-progress` updates,
+    /// if it can't be recognized.
+    pub launcher_app: Option<String>,
+    /// Key that `read --sign` signs images with (`[signing]` section).
+    pub signing_key: Option<SigningKey>,
+    /// The most a compressed image may take up when decompressed to a temp
+    /// file before writing; larger images are streamed instead.
+    pub max_temp_size: Option<u64>,
+    /// The most memory I/O buffers may take up, together.
+    pub memory_limit: Option<u64>,
+    /// When a device writes suspiciously slowly (`[slow_write]` section).
+    pub slow_write: SlowWrite,
+    /// Number of writes after which a card is flagged as possibly worn out.
+    pub wear_warning: Option<u32>,
+    /// Command handed a record of each flashed card, e.g. to print a label
+    /// (`[label]` section).
+    pub label_command: Option<String>,
+    /// Devices must be unlocked with `etchr unlock` before being written.
+    pub require_unlock: bool,
+    /// How long an unlock lasts.
+    pub unlock_window: Option<Duration>,
+    /// Seconds counted down after confirming a write, to stop with Ctrl+C.
+    pub countdown: Option<u32>,
+    /// Whether writes and clones are verified, unless `--no-verify`.
+    pub verify: Option<bool>,
+    /// Whether several devices are written at once, as with `--parallel`.
+    pub parallel: Option<bool>,
+    /// Whether written devices are ejected, as with `--eject`.
+    pub eject: Option<bool>,
+    /// The level reads compress with, for each codec, where `--compress`
+    /// doesn't say.
+    pub compress: Vec<compress::Compression>,
+}
+
+/// Returns the path of the user's config file.
+pub fn config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("etchr").join("config.toml"))
+}
+
+/// Parses the config file into a flat map of `section.key` to raw values.
+pub fn parse(contents: &str) -> Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+
+    for (lineno, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected `key = value`", lineno + 1))?;
+        let key = key.trim();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+
+        let full_key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{section}.{key}")
+        };
+        values.insert(full_key, value.to_string());
+    }
+
+    Ok(values)
+}
+
+/// Expands a leading `~` to the user's home directory.
+pub fn expand_home(value: &str) -> PathBuf {
+    match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(value),
+    }
+}
+
+/// Parses a size in bytes, optionally with a binary suffix: `K`, `M`, `G`
+/// or `T`, e.g. `8G`.
+pub fn parse_size(key: &str, value: &str) -> Result<u64> {
+    let value = value.trim();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let shift = match value[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(anyhow!("{key}: '{value}' is not a size (e.g. 8G)")),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| anyhow!("{key}: '{value}' is not a size (e.g. 8G)"))
+}
+
+pub fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(anyhow!("{key}: expected true or false, got '{value}'")),
+    }
+}
+
+fn parse_ip(key: &str, value: &str) -> Result<IpAddr> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("{key}: '{value}' is not an IP address"))
+}
+
+/// Replaces settings with those of the profile `name`, e.g. `safety` with
+/// `profile.factory.safety` for `[profile.factory]`. Fails if there's no
+/// such profile, naming the ones there are.
+fn apply_profile(values: &mut HashMap<String, String>, name: &str) -> Result<()> {
+    let prefix = format!("profile.{name}.");
+    let overrides: Vec<(String, String)> = values
+        .iter()
+        .filter_map(|(key, value)| Some((key.strip_prefix(&prefix)?.to_string(), value.clone())))
+        .collect();
+    if overrides.is_empty() {
+        let mut names: Vec<&str> = values
+            .keys()
+            .filter_map(|key| Some(key.strip_prefix("profile.")?.split_once('.')?.0))
+            .collect();
+        names.sort_unstable();
+        names.dedup();
+        return Err(if names.is_empty() {
+            anyhow!("there's no [profile.{name}] section, nor any other profile")
+        } else {
+            anyhow!(
+                "there's no [profile.{name}] section; the profiles are: {}",
+                names.join(", ")
+            )
+        });
+    }
+    values.extend(overrides);
+    Ok(())
+}
+
+/// Loads the user's configuration, with the settings of `profile` if
+/// given. A missing file yields the defaults.
+pub fn load(profile: Option<&str>) -> Result<Config> {
+    let Some(path) = config_path().filter(|path| path.exists()) else {
+        if let Some(name) = profile {
+            return Err(anyhow!(
+                "--profile {name}: there's no config file to take the profile from"
+            ));
+        }
+        return Ok(Config::default());
+    };
+
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+    let mut values = parse(&contents).with_context(|| format!("parsing {}", path.display()))?;
+    let parse_context = || format!("parsing {}", path.display());
+    if let Some(name) = profile {
+        apply_profile(&mut values, name).with_context(parse_context)?;
+    }
+
+    Ok(Config {
+        profile: profile.map(str::to_string),
+        image_dir: values.get("image_dir").map(|v| expand_home(v)),
+        safety: values
+            .get("safety")
+            .map(|v| SafetyProfile::from_config(v))
+            .transpose()?,
+        spinner: values
+            .get("spinner")
+            .map(|v| spinner::Theme::from_config(v))
+            .transpose()?,
+        catalog_keyring: values.get("catalog.keyring").map(|v| expand_home(v)),
+        policy: Policy::from_values(&values).with_context(parse_context)?,
+        network_interface: values.get("network.interface").cloned(),
+        network_gateway: values
+            .get("network.gateway")
+            .map(|v| parse_ip("network.gateway", v))
+            .transpose()
+            .with_context(parse_context)?,
+        // A comma-separated list, as arrays aren't supported
+        network_dns: values
+            .get("network.dns")
+            .map(|v| {
+                v.split(',')
+                    .map(|ip| parse_ip("network.dns", ip))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()
+            .with_context(parse_context)?
+            .unwrap_or_default(),
+        launcher_app: values.get("launcher_app").cloned(),
+        signing_key: values
+            .get("signing.key")
+            .map(|key| SigningKey::from_config(values.get("signing.tool").map(String::as_str), key))
+            .transpose()
+            .with_context(parse_context)?,
+        max_temp_size: values
+            .get("max_temp_size")
+            .map(|v| parse_size("max_temp_size", v))
+            .transpose()
+            .with_context(parse_context)?,
+        memory_limit: values
+            .get("memory_limit")
+            .map(|v| parse_size("memory_limit", v))
+            .transpose()
+            .with_context(parse_context)?,
+        slow_write: SlowWrite::from_values(&values).with_context(parse_context)?,
+        wear_warning: values
+            .get("wear_warning")
+            .map(|v| {
+                v.trim()
+                    .parse()
+                    .map_err(|_| anyhow!("wear_warning: '{v}' is not a number of writes"))
+            })
+            .transpose()
+            .with_context(parse_context)?,
+        label_command: values.get("label.command").cloned(),
+        require_unlock: values
+            .get("require_unlock")
+            .map(|v| parse_bool("require_unlock", v))
+            .transpose()
+            .with_context(parse_context)?
+            .unwrap_or(false),
+        unlock_window: values
+            .get("unlock_minutes")
+            .map(|v| {
+                v.trim()
+                    .parse::<u64>()
+                    .map(|minutes| Duration::from_secs(minutes * 60))
+                    .map_err(|_| anyhow!("unlock_minutes: '{v}' is not a number of minutes"))
+            })
+            .transpose()
+            .with_context(parse_context)?,
+        countdown: values
+            .get("countdown")
+            .map(|v| {
+                v.trim()
+                    .parse()
+                    .map_err(|_| anyhow!("countdown: '{v}' is not a number of seconds"))
+            })
+            .transpose()
+            .with_context(parse_context)?
+            // 0 turns it off, e.g. in a profile
+            .filter(|seconds| *seconds > 0),
+        verify: values
+            .get("verify")
+            .map(|v| parse_bool("verify", v))
+            .transpose()
+            .with_context(parse_context)?,
+        parallel: values
+            .get("parallel")
+            .map(|v| parse_bool("parallel", v))
+            .transpose()
+            .with_context(parse_context)?,
+        eject: values
+            .get("eject")
+            .map(|v| parse_bool("eject", v))
+            .transpose()
+            .with_context(parse_context)?,
+        // A comma-separated list, e.g. "zstd:19, xz:9"
+        compress: values
+            .get("compress")
+            .map(|v| {
+                v.split(',')
+                    .map(|choice| match choice.trim().parse() {
+                        Ok(compress::Choice::Fixed(compression)) => Ok(compression),
+                        Ok(compress::Choice::Auto) => Err(anyhow!(
+                            "compress: 'auto' can only be given with --compress"
+                        )),
+                        Err(e) => Err(anyhow!("compress: {e}")),
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .transpose()
+            .with_context(parse_context)?
+            .unwrap_or_default(),
+    })
+}
@@ -0,0 +1,833 @@
+#[cfg(not(target_os = "macos"))]
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+#[cfg(not(target_os = "macos"))]
+use std::io;
+use std::net::IpAddr;
+#[cfg(not(target_os = "macos"))]
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{PermissionsExt, chown, symlink};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow};
+use tempfile::TempDir;
+
+use crate::device;
+use crate::output::{info, verbose};
+
+/// Changes applied to the flashed system after writing.
+#[derive(Default)]
+pub struct Customizations {
+    /// Script to run once, on the first boot.
+    pub firstboot: Option<PathBuf>,
+    pub hostname: Option<String>,
+    pub network: Option<Network>,
+    /// authorized_keys lines for the default user, already fetched.
+    pub ssh_keys: Option<String>,
+}
+
+impl Customizations {
+    pub fn is_empty(&self) -> bool {
+        self.firstboot.is_none()
+            && self.hostname.is_none()
+            && self.network.is_none()
+            && self.ssh_keys.is_none()
+    }
+}
+
+/// Checks that `name` is a valid hostname (RFC 1123), for use as a clap
+/// value parser.
+pub fn parse_hostname(name: &str) -> Result<String, String> {
+    let valid_label = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    };
+    if name.len() > 253 || !name.split('.').all(valid_label) {
+        return Err(format!("'{name}' is not a valid hostname"));
+    }
+    Ok(name.to_string())
+}
+
+/// An address with its prefix length, e.g. `192.168.1.10/24`.
+#[derive(Clone, Copy)]
+pub struct StaticAddress {
+    pub ip: IpAddr,
+    pub prefix: u8,
+}
+
+impl FromStr for StaticAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (ip, prefix) = s
+            .split_once('/')
+            .ok_or_else(|| format!("'{s}' needs a prefix length, e.g. 192.168.1.10/24"))?;
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| format!("'{ip}' is not an IP address"))?;
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix
+            .parse()
+            .ok()
+            .filter(|p| *p <= max)
+            .ok_or_else(|| format!("'{prefix}' is not a prefix length (0-{max})"))?;
+        Ok(Self { ip, prefix })
+    }
+}
+
+impl fmt::Display for StaticAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix)
+    }
+}
+
+/// Where to get SSH public keys from: a file, or `github:<user>` for the
+/// keys a GitHub user has published.
+#[derive(Clone)]
+pub enum SshKeySource {
+    File(PathBuf),
+    GitHub(String),
+}
+
+impl FromStr for SshKeySource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.strip_prefix("github:") {
+            Some(user)
+                if !user.is_empty()
+                    && user.len() <= 39
+                    && user.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') =>
+            {
+                Ok(SshKeySource::GitHub(user.to_string()))
+            }
+            Some(user) => Err(format!("'{user}' is not a GitHub user name")),
+            None => Ok(SshKeySource::File(PathBuf::from(s))),
+        }
+    }
+}
+
+impl SshKeySource {
+    /// Returns the public keys, one per line.
+    pub fn fetch(&self) -> Result<String> {
+        let (origin, keys) = match self {
+            SshKeySource::File(path) => (
+                path.display().to_string(),
+                fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?,
+            ),
+            SshKeySource::GitHub(user) => {
+                let url = format!("https://github.com/{user}.keys");
+                let output = Command::new("curl")
+                    .args(["--fail", "--silent", "--show-error", "--location"])
+                    .args(["--proto", "=https", "--max-time", "30"])
+                    .arg(&url)
+                    .output()
+                    .context("running curl (is it installed?)")?;
+                if !output.status.success() {
+                    return Err(anyhow!(
+                        "fetching {url} failed: {}",
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    ));
+                }
+                (url, String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+        };
+
+        let keys: Vec<&str> = keys
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        if keys.is_empty() {
+            return Err(anyhow!("no SSH keys found in {origin}"));
+        }
+        verbose!("Found {} SSH key(s) in {origin}", keys.len());
+        Ok(keys.join("\n") + "\n")
+    }
+}
+
+/// A static network configuration for one interface.
+pub struct Network {
+    pub interface: String,
+    pub address: StaticAddress,
+    pub gateway: Option<IpAddr>,
+    pub dns: Vec<IpAddr>,
+}
+
+/// A partition mounted in a temp dir for the duration of the customization.
+struct Mount {
+    dir: TempDir,
+}
+
+impl Mount {
+    /// Mounts `partition`, trying each filesystem type the kernel supports.
+    #[cfg(not(target_os = "macos"))]
+    fn new(partition: &Path) -> Result<Self> {
+        let dir = TempDir::new()?;
+        let source = CString::new(partition.as_os_str().as_bytes())?;
+        let target = CString::new(dir.path().as_os_str().as_bytes())?;
+
+        let mut last_error = io::Error::from_raw_os_error(libc::ENODEV);
+        for fstype in block_filesystems() {
+            let fstype = CString::new(fstype)?;
+            let ret = unsafe {
+                libc::mount(
+                    source.as_ptr(),
+                    target.as_ptr(),
+                    fstype.as_ptr(),
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            if ret == 0 {
+                verbose!(
+                    "Mounted {} ({}) at {}",
+                    partition.display(),
+                    fstype.to_string_lossy(),
+                    dir.path().display()
+                );
+                return Ok(Self { dir });
+            }
+            last_error = io::Error::last_os_error();
+            // Anything but "wrong filesystem type" won't get better with another one
+            if !matches!(last_error.raw_os_error(), Some(libc::EINVAL | libc::ENODEV)) {
+                break;
+            }
+        }
+        Err(last_error).with_context(|| format!("mounting {}", partition.display()))
+    }
+
+    /// Mounts `partition` with `diskutil`, for the filesystems macOS can
+    /// mount. Linux root filesystems such as ext4 aren't among them.
+    #[cfg(target_os = "macos")]
+    fn new(partition: &Path) -> Result<Self> {
+        let dir = TempDir::new()?;
+        let output = Command::new("diskutil")
+            .args(["mount", "-mountPoint"])
+            .arg(dir.path())
+            .arg(partition)
+            .output()
+            .context("running diskutil")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "mounting {}: {}",
+                partition.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        verbose!(
+            "Mounted {} at {}",
+            partition.display(),
+            dir.path().display()
+        );
+        Ok(Self { dir })
+    }
+
+    fn path(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for Mount {
+    fn drop(&mut self) {
+        // Don't leave it behind even if something still holds it
+        Command::new("diskutil")
+            .args(["unmount", "force"])
+            .arg(self.dir.path())
+            .output()
+            .ok();
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+impl Drop for Mount {
+    fn drop(&mut self) {
+        if let Ok(target) = CString::new(self.dir.path().as_os_str().as_bytes()) {
+            unsafe {
+                if libc::umount(target.as_ptr()) != 0 {
+                    // Don't leave it behind even if something still holds it
+                    libc::umount2(target.as_ptr(), libc::MNT_DETACH);
+                }
+            }
+        }
+    }
+}
+
+/// Filesystem types that live on block devices, from /proc/filesystems.
+#[cfg(not(target_os = "macos"))]
+fn block_filesystems() -> Vec<String> {
+    fs::read_to_string("/proc/filesystems")
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.starts_with("nodev"))
+        .map(|line| line.trim().to_string())
+        .filter(|fstype| !fstype.is_empty())
+        .collect()
+}
+
+/// Resolves `path` (absolute, as seen from inside the flashed system)
+/// against `root`, following symlinks as the flashed system would, so that
+/// an absolute symlink in the image can't redirect writes to the host.
+/// Components that don't exist yet are appended as is.
+fn resolve(root: &Path, path: &str) -> Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    let mut pending: Vec<PathBuf> = Path::new(path)
+        .components()
+        .rev()
+        .map(|c| PathBuf::from(c.as_os_str()))
+        .collect();
+    let mut links = 0;
+
+    while let Some(component) = pending.pop() {
+        match component.components().next() {
+            Some(Component::RootDir) | Some(Component::CurDir) | None => continue,
+            Some(Component::ParentDir) => {
+                if resolved != root {
+                    resolved.pop();
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let next = resolved.join(&component);
+        match fs::read_link(&next) {
+            Ok(target) => {
+                links += 1;
+                if links > 40 {
+                    return Err(anyhow!("too many symlinks resolving {path}"));
+                }
+                if target.is_absolute() {
+                    resolved = root.to_path_buf();
+                }
+                pending.extend(
+                    target
+                        .components()
+                        .rev()
+                        .map(|c| PathBuf::from(c.as_os_str())),
+                );
+            }
+            Err(_) => resolved = next,
+        }
+    }
+    Ok(resolved)
+}
+
+/// How the flashed system boots, which decides how first-boot hooks work.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Init {
+    /// OpenWrt, which runs and removes /etc/uci-defaults scripts once.
+    OpenWrt,
+    Systemd,
+    OpenRc,
+    /// Anything else; falls back to /etc/rc.local.
+    SysV,
+}
+
+impl Init {
+    /// The mechanism used for first-boot hooks.
+    fn hook_name(self) -> &'static str {
+        match self {
+            Init::OpenWrt => "uci-defaults",
+            Init::Systemd => "systemd unit",
+            Init::OpenRc => "OpenRC local.d",
+            Init::SysV => "rc.local",
+        }
+    }
+}
+
+/// The flashed system's root filesystem, mounted.
+struct Target {
+    root: PathBuf,
+    /// `ID` from /etc/os-release, e.g. `debian` or `openwrt`.
+    distro: String,
+    init: Init,
+}
+
+impl Target {
+    /// Returns the host path of `path` inside the flashed system.
+    fn path(&self, path: &str) -> Result<PathBuf> {
+        resolve(&self.root, path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.path(path).map(|p| p.exists()).unwrap_or(false)
+    }
+
+    /// Writes a file into the flashed system, creating parent directories.
+    fn write(&self, path: &str, contents: &[u8], mode: u32) -> Result<()> {
+        let host_path = self.path(path)?;
+        if let Some(dir) = host_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&host_path, contents).with_context(|| format!("writing {path}"))?;
+        fs::set_permissions(&host_path, fs::Permissions::from_mode(mode))?;
+        verbose!("Wrote {path}");
+        Ok(())
+    }
+
+    /// Creates (or replaces) a symlink inside the flashed system.
+    fn symlink(&self, target: &str, path: &str) -> Result<()> {
+        let host_path = self.path(path)?;
+        if let Some(dir) = host_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::remove_file(&host_path).ok();
+        symlink(target, &host_path).with_context(|| format!("linking {path}"))
+    }
+
+    /// Creates a directory inside the flashed system unless it exists.
+    fn create_dir(&self, path: &str, mode: u32, owner: (u32, u32)) -> Result<()> {
+        let host_path = self.path(path)?;
+        if host_path.is_dir() {
+            return Ok(());
+        }
+        fs::create_dir_all(&host_path).with_context(|| format!("creating {path}"))?;
+        fs::set_permissions(&host_path, fs::Permissions::from_mode(mode))?;
+        chown(&host_path, Some(owner.0), Some(owner.1))?;
+        Ok(())
+    }
+
+    fn read_to_string(&self, path: &str) -> Option<String> {
+        fs::read_to_string(self.path(path).ok()?).ok()
+    }
+
+    fn detect(root: &Path) -> Option<Self> {
+        let mut target = Target {
+            root: root.to_path_buf(),
+            distro: String::new(),
+            init: Init::SysV,
+        };
+        let os_release = target
+            .read_to_string("/etc/os-release")
+            .or_else(|| target.read_to_string("/usr/lib/os-release"))?;
+        target.distro = os_release
+            .lines()
+            .find_map(|l| l.strip_prefix("ID="))
+            .map(|id| id.trim_matches('"').to_string())
+            .unwrap_or_default();
+
+        target.init = if target.distro == "openwrt" {
+            Init::OpenWrt
+        } else if target.exists("/lib/systemd/systemd") || target.exists("/usr/lib/systemd/systemd")
+        {
+            Init::Systemd
+        } else if target.exists("/sbin/openrc") {
+            Init::OpenRc
+        } else {
+            Init::SysV
+        };
+        Some(target)
+    }
+}
+
+/// Sets the hostname, in /etc/hostname and the 127.0.1.1 line of /etc/hosts
+/// that Debian-style systems use to resolve it.
+fn install_hostname(target: &Target, hostname: &str) -> Result<()> {
+    if target.init == Init::OpenWrt {
+        let script =
+            format!("uci set system.@system[0].hostname='{hostname}'\nuci commit system\n");
+        target.write(
+            "/etc/uci-defaults/98-etchr-hostname",
+            script.as_bytes(),
+            0o755,
+        )?;
+    } else {
+        target.write("/etc/hostname", format!("{hostname}\n").as_bytes(), 0o644)?;
+        let hosts = target.read_to_string("/etc/hosts").unwrap_or_default();
+        let mut lines: Vec<String> = hosts
+            .lines()
+            .filter(|line| !line.starts_with("127.0.1.1"))
+            .map(str::to_string)
+            .collect();
+        let at = lines
+            .iter()
+            .position(|line| line.starts_with("127.0.0.1"))
+            .map_or(0, |pos| pos + 1);
+        lines.insert(at, format!("127.0.1.1\t{hostname}"));
+        target.write("/etc/hosts", (lines.join("\n") + "\n").as_bytes(), 0o644)?;
+    }
+    info!("Set hostname to {hostname}");
+    Ok(())
+}
+
+/// How the flashed system configures its network.
+#[derive(Clone, Copy)]
+enum NetworkBackend {
+    Uci,
+    NetworkManager,
+    /// Debian's ifupdown, /etc/network/interfaces.
+    Ifupdown,
+    Networkd,
+}
+
+impl NetworkBackend {
+    fn detect(target: &Target) -> Option<Self> {
+        if target.init == Init::OpenWrt {
+            Some(NetworkBackend::Uci)
+        } else if target.exists("/usr/sbin/NetworkManager")
+            || target.exists("/usr/bin/NetworkManager")
+        {
+            Some(NetworkBackend::NetworkManager)
+        } else if target.exists("/etc/network/interfaces") {
+            Some(NetworkBackend::Ifupdown)
+        } else if target.init == Init::Systemd && networkd_unit(target).is_some() {
+            Some(NetworkBackend::Networkd)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            NetworkBackend::Uci => "uci",
+            NetworkBackend::NetworkManager => "NetworkManager",
+            NetworkBackend::Ifupdown => "ifupdown",
+            NetworkBackend::Networkd => "systemd-networkd",
+        }
+    }
+}
+
+/// The systemd-networkd unit in the flashed system, if it has one.
+fn networkd_unit(target: &Target) -> Option<&'static str> {
+    [
+        "/lib/systemd/system/systemd-networkd.service",
+        "/usr/lib/systemd/system/systemd-networkd.service",
+    ]
+    .into_iter()
+    .find(|unit| target.exists(unit))
+}
+
+fn join(addresses: &[IpAddr], separator: &str) -> String {
+    addresses
+        .iter()
+        .map(IpAddr::to_string)
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Writes a static configuration for `network.interface`, in whatever
+/// format the flashed system's network manager reads.
+fn install_network(target: &Target, network: &Network) -> Result<()> {
+    let backend = NetworkBackend::detect(target).ok_or_else(|| {
+        anyhow!("don't know how the flashed system configures its network (no NetworkManager, ifupdown or systemd-networkd found)")
+    })?;
+    let iface = &network.interface;
+    let address = network.address;
+    let v6 = address.ip.is_ipv6();
+
+    match backend {
+        NetworkBackend::Uci => {
+            // The LAN bridge, not a single port, is what carries the address
+            let mut script = format!(
+                "uci set network.lan.proto='static'\nuci set network.lan.{}='{address}'\n",
+                if v6 { "ip6addr" } else { "ipaddr" }
+            );
+            if let Some(gateway) = network.gateway {
+                let key = if v6 { "ip6gw" } else { "gateway" };
+                script += &format!("uci set network.lan.{key}='{gateway}'\n");
+            }
+            if !network.dns.is_empty() {
+                script += &format!("uci set network.lan.dns='{}'\n", join(&network.dns, " "));
+            }
+            script += "uci commit network\n";
+            target.write(
+                "/etc/uci-defaults/98-etchr-network",
+                script.as_bytes(),
+                0o755,
+            )?;
+        }
+        NetworkBackend::NetworkManager => {
+            let (family, other) = if v6 {
+                ("ipv6", "ipv4")
+            } else {
+                ("ipv4", "ipv6")
+            };
+            let mut keyfile = format!(
+                "[connection]\nid=etchr-{iface}\ntype=ethernet\ninterface-name={iface}\n\n\
+                 [{family}]\nmethod=manual\naddress1={address}"
+            );
+            if let Some(gateway) = network.gateway {
+                keyfile += &format!(",{gateway}");
+            }
+            keyfile += "\n";
+            if !network.dns.is_empty() {
+                keyfile += &format!("dns={};\n", join(&network.dns, ";"));
+            }
+            keyfile += &format!("\n[{other}]\nmethod=auto\n");
+            // NetworkManager ignores keyfiles that others can read
+            target.write(
+                &format!("/etc/NetworkManager/system-connections/etchr-{iface}.nmconnection"),
+                keyfile.as_bytes(),
+                0o600,
+            )?;
+        }
+        NetworkBackend::Ifupdown => {
+            let mut stanza = format!(
+                "auto {iface}\niface {iface} {} static\n    address {address}\n",
+                if v6 { "inet6" } else { "inet" }
+            );
+            if let Some(gateway) = network.gateway {
+                stanza += &format!("    gateway {gateway}\n");
+            }
+            if !network.dns.is_empty() {
+                stanza += &format!("    dns-nameservers {}\n", join(&network.dns, " "));
+            }
+            let interfaces = target
+                .read_to_string("/etc/network/interfaces")
+                .unwrap_or_default();
+            if interfaces.contains("/etc/network/interfaces.d") {
+                target.write(
+                    &format!("/etc/network/interfaces.d/etchr-{iface}"),
+                    stanza.as_bytes(),
+                    0o644,
+                )?;
+            } else {
+                let interfaces = format!("{}\n{stanza}", interfaces.trim_end());
+                target.write("/etc/network/interfaces", interfaces.as_bytes(), 0o644)?;
+            }
+        }
+        NetworkBackend::Networkd => {
+            let mut unit = format!("[Match]\nName={iface}\n\n[Network]\nAddress={address}\n");
+            if let Some(gateway) = network.gateway {
+                unit += &format!("Gateway={gateway}\n");
+            }
+            for dns in &network.dns {
+                unit += &format!("DNS={dns}\n");
+            }
+            target.write(
+                &format!("/etc/systemd/network/10-etchr-{iface}.network"),
+                unit.as_bytes(),
+                0o644,
+            )?;
+            if let Some(service) = networkd_unit(target) {
+                target.symlink(
+                    service,
+                    "/etc/systemd/system/multi-user.target.wants/systemd-networkd.service",
+                )?;
+            }
+        }
+    }
+    let configured = match backend {
+        NetworkBackend::Uci => "lan",
+        _ => iface,
+    };
+    info!("Configured {configured} as {address} ({})", backend.name());
+    Ok(())
+}
+
+/// A user account of the flashed system, from its /etc/passwd.
+struct User {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+/// Returns the account a person logs in with: the first regular user with
+/// a login shell, as created by distributions that ship a default user
+/// (`pi`, `ubuntu`, ...), or root if there is none.
+fn default_user(target: &Target) -> Result<User> {
+    let passwd = target
+        .read_to_string("/etc/passwd")
+        .ok_or_else(|| anyhow!("the flashed system has no /etc/passwd"))?;
+    let users: Vec<User> = passwd
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            let shell = fields.get(6)?;
+            if shell.ends_with("nologin") || shell.ends_with("false") {
+                return None;
+            }
+            Some(User {
+                name: fields[0].to_string(),
+                uid: fields[2].parse().ok()?,
+                gid: fields[3].parse().ok()?,
+                home: fields[5].to_string(),
+            })
+        })
+        .collect();
+
+    users
+        .iter()
+        .position(|u| (1000..60000).contains(&u.uid))
+        .or_else(|| users.iter().position(|u| u.uid == 0))
+        .map(|i| users.into_iter().nth(i).expect("index is valid"))
+        .ok_or_else(|| anyhow!("the flashed system has no user to install SSH keys for"))
+}
+
+/// Adds `keys` to the default user's authorized_keys, keeping any keys
+/// that are already there.
+fn install_ssh_keys(target: &Target, keys: &str) -> Result<()> {
+    let user = default_user(target)?;
+    let owner = (user.uid, user.gid);
+    // Dropbear on OpenWrt reads root's keys from its own directory
+    let authorized_keys = if target.init == Init::OpenWrt && user.uid == 0 {
+        "/etc/dropbear/authorized_keys".to_string()
+    } else {
+        let home = user.home.trim_end_matches('/');
+        target.create_dir(home, 0o755, owner)?;
+        target.create_dir(&format!("{home}/.ssh"), 0o700, owner)?;
+        format!("{home}/.ssh/authorized_keys")
+    };
+
+    let mut contents = target.read_to_string(&authorized_keys).unwrap_or_default();
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        contents.push('\n');
+    }
+    let existing: Vec<String> = contents.lines().map(str::to_string).collect();
+    for key in keys.lines() {
+        if !existing.iter().any(|line| line == key) {
+            contents += &format!("{key}\n");
+        }
+    }
+    target.write(&authorized_keys, contents.as_bytes(), 0o600)?;
+    chown(
+        target.path(&authorized_keys)?,
+        Some(user.uid),
+        Some(user.gid),
+    )?;
+    info!("Installed SSH keys for {}", user.name);
+    Ok(())
+}
+
+const FIRSTBOOT_SCRIPT: &str = "/usr/local/sbin/etchr-firstboot";
+const FIRSTBOOT_UNIT: &str = "etchr-firstboot.service";
+
+/// Installs `script` to run once on the first boot, using the flashed
+/// system's own mechanism. As with OpenWrt's uci-defaults, a script that
+/// fails is retried on the next boot.
+fn install_firstboot(target: &Target, script: &[u8]) -> Result<()> {
+    match target.init {
+        Init::OpenWrt => {
+            target.write("/etc/uci-defaults/99-etchr-firstboot", script, 0o755)?;
+        }
+        Init::Systemd => {
+            target.write(FIRSTBOOT_SCRIPT, script, 0o755)?;
+            let unit = format!(
+                "[Unit]\n\
+                 Description=First-boot provisioning (installed by etchr)\n\
+                 Wants=network-online.target\n\
+                 After=network-online.target\n\
+                 ConditionPathExists={FIRSTBOOT_SCRIPT}\n\
+                 \n\
+                 [Service]\n\
+                 Type=oneshot\n\
+                 ExecStart={FIRSTBOOT_SCRIPT}\n\
+                 ExecStartPost=/bin/rm -f {FIRSTBOOT_SCRIPT}\n\
+                 \n\
+                 [Install]\n\
+                 WantedBy=multi-user.target\n"
+            );
+            let unit_path = format!("/etc/systemd/system/{FIRSTBOOT_UNIT}");
+            target.write(&unit_path, unit.as_bytes(), 0o644)?;
+            // What `systemctl enable` would do
+            target.symlink(
+                &unit_path,
+                &format!("/etc/systemd/system/multi-user.target.wants/{FIRSTBOOT_UNIT}"),
+            )?;
+        }
+        Init::OpenRc => {
+            target.write(FIRSTBOOT_SCRIPT, script, 0o755)?;
+            let hook = format!(
+                "#!/bin/sh\n[ -x {FIRSTBOOT_SCRIPT} ] && {FIRSTBOOT_SCRIPT} && rm -f {FIRSTBOOT_SCRIPT}\n"
+            );
+            target.write("/etc/local.d/etchr-firstboot.start", hook.as_bytes(), 0o755)?;
+            target.symlink("/etc/init.d/local", "/etc/runlevels/default/local")?;
+        }
+        Init::SysV => {
+            target.write(FIRSTBOOT_SCRIPT, script, 0o755)?;
+            let line = format!(
+                "[ -x {FIRSTBOOT_SCRIPT} ] && {FIRSTBOOT_SCRIPT} && rm -f {FIRSTBOOT_SCRIPT}\n"
+            );
+            let rc_local = target
+                .read_to_string("/etc/rc.local")
+                .unwrap_or_else(|| "#!/bin/sh\n".to_string());
+            // Insert before a final `exit 0`, which would skip the hook
+            let rc_local = match rc_local.rfind("\nexit 0") {
+                Some(pos) => format!("{}\n{line}{}", &rc_local[..pos], &rc_local[pos + 1..]),
+                None => format!("{rc_local}{line}"),
+            };
+            target.write("/etc/rc.local", rc_local.as_bytes(), 0o755)?;
+        }
+    }
+    info!("Installed first-boot script ({})", target.init.hook_name());
+    Ok(())
+}
+
+/// Mounts the freshly written device's partitions, finds the root
+/// filesystem and applies `customizations` to it. Requires root.
+pub fn apply(device_path: &Path, customizations: &Customizations) -> Result<()> {
+    // Read the script before touching anything, so a typo fails early
+    let firstboot = customizations
+        .firstboot
+        .as_ref()
+        .map(|path| fs::read(path).with_context(|| format!("reading {}", path.display())))
+        .transpose()?;
+
+    let partitions = device::reread_partitions(device_path)?;
+    if partitions.is_empty() {
+        return Err(anyhow!(
+            "no partitions found on {} to customize",
+            device_path.display()
+        ));
+    }
+
+    let mut target = None;
+    let mut mounts = Vec::new();
+    for partition in &partitions {
+        let mount = match Mount::new(partition) {
+            Ok(mount) => mount,
+            Err(e) => {
+                verbose!("Skipping {}: {e:#}", partition.display());
+                continue;
+            }
+        };
+        if let Some(found) = Target::detect(mount.path()) {
+            info!(
+                "Customizing {} ({})",
+                partition.display(),
+                if found.distro.is_empty() {
+                    "unknown distro"
+                } else {
+                    &found.distro
+                }
+            );
+            target = Some(found);
+            mounts.push(mount);
+            break;
+        }
+    }
+    let target = target.ok_or_else(|| {
+        anyhow!(
+            "no root filesystem found on {} (is it a Linux image?)",
+            device_path.display()
+        )
+    })?;
+
+    if let Some(hostname) = &customizations.hostname {
+        install_hostname(&target, hostname)?;
+    }
+    if let Some(network) = &customizations.network {
+        install_network(&target, network)?;
+    }
+    if let Some(keys) = &customizations.ssh_keys {
+        install_ssh_keys(&target, keys)?;
+    }
+    if let Some(script) = &firstboot {
+        install_firstboot(&target, script)?;
+    }
+
+    // Unmounting flushes everything to the device
+    drop(mounts);
+    Ok(())
+}
@@ -1,25 +1,105 @@
 use anyhow::{Result, anyhow};
-use dialoguer::{Confirm, Select, theme::ColorfulTheme};
 use std::fmt;
-use std::fs; // Used for reading /sys/block
-use std::io; // Used for error handling on file reads
+use std::fs::{self, File}; // Used for reading /sys/block
+use std::io::{self, IsTerminal, Write}; // Used for error handling on file reads
+use std::os::unix::fs::FileTypeExt;
+#[cfg(not(target_os = "macos"))]
+use std::os::unix::fs::MetadataExt;
+#[cfg(not(target_os = "macos"))]
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 
+use console::style;
+use indicatif::HumanBytes;
+#[cfg(not(target_os = "macos"))]
+use nix::{ioctl_none, ioctl_read};
+
+use crate::cancel::CancelToken;
+use crate::card::CardInfo;
+use crate::catalog;
+use crate::events;
+#[cfg(not(target_os = "macos"))]
+use crate::exclude::Exclusions;
+use crate::i18n::{Msg, tr, trf};
+use crate::link::Link;
+use crate::menu;
+#[cfg(not(target_os = "macos"))]
+use crate::output::verbose;
+use crate::output::{self, info};
+use crate::picker::format_age;
+use crate::prompt;
+#[cfg(not(target_os = "macos"))]
+use crate::wear;
+use crate::write;
+
+/// Images smaller than this are suspicious and need an extra acknowledgement.
+const MIN_PLAUSIBLE_IMAGE_SIZE: u64 = 1024 * 1024;
+
+/// Images smaller than 1/N of the device need an extra acknowledgement.
+const MAX_PLAUSIBLE_SIZE_RATIO: u64 = 1000;
+
 #[derive(Clone)]
 pub struct Device {
     pub path: PathBuf,
     /// The kernel name of the device (e.g., "sdd").
     pub name: String,
     pub size_gb: f64,
+    pub size_bytes: u64,
+    pub mount_point: String,
+    /// Whether the kernel flags the device as removable.
+    pub removable: bool,
+    /// Whether the disk is attached over USB, like a SATA or NVMe enclosure
+    /// that doesn't flag itself as removable.
+    pub usb: bool,
+    /// CID data, for SD cards and eMMC in a native slot.
+    pub(crate) card: Option<CardInfo>,
+    /// How the disk is connected, on USB or in a card slot.
+    pub(crate) link: Option<Link>,
+    /// How many times etchr has written the disk, if it has a serial number.
+    pub burns: Option<u32>,
+    /// The filesystems mounted from it, to show what overwriting it
+    /// destroys.
+    pub(crate) usage: Vec<Usage>,
+}
+
+/// How full a filesystem mounted from a device is.
+#[derive(Clone)]
+pub struct Usage {
+    /// The partition or mapped device it's on, e.g. "sdd2".
+    pub source: String,
     pub mount_point: String,
+    pub fs_type: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let used = self.total.saturating_sub(self.available);
+        write!(
+            f,
+            "{}",
+            trf(
+                Msg::FilesystemUsage,
+                &[
+                    &self.source,
+                    &self.mount_point,
+                    &self.fs_type,
+                    &HumanBytes(used),
+                    &HumanBytes(self.total),
+                    &HumanBytes(self.available),
+                ]
+            )
+        )
+    }
 }
 
 impl fmt::Display for Device {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mount_info = if !self.mount_point.is_empty() {
-            format!("[Mounted at {}]", self.mount_point)
+            trf(Msg::MountedAt, &[&self.mount_point])
         } else {
-            "[Not mounted]".to_string()
+            tr(Msg::NotMountedTag).to_string()
         };
 
         write!(
@@ -28,8 +108,154 @@ impl fmt::Display for Device {
             self.path.display(), // e.g., "/dev/sdd"
             self.size_gb,
             mount_info
-        )
+        )?;
+        if !self.removable {
+            let marker = if self.usb {
+                Msg::UsbDisk
+            } else {
+                Msg::InternalDisk
+            };
+            write!(f, " {}", tr(marker))?;
+        }
+        if let Some(burns) = self.burns.filter(|b| *b > 0) {
+            write!(f, " {}", trf(Msg::BurnCount, &[&burns]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Flags to open devices with for unbuffered I/O. macOS has no O_DIRECT,
+/// but its raw disk nodes (`/dev/rdisk4`) bypass the buffer cache anyway.
+#[cfg(not(target_os = "macos"))]
+pub const O_DIRECT: libc::c_int = libc::O_DIRECT;
+#[cfg(target_os = "macos")]
+pub const O_DIRECT: libc::c_int = 0;
+
+// Define the `nix` ioctl for `BLKGETSIZE64` (u64 device size in bytes).
+#[cfg(not(target_os = "macos"))]
+ioctl_read!(blkgetsize64, 0x12, 114, u64);
+
+/// Returns the size of an open file in bytes. For block devices the size
+/// is queried with `BLKGETSIZE64` (on macOS, from the block count and
+/// size), since their metadata reports zero.
+pub fn file_size(file: &File) -> io::Result<u64> {
+    let metadata = file.metadata()?;
+    if !is_disk(metadata.file_type()) {
+        return Ok(metadata.len());
     }
+
+    #[cfg(target_os = "macos")]
+    let size_bytes = crate::macos::disk_size(file)?;
+    #[cfg(not(target_os = "macos"))]
+    let size_bytes = {
+        let mut size_bytes: u64 = 0;
+        unsafe {
+            blkgetsize64(file.as_raw_fd(), &mut size_bytes)?;
+        }
+        size_bytes
+    };
+    Ok(size_bytes)
+}
+
+/// Whether `file_type` is that of a disk: a block device, or on macOS also
+/// a raw disk, which is a character device there.
+fn is_disk(file_type: fs::FileType) -> bool {
+    file_type.is_block_device() || (cfg!(target_os = "macos") && file_type.is_char_device())
+}
+
+/// Returns true if `path` is a block device (e.g., `/dev/sdb`), or on
+/// macOS a raw disk (e.g., `/dev/rdisk4`).
+pub fn is_block_device(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|m| is_disk(m.file_type()))
+        .unwrap_or(false)
+}
+
+/// Gets the device at `device_path` ready to be overwritten. macOS refuses
+/// raw writes to a disk with mounted volumes, so they're unmounted there;
+/// Linux lets the write go ahead, over whatever the summary showed mounted.
+pub fn release(device_path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    crate::macos::unmount_disk(device_path)?;
+    #[cfg(not(target_os = "macos"))]
+    let _ = device_path;
+    Ok(())
+}
+
+/// Ejects the device at `device_path` once it's written, so that it can be
+/// unplugged: with `eject` on Linux, and `diskutil` on macOS.
+pub fn eject(device_path: &Path) -> Result<()> {
+    #[cfg(target_os = "macos")]
+    return crate::macos::eject_disk(device_path);
+    #[cfg(not(target_os = "macos"))]
+    {
+        let output = std::process::Command::new("eject")
+            .arg(device_path)
+            .output()
+            .map_err(|e| anyhow!(trf(Msg::RunningEject, &[&e])))?;
+        if !output.status.success() {
+            return Err(anyhow!(trf(
+                Msg::ActionFailed,
+                &[
+                    &format!("eject {}", device_path.display()),
+                    &String::from_utf8_lossy(&output.stderr).trim()
+                ]
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+ioctl_none!(blkrrpart, 0x12, 95);
+
+/// Returns the partitions of `device_path` once it was overwritten. macOS
+/// probes the new partition table by itself.
+#[cfg(target_os = "macos")]
+pub fn reread_partitions(device_path: &Path) -> Result<Vec<PathBuf>> {
+    crate::macos::partitions(device_path)
+}
+
+/// Asks the kernel to re-read the partition table of `device_path` after
+/// it was overwritten, then returns its partitions (e.g. `/dev/sdb1`) in
+/// order. Fails with EBUSY if a partition is still mounted (e.g. by a
+/// desktop automounter); the partitions known to the kernel are used then.
+#[cfg(not(target_os = "macos"))]
+pub fn reread_partitions(device_path: &Path) -> Result<Vec<PathBuf>> {
+    let file = File::open(device_path)?;
+    if let Err(e) = unsafe { blkrrpart(file.as_raw_fd()) } {
+        verbose!("Re-reading the partition table failed: {e}");
+    }
+    drop(file);
+
+    let name = device_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!(trf(Msg::InvalidDevicePath, &[&device_path.display()])))?;
+
+    // udev needs a moment to create the new device nodes
+    let mut partitions: Vec<(u32, PathBuf)> = Vec::new();
+    for _ in 0..20 {
+        partitions = fs::read_dir(PathBuf::from("/sys/block").join(&name))?
+            .flatten()
+            .filter_map(|entry| {
+                let part = entry.file_name().to_string_lossy().to_string();
+                let number = read_sys_file(&name, &format!("{part}/partition"))
+                    .ok()?
+                    .parse()
+                    .ok()?;
+                let path = PathBuf::from("/dev").join(part);
+                path.exists().then_some((number, path))
+            })
+            .collect();
+        if !partitions.is_empty() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    }
+
+    partitions.sort();
+    Ok(partitions.into_iter().map(|(_, path)| path).collect())
 }
 
 /// Helper to read a specific file from the /sys/block filesystem.
@@ -38,41 +264,217 @@ fn read_sys_file(device_name: &str, file: &str) -> io::Result<String> {
     fs::read_to_string(path).map(|s| s.trim().to_string())
 }
 
-/// Helper to find the parent device of a partition (e.g., /dev/sda1 -> /dev/sda).
-/// This is used to find the system drive's parent for exclusion.
-fn get_parent_device_path(path: &Path) -> PathBuf {
-    let path_str = path.to_string_lossy();
+/// Returns the serial number of the disk `device_name`: the one in the CID
+/// of an SD card or eMMC, or else that of the USB device it's part of
+/// (e.g. a card reader or stick), if there is one.
+pub fn serial(device_name: &str) -> Option<String> {
+    if let Some(card) = CardInfo::read(device_name) {
+        return Some(card.serial).filter(|s| !s.is_empty());
+    }
+    let path = PathBuf::from("/sys/block")
+        .join(device_name)
+        .canonicalize()
+        .ok()?;
+    path.ancestors()
+        .filter_map(|dir| fs::read_to_string(dir.join("serial")).ok())
+        .map(|s| s.trim().to_string())
+        .find(|s| !s.is_empty())
+}
+
+/// Returns the product name of an SD card or eMMC in `device_name`, or
+/// else the vendor and model the disk reports, if any.
+pub fn model(device_name: &str) -> Option<String> {
+    if let Some(card) = CardInfo::read(device_name) {
+        return Some(card.product).filter(|p| !p.is_empty());
+    }
+    let vendor = read_sys_file(device_name, "device/vendor").unwrap_or_default();
+    let model = read_sys_file(device_name, "device/model").unwrap_or_default();
+    let name = format!("{vendor} {model}").trim().to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Returns the world wide name of the disk `device_name`, if it has one.
+pub fn wwid(device_name: &str) -> Option<String> {
+    ["wwid", "device/wwid"]
+        .iter()
+        .find_map(|file| read_sys_file(device_name, file).ok())
+        .filter(|s| !s.is_empty())
+}
 
-    if path_str.starts_with("/dev/sd") {
-        if let Some(index) = path_str.rfind(|c: char| c.is_alphabetic()) {
-            return PathBuf::from(&path_str[..=index]);
+/// Returns the `/dev/disk/by-path` name of the disk `device_name`, which
+/// names the port or slot it's plugged into rather than the disk itself.
+pub fn by_path(device_name: &str) -> Option<String> {
+    let device = Path::new("/dev").join(device_name);
+    fs::read_dir("/dev/disk/by-path")
+        .ok()?
+        .flatten()
+        .find(|entry| entry.path().canonicalize().is_ok_and(|p| p == device))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+/// Mount points whose disks are never offered as targets, besides swap.
+#[cfg(not(target_os = "macos"))]
+const SYSTEM_MOUNTS: &[&str] = &["/", "/boot", "/boot/efi", "/efi", "/home"];
+
+/// Adds the whole disks under the block device `dev` (a device number) to
+/// `disks`, as kernel names paired with `what` they hold. Partitions map to
+/// their disk, and device-mapper or md devices (dm-crypt, LVM, RAID) to the
+/// disks beneath them. Returns false if `dev` isn't a known block device.
+#[cfg(not(target_os = "macos"))]
+fn add_backing_disks(dev: u64, what: &str, disks: &mut Vec<(String, String)>) -> bool {
+    let sys = PathBuf::from(format!(
+        "/sys/dev/block/{}:{}",
+        libc::major(dev),
+        libc::minor(dev)
+    ));
+    let Ok(mut dir) = sys.canonicalize() else {
+        return false;
+    };
+    if dir.join("partition").exists() {
+        dir.pop();
+    }
+    let slaves: Vec<PathBuf> = fs::read_dir(dir.join("slaves"))
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    if slaves.is_empty() {
+        let name = dir.file_name().unwrap().to_string_lossy().to_string();
+        if !disks.iter().any(|(disk, _)| *disk == name) {
+            disks.push((name, what.to_string()));
         }
-    } else if path_str.starts_with("/dev/mmcblk") || path_str.starts_with("/dev/nvme") {
-        if let Some(index) = path_str.find('p') {
-            return PathBuf::from(&path_str[..index]);
+        return true;
+    }
+    for slave in slaves {
+        if let Ok(dev) = fs::read_to_string(slave.join("dev"))
+            && let Some((major, minor)) = dev.trim().split_once(':')
+            && let (Ok(major), Ok(minor)) = (major.parse(), minor.parse())
+        {
+            add_backing_disks(libc::makedev(major, minor), what, disks);
         }
     }
+    true
+}
 
-    path.to_path_buf()
+/// Finds the whole disks beneath the block device at `path` (e.g.
+/// /dev/mapper/root), as kernel names.
+#[cfg(not(target_os = "macos"))]
+fn backing_disks(path: &Path) -> Vec<String> {
+    let mut disks = Vec::new();
+    if let Ok(metadata) = fs::metadata(path)
+        && metadata.file_type().is_block_device()
+    {
+        add_backing_disks(metadata.rdev(), "", &mut disks);
+    }
+    disks.into_iter().map(|(disk, _)| disk).collect()
 }
 
-/// Scans for all removable block devices, excluding the main system drive.
-pub fn get_removable_devices() -> Result<Vec<Device>> {
-    // Use `sysinfo` to find the system drive's parent (e.g., /dev/nvme0n1)
-    // so it can be reliably excluded.
-    let disks = sysinfo::Disks::new_with_refreshed_list();
-    let mut system_disk_parent = None;
-    for disk in disks.iter() {
-        if disk.mount_point() == Path::new("/") {
-            // e.g., disk.name() is "nvme0n1p2"
-            let path = PathBuf::from("/dev/").join(disk.name());
-            // system_disk_parent becomes "/dev/nvme0n1"
-            system_disk_parent = Some(get_parent_device_path(&path));
-            break;
+/// Finds the disks holding the running system: those behind `/`, the boot
+/// and EFI partitions, `/home` and swap, as kernel names (e.g. "nvme0n1")
+/// paired with what they hold, and whether the disk behind `/` was among
+/// them. It can't be found on ZFS, in containers or in some live sessions.
+#[cfg(not(target_os = "macos"))]
+fn system_disks() -> Result<(Vec<(String, String)>, bool)> {
+    let mut disks = Vec::new();
+    let mut found_root = false;
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let Some(&mount_point) = fields.get(4) else {
+            continue;
+        };
+        if !SYSTEM_MOUNTS.contains(&mount_point) {
+            continue;
         }
+        // btrfs reports a virtual device number, so prefer the source's
+        let source = fields
+            .iter()
+            .position(|&f| f == "-")
+            .and_then(|i| fields.get(i + 2));
+        let dev = match source.and_then(|s| fs::metadata(s).ok()) {
+            Some(m) if m.file_type().is_block_device() => m.rdev(),
+            _ => {
+                let Some((major, minor)) = fields[2].split_once(':') else {
+                    continue;
+                };
+                let (Ok(major), Ok(minor)) = (major.parse(), minor.parse()) else {
+                    continue;
+                };
+                libc::makedev(major, minor)
+            }
+        };
+        let found = add_backing_disks(dev, mount_point, &mut disks);
+        found_root |= found && mount_point == "/";
+    }
+
+    // Swap files live on whatever filesystem holds them
+    let swaps = fs::read_to_string("/proc/swaps").unwrap_or_default();
+    for line in swaps.lines().skip(1) {
+        let Some(metadata) = line
+            .split_whitespace()
+            .next()
+            .and_then(|p| fs::metadata(p).ok())
+        else {
+            continue;
+        };
+        let dev = if metadata.file_type().is_block_device() {
+            metadata.rdev()
+        } else {
+            metadata.dev()
+        };
+        add_backing_disks(dev, "swap", &mut disks);
+    }
+    Ok((disks, found_root))
+}
+
+/// The bus a disk hangs off, going by its place in sysfs.
+#[derive(PartialEq)]
+#[cfg(not(target_os = "macos"))]
+enum Bus {
+    Usb,
+    /// An SD card reader or eMMC.
+    Mmc,
+    Other,
+}
+
+#[cfg(not(target_os = "macos"))]
+fn bus(device_name: &str) -> Bus {
+    let Ok(path) = PathBuf::from("/sys/block").join(device_name).canonicalize() else {
+        return Bus::Other;
+    };
+    let path = path.to_string_lossy();
+    if path.contains("/usb") {
+        Bus::Usb
+    } else if path.contains("/mmc_host/") {
+        Bus::Mmc
+    } else {
+        Bus::Other
+    }
+}
+
+/// Scans for removable disks with `diskutil`, as the Linux version below
+/// does in `/sys/block`.
+#[cfg(target_os = "macos")]
+pub fn get_removable_devices(include_internal: bool) -> Result<Vec<Device>> {
+    let devices = crate::macos::removable_devices(include_internal)?;
+    events::devices(&devices);
+    Ok(devices)
+}
+
+/// Scans for all removable block devices, excluding the system drives.
+/// With `include_internal`, non-removable disks are listed as well; the
+/// system drives are always excluded. Disks attached over USB are listed
+/// even if not flagged removable. If the disk behind `/` can't be
+/// found, only removable USB and MMC disks are listed, with a warning.
+#[cfg(not(target_os = "macos"))]
+pub fn get_removable_devices(include_internal: bool) -> Result<Vec<Device>> {
+    // Find the disks the running system lives on (e.g., /dev/nvme0n1) so
+    // they can be reliably excluded.
+    let (system_disks, root_known) = system_disks()?;
+    if !root_known {
+        // Without knowing the system drive, only offer what can't be it
+        output::warn(tr(Msg::SystemDriveUnknown));
     }
-    let system_disk_parent = system_disk_parent
-        .ok_or_else(|| anyhow!("Could not determine system drive. Aborting for safety."))?;
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let exclusions = Exclusions::load();
 
     // Iterate over all block devices in /sys/block for reliable detection.
     let mut devices = Vec::new();
@@ -87,8 +489,15 @@ pub fn get_removable_devices() -> Result<Vec<Device>> {
             continue;
         }
 
-        // Filter 2: Skip the system drive's parent (e.g., /dev/nvme0n1)
-        if device_path == system_disk_parent {
+        // Filter 2: Skip the system drives (e.g., /dev/nvme0n1)
+        if let Some((_, what)) = system_disks.iter().find(|(disk, _)| *disk == device_name) {
+            verbose!("Skipping {device_name}: system drive (holds {what})");
+            continue;
+        }
+
+        // Filter 2b: Skip disks the administrator has set aside
+        if let Some(entry) = exclusions.matching(&device_name) {
+            verbose!("Skipping {device_name}: excluded by the administrator ({entry})");
             continue;
         }
 
@@ -100,9 +509,18 @@ pub fn get_removable_devices() -> Result<Vec<Device>> {
             .map(|s| s == "1")
             .unwrap_or(false);
 
-        if !is_removable {
+        // USB bridges to SATA or NVMe disks often don't flag them removable,
+        // but they're exactly what gets imaged, so they're offered anyway.
+        let bus = bus(&device_name);
+        let usb = bus == Bus::Usb;
+        if !is_removable && (!(include_internal || usb) || !root_known) {
+            verbose!("Skipping {device_name}: not removable");
             continue; // Will filter out internal drives like /dev/sda
         }
+        if !root_known && bus == Bus::Other {
+            verbose!("Skipping {device_name}: not on USB or MMC, and the system drive is unknown");
+            continue;
+        }
 
         // Filter 4: Check for 0 size (empty card slots)
         // (e.g., /sys/block/sdb/size == "0")
@@ -114,59 +532,292 @@ pub fn get_removable_devices() -> Result<Vec<Device>> {
             .unwrap_or(0);
 
         if size_sectors == 0 {
+            verbose!("Skipping {device_name}: no medium (size is 0)");
             continue; // Will filter out empty slots like /dev/sdb, /dev/sdc
         }
 
-        let size_gb = (size_sectors * 512) as f64 / (1024.0 * 1024.0 * 1024.0);
+        let size_bytes = size_sectors * 512;
+        let size_gb = size_bytes as f64 / (1024.0 * 1024.0 * 1024.0);
 
         // Filter 5: Try to find a mount point by checking the `sysinfo` list.
-        // `disks` is a list of partitions, so we check if any partition
-        // (e.g., "sdd1") starts with the parent device name (e.g., "sdd").
+        // `disks` is a list of mounted partitions (e.g., "sdd1") or mapped
+        // devices, so we check which disks they live on.
+        // Each filesystem's usage comes from statvfs on its mount point.
         let mut mount_point = "".to_string();
+        let mut usage = Vec::new();
         for disk in disks.iter() {
-            if disk.name().to_string_lossy().starts_with(&device_name) {
+            let path = PathBuf::from("/dev").join(disk.name());
+            if backing_disks(&path).contains(&device_name) {
                 let mp = disk.mount_point().to_string_lossy().to_string();
-                if !mp.is_empty() {
-                    mount_point = mp;
-                    break; // Use the first mount point found
+                if mp.is_empty() {
+                    continue;
+                }
+                if mount_point.is_empty() {
+                    mount_point = mp.clone(); // Use the first mount point found
                 }
+                usage.push(Usage {
+                    source: path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned(),
+                    mount_point: mp,
+                    fs_type: disk.file_system().to_string_lossy().into_owned(),
+                    total: disk.total_space(),
+                    available: disk.available_space(),
+                });
             }
         }
 
+        let card = CardInfo::read(&device_name);
+        let link = Link::read(&device_name);
+        let burns = wear::count(&device_name);
         devices.push(Device {
             path: device_path,
             name: device_name,
             size_gb,
+            size_bytes,
             mount_point,
+            removable: is_removable,
+            usb,
+            card,
+            link,
+            burns,
+            usage,
         });
     }
 
+    events::devices(&devices);
     Ok(devices)
 }
 
-/// Presents an interactive menu for the user to select a device.
-pub fn select_device(devices: &[Device], prompt: &str) -> Result<Device> {
-    if devices.is_empty() {
-        return Err(anyhow!("No removable devices found."));
+/// Presents an interactive menu for the user to select a device. A last
+/// entry rescans for devices (listing `include_internal` disks as
+/// [`get_removable_devices`] does), which is also all there is to choose
+/// until one is plugged in.
+pub fn select_device(devices: &[Device], include_internal: bool, prompt: &str) -> Result<Device> {
+    let mut devices = devices.to_vec();
+    loop {
+        if !io::stdin().is_terminal() {
+            // Nothing to choose with; scripts have to name the device
+            return Err(anyhow!(if devices.is_empty() {
+                tr(Msg::NoRemovableDevices)
+            } else {
+                tr(Msg::NameDeviceWithoutTerminal)
+            }));
+        }
+
+        // The last entry re-enumerates devices, e.g. after plugging a card in
+        let mut items: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+        items.push(tr(Msg::RescanDevices).to_string());
+        let prompt = if devices.is_empty() {
+            tr(Msg::PlugInAndRescan)
+        } else {
+            prompt
+        };
+
+        let selection = menu::select(prompt, &items)?;
+        if let Some(device) = devices.get(selection) {
+            return Ok(device.clone());
+        }
+        devices = get_removable_devices(include_internal)?;
     }
+}
+
+/// Picks the device given as `name` (e.g. `sdb` or `/dev/sdb`) from the
+/// ones offered for selection, so `--device` can't reach any other disk.
+pub fn find_device(devices: &[Device], name: &str) -> Result<Device> {
+    devices
+        .iter()
+        .find(|d| {
+            d.name == name
+                || d.path == Path::new(name)
+                // e.g. /dev/disk/by-id/usb-...
+                || Path::new(name).canonicalize().is_ok_and(|p| p == d.path)
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!(trf(Msg::DeviceNotOffered, &[&name])))
+}
+
+/// Prints the size, age, hash and catalog match of the image about to be written, and
+/// (if `interactive`) asks for an extra acknowledgement if it looks
+/// implausibly small. The image is only hashed if there's a catalog to look
+/// it up in; otherwise a hash is shown only if it was already computed.
+/// Returns `false` if the user backs out.
+fn review_image(
+    device: &Device,
+    image: &Path,
+    interactive: bool,
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let metadata = fs::metadata(image)?;
+    // Hashing reads all of it, which for a large image (or a device, with
+    // --from-device) takes a while; a device is never in the catalog
+    let catalog = catalog::load();
+    let hash = if catalog.is_empty() || is_block_device(image) {
+        write::cached_hash(image)
+    } else {
+        Some(write::image_hash(image, cancel)?)
+    };
 
-    let items: Vec<String> = devices.iter().map(|d| d.to_string()).collect();
+    let size = match write::uncompressed_size(image) {
+        Some(size) => {
+            println!(
+                "{}{} / {} {}",
+                tr(Msg::SizeLabel),
+                HumanBytes(size),
+                HumanBytes(device.size_bytes),
+                trf(
+                    Msg::OfDevice,
+                    &[&format!(
+                        "{:.1}",
+                        size as f64 * 100.0 / device.size_bytes as f64
+                    )]
+                )
+            );
+            Some(size)
+        }
+        None => {
+            println!(
+                "{}{}",
+                tr(Msg::SizeLabel),
+                trf(Msg::CompressedSize, &[&HumanBytes(metadata.len())])
+            );
+            None
+        }
+    };
+    if let Ok(modified) = metadata.modified() {
+        println!("{}{}", tr(Msg::ModifiedLabel), format_age(modified));
+    }
+    if let Some(hash) = &hash {
+        println!("{}{}", tr(Msg::HashLabel), style(&hash[..12]).dim());
+    }
 
-    let selection = Select::with_theme(&ColorfulTheme::default())
-        .with_prompt(prompt)
-        .items(&items)
-        .default(0)
-        .interact()?;
+    // Only flag unknown images once the user has a catalog to compare with
+    if let Some(hash) = &hash
+        && !catalog.is_empty()
+    {
+        match catalog::lookup(&catalog, hash) {
+            Some(entry) => println!(
+                "{}{}",
+                tr(Msg::CatalogLabel),
+                style(trf(Msg::RecognizedImage, &[&entry.label()])).green()
+            ),
+            None => println!(
+                "{}{}",
+                tr(Msg::CatalogLabel),
+                style(tr(Msg::UnknownImage)).yellow()
+            ),
+        }
+    }
 
-    Ok(devices[selection].clone())
+    // A compressed image whose format doesn't record its size decompresses
+    // to an unknown multiple of the file, so only the absolute minimum
+    // applies to it, not the ratio to the device
+    let suspicious = match size {
+        Some(size) => {
+            size < MIN_PLAUSIBLE_IMAGE_SIZE
+                || size.saturating_mul(MAX_PLAUSIBLE_SIZE_RATIO) < device.size_bytes
+        }
+        None => metadata.len() < MIN_PLAUSIBLE_IMAGE_SIZE,
+    };
+    if !suspicious {
+        return Ok(true);
+    }
+    let size = size.unwrap_or(metadata.len());
+
+    println!();
+    println!(
+        "{} {}",
+        style(tr(Msg::Warning)).yellow().bold(),
+        trf(Msg::SuspiciousImage, &[&HumanBytes(size)])
+    );
+    if !interactive {
+        return Ok(true);
+    }
+    prompt::confirm(tr(Msg::AcknowledgeSmallImage), false)
+}
+
+/// How the final confirmation is obtained.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmMode {
+    /// Ask a yes/no question.
+    Prompt,
+    /// Require the user to type the device name.
+    Typed,
+    /// Don't ask (`--yes`).
+    AssumeYes,
 }
 
 /// Presents a final "Yes/No" confirmation to the user.
-pub fn confirm_operation(prompt: &str, _device: &Device, _image: &Path) -> Result<bool> {
-    let confirmation = Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(prompt)
-        .default(false)
-        .interact()?;
+/// When writing, `image` is the source and its details are reviewed first,
+/// against the smallest of the target `devices`; hashing it stops early
+/// if `cancel` is cancelled.
+pub fn confirm_operation(
+    prompt: &str,
+    devices: &[Device],
+    image: Option<&Path>,
+    mode: ConfirmMode,
+    cancel: &CancelToken,
+) -> Result<bool> {
+    let device = devices
+        .iter()
+        .min_by_key(|d| d.size_bytes)
+        .ok_or_else(|| anyhow!(tr(Msg::NoRemovableDevices)))?;
+    if mode == ConfirmMode::AssumeYes {
+        if let Some(image) = image
+            && !output::is_quiet()
+        {
+            review_image(device, image, false, cancel)?;
+        }
+        info!();
+        return Ok(true);
+    }
+
+    if let Some(image) = image
+        && !review_image(device, image, true, cancel)?
+    {
+        return Ok(false);
+    }
+    println!();
 
-    Ok(confirmation)
+    if mode == ConfirmMode::Typed {
+        // Every device of a queue has to be named
+        let names: Vec<&str> = devices.iter().map(|d| d.name.as_str()).collect();
+        let names = names.join(" ");
+        let answer = prompt::input(&trf(Msg::TypeToConfirm, &[&names]))?;
+        return Ok(answer.split_whitespace().collect::<Vec<_>>().join(" ") == names);
+    }
+
+    prompt::confirm(prompt, false)
+}
+
+/// Counts down `seconds` before `devices` are written, e.g. "Writing to
+/// /dev/sdd in 5…4…3…", as a last chance to stop with Ctrl+C after
+/// choosing the wrong device in a hurry. Returns false if stopped. The
+/// count is written on one line without moving the cursor, so it works on
+/// serial consoles too.
+pub fn countdown(devices: &[Device], seconds: u32, cancel: &CancelToken) -> bool {
+    let paths: Vec<String> = devices
+        .iter()
+        .map(|d| d.path.display().to_string())
+        .collect();
+    eprintln!("{}", tr(Msg::StopWithCtrlC));
+    eprint!(
+        "{}",
+        trf(Msg::Countdown, &[&style(paths.join(", ")).cyan()])
+    );
+    for left in (1..=seconds).rev() {
+        eprint!("{left}…");
+        io::stderr().flush().ok();
+        for _ in 0..10 {
+            if cancel.is_cancelled() {
+                eprintln!();
+                return false;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+    eprintln!();
+    !cancel.is_cancelled()
 }
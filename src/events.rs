@@ -0,0 +1,229 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::device::Device;
+use crate::json::quote;
+
+/// Where progress and result records go, with `--progress-fd` or `--json`.
+static EVENTS: Mutex<Option<File>> = Mutex::new(None);
+
+/// Whether records are JSON objects (`--json`) rather than `key=value` lines.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// The phase of the last progress record, to announce the next one.
+static PHASE: Mutex<String> = Mutex::new(String::new());
+
+/// Who is told of events by a program using etchr as a library.
+static OBSERVER: Mutex<Option<Arc<dyn Observer>>> = Mutex::new(None);
+
+/// How a job is getting on, as told to an [`Observer`]. These are the
+/// records `--progress-fd` sends, for each device of a parallel write.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// An operation (`write`, `verify`, `read` or `clone`) started on
+    /// `device`.
+    Started { operation: &'a str, device: &'a str },
+    /// A job writing devices one after another moved on to `device`.
+    Device { device: &'a str },
+    /// `done` of `total` bytes (0 if unknown) of `device` are through
+    /// `phase`, going at `rate` bytes per second. Sent about twice a
+    /// second.
+    Progress {
+        device: &'a str,
+        phase: &'a str,
+        done: u64,
+        total: u64,
+        rate: u64,
+    },
+    /// Something the job carried on despite, e.g. an unreadable sector
+    /// filled with zeros, which the command warns of.
+    Warning { message: &'a str },
+}
+
+/// Follows the jobs of a program using etchr as a library, in place of the
+/// progress bars the command draws. Events come from the threads doing the
+/// work, from several at once when devices are written in parallel.
+pub trait Observer: Send + Sync {
+    fn event(&self, event: &Event<'_>);
+}
+
+/// Tells `observer` of every event from now on, or no one with `None`.
+pub fn observe(observer: Option<Arc<dyn Observer>>) {
+    *OBSERVER.lock().unwrap() = observer;
+}
+
+/// Tells the observer, if there is one, of `event`.
+pub fn notify(event: Event<'_>) {
+    // Not held while the observer runs, so it may take its time
+    let observer = OBSERVER.lock().unwrap().clone();
+    if let Some(observer) = observer {
+        observer.event(&event);
+    }
+}
+
+/// Sends records to the file descriptor `fd`, inherited from the parent
+/// process, for the rest of the run. It's closed on exec, so commands
+/// etchr runs don't inherit it.
+pub fn open(fd: RawFd) -> Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 {
+        return Err(anyhow!("--progress-fd {fd} is not an open file descriptor"));
+    }
+    unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) };
+    *EVENTS.lock().unwrap() = Some(unsafe { File::from_raw_fd(fd) });
+    Ok(())
+}
+
+/// Sends records to stdout as JSON objects, one per line, for the rest of
+/// the run. Everything else etchr prints to stdout goes to stderr instead,
+/// so stdout carries nothing but records.
+pub fn open_json() -> Result<()> {
+    let fd = unsafe { libc::dup(libc::STDOUT_FILENO) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error()).context("duplicating stdout for --json");
+    }
+    if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+        return Err(io::Error::last_os_error()).context("redirecting stdout for --json");
+    }
+    JSON.store(true, Ordering::SeqCst);
+    open(fd)
+}
+
+/// Writes one record, given as a record type and its fields, each either
+/// plain text or a number. Failures are ignored; the reader may have gone
+/// away.
+fn emit(kind: &str, fields: &[(&str, Field)]) {
+    let mut events = EVENTS.lock().unwrap();
+    let Some(file) = events.as_mut() else {
+        return;
+    };
+    let record = if JSON.load(Ordering::SeqCst) {
+        let fields: String = fields
+            .iter()
+            .map(|(key, value)| format!(",{}:{}", quote(key), value.json()))
+            .collect();
+        format!("{{\"event\":{}{fields}}}", quote(kind))
+    } else {
+        let fields: String = fields
+            .iter()
+            .map(|(key, value)| format!(" {key}={}", value.text()))
+            .collect();
+        format!("{kind}{fields}")
+    };
+    writeln!(file, "{record}").ok();
+}
+
+/// The value of a record's field.
+enum Field<'a> {
+    Text(&'a str),
+    Number(u64),
+    /// Already formatted as JSON, for fields only JSON records carry.
+    Json(String),
+}
+
+impl Field<'_> {
+    fn json(&self) -> String {
+        match self {
+            Field::Text(text) => quote(text),
+            Field::Number(n) => n.to_string(),
+            Field::Json(json) => json.clone(),
+        }
+    }
+
+    /// Keeps a value on its record's line.
+    fn text(&self) -> String {
+        match self {
+            Field::Text(text) => text.replace(['\n', '\r'], " "),
+            Field::Number(n) => n.to_string(),
+            Field::Json(json) => json.clone(),
+        }
+    }
+}
+
+/// The devices that were found to choose from. Only sent as JSON.
+pub fn devices(devices: &[Device]) {
+    if !JSON.load(Ordering::SeqCst) {
+        return;
+    }
+    let list: Vec<String> = devices
+        .iter()
+        .map(|d| {
+            format!(
+                "{{\"path\":{},\"name\":{},\"size\":{},\"removable\":{},\"usb\":{},\"mount_point\":{}}}",
+                quote(&d.path.display().to_string()),
+                quote(&d.name),
+                d.size_bytes,
+                d.removable,
+                d.usb,
+                quote(&d.mount_point)
+            )
+        })
+        .collect();
+    emit(
+        "devices",
+        &[("devices", Field::Json(format!("[{}]", list.join(","))))],
+    );
+}
+
+/// An operation (`op`, e.g. `write`) started on `device`.
+pub fn start(op: &str, device: &str) {
+    notify(Event::Started {
+        operation: op,
+        device,
+    });
+    emit(
+        "start",
+        &[("op", Field::Text(op)), ("device", Field::Text(device))],
+    );
+}
+
+/// A queued operation moved on to the next `device`.
+pub fn device(device: &str) {
+    notify(Event::Device { device });
+    emit("device", &[("device", Field::Text(device))]);
+}
+
+/// `done` of `total` bytes (0 if unknown) are through `phase`, going at
+/// `rate` bytes per second. JSON records announce each new phase first,
+/// and give the percentage done if the total is known.
+pub fn progress(phase: &str, done: u64, total: u64, rate: u64) {
+    let json = JSON.load(Ordering::SeqCst);
+    if json {
+        let mut last = PHASE.lock().unwrap();
+        if *last != phase {
+            *last = phase.to_string();
+            drop(last);
+            emit("phase", &[("phase", Field::Text(phase))]);
+        }
+    }
+    let mut fields = vec![
+        ("phase", Field::Text(phase)),
+        ("done", Field::Number(done)),
+        ("total", Field::Number(total)),
+        ("rate", Field::Number(rate)),
+    ];
+    if json && total > 0 {
+        let percent = done as f64 * 100.0 / total as f64;
+        fields.push(("percent", Field::Json(format!("{percent:.1}"))));
+    }
+    emit("progress", &fields);
+}
+
+/// The run ended, successfully or with an error.
+pub fn result(result: &Result<()>) {
+    match result {
+        Ok(()) => emit("result", &[("status", Field::Text("ok"))]),
+        Err(e) => emit(
+            "result",
+            &[
+                ("status", Field::Text("error")),
+                ("message", Field::Text(&format!("{e:#}"))),
+            ],
+        ),
+    }
+}
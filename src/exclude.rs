@@ -0,0 +1,98 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::device;
+
+/// Disks that are never offered, whatever the safety profile, one serial
+/// number, world wide name or `/dev/disk/by-path` port per line. Kept by
+/// an administrator, e.g. with `etchr admin exclude`.
+const EXCLUDED_DEVICES_PATH: &str = "/etc/etchr/excluded-devices";
+
+/// The entries of the exclusion file, without comments.
+pub struct Exclusions {
+    entries: Vec<String>,
+}
+
+impl Exclusions {
+    /// Reads the exclusion file. A missing file excludes nothing.
+    pub fn load() -> Self {
+        let contents = fs::read_to_string(EXCLUDED_DEVICES_PATH).unwrap_or_default();
+        Exclusions {
+            entries: contents
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// Returns the entry excluding the disk `device_name`, if any.
+    pub fn matching(&self, device_name: &str) -> Option<&str> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let ids = identifiers(device_name);
+        self.entries
+            .iter()
+            .find(|entry| ids.iter().any(|id| id.eq_ignore_ascii_case(entry)))
+            .map(String::as_str)
+    }
+}
+
+/// Everything the disk `device_name` can be recognized by: its serial
+/// number, world wide name and port.
+pub fn identifiers(device_name: &str) -> Vec<String> {
+    [
+        device::serial(device_name),
+        device::wwid(device_name),
+        device::by_path(device_name),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Adds the disk `name` (e.g. `sda` or `/dev/sda`) to the exclusion file,
+/// by its serial number, or else its world wide name, or else its port.
+/// Returns the entry added.
+pub fn add(name: &str) -> Result<String> {
+    let path = if name.starts_with('/') {
+        PathBuf::from(name)
+    } else {
+        Path::new("/dev").join(name)
+    };
+    if !device::is_block_device(&path) {
+        return Err(anyhow!("{} is not a block device", path.display()));
+    }
+    let device_name = path
+        .canonicalize()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("invalid device path {}", path.display()))?;
+    let entry = identifiers(&device_name)
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            anyhow!("{device_name} has no serial number, WWN or port to exclude it by")
+        })?;
+
+    if Exclusions::load().matching(&device_name).is_some() {
+        return Ok(entry);
+    }
+    let file = Path::new(EXCLUDED_DEVICES_PATH);
+    let context = || format!("adding {device_name} to {}", file.display());
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).with_context(context)?;
+    }
+    let mut excluded = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .with_context(context)?;
+    writeln!(excluded, "{entry}  # {device_name}").with_context(context)?;
+    Ok(entry)
+}
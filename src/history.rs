@@ -0,0 +1,125 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::privileges;
+
+/// Maximum number of entries kept in the history file.
+const MAX_ENTRIES: usize = 100;
+
+/// Returns the directory used for persistent local state,
+/// e.g. `~/.local/state/etchr`. Run through sudo, it's the invoking user's,
+/// not root's. It's worked out on the first call, which `main` makes before
+/// anything is opened, so that the state read before privileges are dropped
+/// is the state written after.
+pub fn state_dir() -> Option<PathBuf> {
+    static STATE_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    STATE_DIR
+        .get_or_init(|| {
+            let home = privileges::invoking_user()
+                .and_then(|user| user.home())
+                .or_else(|| std::env::var_os("HOME").map(PathBuf::from));
+            let base = std::env::var_os("XDG_STATE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| home.map(|h| h.join(".local/state")))?;
+            Some(base.join("etchr"))
+        })
+        .clone()
+}
+
+/// Replaces the state file `path` with `contents`. Still root on a user's
+/// behalf, it's written as the user, so that the files in their home stay
+/// theirs and a link they left there can't point root elsewhere.
+pub fn save(path: &Path, contents: &str) -> io::Result<()> {
+    privileges::as_invoking_user(|| {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, contents)
+    })
+}
+
+/// Appends `line` to the state file `path`, as the user like [`save`].
+pub fn append(path: &Path, line: &str) -> io::Result<()> {
+    privileges::as_invoking_user(|| {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    })
+}
+
+fn history_path() -> Option<PathBuf> {
+    state_dir().map(|d| d.join("history"))
+}
+
+/// A previously flashed image.
+pub struct Entry {
+    pub image: PathBuf,
+    pub used: SystemTime,
+}
+
+/// Reads all history entries, oldest first. Malformed lines are skipped.
+fn read_entries() -> Vec<Entry> {
+    let Some(contents) = history_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (ts, image) = line.split_once('\t')?;
+            let secs: u64 = ts.parse().ok()?;
+            Some(Entry {
+                image: PathBuf::from(image),
+                used: UNIX_EPOCH + Duration::from_secs(secs),
+            })
+        })
+        .collect()
+}
+
+/// Appends `image` to the history of flashed images.
+pub fn record_write(image: &Path) -> io::Result<()> {
+    let Some(path) = history_path() else {
+        return Ok(());
+    };
+    let image = image.canonicalize()?;
+
+    let mut entries = read_entries();
+    entries.push(Entry {
+        image,
+        used: SystemTime::now(),
+    });
+    let skip = entries.len().saturating_sub(MAX_ENTRIES);
+
+    let mut contents = String::new();
+    for entry in &entries[skip..] {
+        let secs = entry
+            .used
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        contents.push_str(&format!("{secs}\t{}\n", entry.image.display()));
+    }
+    save(&path, &contents)
+}
+
+/// Returns up to `limit` distinct, still-existing images, most recent first.
+pub fn recent_images(limit: usize) -> Vec<Entry> {
+    let mut recent: Vec<Entry> = Vec::new();
+    for entry in read_entries().into_iter().rev() {
+        if recent.len() == limit {
+            break;
+        }
+        if entry.image.exists() && !recent.iter().any(|e| e.image == entry.image) {
+            recent.push(entry);
+        }
+    }
+    recent
+}
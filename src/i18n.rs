@@ -0,0 +1,1049 @@
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+
+/// Languages available for user-facing messages.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Lang {
+    /// English
+    En,
+    /// German
+    De,
+}
+
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Picks the language from the environment, following the usual
+/// LC_ALL > LC_MESSAGES > LANG precedence. Unknown locales fall back to English.
+fn lang_from_env() -> Lang {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|v| !v.is_empty())
+        .unwrap_or_default();
+
+    if locale.starts_with("de") {
+        Lang::De
+    } else {
+        Lang::En
+    }
+}
+
+/// Sets the language for the rest of the run. Without an explicit
+/// choice, the language is taken from the environment.
+pub fn init(lang: Option<Lang>) {
+    LANG.set(lang.unwrap_or_else(lang_from_env)).ok();
+}
+
+fn current() -> Lang {
+    *LANG.get_or_init(lang_from_env)
+}
+
+/// User-facing messages. Placeholders are written as `{0}`, `{1}`, ...
+/// and filled in by [`trf`].
+#[derive(Clone, Copy)]
+pub enum Msg {
+    SelectWriteTarget,
+    SelectReadSource,
+    SelectVerifyTarget,
+    SelectCloneSource,
+    SelectCloneTarget,
+    Warning,
+    EraseWarning,
+    ReadSummary,
+    DeviceLabel,
+    ImageLabel,
+    OutputLabel,
+    EstimatedTime,
+    Unavailable,
+    ConfirmProceed,
+    WriteCancelled,
+    ReadCancelled,
+    FlashSuccess,
+    ReadSuccess,
+    SystemDriveUnknown,
+    NoRemovableDevices,
+    NameDeviceWithoutTerminal,
+    RescanDevices,
+    PlugInAndRescan,
+    FoundDevices,
+    SelectImage,
+    NoImagesFound,
+    SelectRecentImage,
+    BrowseForImage,
+    Paused,
+    TypeToConfirm,
+    OverrideForbidden,
+    InternalDisk,
+    UsbDisk,
+    SizeLabel,
+    OfDevice,
+    CompressedSize,
+    ModifiedLabel,
+    HashLabel,
+    SuspiciousImage,
+    AcknowledgeSmallImage,
+    ImageIsBlockDevice,
+    SourceIsTarget,
+    LandlockUnavailable,
+    CatalogLabel,
+    RecognizedImage,
+    UnknownImage,
+    PolicyNeedsImageFile,
+    PolicyNoSignature,
+    PolicyBadSignature,
+    PolicyNotInCatalog,
+    CardLabel,
+    CardDetails,
+    UnknownManufacturer,
+    LinkLabel,
+    UsbLink,
+    MmcLink,
+    UsbBehindSlowHub,
+    UsbSlowPort,
+    UhsNotUsed,
+    DeviceNotOffered,
+    DuplicateDevice,
+    BurnCount,
+    WornCard,
+    Countdown,
+    StopWithCtrlC,
+    FilesystemUsage,
+    ProfileLabel,
+    CanBeUnplugged,
+    EjectSandboxed,
+    CancelledByUser,
+    CleaningUp,
+    PhaseCancelled,
+    PhaseHashing,
+    PhaseDecompression,
+    PhaseWrite,
+    PhaseVerification,
+    PhaseRewrite,
+    PhaseRead,
+    PhaseDownload,
+    PhaseCopy,
+    PhaseComparison,
+    PhaseGenerating,
+    BarHashing,
+    BarDecompress,
+    BarWriting,
+    BarVerifying,
+    BarRewriting,
+    BarReading,
+    DecompressionComplete,
+    WriteComplete,
+    RewriteComplete,
+    ReadComplete,
+    VerificationSuccessful,
+    VerificationFailed,
+    VerificationFailedWith,
+    ConfirmRewrite,
+    OpeningFile,
+    WritingFromArchive,
+    TempSpaceExceeded,
+    StreamingPastTempSpace,
+    ArchiveTruncated,
+    PartitionNeedsTempFile,
+    SeveralDevicesNeedTempFile,
+    DecompressingBeforeWrite,
+    WritingPartition,
+    WritingToDevicesAtOnce,
+    WritingToDevice,
+    WritingDeviceOf,
+    ChecksumMismatch,
+    ChecksumMatches,
+    SavedReport,
+    WriterPanicked,
+    PartitionOfDevice,
+    ImageCutOffBy,
+    ImageCutOff,
+    ImageDoesNotFit,
+    ImageOverflowed,
+    PartiallyWritten,
+    ImageEndedEarly,
+    NotHexBytes,
+    NotHex,
+    NoPartialRead,
+    PartialStateInvalid,
+    CompressingWith,
+    NoCompressionKeepsUp,
+    ReadingDevice,
+    DeviceSizeZero,
+    TrainingDictionary,
+    TrainedDictionary,
+    CodecMismatch,
+    PartialFromOtherDevice,
+    ResumingAt,
+    KeptPartial,
+    UnreadableSector,
+    ReadCompleteTo,
+    CompressedTo,
+    SparseHoles,
+    UnreadableSectors,
+    MappedIn,
+    ListedAbove,
+    BarMeasuring,
+    PhaseMeasuring,
+    ImageTooLarge,
+    ImageWillBeCutOff,
+    ImageSizeUnchecked,
+    NoWrites,
+    WritesPerDay,
+    WritesOk,
+    WritesFailed,
+    WritesSummary,
+    AverageThroughput,
+    WrittenImages,
+    SharedForced,
+    SharedRefused,
+    ExportedOverNfs,
+    SharedOverSamba,
+    AttachedToVm,
+    OverwritePartitions,
+    OverwritePartitionsAndTable,
+    CopyDirection,
+    OppositeDirection,
+    CopiedPartitions,
+    SourceSide,
+    TargetSide,
+    SideDevice,
+    SideSize,
+    SideModel,
+    SideSerial,
+    MountedAt,
+    NotMountedTag,
+    NotMounted,
+    NotifyFinished,
+    NotifyFailed,
+    CustomizeSandboxed,
+    #[cfg(not(feature = "torrent"))]
+    TorrentNotBuilt,
+    DevicesFailed,
+    SignSandboxed,
+    SignNeedsKey,
+    ResumeReadQuestion,
+    ResumeReadHint,
+    SignedImage,
+    Unlocked,
+    UnlockNotRequired,
+    ImportedImages,
+    TestImageNeedsCodec,
+    GeneratedImage,
+    ExcludedDevice,
+    ReadingDictionary,
+    BarDownload,
+    PhaseComplete,
+    PhaseFailed,
+    SpoolFull,
+    SpoolingFailed,
+    OfImage,
+    NotProbedMounted,
+    NotProbedInUse,
+    ProbeRegionTooSmall,
+    UnknownUntilDecompressed,
+    NotAskableJson,
+    NoAnswer,
+    UploadSandboxed,
+    ConnectingTo,
+    RunningCurlTo,
+    ActionFailed,
+    RunningProgram,
+    UploadingTo,
+    CompletingS3Upload,
+    StartingS3Upload,
+    S3NoUploadId,
+    StoringS3Part,
+    S3NoEtag,
+    S3UploadPanicked,
+    NotS3Location,
+    S3NeedsCredentials,
+    DownloadingFailed,
+    NotSshLocation,
+    SpoolPanicked,
+    RunningEject,
+    InvalidDevicePath,
+    SeccompUnavailable,
+    NoDevicesToWrite,
+}
+
+impl Msg {
+    fn en(self) -> &'static str {
+        match self {
+            Msg::SelectWriteTarget => "Select the target device to WRITE to",
+            Msg::SelectReadSource => "Select the source device to READ from",
+            Msg::SelectVerifyTarget => "Select the device to compare with the baseline",
+            Msg::SelectCloneSource => "Step 1 of 2: select the SOURCE, the device to copy FROM",
+            Msg::SelectCloneTarget => {
+                "Step 2 of 2: select the TARGET, the device to copy TO (overwritten)"
+            }
+            Msg::Warning => "WARNING:",
+            Msg::EraseWarning => "This will erase all data on '{0}' ({1} GB).",
+            Msg::ReadSummary => "This will read {0} GB from '{1}'.",
+            Msg::DeviceLabel => "  Device: ",
+            Msg::ImageLabel => "  Image:  ",
+            Msg::OutputLabel => "  Output: ",
+            Msg::EstimatedTime => "  Estimated time: ",
+            Msg::Unavailable => "unavailable ({0})",
+            Msg::ConfirmProceed => "Are you sure you want to proceed?",
+            Msg::WriteCancelled => "Write operation cancelled.",
+            Msg::ReadCancelled => "Read operation cancelled.",
+            Msg::FlashSuccess => "✨ Successfully flashed {0} with {1}.",
+            Msg::ReadSuccess => "✨ Successfully read {0} to {1}.",
+            Msg::SystemDriveUnknown => {
+                "Could not determine the system drive; only removable USB and SD card devices are offered."
+            }
+            Msg::NoRemovableDevices => "No removable devices found.",
+            Msg::NameDeviceWithoutTerminal => {
+                "No terminal to choose a device on; name it with --device."
+            }
+            Msg::RescanDevices => "↻ Rescan devices",
+            Msg::PlugInAndRescan => "No removable devices found; plug one in, then rescan",
+            Msg::FoundDevices => "Found {0} removable devices:",
+            Msg::SelectImage => "Select an image to write ({0})",
+            Msg::NoImagesFound => "No images found in {0}",
+            Msg::SelectRecentImage => "Select a recently used image",
+            Msg::BrowseForImage => "Browse for another image...",
+            Msg::Paused => "⏸  Paused. Press 'p' or send SIGUSR1 to resume.",
+            Msg::TypeToConfirm => "Type '{0}' to confirm",
+            Msg::OverrideForbidden => "{0} is not allowed by the paranoid safety profile.",
+            Msg::InternalDisk => "[internal]",
+            Msg::UsbDisk => "[USB disk]",
+            Msg::SizeLabel => "  Size:   ",
+            Msg::OfDevice => "({0}% of device)",
+            Msg::CompressedSize => "{0} compressed (decompressed size unknown)",
+            Msg::ModifiedLabel => "  Age:    ",
+            Msg::HashLabel => "  Hash:   ",
+            Msg::CatalogLabel => "  Known:  ",
+            Msg::RecognizedImage => "recognized: {0} (verified)",
+            Msg::UnknownImage => "unknown image, not in the local catalog",
+            Msg::PolicyNeedsImageFile => {
+                "Refusing to write from '{0}': the policy only allows checked image files, not stdin, URLs or devices."
+            }
+            Msg::PolicyNoSignature => {
+                "Refusing to write '{0}': the policy requires a signature, but there is no '{0}.asc' or '{0}.sig'."
+            }
+            Msg::PolicyBadSignature => {
+                "Refusing to write '{0}': the policy requires a valid signature."
+            }
+            Msg::PolicyNotInCatalog => {
+                "Refusing to write '{0}': the policy only allows images from the catalog, and this one (sha256 {1}) is not in it."
+            }
+            Msg::SuspiciousImage => "The image is only {0}, much smaller than the device.",
+            Msg::AcknowledgeSmallImage => "Is this really the image you want to write?",
+            Msg::ImageIsBlockDevice => {
+                "'{0}' is a block device, not an image file. Did you swap the arguments? \
+                 The target device is chosen interactively; pass --from-device to copy from a device."
+            }
+            Msg::SourceIsTarget => "The source and target are the same device.",
+            Msg::LandlockUnavailable => {
+                "Landlock is not supported by this kernel; filesystem access is not restricted."
+            }
+            Msg::CardLabel => "  Card:   ",
+            Msg::CardDetails => "{1}, {0} (OEM {2}), serial {3}, made {4}",
+            Msg::UnknownManufacturer => "unknown manufacturer {0}",
+            Msg::LinkLabel => "  Link:   ",
+            Msg::UsbLink => "USB at {0}",
+            Msg::MmcLink => "card slot",
+            Msg::UsbBehindSlowHub => {
+                "{0} is a USB {1} device, but runs at {2} behind a USB 2.0 hub; \
+                 plug it in directly or into a USB 3 hub to write faster."
+            }
+            Msg::UsbSlowPort => {
+                "{0} is a USB {1} device, but runs at {2}; \
+                 a USB 3 port and cable would write faster."
+            }
+            Msg::UhsNotUsed => {
+                "{0} is a UHS card, but the slot runs it in {1} mode, well below its speed."
+            }
+            Msg::DeviceNotOffered => {
+                "'{0}' is not one of the devices etchr may write to; see 'etchr list'."
+            }
+            Msg::DuplicateDevice => "'{0}' is given more than once.",
+            Msg::BurnCount => "[written {0}×]",
+            Msg::WornCard => {
+                "{0} has been written {1} times, more than the {2} set by wear_warning; \
+                 it may be wearing out."
+            }
+            Msg::Countdown => "Writing to {0} in ",
+            Msg::StopWithCtrlC => "Press Ctrl+C to stop.",
+            Msg::FilesystemUsage => "{0} at {1} ({2}): {3} used of {4}, {5} free",
+            Msg::ProfileLabel => "  Profile: ",
+            Msg::CanBeUnplugged => "⏏️  {0} can be unplugged",
+            Msg::EjectSandboxed => {
+                "Not ejecting {0}: --sandbox stops etchr from running eject. Eject it before unplugging it."
+            }
+            Msg::CancelledByUser => "Operation cancelled by user",
+            Msg::CleaningUp => "Received exit signal... cleaning up.",
+            Msg::PhaseCancelled => "❌ {0} cancelled.",
+            Msg::PhaseHashing => "Hashing",
+            Msg::PhaseDecompression => "Decompression",
+            Msg::PhaseWrite => "Write",
+            Msg::PhaseVerification => "Verification",
+            Msg::PhaseRewrite => "Rewrite",
+            Msg::PhaseRead => "Read",
+            Msg::PhaseDownload => "Download",
+            Msg::PhaseCopy => "Copy",
+            Msg::PhaseComparison => "Comparison",
+            Msg::PhaseGenerating => "Generating",
+            Msg::BarHashing => "Hashing",
+            Msg::BarDecompress => "Decompress",
+            Msg::BarWriting => "Writing",
+            Msg::BarVerifying => "Verifying",
+            Msg::BarRewriting => "Rewriting",
+            Msg::BarReading => "Reading",
+            Msg::DecompressionComplete => "✅ Decompression complete.",
+            Msg::WriteComplete => "✅ Write complete.",
+            Msg::RewriteComplete => "✅ Rewrite complete.",
+            Msg::ReadComplete => "✅ Read complete.",
+            Msg::VerificationSuccessful => "✅ Verification successful.",
+            Msg::VerificationFailed => "❌ Verification failed.",
+            Msg::VerificationFailedWith => "❌ Verification failed: {0}.",
+            Msg::ConfirmRewrite => {
+                "Re-write only these chunks from the image and verify them again?"
+            }
+            Msg::OpeningFile => "opening {0}",
+            Msg::WritingFromArchive => "Writing {0} from the archive",
+            Msg::TempSpaceExceeded => {
+                "the image decompresses to more than the {0} of temp space available; write it with --stream instead"
+            }
+            Msg::StreamingPastTempSpace => {
+                "The image decompresses to more than the {0} of temp space available, so it's written as it's decompressed"
+            }
+            Msg::ArchiveTruncated => {
+                "the source archive is truncated: it decompressed to {0}, but records {1}"
+            }
+            Msg::PartitionNeedsTempFile => {
+                "--partition needs the image decompressed to a temp file, which it doesn't fit in"
+            }
+            Msg::SeveralDevicesNeedTempFile => {
+                "writing several devices needs the image decompressed to a temp file, which it doesn't fit in"
+            }
+            Msg::DecompressingBeforeWrite => {
+                "decompressing the image, before any device was written"
+            }
+            Msg::WritingPartition => "Writing partition {0} of the image ({1} at offset {2})",
+            Msg::WritingToDevicesAtOnce => "Writing image \"{0}\" to {1} devices at once: {2}",
+            Msg::WritingToDevice => "{0}Writing image \"{1}\" to device \"{2}\"",
+            Msg::WritingDeviceOf => "writing {0} ({1} of {2} done)",
+            Msg::ChecksumMismatch => {
+                "the downloaded image's SHA-256 is {0}, but {1} gives {2}; what was written is not the published image"
+            }
+            Msg::ChecksumMatches => "✅ The downloaded image matches its published SHA-256 ({0})",
+            Msg::SavedReport => "Saved the report to {0}",
+            Msg::WriterPanicked => "the thread writing {0} panicked",
+            Msg::PartitionOfDevice => "partition {0} of {1}",
+            Msg::ImageCutOffBy => "the image is {0} larger than {1}; the rest of it is cut off",
+            Msg::ImageCutOff => "the image is larger than {0}; the rest of it is cut off",
+            Msg::ImageDoesNotFit => "the image ({0}) doesn't fit on {1} ({2}); nothing was written",
+            Msg::ImageOverflowed => {
+                "the image doesn't fit on {0} ({1}); {2} won't boot as is (--force-oversize writes as much of it as fits)"
+            }
+            Msg::PartiallyWritten => {
+                "{0} is only partially written ({1} of {2}) and won't boot as is"
+            }
+            Msg::ImageEndedEarly => "the image ended early, after {0} of {1}; {2} won't boot as is",
+            Msg::NotHexBytes => "'{0}' is not a whole number of hex bytes",
+            Msg::NotHex => "'{0}' is not hex",
+            Msg::NoPartialRead => "no partial read to resume ({0})",
+            Msg::PartialStateInvalid => "{0} has no valid '{1}'",
+            Msg::CompressingWith => {
+                "Compressing with {0} ({1} MiB/s, {2}% of the size on a sample; the device reads at {3} MiB/s)"
+            }
+            Msg::NoCompressionKeepsUp => {
+                "no compression keeps up with the device; the read will be slower"
+            }
+            Msg::ReadingDevice => "Reading device \"{0}\" to image \"{1}\"",
+            Msg::DeviceSizeZero => "Device size is reported as zero",
+            Msg::TrainingDictionary => "training a zstd dictionary",
+            Msg::TrainedDictionary => "Trained a {0} dictionary, saved to {1}",
+            Msg::CodecMismatch => "{0} will hold {1} data; write only recognizes it as a .{2} file",
+            Msg::PartialFromOtherDevice => "{0} was read from a device of {1}, but {2} has {3}",
+            Msg::ResumingAt => "Resuming at {0} of {1}",
+            Msg::KeptPartial => {
+                "kept the first {0} in {1}; run the same read with --resume to continue"
+            }
+            Msg::UnreadableSector => "unreadable sector at offset {0}, filled with {1}",
+            Msg::ReadCompleteTo => "Read complete: \"{0}\" ({1})",
+            Msg::CompressedTo => "Compressed to {0} ({1}%)",
+            Msg::SparseHoles => "Blocks of zeros were left as holes; it takes up {0} on disk",
+            Msg::UnreadableSectors => {
+                "{0} unreadable sectors ({1}) were filled with {2}; their offsets are {3}"
+            }
+            Msg::MappedIn => "mapped in {0}",
+            Msg::ListedAbove => "listed above; --mapfile saves them for ddrescue",
+            Msg::BarMeasuring => "Measuring",
+            Msg::PhaseMeasuring => "Measuring",
+            Msg::ImageTooLarge => {
+                "the image ({0}) is larger than {1} ({2}); pass --force-oversize to write as much of it as fits"
+            }
+            Msg::ImageWillBeCutOff => {
+                "the image ({0}) is larger than {1} ({2}); the last {3} of it will be cut off (--force-oversize)."
+            }
+            Msg::ImageSizeUnchecked => {
+                "the size of {0} isn't known until it has been written; whatever doesn't fit on {1} ({2}) will be cut off (--force-oversize)."
+            }
+            Msg::NoWrites => "No writes in the last {0} days.",
+            Msg::WritesPerDay => "Writes per day",
+            Msg::WritesOk => "ok",
+            Msg::WritesFailed => "{0} failed",
+            Msg::WritesSummary => "Last {0} days: {1} writes, {2} failed ({3}%)",
+            Msg::AverageThroughput => "Average throughput: {0}/s, verification included",
+            Msg::WrittenImages => "Images",
+            Msg::SharedForced => "{0} is {1}; writing it anyway (--force-shared).",
+            Msg::SharedRefused => {
+                "{0} is {1}; overwriting it would corrupt that from under its users. Stop using it there first, or pass --force-shared"
+            }
+            Msg::ExportedOverNfs => "exported over NFS ({0})",
+            Msg::SharedOverSamba => "shared over Samba as [{0}] ({1})",
+            Msg::AttachedToVm => "attached to the virtual machine{0} ({1}, pid {2})",
+            Msg::OverwritePartitions => "This will overwrite partition(s) {0} on '{1}' ({2} GB).",
+            Msg::OverwritePartitionsAndTable => {
+                "This will overwrite partition(s) {0} and the partition table on '{1}' ({2} GB)."
+            }
+            Msg::CopyDirection => "Which way should the data be copied?",
+            Msg::OppositeDirection => {
+                "that's the opposite of the chosen source and target, so nothing was written; run clone again with them the right way round"
+            }
+            Msg::CopiedPartitions => "\n✨ Copied partition(s) of {0} to {1}.",
+            Msg::SourceSide => "SOURCE (read)",
+            Msg::TargetSide => "TARGET (overwritten)",
+            Msg::SideDevice => "Device",
+            Msg::SideSize => "Size",
+            Msg::SideModel => "Model",
+            Msg::SideSerial => "Serial",
+            Msg::MountedAt => "[Mounted at {0}]",
+            Msg::NotMountedTag => "[Not mounted]",
+            Msg::NotMounted => "(Not mounted)",
+            Msg::NotifyFinished => "Finished",
+            Msg::NotifyFailed => "Failed: {0}",
+            Msg::CustomizeSandboxed => {
+                "customizing the flashed system needs to mount it, which the sandbox forbids"
+            }
+            #[cfg(not(feature = "torrent"))]
+            Msg::TorrentNotBuilt => {
+                "magnet links and .torrent files need etchr built with the 'torrent' feature"
+            }
+            Msg::DevicesFailed => "{0} of {1} devices failed",
+            Msg::SignSandboxed => {
+                "signing the image runs gpg or minisign, which the sandbox forbids"
+            }
+            Msg::SignNeedsKey => "--sign needs a key in the [signing] section of the config file",
+            Msg::ResumeReadQuestion => {
+                "An earlier read into {0} stopped after {1}. Resume it? (No deletes it)"
+            }
+            Msg::ResumeReadHint => {
+                "An earlier read into {0} stopped after {1}; run with --resume to continue it"
+            }
+            Msg::SignedImage => "Signed the image: {0}",
+            Msg::Unlocked => "Unlocked {0} for writing for the next {1} minutes",
+            Msg::UnlockNotRequired => {
+                "(require_unlock isn't set in the config, so it was writable anyway)"
+            }
+            Msg::ImportedImages => "Imported {0} images into the catalog",
+            Msg::TestImageNeedsCodec => "gen-test-image needs a codec, not 'auto'",
+            Msg::GeneratedImage => "Generated {0} ({1})",
+            Msg::ExcludedDevice => "Excluded {0} ({1}); it won't be offered again",
+            Msg::ReadingDictionary => "reading the zstd dictionary {0}",
+            Msg::BarDownload => "Download",
+            Msg::PhaseComplete => "✅ {0} complete.",
+            Msg::PhaseFailed => "❌ {0} failed.",
+            Msg::SpoolFull => {
+                "the image decompresses to more than the {0} of temp space available; write it with --stream instead"
+            }
+            Msg::SpoolingFailed => "spooling the image to a temp file: {0}",
+            Msg::OfImage => "{0} of image",
+            Msg::NotProbedMounted => "not probed while mounted",
+            Msg::NotProbedInUse => "not probed while mounted or in use",
+            Msg::ProbeRegionTooSmall => "probe region too small",
+            Msg::UnknownUntilDecompressed => "unknown until decompressed ({0})",
+            Msg::NotAskableJson => {
+                "\"{0}\" can't be asked with --json; answer it on the command line (e.g. --yes, --device)"
+            }
+            Msg::NoAnswer => "no answer: end of input",
+            Msg::UploadSandboxed => "uploading the image runs curl, which the sandbox forbids",
+            Msg::ConnectingTo => "connecting to {0}",
+            Msg::RunningCurlTo => "running curl to {0}",
+            Msg::ActionFailed => "{0} failed: {1}",
+            Msg::RunningProgram => "running {0} (is it installed?)",
+            Msg::UploadingTo => "uploading to {0}",
+            Msg::CompletingS3Upload => "completing the S3 upload",
+            Msg::StartingS3Upload => "starting the S3 upload",
+            Msg::S3NoUploadId => "S3 returned no upload ID",
+            Msg::StoringS3Part => "storing part {0} ({1}) of the S3 upload",
+            Msg::S3NoEtag => "S3 returned no ETag for part {0}",
+            Msg::S3UploadPanicked => "the S3 upload thread panicked",
+            Msg::NotS3Location => "'s3://{0}' is not s3://BUCKET/KEY",
+            Msg::S3NeedsCredentials => {
+                "S3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY in the environment"
+            }
+            Msg::DownloadingFailed => "downloading {0} failed: {1}",
+            Msg::NotSshLocation => "'{0}' is not [USER@]HOST[:PORT]/PATH",
+            Msg::SpoolPanicked => "the spool thread panicked",
+            Msg::RunningEject => "running eject: {0}",
+            Msg::InvalidDevicePath => "invalid device path {0}",
+            Msg::SeccompUnavailable => {
+                "seccomp is not supported by this kernel or architecture; syscalls are not restricted."
+            }
+            Msg::NoDevicesToWrite => "no device to write the image to",
+        }
+    }
+
+    fn de(self) -> &'static str {
+        match self {
+            Msg::SelectWriteTarget => "Zielgerät zum SCHREIBEN auswählen",
+            Msg::SelectReadSource => "Quellgerät zum LESEN auswählen",
+            Msg::SelectVerifyTarget => "Gerät zum Vergleich mit der Vorlage auswählen",
+            Msg::SelectCloneSource => {
+                "Schritt 1 von 2: QUELLE auswählen, das Gerät, VON dem kopiert wird"
+            }
+            Msg::SelectCloneTarget => {
+                "Schritt 2 von 2: ZIEL auswählen, das Gerät, AUF das kopiert wird (wird überschrieben)"
+            }
+            Msg::Warning => "WARNUNG:",
+            Msg::EraseWarning => "Alle Daten auf '{0}' ({1} GB) werden gelöscht.",
+            Msg::ReadSummary => "Es werden {0} GB von '{1}' gelesen.",
+            Msg::DeviceLabel => "  Gerät:   ",
+            Msg::ImageLabel => "  Abbild:  ",
+            Msg::OutputLabel => "  Ausgabe: ",
+            Msg::EstimatedTime => "  Geschätzte Dauer: ",
+            Msg::Unavailable => "nicht verfügbar ({0})",
+            Msg::ConfirmProceed => "Wirklich fortfahren?",
+            Msg::WriteCancelled => "Schreibvorgang abgebrochen.",
+            Msg::ReadCancelled => "Lesevorgang abgebrochen.",
+            Msg::FlashSuccess => "✨ {1} erfolgreich auf {0} geschrieben.",
+            Msg::ReadSuccess => "✨ {0} erfolgreich nach {1} gelesen.",
+            Msg::SystemDriveUnknown => {
+                "Systemlaufwerk konnte nicht ermittelt werden; nur USB- und SD-Wechseldatenträger werden angeboten."
+            }
+            Msg::NoRemovableDevices => "Keine Wechseldatenträger gefunden.",
+            Msg::NameDeviceWithoutTerminal => {
+                "Kein Terminal zur Auswahl eines Geräts; gib es mit --device an."
+            }
+            Msg::RescanDevices => "↻ Geräte neu suchen",
+            Msg::PlugInAndRescan => {
+                "Keine Wechseldatenträger gefunden; einen anschließen und neu suchen"
+            }
+            Msg::FoundDevices => "{0} Wechseldatenträger gefunden:",
+            Msg::SelectImage => "Abbild zum Schreiben auswählen ({0})",
+            Msg::NoImagesFound => "Keine Abbilder in {0} gefunden",
+            Msg::SelectRecentImage => "Zuletzt verwendetes Abbild auswählen",
+            Msg::BrowseForImage => "Anderes Abbild suchen...",
+            Msg::Paused => "⏸  Pausiert. 'p' drücken oder SIGUSR1 senden zum Fortsetzen.",
+            Msg::TypeToConfirm => "Zur Bestätigung '{0}' eingeben",
+            Msg::OverrideForbidden => "{0} ist im Sicherheitsprofil 'paranoid' nicht erlaubt.",
+            Msg::InternalDisk => "[intern]",
+            Msg::UsbDisk => "[USB-Festplatte]",
+            Msg::SizeLabel => "  Größe:   ",
+            Msg::OfDevice => "({0}% des Geräts)",
+            Msg::CompressedSize => "{0} komprimiert (entpackte Größe unbekannt)",
+            Msg::ModifiedLabel => "  Alter:   ",
+            Msg::HashLabel => "  Hash:    ",
+            Msg::CatalogLabel => "  Bekannt: ",
+            Msg::RecognizedImage => "erkannt: {0} (verifiziert)",
+            Msg::UnknownImage => "unbekanntes Abbild, nicht im lokalen Katalog",
+            Msg::PolicyNeedsImageFile => {
+                "'{0}' wird nicht geschrieben: Die Richtlinie erlaubt nur geprüfte Abbilddateien, keine Standardeingabe, URLs oder Geräte."
+            }
+            Msg::PolicyNoSignature => {
+                "'{0}' wird nicht geschrieben: Die Richtlinie verlangt eine Signatur, aber es gibt weder '{0}.asc' noch '{0}.sig'."
+            }
+            Msg::PolicyBadSignature => {
+                "'{0}' wird nicht geschrieben: Die Richtlinie verlangt eine gültige Signatur."
+            }
+            Msg::PolicyNotInCatalog => {
+                "'{0}' wird nicht geschrieben: Die Richtlinie erlaubt nur Abbilder aus dem Katalog, und dieses (sha256 {1}) ist nicht darin."
+            }
+            Msg::SuspiciousImage => "Das Abbild ist nur {0} groß, viel kleiner als das Gerät.",
+            Msg::AcknowledgeSmallImage => {
+                "Ist das wirklich das Abbild, das geschrieben werden soll?"
+            }
+            Msg::ImageIsBlockDevice => {
+                "'{0}' ist ein Blockgerät, keine Abbilddatei. Wurden die Argumente vertauscht? \
+                 Das Zielgerät wird interaktiv gewählt; mit --from-device kann von einem Gerät kopiert werden."
+            }
+            Msg::SourceIsTarget => "Quelle und Ziel sind dasselbe Gerät.",
+            Msg::LandlockUnavailable => {
+                "Landlock wird von diesem Kernel nicht unterstützt; der Dateizugriff ist nicht eingeschränkt."
+            }
+            Msg::CardLabel => "  Karte:   ",
+            Msg::CardDetails => "{1}, {0} (OEM {2}), Seriennummer {3}, hergestellt {4}",
+            Msg::UnknownManufacturer => "unbekannter Hersteller {0}",
+            Msg::LinkLabel => "  Bus:     ",
+            Msg::UsbLink => "USB mit {0}",
+            Msg::MmcLink => "Kartensteckplatz",
+            Msg::UsbBehindSlowHub => {
+                "{0} ist ein USB-{1}-Gerät, läuft aber hinter einem USB-2.0-Hub mit {2}; \
+                 direkt oder an einem USB-3-Hub angeschlossen schreibt es schneller."
+            }
+            Msg::UsbSlowPort => {
+                "{0} ist ein USB-{1}-Gerät, läuft aber mit {2}; \
+                 an einem USB-3-Anschluss und -Kabel schreibt es schneller."
+            }
+            Msg::UhsNotUsed => {
+                "{0} ist eine UHS-Karte, der Steckplatz betreibt sie aber im Modus {1}, weit unter ihrer Geschwindigkeit."
+            }
+            Msg::DeviceNotOffered => {
+                "'{0}' gehört nicht zu den Geräten, auf die etchr schreiben darf; siehe 'etchr list'."
+            }
+            Msg::DuplicateDevice => "'{0}' wurde mehrfach angegeben.",
+            Msg::BurnCount => "[{0}× beschrieben]",
+            Msg::WornCard => {
+                "{0} wurde {1}-mal beschrieben, öfter als die mit wear_warning festgelegten {2}; \
+                 es könnte verschleißen."
+            }
+            Msg::Countdown => "Schreibe auf {0} in ",
+            Msg::StopWithCtrlC => "Strg+C zum Abbrechen.",
+            Msg::FilesystemUsage => "{0} unter {1} ({2}): {3} von {4} belegt, {5} frei",
+            Msg::ProfileLabel => "  Profil:  ",
+            Msg::CanBeUnplugged => "⏏️  {0} kann abgezogen werden",
+            Msg::EjectSandboxed => {
+                "{0} wird nicht ausgeworfen: --sandbox hindert etchr daran, eject auszuführen. Vor dem Abziehen auswerfen."
+            }
+            Msg::CancelledByUser => "Vom Benutzer abgebrochen",
+            Msg::CleaningUp => "Abbruchsignal erhalten... räume auf.",
+            Msg::PhaseCancelled => "❌ {0} abgebrochen.",
+            Msg::PhaseHashing => "Hashen",
+            Msg::PhaseDecompression => "Entpacken",
+            Msg::PhaseWrite => "Schreiben",
+            Msg::PhaseVerification => "Prüfung",
+            Msg::PhaseRewrite => "Neuschreiben",
+            Msg::PhaseRead => "Lesen",
+            Msg::PhaseDownload => "Download",
+            Msg::PhaseCopy => "Kopieren",
+            Msg::PhaseComparison => "Vergleich",
+            Msg::PhaseGenerating => "Erzeugen",
+            Msg::BarHashing => "Hashen",
+            Msg::BarDecompress => "Entpacken",
+            Msg::BarWriting => "Schreiben",
+            Msg::BarVerifying => "Prüfen",
+            Msg::BarRewriting => "Korrektur",
+            Msg::BarReading => "Lesen",
+            Msg::DecompressionComplete => "✅ Entpacken abgeschlossen.",
+            Msg::WriteComplete => "✅ Schreiben abgeschlossen.",
+            Msg::RewriteComplete => "✅ Neuschreiben abgeschlossen.",
+            Msg::ReadComplete => "✅ Lesen abgeschlossen.",
+            Msg::VerificationSuccessful => "✅ Prüfung erfolgreich.",
+            Msg::VerificationFailed => "❌ Prüfung fehlgeschlagen.",
+            Msg::VerificationFailedWith => "❌ Prüfung fehlgeschlagen: {0}.",
+            Msg::ConfirmRewrite => {
+                "Nur diese Abschnitte aus dem Abbild neu schreiben und erneut prüfen?"
+            }
+            Msg::OpeningFile => "{0} wird geöffnet",
+            Msg::WritingFromArchive => "Schreibe {0} aus dem Archiv",
+            Msg::TempSpaceExceeded => {
+                "das Abbild ist entpackt größer als der verfügbare temporäre Speicher von {0}; stattdessen mit --stream schreiben"
+            }
+            Msg::StreamingPastTempSpace => {
+                "Das Abbild ist entpackt größer als der verfügbare temporäre Speicher von {0} und wird daher beim Entpacken geschrieben"
+            }
+            Msg::ArchiveTruncated => {
+                "das Quellarchiv ist abgeschnitten: es ergab entpackt {0}, verzeichnet aber {1}"
+            }
+            Msg::PartitionNeedsTempFile => {
+                "--partition braucht das Abbild entpackt in einer temporären Datei, in die es nicht passt"
+            }
+            Msg::SeveralDevicesNeedTempFile => {
+                "mehrere Geräte zu schreiben braucht das Abbild entpackt in einer temporären Datei, in die es nicht passt"
+            }
+            Msg::DecompressingBeforeWrite => {
+                "beim Entpacken des Abbilds, bevor ein Gerät geschrieben wurde"
+            }
+            Msg::WritingPartition => "Schreibe Partition {0} des Abbilds ({1} ab Offset {2})",
+            Msg::WritingToDevicesAtOnce => {
+                "Schreibe Abbild \"{0}\" gleichzeitig auf {1} Geräte: {2}"
+            }
+            Msg::WritingToDevice => "{0}Schreibe Abbild \"{1}\" auf Gerät \"{2}\"",
+            Msg::WritingDeviceOf => "beim Schreiben von {0} ({1} von {2} fertig)",
+            Msg::ChecksumMismatch => {
+                "die SHA-256 des heruntergeladenen Abbilds ist {0}, {1} nennt aber {2}; das Geschriebene ist nicht das veröffentlichte Abbild"
+            }
+            Msg::ChecksumMatches => {
+                "✅ Das heruntergeladene Abbild stimmt mit seiner veröffentlichten SHA-256 überein ({0})"
+            }
+            Msg::SavedReport => "Bericht in {0} gespeichert",
+            Msg::WriterPanicked => "der Thread, der {0} schreibt, ist abgestürzt",
+            Msg::PartitionOfDevice => "Partition {0} von {1}",
+            Msg::ImageCutOffBy => "das Abbild ist {0} größer als {1}; der Rest wird abgeschnitten",
+            Msg::ImageCutOff => "das Abbild ist größer als {0}; der Rest wird abgeschnitten",
+            Msg::ImageDoesNotFit => {
+                "das Abbild ({0}) passt nicht auf {1} ({2}); es wurde nichts geschrieben"
+            }
+            Msg::ImageOverflowed => {
+                "das Abbild passt nicht auf {0} ({1}); {2} startet so nicht (--force-oversize schreibt so viel davon, wie passt)"
+            }
+            Msg::PartiallyWritten => {
+                "{0} ist nur teilweise geschrieben ({1} von {2}) und startet so nicht"
+            }
+            Msg::ImageEndedEarly => {
+                "das Abbild endete vorzeitig, nach {0} von {1}; {2} startet so nicht"
+            }
+            Msg::NotHexBytes => "'{0}' ist keine ganze Zahl von Hex-Bytes",
+            Msg::NotHex => "'{0}' ist kein Hex",
+            Msg::NoPartialRead => "kein abgebrochenes Lesen zum Fortsetzen ({0})",
+            Msg::PartialStateInvalid => "{0} enthält kein gültiges '{1}'",
+            Msg::CompressingWith => {
+                "Komprimiere mit {0} ({1} MiB/s, {2}% der Größe bei einer Stichprobe; das Gerät liest mit {3} MiB/s)"
+            }
+            Msg::NoCompressionKeepsUp => {
+                "keine Kompression hält mit dem Gerät Schritt; das Lesen wird langsamer"
+            }
+            Msg::ReadingDevice => "Lese Gerät \"{0}\" in Abbild \"{1}\"",
+            Msg::DeviceSizeZero => "Die Gerätegröße wird als null gemeldet",
+            Msg::TrainingDictionary => "beim Trainieren eines zstd-Wörterbuchs",
+            Msg::TrainedDictionary => "Wörterbuch mit {0} trainiert, gespeichert in {1}",
+            Msg::CodecMismatch => {
+                "{0} wird {1}-Daten enthalten; write erkennt sie nur als .{2}-Datei"
+            }
+            Msg::PartialFromOtherDevice => {
+                "{0} wurde von einem Gerät mit {1} gelesen, {2} hat aber {3}"
+            }
+            Msg::ResumingAt => "Setze bei {0} von {1} fort",
+            Msg::KeptPartial => {
+                "die ersten {0} wurden in {1} behalten; dasselbe Lesen mit --resume setzt es fort"
+            }
+            Msg::UnreadableSector => "unlesbarer Sektor bei Offset {0}, mit {1} gefüllt",
+            Msg::ReadCompleteTo => "Lesen abgeschlossen: \"{0}\" ({1})",
+            Msg::CompressedTo => "Auf {0} komprimiert ({1}%)",
+            Msg::SparseHoles => {
+                "Blöcke aus Nullen wurden als Lücken belassen; es belegt {0} auf der Platte"
+            }
+            Msg::UnreadableSectors => {
+                "{0} unlesbare Sektoren ({1}) wurden mit {2} gefüllt; ihre Offsets sind {3}"
+            }
+            Msg::MappedIn => "in {0} verzeichnet",
+            Msg::ListedAbove => "oben aufgeführt; --mapfile speichert sie für ddrescue",
+            Msg::BarMeasuring => "Messen",
+            Msg::PhaseMeasuring => "Messen",
+            Msg::ImageTooLarge => {
+                "das Abbild ({0}) ist größer als {1} ({2}); mit --force-oversize wird so viel davon geschrieben, wie passt"
+            }
+            Msg::ImageWillBeCutOff => {
+                "das Abbild ({0}) ist größer als {1} ({2}); die letzten {3} davon werden abgeschnitten (--force-oversize)."
+            }
+            Msg::ImageSizeUnchecked => {
+                "die Größe von {0} ist erst nach dem Schreiben bekannt; was nicht auf {1} ({2}) passt, wird abgeschnitten (--force-oversize)."
+            }
+            Msg::NoWrites => "Keine Schreibvorgänge in den letzten {0} Tagen.",
+            Msg::WritesPerDay => "Schreibvorgänge pro Tag",
+            Msg::WritesOk => "ok",
+            Msg::WritesFailed => "{0} fehlgeschlagen",
+            Msg::WritesSummary => "Letzte {0} Tage: {1} Schreibvorgänge, {2} fehlgeschlagen ({3}%)",
+            Msg::AverageThroughput => "Durchschnittlicher Durchsatz: {0}/s, einschließlich Prüfung",
+            Msg::WrittenImages => "Abbilder",
+            Msg::SharedForced => "{0} ist {1}; es wird trotzdem geschrieben (--force-shared).",
+            Msg::SharedRefused => {
+                "{0} ist {1}; es zu überschreiben würde es unter seinen Nutzern beschädigen. Zuerst dort nicht mehr verwenden, oder --force-shared angeben"
+            }
+            Msg::ExportedOverNfs => "über NFS exportiert ({0})",
+            Msg::SharedOverSamba => "über Samba als [{0}] freigegeben ({1})",
+            Msg::AttachedToVm => "an die virtuelle Maschine{0} angebunden ({1}, PID {2})",
+            Msg::OverwritePartitions => "Dies überschreibt Partition(en) {0} auf '{1}' ({2} GB).",
+            Msg::OverwritePartitionsAndTable => {
+                "Dies überschreibt Partition(en) {0} und die Partitionstabelle auf '{1}' ({2} GB)."
+            }
+            Msg::CopyDirection => "In welche Richtung sollen die Daten kopiert werden?",
+            Msg::OppositeDirection => {
+                "das ist das Gegenteil der gewählten Quelle und des Ziels, daher wurde nichts geschrieben; clone erneut mit ihnen in der richtigen Reihenfolge ausführen"
+            }
+            Msg::CopiedPartitions => "\n✨ Partition(en) von {0} nach {1} kopiert.",
+            Msg::SourceSide => "QUELLE (gelesen)",
+            Msg::TargetSide => "ZIEL (überschrieben)",
+            Msg::SideDevice => "Gerät",
+            Msg::SideSize => "Größe",
+            Msg::SideModel => "Modell",
+            Msg::SideSerial => "Seriennr",
+            Msg::MountedAt => "[Eingehängt in {0}]",
+            Msg::NotMountedTag => "[Nicht eingehängt]",
+            Msg::NotMounted => "(Nicht eingehängt)",
+            Msg::NotifyFinished => "Fertig",
+            Msg::NotifyFailed => "Fehlgeschlagen: {0}",
+            Msg::CustomizeSandboxed => {
+                "das Anpassen des geschriebenen Systems muss es einhängen, was die Sandbox verbietet"
+            }
+            #[cfg(not(feature = "torrent"))]
+            Msg::TorrentNotBuilt => {
+                "Magnet-Links und .torrent-Dateien brauchen ein mit dem Feature 'torrent' gebautes etchr"
+            }
+            Msg::DevicesFailed => "{0} von {1} Geräten fehlgeschlagen",
+            Msg::SignSandboxed => {
+                "das Signieren des Abbilds führt gpg oder minisign aus, was die Sandbox verbietet"
+            }
+            Msg::SignNeedsKey => {
+                "--sign braucht einen Schlüssel im Abschnitt [signing] der Konfigurationsdatei"
+            }
+            Msg::ResumeReadQuestion => {
+                "Ein früheres Lesen nach {0} brach nach {1} ab. Fortsetzen? (Nein löscht es)"
+            }
+            Msg::ResumeReadHint => {
+                "Ein früheres Lesen nach {0} brach nach {1} ab; mit --resume wird es fortgesetzt"
+            }
+            Msg::SignedImage => "Abbild signiert: {0}",
+            Msg::Unlocked => "{0} für die nächsten {1} Minuten zum Schreiben freigegeben",
+            Msg::UnlockNotRequired => {
+                "(require_unlock ist in der Konfiguration nicht gesetzt, es war also ohnehin beschreibbar)"
+            }
+            Msg::ImportedImages => "{0} Abbilder in den Katalog importiert",
+            Msg::TestImageNeedsCodec => "gen-test-image braucht einen Codec, nicht 'auto'",
+            Msg::GeneratedImage => "{0} erzeugt ({1})",
+            Msg::ExcludedDevice => "{0} ausgeschlossen ({1}); es wird nicht mehr angeboten",
+            Msg::ReadingDictionary => "Lesen des zstd-Wörterbuchs {0}",
+            Msg::BarDownload => "Download",
+            Msg::PhaseComplete => "✅ {0} abgeschlossen.",
+            Msg::PhaseFailed => "❌ {0} fehlgeschlagen.",
+            Msg::SpoolFull => {
+                "das Abbild entpackt sich zu mehr als den {0} verfügbaren temporären Speichers; stattdessen mit --stream schreiben"
+            }
+            Msg::SpoolingFailed => "Zwischenspeichern des Abbilds in einer temporären Datei: {0}",
+            Msg::OfImage => "{0} des Abbilds",
+            Msg::NotProbedMounted => "nicht gemessen, solange eingehängt",
+            Msg::NotProbedInUse => "nicht gemessen, solange eingehängt oder in Benutzung",
+            Msg::ProbeRegionTooSmall => "Messbereich zu klein",
+            Msg::UnknownUntilDecompressed => "unbekannt bis zum Entpacken ({0})",
+            Msg::NotAskableJson => {
+                "\"{0}\" kann mit --json nicht gefragt werden; auf der Kommandozeile beantworten (z. B. --yes, --device)"
+            }
+            Msg::NoAnswer => "keine Antwort: Ende der Eingabe",
+            Msg::UploadSandboxed => {
+                "das Hochladen des Abbilds führt curl aus, was die Sandbox verbietet"
+            }
+            Msg::ConnectingTo => "Verbinden mit {0}",
+            Msg::RunningCurlTo => "Ausführen von curl für {0}",
+            Msg::ActionFailed => "{0} fehlgeschlagen: {1}",
+            Msg::RunningProgram => "Ausführen von {0} (ist es installiert?)",
+            Msg::UploadingTo => "Hochladen nach {0}",
+            Msg::CompletingS3Upload => "Abschließen des S3-Uploads",
+            Msg::StartingS3Upload => "Starten des S3-Uploads",
+            Msg::S3NoUploadId => "S3 lieferte keine Upload-ID",
+            Msg::StoringS3Part => "Speichern von Teil {0} ({1}) des S3-Uploads",
+            Msg::S3NoEtag => "S3 lieferte kein ETag für Teil {0}",
+            Msg::S3UploadPanicked => "der S3-Upload-Thread ist abgestürzt",
+            Msg::NotS3Location => "'s3://{0}' ist nicht s3://BUCKET/KEY",
+            Msg::S3NeedsCredentials => {
+                "S3 braucht AWS_ACCESS_KEY_ID und AWS_SECRET_ACCESS_KEY in der Umgebung"
+            }
+            Msg::DownloadingFailed => "Herunterladen von {0} fehlgeschlagen: {1}",
+            Msg::NotSshLocation => "'{0}' ist nicht [USER@]HOST[:PORT]/PATH",
+            Msg::SpoolPanicked => "der Zwischenspeicher-Thread ist abgestürzt",
+            Msg::RunningEject => "Ausführen von eject: {0}",
+            Msg::InvalidDevicePath => "ungültiger Gerätepfad {0}",
+            Msg::SeccompUnavailable => {
+                "seccomp wird von diesem Kernel oder dieser Architektur nicht unterstützt; Systemaufrufe sind nicht eingeschränkt."
+            }
+            Msg::NoDevicesToWrite => "kein Gerät, auf das das Abbild geschrieben werden kann",
+        }
+    }
+}
+
+/// Returns the character grouping the digits of large numbers in the
+/// current language.
+pub fn thousands_separator() -> char {
+    match current() {
+        Lang::En => ',',
+        Lang::De => '.',
+    }
+}
+
+/// Returns the message in `lang`.
+fn text(msg: Msg, lang: Lang) -> &'static str {
+    match lang {
+        Lang::En => msg.en(),
+        Lang::De => msg.de(),
+    }
+}
+
+/// Returns the message in the current language.
+pub fn tr(msg: Msg) -> &'static str {
+    text(msg, current())
+}
+
+/// Replaces the placeholders in `template` by `args`. Only the template's
+/// own are replaced: an argument that contains `{1}`, like a file name,
+/// is left as it is.
+fn fill(template: &str, args: &[&dyn std::fmt::Display]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open + 1..];
+        let arg = rest.split_once('}').and_then(|(index, after)| {
+            let arg = args.get(index.parse::<usize>().ok()?)?;
+            Some((arg, after))
+        });
+        match arg {
+            Some((arg, after)) => {
+                text.push_str(&arg.to_string());
+                rest = after;
+            }
+            None => text.push('{'),
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Returns the message in the current language with its placeholders
+/// replaced by `args`, in order.
+pub fn trf(msg: Msg, args: &[&dyn std::fmt::Display]) -> String {
+    fill(tr(msg), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use super::*;
+
+    /// The placeholders each message of the `fn {lang}(self)` table in
+    /// this file has, by the name of the message.
+    fn placeholders(lang: &str) -> BTreeMap<String, BTreeSet<String>> {
+        let source = include_str!("i18n.rs");
+        let start = source
+            .find(&format!("fn {lang}(self)"))
+            .expect("the table exists");
+        let end = start + source[start..].find("\n    }\n").unwrap();
+        let mut messages = BTreeMap::new();
+        for arm in source[start..end].split("Msg::").skip(1) {
+            let (name, text) = arm.split_once(" => ").unwrap();
+            let text = text.trim_start_matches(['{', ' ', '\n']);
+            let text = text.strip_prefix('"').unwrap();
+            let text = &text[..text.find("\",").or_else(|| text.find("\"\n")).unwrap()];
+            let found = text
+                .split('{')
+                .skip(1)
+                .filter_map(|p| p.split_once('}'))
+                .filter(|(index, _)| index.parse::<usize>().is_ok())
+                .map(|(index, _)| index.to_string())
+                .collect();
+            messages.insert(name.to_string(), found);
+        }
+        messages
+    }
+
+    #[test]
+    fn translations_have_the_same_placeholders() {
+        let en = placeholders("en");
+        let de = placeholders("de");
+        assert!(en.len() > 100);
+        assert_eq!(en, de);
+    }
+
+    #[test]
+    fn messages_are_looked_up_by_language() {
+        assert_eq!(text(Msg::Warning, Lang::En), Msg::Warning.en());
+        assert_eq!(text(Msg::Warning, Lang::De), Msg::Warning.de());
+        assert_ne!(text(Msg::Warning, Lang::En), text(Msg::Warning, Lang::De));
+    }
+
+    #[test]
+    fn fill_replaces_each_placeholder() {
+        assert_eq!(fill("{1} before {0}, {1}", &[&"a", &2]), "2 before a, 2");
+        assert_eq!(fill("no placeholders", &[&"a"]), "no placeholders");
+    }
+
+    #[test]
+    fn fill_leaves_arguments_and_other_braces_alone() {
+        assert_eq!(fill("{0} and {1}", &[&"{1}", &"b"]), "{1} and b");
+        assert_eq!(fill("{prefix} {0} {2} {", &[&"a"]), "{prefix} a {2} {");
+    }
+}
@@ -0,0 +1,167 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::device;
+use crate::history;
+use crate::json;
+use crate::output::{info, verbose};
+use crate::privileges;
+
+/// How often the label command is tried before its payload is queued.
+const ATTEMPTS: u32 = 3;
+
+/// Pause between attempts, e.g. for a printer that ran out of labels.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// How long the label command may take before it's considered failed, so a
+/// hung printer can't keep etchr from exiting.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serializes appends to the queue file from the background threads.
+static QUEUE_LOCK: Mutex<()> = Mutex::new(());
+
+fn queue_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("label-queue"))
+}
+
+/// Builds the JSON payload describing a card just flashed with `image`:
+/// its serial number, the image and its hash, the bay it sits in (its
+/// `/dev/disk/by-path` name, which stays the same for a USB port or card
+/// slot), and when it was flashed.
+pub fn payload(device_name: &str, image: &Path, hash: Option<&str>) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let optional = |value: Option<&str>| value.map_or("null".to_string(), json::quote);
+    format!(
+        "{{\"serial\": {}, \"image\": {}, \"sha256\": {}, \"device\": {}, \"bay\": {}, \"timestamp\": {timestamp}}}",
+        optional(device::serial(device_name).as_deref()),
+        json::quote(&image.display().to_string()),
+        optional(hash),
+        json::quote(device_name),
+        optional(device::by_path(device_name).as_deref()),
+    )
+}
+
+/// Hands payloads to the label command in the background, so a slow or
+/// failing printer doesn't hold up writing the next device. Payloads the
+/// command keeps failing on are queued and handed over again on the next
+/// run.
+pub struct Labeler {
+    command: String,
+    /// Commands can't be run inside the sandbox; payloads are only queued.
+    sandboxed: bool,
+    jobs: Vec<JoinHandle<()>>,
+}
+
+impl Labeler {
+    /// Sets up `command`, which is run by `sh -c` with a payload on stdin,
+    /// and hands it the payloads queued by earlier runs.
+    pub fn new(command: String, sandboxed: bool) -> Self {
+        let mut labeler = Labeler {
+            command,
+            sandboxed,
+            jobs: Vec::new(),
+        };
+        if !sandboxed {
+            for payload in take_queue() {
+                labeler.submit(payload);
+            }
+        }
+        labeler
+    }
+
+    /// Runs the label command with `payload`, retrying in the background
+    /// and queuing it if it keeps failing.
+    pub fn submit(&mut self, payload: String) {
+        if self.sandboxed {
+            verbose!("The label command can't run inside the sandbox; queued for the next run");
+            enqueue(&payload);
+            return;
+        }
+        let command = self.command.clone();
+        self.jobs.push(thread::spawn(move || {
+            for attempt in 1..=ATTEMPTS {
+                match run(&command, &payload) {
+                    Ok(()) => return,
+                    Err(e) => verbose!("The label command failed (attempt {attempt}): {e}"),
+                }
+                if attempt < ATTEMPTS {
+                    thread::sleep(RETRY_DELAY);
+                }
+            }
+            info!("The label command keeps failing; the label is queued for the next run");
+            enqueue(&payload);
+        }));
+    }
+
+    /// Waits for the label commands still running.
+    pub fn finish(self) {
+        for job in self.jobs {
+            job.join().ok();
+        }
+    }
+}
+
+/// Runs `command` with `payload` on stdin, killing it after [`TIMEOUT`].
+fn run(command: &str, payload: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "{payload}")?;
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::other(format!("it exited with {status}")))
+            };
+        }
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(io::Error::other("it timed out"));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Appends `payload` to the queue of labels to print on the next run.
+fn enqueue(payload: &str) {
+    let Some(path) = queue_path() else {
+        return;
+    };
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    if let Err(e) = history::append(&path, &format!("{payload}\n")) {
+        info!("Could not queue the label in {}: {e}", path.display());
+    }
+}
+
+/// Removes and returns the queued payloads.
+fn take_queue() -> Vec<String> {
+    let Some(path) = queue_path() else {
+        return Vec::new();
+    };
+    let _lock = QUEUE_LOCK.lock().unwrap();
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Vec::new();
+    };
+    privileges::as_invoking_user(|| fs::remove_file(&path)).ok();
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,84 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+use crate::output::verbose;
+use crate::privileges;
+
+/// Desktop file of the terminal whose launcher icon shows progress, once
+/// enabled, and the session bus address to reach it on, if it needs setting.
+static TARGET: OnceLock<(String, Option<String>)> = OnceLock::new();
+
+/// Desktop files of terminals that can be recognized by their environment.
+const TERMINALS: &[(&str, &str)] = &[
+    ("KONSOLE_VERSION", "org.kde.konsole.desktop"),
+    ("GNOME_TERMINAL_SERVICE", "org.gnome.Terminal.desktop"),
+    ("TILIX_ID", "com.gexperts.Tilix.desktop"),
+    ("KITTY_WINDOW_ID", "kitty.desktop"),
+    ("ALACRITTY_WINDOW_ID", "Alacritty.desktop"),
+];
+
+/// Turns on progress on the terminal's launcher (dock or taskbar) icon, for
+/// the terminal with desktop file `app` or, if not given, the one recognized
+/// from the environment.
+pub fn enable(app: Option<String>) {
+    let app = app.or_else(|| {
+        TERMINALS
+            .iter()
+            .find(|(var, _)| std::env::var_os(var).is_some())
+            .map(|(_, app)| app.to_string())
+    });
+    let Some(app) = app else {
+        verbose!("Unknown terminal; set launcher_app in the config file");
+        return;
+    };
+
+    // sudo doesn't pass on the address of the invoking user's session bus.
+    // Work it out now, before privileges are dropped.
+    let bus = privileges::invoking_user()
+        .filter(|_| std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none())
+        .map(|user| format!("/run/user/{}/bus", user.uid()))
+        .filter(|bus| Path::new(bus).exists())
+        .map(|bus| format!("unix:path={bus}"));
+    TARGET.set((app, bus)).ok();
+}
+
+pub fn is_enabled() -> bool {
+    TARGET.get().is_some()
+}
+
+/// Shows `progress` (from 0 to 1) on the launcher icon, or hides it.
+///
+/// This emits `com.canonical.Unity.LauncherEntry.Update` on the session bus
+/// with `gdbus`. Docks and task managers that implement it (GNOME's Dash to
+/// Dock, KDE Plasma, Plank) draw a progress bar on the app's icon. Failures
+/// are ignored; with `--sandbox`, `gdbus` can't be started at all.
+pub fn set_progress(progress: Option<f64>) {
+    let Some((app, bus)) = TARGET.get() else {
+        return;
+    };
+    let properties = match progress {
+        Some(progress) => format!("{{'progress': <{progress:.3}>, 'progress-visible': <true>}}"),
+        None => "{'progress-visible': <false>}".to_string(),
+    };
+
+    let mut command = Command::new("gdbus");
+    command
+        .args(["emit", "--session", "--object-path"])
+        .arg(format!(
+            "/com/canonical/unity/launcherentry/{}",
+            std::process::id()
+        ))
+        .args(["--signal", "com.canonical.Unity.LauncherEntry.Update"])
+        .arg(format!("application://{app}"))
+        .arg(properties)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    if let Some(bus) = bus {
+        command.env("DBUS_SESSION_BUS_ADDRESS", bus);
+    }
+    if let Err(e) = command.status() {
+        verbose!("Updating the launcher icon failed: {e}");
+    }
+}
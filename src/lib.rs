@@ -0,0 +1,83 @@
+//! The workings of the `etchr` command, for programs that flash images
+//! themselves. [`Device::enumerate`] finds the devices that are safe to
+//! write, [`WriteJob`] and [`ReadJob`] write an image to them or read one
+//! back, and an [`Observer`] is told how a job is getting on, in place of
+//! the progress bars the command draws.
+
+mod api;
+mod audit;
+mod buffers;
+mod cancel;
+mod card;
+mod catalog;
+mod checksum;
+mod clone;
+mod compress;
+mod compression;
+mod config;
+mod customize;
+mod device;
+mod events;
+mod exclude;
+mod history;
+mod i18n;
+mod json;
+mod label;
+mod launcher;
+mod link;
+mod list;
+#[cfg(target_os = "macos")]
+mod macos;
+mod man;
+mod mapfile;
+mod menu;
+mod notify;
+mod output;
+mod partition;
+mod pause;
+mod picker;
+mod policy;
+mod privileges;
+mod probe;
+mod progress;
+mod prompt;
+mod read;
+mod readahead;
+mod report;
+mod s3;
+mod safety;
+mod sandbox;
+mod setup;
+mod shared;
+mod signature;
+mod sink;
+mod slow;
+mod source;
+mod spinner;
+mod spool;
+mod stats;
+mod status;
+mod testimage;
+mod timing;
+mod title;
+#[cfg(feature = "torrent")]
+mod torrent;
+mod units;
+mod unlock;
+mod verify;
+mod wear;
+mod write;
+mod zip;
+
+/// The command itself, which `main.rs` runs. Not part of the library.
+#[doc(hidden)]
+// Its doc comments are the command's help, not rustdoc
+#[allow(rustdoc::invalid_html_tags)]
+pub mod cli;
+
+pub use api::{ReadJob, WriteJob};
+pub use cancel::CancelToken;
+pub use compress::{Codec, Compression};
+pub use device::Device;
+pub use events::{Event, Observer};
+pub use write::Failure;
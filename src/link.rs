@@ -0,0 +1,207 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::{Msg, tr, trf};
+
+/// How a disk is connected and how fast the connection runs, which is
+/// often what limits write speed rather than the card itself.
+#[derive(Clone)]
+pub enum Link {
+    /// A disk, stick or card reader on USB.
+    Usb {
+        /// Negotiated speed in Mbps, e.g. 480 or 5000.
+        speed: f64,
+        /// USB version the device supports, e.g. 3.2.
+        version: Option<f64>,
+        /// Whether a hub between it and the computer only runs at USB 2.0
+        /// speed or slower.
+        behind_slow_hub: bool,
+    },
+    /// An SD card or eMMC in a native slot.
+    Mmc {
+        /// Bus timing, e.g. `sd uhs SDR104`, if the kernel's debugfs can
+        /// be read.
+        timing: Option<String>,
+        /// Bus clock in Hz.
+        clock: Option<u64>,
+        /// UHS speed grade from the card's status register, 0 if it isn't a
+        /// UHS card.
+        uhs_grade: Option<u8>,
+        /// Sequential write speed in bytes per second that the card's speed
+        /// class, UHS grade or video speed class guarantees, the highest.
+        class_speed: Option<u64>,
+    },
+}
+
+/// USB 3 runs at 5 Gbps and up; anything slower is USB 2.0 or older.
+const USB3_SPEED: f64 = 5000.0;
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Formats a speed in Mbps, e.g. `480 Mbps` or `10 Gbps`.
+fn describe_speed(mbps: f64) -> String {
+    if mbps >= 1000.0 {
+        format!("{} Gbps", mbps / 1000.0)
+    } else {
+        format!("{mbps} Mbps")
+    }
+}
+
+impl Link {
+    /// Reads the connection of block device `device_name` (e.g. `sdb`)
+    /// from sysfs. Returns `None` if it's on neither USB nor MMC.
+    pub fn read(device_name: &str) -> Option<Self> {
+        let path = PathBuf::from("/sys/block")
+            .join(device_name)
+            .canonicalize()
+            .ok()?;
+        if path.to_string_lossy().contains("/usb") {
+            Self::read_usb(&path)
+        } else if path.to_string_lossy().contains("/mmc_host/") {
+            Some(Self::read_mmc(device_name, &path))
+        } else {
+            None
+        }
+    }
+
+    /// Finds the USB device among the ancestors of `path`, the first with a
+    /// `speed`; the ones above it are hubs, root hubs being named `usbN`.
+    fn read_usb(path: &Path) -> Option<Self> {
+        let mut usb_devices = path
+            .ancestors()
+            .filter_map(|dir| Some((dir, read_trimmed(&dir.join("speed"))?.parse::<f64>().ok()?)));
+        let (device, speed) = usb_devices.next()?;
+        let version = read_trimmed(&device.join("version")).and_then(|v| v.parse().ok());
+        let behind_slow_hub = usb_devices.any(|(hub, speed)| {
+            let root = hub
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("usb"));
+            !root && speed < USB3_SPEED
+        });
+        Some(Link::Usb {
+            speed,
+            version,
+            behind_slow_hub,
+        })
+    }
+
+    fn read_mmc(device_name: &str, path: &Path) -> Self {
+        // e.g. .../mmc_host/mmc0/mmc0:aaaa/block/mmcblk0
+        let host = path
+            .ancestors()
+            .find(|dir| dir.parent().is_some_and(|p| p.ends_with("mmc_host")))
+            .and_then(|dir| dir.file_name())
+            .map(|name| name.to_string_lossy().into_owned());
+        // Only root can read debugfs, and only if it's mounted
+        let ios = host.and_then(|host| {
+            fs::read_to_string(PathBuf::from("/sys/kernel/debug").join(host).join("ios")).ok()
+        });
+        let field = |name: &str| {
+            ios.as_deref()?
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .map(str::trim)
+        };
+        // e.g. "timing spec:	6 (sd uhs SDR104)"
+        let timing = field("timing spec")
+            .and_then(|v| v.split_once('(')?.1.strip_suffix(')'))
+            .map(str::to_string);
+        let clock = field("actual clock")
+            .or_else(|| field("clock"))
+            .and_then(|v| v.trim_end_matches("Hz").trim().parse().ok());
+
+        // The 512-bit SD status, as hex; bit 511 comes first
+        let ssr = read_trimmed(
+            &PathBuf::from("/sys/block")
+                .join(device_name)
+                .join("device/ssr"),
+        )
+        .filter(|ssr| ssr.len() == 128);
+        let bits = |high: usize, low: usize| {
+            let ssr = ssr.as_deref()?;
+            let bits = u32::from_str_radix(&ssr[(511 - high) / 4..(511 - low) / 4 + 1], 16).ok()?;
+            Some(bits)
+        };
+        let uhs_grade = bits(399, 396).map(|grade| grade as u8);
+        // SPEED_CLASS 1-4 stands for class 2, 4, 6 and 10 (in MB/s); UHS
+        // grades 1 and 3 and video speed classes are MB/s as is
+        let speed_class =
+            bits(447, 440).and_then(|class| [0, 2, 4, 6, 10].get(class as usize).copied());
+        let class_speed = [
+            speed_class,
+            uhs_grade.map(|g| g as u32 * 10),
+            bits(391, 384),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .filter(|mbs| *mbs > 0)
+        .map(|mbs| u64::from(mbs) * 1_000_000);
+        Link::Mmc {
+            timing,
+            clock,
+            uhs_grade,
+            class_speed,
+        }
+    }
+
+    /// The write speed in bytes per second the disk guarantees, if known.
+    /// Only cards in a native slot tell; behind a USB reader, they don't.
+    pub fn guaranteed_write_speed(&self) -> Option<u64> {
+        match self {
+            Link::Mmc { class_speed, .. } => *class_speed,
+            Link::Usb { .. } => None,
+        }
+    }
+
+    /// Explains why the link holds back the disk named `device_name`, if
+    /// it does: a USB 3 device running at USB 2.0 speed, or a UHS card
+    /// running in an older mode.
+    pub fn warning(&self, device_name: &str) -> Option<String> {
+        match self {
+            Link::Usb {
+                speed,
+                version: Some(version),
+                behind_slow_hub,
+            } if *version >= 3.0 && *speed < USB3_SPEED => {
+                let msg = if *behind_slow_hub {
+                    Msg::UsbBehindSlowHub
+                } else {
+                    Msg::UsbSlowPort
+                };
+                Some(trf(msg, &[&device_name, version, &describe_speed(*speed)]))
+            }
+            Link::Mmc {
+                timing: Some(timing),
+                uhs_grade: Some(grade),
+                ..
+            } if *grade > 0 && !timing.contains("uhs") => {
+                Some(trf(Msg::UhsNotUsed, &[&device_name, timing]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Link::Usb { speed, .. } => {
+                write!(f, "{}", trf(Msg::UsbLink, &[&describe_speed(*speed)]))
+            }
+            Link::Mmc { timing, clock, .. } => {
+                write!(f, "{}", tr(Msg::MmcLink))?;
+                if let Some(timing) = timing {
+                    write!(f, ", {timing}")?;
+                }
+                if let Some(clock) = clock {
+                    write!(f, ", {} MHz", clock / 1_000_000)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use clap::ValueEnum;
+use console::{Term, measure_text_width, truncate_str};
+
+use crate::device::Device;
+use crate::i18n::{Msg, tr, trf};
+
+/// Output formats for `etchr list`.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// Human-readable table sized to the terminal
+    #[default]
+    Table,
+    /// Tab-separated values
+    Tsv,
+    /// Comma-separated values
+    Csv,
+}
+
+/// Width assumed when stdout is not a terminal.
+const DEFAULT_WIDTH: usize = 80;
+
+const DEVICE_WIDTH: usize = 12;
+const SIZE_WIDTH: usize = 10;
+const MIN_NAME_WIDTH: usize = 8;
+const MAX_NAME_WIDTH: usize = 25;
+const MIN_LOCATION_WIDTH: usize = 14;
+
+fn location(device: &Device) -> &str {
+    if device.mount_point.is_empty() {
+        tr(Msg::NotMounted)
+    } else {
+        &device.mount_point
+    }
+}
+
+/// Pads `s` with spaces to `width` columns, truncating with an ellipsis if needed.
+fn fit(s: &str, width: usize) -> String {
+    let s = truncate_str(s, width, "…");
+    let pad = width.saturating_sub(measure_text_width(&s));
+    format!("{s}{}", " ".repeat(pad))
+}
+
+fn print_table(devices: &[Device]) {
+    let width = Term::stdout()
+        .size_checked()
+        .map(|(_, cols)| cols as usize)
+        .unwrap_or(DEFAULT_WIDTH);
+
+    // Fixed columns plus indentation and separators.
+    let fixed = 2 + DEVICE_WIDTH + 1 + 1 + SIZE_WIDTH + 2;
+    let remaining = width.saturating_sub(fixed);
+    let name_width = remaining
+        .saturating_sub(MIN_LOCATION_WIDTH)
+        .clamp(MIN_NAME_WIDTH, MAX_NAME_WIDTH);
+    let location_width = remaining.saturating_sub(name_width).max(MIN_LOCATION_WIDTH);
+
+    println!("{}", trf(Msg::FoundDevices, &[&devices.len()]));
+    println!(
+        "\n  {} {} {:<SIZE_WIDTH$}  LOCATION",
+        fit("DEVICE", DEVICE_WIDTH),
+        fit("NAME", name_width),
+        "SIZE"
+    );
+    println!(
+        "  {} {} {}  {}",
+        "-".repeat(DEVICE_WIDTH),
+        "-".repeat(name_width),
+        "-".repeat(SIZE_WIDTH),
+        "-".repeat(location_width)
+    );
+    for device in devices {
+        println!(
+            "  {} {} {:>7.1} GB  {}",
+            fit(&device.path.display().to_string(), DEVICE_WIDTH),
+            fit(&device.name, name_width),
+            device.size_gb,
+            truncate_str(location(device), location_width, "…")
+        );
+        if let Some(card) = &device.card {
+            println!("  {}{card}", " ".repeat(DEVICE_WIDTH + 1));
+        }
+        if let Some(link) = &device.link {
+            println!("  {}{link}", " ".repeat(DEVICE_WIDTH + 1));
+            if let Some(warning) = link.warning(&device.name) {
+                println!("  {}{warning}", " ".repeat(DEVICE_WIDTH + 1));
+            }
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains a separator, quote or newline.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn print_delimited(devices: &[Device], sep: &str, quote: fn(&str) -> String) {
+    println!(
+        "{}",
+        [
+            "device",
+            "name",
+            "size_bytes",
+            "mount_point",
+            "card_manufacturer_id",
+            "card_product",
+            "card_serial",
+            "card_date",
+            "link"
+        ]
+        .join(sep)
+    );
+    for device in devices {
+        let card = device.card.as_ref();
+        let fields = [
+            device.path.display().to_string(),
+            device.name.clone(),
+            device.size_bytes.to_string(),
+            device.mount_point.clone(),
+            card.map(|c| format!("{:#04x}", c.manufacturer_id))
+                .unwrap_or_default(),
+            card.map(|c| c.product.clone()).unwrap_or_default(),
+            card.map(|c| c.serial.clone()).unwrap_or_default(),
+            card.map(|c| c.date.clone()).unwrap_or_default(),
+            device
+                .link
+                .as_ref()
+                .map(|l| l.to_string())
+                .unwrap_or_default(),
+        ];
+        let fields: Vec<String> = fields.iter().map(|f| quote(f)).collect();
+        println!("{}", fields.join(sep));
+    }
+}
+
+/// Prints the device list in the requested format.
+pub fn print(devices: &[Device], format: Format) {
+    match format {
+        Format::Table => {
+            if devices.is_empty() {
+                println!("{}", tr(Msg::NoRemovableDevices));
+            } else {
+                print_table(devices);
+            }
+        }
+        Format::Tsv => print_delimited(devices, "\t", |s| s.replace('\t', " ")),
+        Format::Csv => print_delimited(devices, ",", csv_field),
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+use console::style;
+use nix::ioctl_read;
+
+use crate::device::{Device, Usage};
+use crate::exclude::Exclusions;
+use crate::i18n::{Msg, tr};
+use crate::output::verbose;
+use crate::wear;
+
+// From <sys/disk.h>: the size of a disk's blocks and how many it has.
+ioctl_read!(dkiocgetblocksize, b'd', 24, u32);
+ioctl_read!(dkiocgetblockcount, b'd', 25, u64);
+
+/// Returns the size of the disk open as `file`, which its metadata reports
+/// as zero.
+pub fn disk_size(file: &File) -> io::Result<u64> {
+    let mut block_size: u32 = 0;
+    let mut block_count: u64 = 0;
+    unsafe {
+        dkiocgetblocksize(file.as_raw_fd(), &mut block_size)?;
+        dkiocgetblockcount(file.as_raw_fd(), &mut block_count)?;
+    }
+    Ok(block_count * block_size as u64)
+}
+
+/// The disk behind a device node, e.g. "disk4" for `/dev/rdisk4`.
+fn disk_name(path: &Path) -> String {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.strip_prefix('r').unwrap_or(&name).to_string()
+}
+
+/// The whole disk a partition is on, e.g. "disk0" for "disk0s2".
+fn whole_disk(name: &str) -> &str {
+    let digits = name["disk".len().min(name.len())..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(name.len(), |i| i + "disk".len());
+    &name[..digits]
+}
+
+/// Runs `diskutil` and returns what it printed, a property list with
+/// `-plist`.
+fn diskutil(args: &[&str]) -> Result<String> {
+    let output = Command::new("diskutil")
+        .args(args)
+        .output()
+        .context("running diskutil")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "diskutil {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Every value of `key` in a property list, anywhere in it: strings and
+/// numbers as they're written, and booleans as "true" or "false".
+fn values(plist: &str, key: &str) -> Vec<String> {
+    let tag = format!("<key>{key}</key>");
+    let mut values = Vec::new();
+    let mut rest = plist;
+    while let Some(at) = rest.find(&tag) {
+        rest = rest[at + tag.len()..].trim_start();
+        if rest.starts_with("<true/>") {
+            values.push("true".to_string());
+        } else if rest.starts_with("<false/>") {
+            values.push("false".to_string());
+        } else if let Some(value) = element(rest) {
+            values.push(value);
+        }
+    }
+    values
+}
+
+/// The first value of `key` in a property list.
+fn value(plist: &str, key: &str) -> Option<String> {
+    values(plist, key).into_iter().next()
+}
+
+fn is_true(plist: &str, key: &str) -> bool {
+    value(plist, key).as_deref() == Some("true")
+}
+
+/// The strings in the array under `key`, e.g. `WholeDisks`.
+fn strings(plist: &str, key: &str) -> Vec<String> {
+    let tag = format!("<key>{key}</key>");
+    let Some(at) = plist.find(&tag) else {
+        return Vec::new();
+    };
+    let rest = plist[at + tag.len()..].trim_start();
+    let Some(array) = rest
+        .strip_prefix("<array>")
+        .and_then(|array| Some(&array[..array.find("</array>")?]))
+    else {
+        return Vec::new();
+    };
+    array
+        .split("<string>")
+        .skip(1)
+        .filter_map(|s| Some(unescape(&s[..s.find("</string>")?])))
+        .collect()
+}
+
+/// The contents of the simple element `text` starts with, e.g. "disk4" for
+/// `<string>disk4</string>`.
+fn element(text: &str) -> Option<String> {
+    let name = text.strip_prefix('<')?.split('>').next()?;
+    let contents = &text[name.len() + 2..];
+    let end = contents.find(&format!("</{name}>"))?;
+    Some(unescape(&contents[..end]))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The disks holding the running system: the one `/` is on, and for APFS
+/// the physical disks behind its container.
+fn system_disks() -> Vec<String> {
+    let Ok(info) = diskutil(&["info", "-plist", "/"]) else {
+        return Vec::new();
+    };
+    let mut disks: Vec<String> = values(&info, "ParentWholeDisk");
+    disks.extend(
+        values(&info, "APFSPhysicalStore")
+            .iter()
+            .map(|store| whole_disk(store).to_string()),
+    );
+    disks
+}
+
+/// Finds the disks to offer as [`crate::device::get_removable_devices`]
+/// does on Linux, from `diskutil`: removable and USB disks, or with
+/// `include_internal` any physical disk, but never the system's. They're
+/// given as their raw nodes (`/dev/rdisk4`), which skip the buffer cache
+/// and are many times faster to write.
+pub fn removable_devices(include_internal: bool) -> Result<Vec<Device>> {
+    let system = system_disks();
+    let root_known = !system.is_empty();
+    if !root_known {
+        // Without knowing the system drive, only offer what can't be it
+        eprintln!(
+            "{} {}",
+            style(tr(Msg::Warning)).yellow().bold(),
+            tr(Msg::SystemDriveUnknown)
+        );
+    }
+    let exclusions = Exclusions::load();
+    let list = diskutil(&["list", "-plist", "physical"])?;
+    let all_disks = strings(&list, "AllDisks");
+
+    let mut devices = Vec::new();
+    for name in strings(&list, "WholeDisks") {
+        if system.contains(&name) {
+            verbose!("Skipping {name}: system drive");
+            continue;
+        }
+        if let Some(entry) = exclusions.matching(&name) {
+            verbose!("Skipping {name}: excluded by the administrator ({entry})");
+            continue;
+        }
+
+        let info = diskutil(&["info", "-plist", &name])?;
+        let removable = is_true(&info, "RemovableMedia") || is_true(&info, "Removable");
+        let usb = value(&info, "BusProtocol").as_deref() == Some("USB");
+        if !(removable || usb || (include_internal && root_known)) {
+            verbose!("Skipping {name}: not removable");
+            continue;
+        }
+        let size_bytes: u64 = value(&info, "TotalSize")
+            .or_else(|| value(&info, "Size"))
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(0);
+        if size_bytes == 0 {
+            verbose!("Skipping {name}: no medium (size is 0)");
+            continue;
+        }
+
+        let mut mount_point = String::new();
+        let mut usage = Vec::new();
+        let prefix = format!("{name}s");
+        for partition in all_disks.iter().filter(|d| d.starts_with(&prefix)) {
+            let Ok(info) = diskutil(&["info", "-plist", partition]) else {
+                continue;
+            };
+            let Some(mp) = value(&info, "MountPoint").filter(|mp| !mp.is_empty()) else {
+                continue;
+            };
+            if mount_point.is_empty() {
+                mount_point = mp.clone();
+            }
+            let number = |key: &str| value(&info, key).and_then(|v| v.parse().ok());
+            usage.push(Usage {
+                source: partition.clone(),
+                mount_point: mp,
+                fs_type: value(&info, "FilesystemType").unwrap_or_default(),
+                total: number("TotalSize").unwrap_or(0),
+                available: number("FreeSpace").unwrap_or(0),
+            });
+        }
+
+        let burns = wear::count(&name);
+        devices.push(Device {
+            path: PathBuf::from(format!("/dev/r{name}")),
+            name,
+            size_gb: size_bytes as f64 / (1024.0 * 1024.0 * 1024.0),
+            size_bytes,
+            mount_point,
+            removable,
+            usb,
+            card: None,
+            link: None,
+            burns,
+            usage,
+        });
+    }
+    Ok(devices)
+}
+
+/// Unmounts every volume of the disk at `device_path`, which macOS
+/// requires before the disk can be written raw. The disk stays attached.
+pub fn unmount_disk(device_path: &Path) -> Result<()> {
+    let name = disk_name(device_path);
+    diskutil(&["unmountDisk", &name])?;
+    verbose!("Unmounted the volumes of {name}");
+    Ok(())
+}
+
+/// Ejects the disk at `device_path`, unmounting its volumes first.
+pub fn eject_disk(device_path: &Path) -> Result<()> {
+    diskutil(&["eject", &disk_name(device_path)])?;
+    Ok(())
+}
+
+/// The partitions of the disk at `device_path` (e.g. `/dev/disk4s1`), in
+/// order, as macOS finds them once the disk is written.
+pub fn partitions(device_path: &Path) -> Result<Vec<PathBuf>> {
+    let name = disk_name(device_path);
+    let list = diskutil(&["list", "-plist", &name])?;
+    let prefix = format!("{name}s");
+    let mut partitions: Vec<(u32, PathBuf)> = strings(&list, "AllDisks")
+        .into_iter()
+        .filter_map(|disk| {
+            let number = disk.strip_prefix(&prefix)?.parse().ok()?;
+            Some((number, PathBuf::from("/dev").join(disk)))
+        })
+        .collect();
+    partitions.sort();
+    Ok(partitions.into_iter().map(|(_, path)| path).collect())
+}
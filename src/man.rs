@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use clap::Command;
+use clap_mangen::Man;
+
+/// Builds `cmd`, so that global arguments are propagated into its
+/// subcommands and each is named after its page (e.g. `etchr-write`).
+fn build(cmd: Command) -> Command {
+    let mut cmd = cmd.disable_help_subcommand(true);
+    cmd.build();
+    cmd
+}
+
+/// The page of `cmd`, with the program and its version (e.g.
+/// `etchr 1.0.0`) in the footer, which subcommands don't carry.
+fn page(cmd: Command, source: &str) -> Man {
+    Man::new(cmd).source(source)
+}
+
+/// The footer [`page`] gives the pages of `cmd`.
+fn source(cmd: &Command) -> String {
+    format!(
+        "{} {}",
+        cmd.get_name(),
+        cmd.get_version().unwrap_or_default()
+    )
+}
+
+/// Subcommands that get their own page.
+fn documented_subcommands(cmd: &Command) -> impl Iterator<Item = &Command> {
+    cmd.get_subcommands().filter(|s| !s.is_hide_set())
+}
+
+/// Renders the man page of `cmd`, or of its subcommand `name` (e.g.
+/// `write`) if given.
+pub fn render(cmd: Command, name: Option<&str>) -> Result<Vec<u8>> {
+    let cmd = build(cmd);
+    let source = source(&cmd);
+    let cmd = match name {
+        Some(name) => documented_subcommands(&cmd)
+            .find(|sub| sub.get_name() == name)
+            .cloned()
+            .ok_or_else(|| anyhow!("No man page named {}-{name}.1", cmd.get_name()))?,
+        None => cmd,
+    };
+    let mut contents = Vec::new();
+    page(cmd, &source).render(&mut contents)?;
+    Ok(contents)
+}
+
+/// Writes the man pages of `cmd` and its subcommands into `dir`, creating
+/// it if needed.
+pub fn install(cmd: Command, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let cmd = build(cmd);
+    let source = source(&cmd);
+    for cmd in std::iter::once(&cmd).chain(documented_subcommands(&cmd)) {
+        let path = page(cmd.clone(), &source)
+            .generate_to(dir)
+            .with_context(|| format!("writing a man page into {}", dir.display()))?;
+        println!("Installed {}", path.display());
+    }
+    Ok(())
+}
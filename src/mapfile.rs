@@ -0,0 +1,55 @@
+use std::io::{self, Write};
+
+/// Status characters of a GNU ddrescue mapfile block.
+const FINISHED: char = '+';
+const BAD_SECTOR: char = '-';
+const NON_TRIED: char = '?';
+
+/// Writes the state of a read as a GNU ddrescue mapfile, so that ddrescue
+/// can carry on with the recovery: the first `done` bytes of the `size`
+/// bytes were read, except the `sector_size`-byte sectors at the sorted
+/// offsets in `bad`.
+pub fn write(
+    out: &mut impl Write,
+    size: u64,
+    done: u64,
+    bad: &[u64],
+    sector_size: u64,
+) -> io::Result<()> {
+    let mut blocks: Vec<(u64, u64, char)> = Vec::new();
+    let mut push = |pos: u64, end: u64, status: char| {
+        if end <= pos {
+            return;
+        }
+        // Adjacent blocks of the same status are merged, as ddrescue does
+        match blocks.last_mut() {
+            Some((last_pos, last_len, last)) if *last == status && *last_pos + *last_len == pos => {
+                *last_len = end - *last_pos;
+            }
+            _ => blocks.push((pos, end - pos, status)),
+        }
+    };
+
+    let mut pos = 0;
+    for &sector in bad {
+        push(pos, sector, FINISHED);
+        pos = (sector + sector_size).min(size);
+        push(sector, pos, BAD_SECTOR);
+    }
+    push(pos, done, FINISHED);
+    push(done, size, NON_TRIED);
+
+    let status = if done < size { NON_TRIED } else { FINISHED };
+    writeln!(
+        out,
+        "# Mapfile. Created by etchr version {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(out, "# current_pos  current_status  current_pass")?;
+    writeln!(out, "0x{done:08X}     {status}               1")?;
+    writeln!(out, "#      pos        size  status")?;
+    for (pos, len, status) in blocks {
+        writeln!(out, "0x{pos:08X}  0x{len:08X}  {status}")?;
+    }
+    Ok(())
+}
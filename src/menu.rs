@@ -0,0 +1,48 @@
+use anyhow::Result;
+use dialoguer::{FuzzySelect, Select, theme::ColorfulTheme};
+
+use crate::output;
+use crate::prompt;
+
+/// Lists longer than this can be filtered by typing.
+const FILTER_THRESHOLD: usize = 10;
+
+/// Lists `items` as numbered lines and reads the number of one, for
+/// serial consoles where the cursor can't be moved around.
+fn select_numbered(prompt: &str, items: &[String]) -> Result<usize> {
+    for (i, item) in items.iter().enumerate() {
+        eprintln!("  {:>2}) {item}", i + 1);
+    }
+    loop {
+        let answer = prompt::read_line(&format!("{prompt} [1-{}]:", items.len()))?;
+        if let Ok(number) = answer.trim().parse::<usize>()
+            && (1..=items.len()).contains(&number)
+        {
+            return Ok(number - 1);
+        }
+    }
+}
+
+/// Presents a selection menu and returns the index of the chosen item.
+/// Long lists can be narrowed by typing a few characters, which shows only
+/// fuzzy matches, best first. On a serial console, all items are listed
+/// with numbers instead.
+pub fn select(prompt: &str, items: &[String]) -> Result<usize> {
+    prompt::ensure_askable(prompt)?;
+    if output::is_serial() {
+        return select_numbered(prompt, items);
+    }
+    let theme = ColorfulTheme::default();
+    if items.len() <= FILTER_THRESHOLD {
+        return Ok(Select::with_theme(&theme)
+            .with_prompt(prompt)
+            .items(items)
+            .default(0)
+            .interact()?);
+    }
+    Ok(FuzzySelect::with_theme(&theme)
+        .with_prompt(prompt)
+        .items(items)
+        .default(0)
+        .interact()?)
+}
@@ -0,0 +1,81 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::OnceLock;
+
+use indicatif::HumanBytes;
+
+use crate::output::verbose;
+
+/// The connection to systemd's notification socket, when running as a
+/// service with `Type=notify` (or with `WatchdogSec=`).
+struct Notifier {
+    socket: UnixDatagram,
+    /// Whether systemd expects watchdog pings from this process.
+    watchdog: bool,
+}
+
+static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+
+/// Connects to `$NOTIFY_SOCKET`, if systemd set it. This happens up
+/// front, as the sandbox doesn't allow connecting later.
+pub fn init() {
+    NOTIFIER.get_or_init(|| {
+        let path = std::env::var("NOTIFY_SOCKET").ok()?;
+        let connect = || {
+            let socket = UnixDatagram::unbound()?;
+            match path.strip_prefix('@') {
+                Some(name) => socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?,
+                None => socket.connect(&path)?,
+            }
+            std::io::Result::Ok(socket)
+        };
+        let socket = connect()
+            .inspect_err(|e| verbose!("Connecting to systemd's notification socket failed: {e}"))
+            .ok()?;
+        let watchdog = std::env::var("WATCHDOG_USEC").is_ok()
+            && std::env::var("WATCHDOG_PID")
+                .ok()
+                .is_none_or(|pid| pid == std::process::id().to_string());
+        Some(Notifier { socket, watchdog })
+    });
+}
+
+fn send(message: &str) {
+    if let Some(Some(notifier)) = NOTIFIER.get() {
+        notifier.socket.send(message.as_bytes()).ok();
+    }
+}
+
+/// Tells systemd that etchr has started up.
+pub fn ready() {
+    send("READY=1\nSTATUS=Starting");
+}
+
+/// Shows `status` in `systemctl status`.
+pub fn status(status: &str) {
+    send(&format!("STATUS={}", status.replace('\n', " ")));
+}
+
+/// Shows how far `phase` of the operation on `device` got, and tells the
+/// watchdog that etchr is still making progress.
+pub fn progress(device: &str, phase: &str, done: u64, total: u64, rate: u64) {
+    let status = match (done * 100).checked_div(total) {
+        Some(percent) => format!(
+            "{phase} {device}: {percent}% ({} of {}, {}/s)",
+            HumanBytes(done),
+            HumanBytes(total),
+            HumanBytes(rate)
+        ),
+        None => format!(
+            "{phase} {device}: {} ({}/s)",
+            HumanBytes(done),
+            HumanBytes(rate)
+        ),
+    };
+    let watchdog = matches!(NOTIFIER.get(), Some(Some(n)) if n.watchdog);
+    if watchdog {
+        send(&format!("STATUS={status}\nWATCHDOG=1"));
+    } else {
+        send(&format!("STATUS={status}"));
+    }
+}
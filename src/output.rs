@@ -0,0 +1,88 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use console::style;
+
+use crate::events::{self, Event};
+use crate::i18n::{Msg, tr};
+use crate::progress;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static SILENT: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+static SERIAL: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Sets the output verbosity for the rest of the run, whether the
+/// terminal is a serial console without cursor addressing, and whether a
+/// program reads JSON records instead (`--json`).
+pub fn init(quiet: bool, verbose: bool, serial: bool, json: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+    VERBOSE.store(verbose, Ordering::SeqCst);
+    SERIAL.store(serial, Ordering::SeqCst);
+    JSON.store(json, Ordering::SeqCst);
+}
+
+/// Prints nothing at all while `silent`, not even warnings, as for a job
+/// run by a program using etchr as a library.
+pub fn silence(silent: bool) {
+    SILENT.store(silent, Ordering::SeqCst);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::SeqCst) || SILENT.load(Ordering::SeqCst)
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::SeqCst)
+}
+
+/// Whether prompts and progress must do without moving the cursor, as
+/// with `--serial-console`.
+pub fn is_serial() -> bool {
+    SERIAL.load(Ordering::SeqCst)
+}
+
+/// Whether a program drives etchr through JSON records, as with `--json`:
+/// there are no progress bars, and nobody to answer prompts.
+pub fn is_json() -> bool {
+    JSON.load(Ordering::SeqCst)
+}
+
+/// Prints an informational message to stdout, unless running with `--quiet`.
+/// Progress bars on screen are redrawn below it.
+macro_rules! info {
+    () => {
+        $crate::output::info!("")
+    };
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            let line = format!($($arg)*);
+            $crate::progress::suspend(|| println!("{line}"));
+        }
+    };
+}
+
+/// Prints a diagnostic message to stderr, only when running with `--verbose`.
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::output::is_verbose() {
+            let line = format!($($arg)*);
+            $crate::progress::suspend(|| {
+                eprintln!("{} {line}", console::style("[verbose]").dim())
+            });
+        }
+    };
+}
+
+pub(crate) use {info, verbose};
+
+/// Warns of something the operation carries on despite, on stderr even
+/// with `--quiet`. A library's observer is told instead.
+pub fn warn(message: impl Display) {
+    let message = message.to_string();
+    events::notify(Event::Warning { message: &message });
+    if !SILENT.load(Ordering::SeqCst) {
+        progress::suspend(|| eprintln!("{} {message}", style(tr(Msg::Warning)).yellow().bold()));
+    }
+}
@@ -0,0 +1,394 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+use anyhow::{Result, anyhow};
+use indicatif::HumanBytes;
+
+use crate::device;
+
+/// MBR partition types that hold logical partitions.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0f, 0x85];
+
+/// The MBR partition type covering a disk that has a GPT.
+const GPT_PROTECTIVE: u8 = 0xee;
+
+/// Logical partitions followed before giving up on a looping EBR chain.
+const MAX_LOGICAL: u32 = 128;
+
+/// A partition found in a partition table, in bytes from the start of the
+/// disk or image.
+#[derive(Clone, Copy, Debug)]
+pub struct Partition {
+    /// The number the kernel gives it, e.g. 2 for `sdb2`. Logical MBR
+    /// partitions start at 5.
+    pub number: u32,
+    pub start: u64,
+    pub len: u64,
+}
+
+/// The part of a disk an offset falls in, to tell what a difference at
+/// that offset affects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Region {
+    /// Before the first partition: the partition table, and whatever
+    /// boot loader sits beside it.
+    Table,
+    /// Partition number `n`.
+    Partition(u32),
+    /// Between or after the partitions, e.g. a GPT's backup.
+    Unpartitioned,
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Region::Table => write!(f, "partition table and boot area"),
+            Region::Partition(number) => write!(f, "partition {number}"),
+            Region::Unpartitioned => write!(f, "unpartitioned space"),
+        }
+    }
+}
+
+/// Where a partition table keeps its data, for copying it to another disk.
+pub enum Layout {
+    /// An MBR, and the offsets of the extended boot records that chain its
+    /// logical partitions.
+    Mbr { ebrs: Vec<u64> },
+    /// A GPT with `sector_size`-byte sectors, whose partition entries take
+    /// up `entries_len` bytes.
+    Gpt { sector_size: u64, entries_len: u64 },
+}
+
+/// The partitions of a disk or image, sorted by number.
+pub struct Table {
+    pub partitions: Vec<Partition>,
+    pub layout: Layout,
+}
+
+fn le32(bytes: &[u8]) -> u64 {
+    u64::from(u32::from_le_bytes(bytes[..4].try_into().unwrap()))
+}
+
+fn le64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+fn read_sector(file: &File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    file.read_exact_at(&mut buf, offset)?;
+    Ok(buf)
+}
+
+/// Reads the GPT whose header is at `sector_size`, if there is one there.
+fn read_gpt(file: &File, sector_size: u64) -> io::Result<Option<Table>> {
+    let header = read_sector(file, sector_size, 92)?;
+    if &header[..8] != b"EFI PART" {
+        return Ok(None);
+    }
+    let entries_lba = le64(&header[72..]);
+    let count = le32(&header[80..]) as usize;
+    let entry_size = le32(&header[84..]) as usize;
+    if entry_size < 128 || count > 1024 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "malformed GPT header",
+        ));
+    }
+
+    let entries = read_sector(file, entries_lba * sector_size, count * entry_size)?;
+    let partitions = entries
+        .chunks(entry_size)
+        .enumerate()
+        // An all-zero type GUID marks an unused entry
+        .filter(|(_, entry)| entry[..16].iter().any(|&b| b != 0))
+        .map(|(i, entry)| {
+            let first = le64(&entry[32..]);
+            let last = le64(&entry[40..]);
+            Partition {
+                number: i as u32 + 1,
+                start: first * sector_size,
+                len: (last + 1).saturating_sub(first) * sector_size,
+            }
+        })
+        .collect();
+    Ok(Some(Table {
+        partitions,
+        layout: Layout::Gpt {
+            sector_size,
+            entries_len: (count * entry_size) as u64,
+        },
+    }))
+}
+
+/// Follows the chain of extended boot records starting at `extended`,
+/// noting where each is in `ebrs`.
+fn read_logical(
+    file: &File,
+    extended: u64,
+    partitions: &mut Vec<Partition>,
+    ebrs: &mut Vec<u64>,
+) -> io::Result<()> {
+    let mut ebr = extended;
+    for number in 5..5 + MAX_LOGICAL {
+        let sector = read_sector(file, ebr * 512, 512)?;
+        if sector[510..] != [0x55, 0xaa] {
+            break;
+        }
+        ebrs.push(ebr * 512);
+        let entry = &sector[446..462];
+        if entry[4] != 0 {
+            partitions.push(Partition {
+                number,
+                start: (ebr + le32(&entry[8..])) * 512,
+                len: le32(&entry[12..]) * 512,
+            });
+        }
+        // The next EBR is given relative to the extended partition
+        let next = &sector[462..478];
+        if next[4] == 0 {
+            break;
+        }
+        ebr = extended + le32(&next[8..]);
+    }
+    Ok(())
+}
+
+/// Reads the partition table of a disk or disk image: a GPT (with 512 or
+/// 4096-byte sectors), or an MBR including its logical partitions.
+pub fn read_table(file: &File) -> io::Result<Table> {
+    let mbr = read_sector(file, 0, 512)?;
+    if mbr[510..] != [0x55, 0xaa] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no partition table found",
+        ));
+    }
+
+    let entries: Vec<&[u8]> = mbr[446..510].chunks(16).collect();
+    if entries.iter().any(|entry| entry[4] == GPT_PROTECTIVE) {
+        for sector_size in [512, 4096] {
+            if let Some(table) = read_gpt(file, sector_size)? {
+                return Ok(table);
+            }
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "protective MBR without a GPT",
+        ));
+    }
+
+    let mut partitions = Vec::new();
+    let mut ebrs = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let kind = entry[4];
+        if kind == 0 {
+            continue;
+        }
+        let start = le32(&entry[8..]);
+        if EXTENDED_TYPES.contains(&kind) {
+            read_logical(file, start, &mut partitions, &mut ebrs)?;
+        } else {
+            partitions.push(Partition {
+                number: i as u32 + 1,
+                start: start * 512,
+                len: le32(&entry[12..]) * 512,
+            });
+        }
+    }
+    partitions.sort_by_key(|p| p.number);
+    Ok(Table {
+        partitions,
+        layout: Layout::Mbr { ebrs },
+    })
+}
+
+/// Finds partition `number` of `file`, which is called `what` in errors.
+/// Fails, listing the partitions there are, if it doesn't exist or lies
+/// beyond the end of the file.
+pub fn find(file: &File, number: u32, what: &str) -> Result<Partition> {
+    let table =
+        read_table(file).map_err(|e| anyhow!("reading the partition table of {what}: {e}"))?;
+    let Some(partition) = table.get(number) else {
+        return Err(anyhow!(
+            "{what} has no partition {number}; its partitions are: {}",
+            table.describe()
+        ));
+    };
+    let size = device::file_size(file)?;
+    if partition.start + partition.len > size {
+        return Err(anyhow!(
+            "partition {number} extends past the end of {what}, which may be truncated"
+        ));
+    }
+    Ok(partition)
+}
+
+impl Table {
+    pub fn get(&self, number: u32) -> Option<Partition> {
+        self.partitions.iter().find(|p| p.number == number).copied()
+    }
+
+    /// Lists the partitions with their sizes, e.g. "1 (256 MiB), 2 (7 GiB)".
+    pub fn describe(&self) -> String {
+        if self.partitions.is_empty() {
+            return "none".to_string();
+        }
+        let list: Vec<String> = self
+            .partitions
+            .iter()
+            .map(|p| format!("{} ({})", p.number, HumanBytes(p.len)))
+            .collect();
+        list.join(", ")
+    }
+
+    /// The size of the sectors the table counts in, to give offsets as LBAs.
+    pub fn sector_size(&self) -> u64 {
+        match self.layout {
+            Layout::Mbr { .. } => 512,
+            Layout::Gpt { sector_size, .. } => sector_size,
+        }
+    }
+
+    /// The region `offset` falls in.
+    pub fn region(&self, offset: u64) -> Region {
+        if let Some(partition) = self
+            .partitions
+            .iter()
+            .find(|p| (p.start..p.start + p.len).contains(&offset))
+        {
+            return Region::Partition(partition.number);
+        }
+        let first = self.partitions.iter().map(|p| p.start).min();
+        if first.is_some_and(|first| offset < first) {
+            Region::Table
+        } else {
+            Region::Unpartitioned
+        }
+    }
+
+    /// The smallest disk the table can be copied to: one that holds every
+    /// partition, and for a GPT its backup at the end.
+    pub fn min_disk_size(&self) -> u64 {
+        let end = self.partitions.iter().map(|p| p.start + p.len).max();
+        let end = end.unwrap_or_default();
+        match self.layout {
+            Layout::Mbr { .. } => end,
+            Layout::Gpt {
+                sector_size,
+                entries_len,
+            } => end + entries_len.next_multiple_of(sector_size) + sector_size,
+        }
+    }
+
+    /// Copies the table from `source` to `target`, a disk of `target_size`
+    /// bytes, along with everything before the first partition (such as a
+    /// boot loader). A GPT gets its backup rewritten for the end of the
+    /// target. Partitions themselves aren't copied.
+    pub fn copy(&self, source: &File, target: &File, target_size: u64) -> io::Result<()> {
+        let first = self.partitions.iter().map(|p| p.start).min();
+        let first = first.unwrap_or(512);
+        let mut buf = vec![0u8; 1024 * 1024];
+        let mut offset = 0;
+        while offset < first {
+            let len = std::cmp::min(buf.len() as u64, first - offset) as usize;
+            source.read_exact_at(&mut buf[..len], offset)?;
+            target.write_all_at(&buf[..len], offset)?;
+            offset += len as u64;
+        }
+
+        match self.layout {
+            Layout::Mbr { ref ebrs } => {
+                for &ebr in ebrs {
+                    target.write_all_at(&read_sector(source, ebr, 512)?, ebr)?;
+                }
+            }
+            Layout::Gpt {
+                sector_size,
+                entries_len,
+            } => {
+                let entries_sectors = entries_len.div_ceil(sector_size);
+                let last_lba = target_size / sector_size - 1;
+                let mut header = read_sector(source, sector_size, sector_size as usize)?;
+                let header_len = (le32(&header[12..]) as usize).clamp(92, header.len());
+                let entries_lba = le64(&header[72..]);
+                let entries = read_sector(source, entries_lba * sector_size, entries_len as usize)?;
+
+                // The primary header points at the backup, at the target's end
+                put64(&mut header, 32, last_lba);
+                put64(&mut header, 48, last_lba - entries_sectors - 1);
+                seal_gpt_header(&mut header, header_len);
+                target.write_all_at(&header, sector_size)?;
+
+                let backup_entries_lba = last_lba - entries_sectors;
+                put64(&mut header, 24, last_lba);
+                put64(&mut header, 32, 1);
+                put64(&mut header, 72, backup_entries_lba);
+                seal_gpt_header(&mut header, header_len);
+                target.write_all_at(&entries, backup_entries_lba * sector_size)?;
+                target.write_all_at(&header, last_lba * sector_size)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn put64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Recomputes the CRC32 of a GPT header, which covers its first
+/// `header_len` bytes with the CRC field zeroed.
+fn seal_gpt_header(header: &mut [u8], header_len: usize) {
+    header[16..20].fill(0);
+    let crc = crc32fast::hash(&header[..header_len]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Describes what `partition` of `file` holds by its superblock: the
+/// filesystem's label and type, e.g. "rootfs ext4", or just the type.
+/// `None` for a filesystem that isn't recognized, or an unreadable one.
+pub fn contents(file: &File, partition: &Partition) -> Option<String> {
+    let sb = read_sector(file, partition.start, 4096).ok()?;
+    // A label is padded with NULs or spaces
+    let label = |bytes: &[u8]| {
+        let label = String::from_utf8_lossy(bytes);
+        let label = label.trim_end_matches(['\0', ' ']);
+        (!label.is_empty() && label != "NO NAME").then(|| label.to_string())
+    };
+    let (label, kind) = if sb[1080..1082] == [0x53, 0xef] {
+        let compat = le32(&sb[1116..]);
+        let incompat = le32(&sb[1120..]);
+        let kind = if incompat & 0x40 != 0 {
+            "ext4"
+        } else if compat & 0x4 != 0 {
+            "ext3"
+        } else {
+            "ext2"
+        };
+        (label(&sb[1144..1160]), kind)
+    } else if &sb[82..87] == b"FAT32" {
+        (label(&sb[71..82]), "vfat")
+    } else if &sb[54..57] == b"FAT" {
+        (label(&sb[43..54]), "vfat")
+    } else if &sb[3..11] == b"EXFAT   " {
+        (None, "exfat")
+    } else if &sb[3..11] == b"NTFS    " {
+        (None, "ntfs")
+    } else if &sb[..4] == b"XFSB" {
+        (label(&sb[108..120]), "xfs")
+    } else if &sb[..4] == b"hsqs" {
+        (None, "squashfs")
+    } else if le32(&sb[1024..]) == 0xf2f5_2010 {
+        (None, "f2fs")
+    } else if &sb[4086..] == b"SWAPSPACE2" {
+        (label(&sb[1052..1068]), "swap")
+    } else {
+        return None;
+    };
+    Some(match label {
+        Some(label) => format!("{label} {kind}"),
+        None => kind.to_string(),
+    })
+}
@@ -0,0 +1,156 @@
+use std::io::{IsTerminal, stdin};
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use indicatif::ProgressBar;
+use termios::{ECHO, ICANON, TCSANOW, Termios, tcsetattr};
+
+use crate::cancel::CancelToken;
+use crate::i18n::{Msg, tr};
+
+/// Set while the user has paused the running operation.
+/// Toggled by SIGUSR1 and by pressing 'p' on the terminal.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigusr1(_: libc::c_int) {
+    // Atomic operations are async-signal-safe.
+    PAUSED.fetch_xor(true, Ordering::SeqCst);
+}
+
+/// Installs the SIGUSR1 handler that toggles pause/resume.
+pub fn install_signal_handler() {
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            handle_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn is_paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+/// Blocks while the operation is paused, showing the state on `pb`.
+/// Returns early if the operation is cancelled in the meantime.
+pub fn wait_while_paused(pb: &ProgressBar, cancel: &CancelToken) {
+    if !is_paused() {
+        return;
+    }
+
+    let msg = pb.message();
+    pb.set_message(tr(Msg::Paused));
+    while is_paused() && !cancel.is_cancelled() {
+        thread::sleep(Duration::from_millis(100));
+    }
+    pb.set_message(msg);
+    // Don't let the pause skew the speed and ETA estimates.
+    pb.reset_eta();
+}
+
+/// Held by the [`KeyListener`] while it takes a key, and by
+/// [`suspend_keys`] while a question is asked. Holds the terminal settings
+/// from before the listener changed them, while it runs.
+static TERMINAL: Mutex<Option<Termios>> = Mutex::new(None);
+
+/// Runs `f`, which asks a question, with the terminal as it was before
+/// the [`KeyListener`] started (if one runs), so the answer is echoed and
+/// read by the question rather than taken as a key press.
+pub fn suspend_keys<R>(f: impl FnOnce() -> R) -> R {
+    let terminal = TERMINAL.lock().unwrap();
+    let Some(original) = *terminal else {
+        return f();
+    };
+    let fd = stdin().as_raw_fd();
+    let raw = Termios::from_fd(fd).ok();
+    tcsetattr(fd, TCSANOW, &original).ok();
+    let result = f();
+    if let Some(raw) = raw {
+        tcsetattr(fd, TCSANOW, &raw).ok();
+    }
+    result
+}
+
+/// Watches the terminal for 'p' key presses while an operation runs.
+/// The terminal is switched to non-canonical mode for the lifetime of the
+/// listener and restored when it is dropped, or while a question is asked
+/// (see [`suspend_keys`]).
+pub struct KeyListener {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    original_termios: Option<Termios>,
+}
+
+impl KeyListener {
+    pub fn start() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        if !stdin().is_terminal() {
+            return Self {
+                stop,
+                handle: None,
+                original_termios: None,
+            };
+        }
+
+        let fd = stdin().as_raw_fd();
+        let original_termios = Termios::from_fd(fd).ok().and_then(|original| {
+            let mut raw = original;
+            raw.c_lflag &= !(ICANON | ECHO);
+            tcsetattr(fd, TCSANOW, &raw).ok().map(|_| original)
+        });
+        *TERMINAL.lock().unwrap() = original_termios;
+
+        let s = stop.clone();
+        let handle = thread::spawn(move || {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            while !s.load(Ordering::SeqCst) {
+                // Poll with a timeout so the thread notices `stop` promptly.
+                let ready = unsafe { libc::poll(&mut pollfd, 1, 100) };
+                if ready <= 0 {
+                    continue;
+                }
+
+                // A question may have been asked meanwhile and taken the
+                // key as its answer, so check again that there is one
+                let _terminal = TERMINAL.lock().unwrap();
+                if unsafe { libc::poll(&mut pollfd, 1, 0) } <= 0 {
+                    continue;
+                }
+                let mut byte = 0u8;
+                let n = unsafe { libc::read(fd, (&mut byte as *mut u8).cast(), 1) };
+                if n <= 0 {
+                    break;
+                }
+                if byte == b'p' || byte == b'P' {
+                    PAUSED.fetch_xor(true, Ordering::SeqCst);
+                }
+            }
+        });
+
+        Self {
+            stop,
+            handle: Some(handle),
+            original_termios,
+        }
+    }
+}
+
+impl Drop for KeyListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+        if let Some(ref original_termios) = self.original_termios {
+            tcsetattr(stdin().as_raw_fd(), TCSANOW, original_termios).ok();
+        }
+        *TERMINAL.lock().unwrap() = None;
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Result, anyhow};
+use indicatif::HumanBytes;
+
+use crate::i18n::{Msg, tr, trf};
+use crate::{history, menu};
+
+/// Number of recently used images offered for quick selection.
+const RECENT_LIMIT: usize = 9;
+
+/// File extensions recognized as disk images, compressed or not.
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "img", "iso", "raw", "bin", "wic", "gz", "gzip", "xz", "zst", "zstd", "zip",
+];
+
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Formats how long ago `time` was, e.g. "5 min ago" or "3 days ago".
+pub fn format_age(time: SystemTime) -> String {
+    let secs = SystemTime::now()
+        .duration_since(time)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86400),
+    }
+}
+
+enum Entry {
+    Parent(PathBuf),
+    Dir(PathBuf),
+    Image(PathBuf),
+}
+
+/// Lists the subdirectories and recognized images in `dir`, sorted by name.
+fn list_entries(dir: &Path) -> Result<(Vec<Entry>, Vec<String>)> {
+    let mut dirs = Vec::new();
+    let mut images = Vec::new();
+
+    for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            dirs.push((name, path));
+        } else if is_image(&path) {
+            images.push((name, path, metadata));
+        }
+    }
+    dirs.sort_by(|a, b| a.0.cmp(&b.0));
+    images.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut entries = Vec::new();
+    let mut labels = Vec::new();
+
+    if let Some(parent) = dir.parent() {
+        entries.push(Entry::Parent(parent.to_path_buf()));
+        labels.push("../".to_string());
+    }
+    for (name, path) in dirs {
+        entries.push(Entry::Dir(path));
+        labels.push(format!("{name}/"));
+    }
+    for (name, path, metadata) in images {
+        let modified = metadata
+            .modified()
+            .map(format_age)
+            .unwrap_or_else(|_| "-".to_string());
+        entries.push(Entry::Image(path));
+        labels.push(format!(
+            "{:<40} {:>10}  {}",
+            name,
+            HumanBytes(metadata.len()).to_string(),
+            modified
+        ));
+    }
+
+    Ok((entries, labels))
+}
+
+/// Presents a simple file browser starting at `start_dir` and returns
+/// the image file selected by the user.
+pub fn pick_image(start_dir: &Path) -> Result<PathBuf> {
+    let mut dir = start_dir
+        .canonicalize()
+        .map_err(|e| anyhow!("Cannot open image directory {}: {e}", start_dir.display()))?;
+
+    loop {
+        let (mut entries, labels) = list_entries(&dir)?;
+        if entries.is_empty() {
+            return Err(anyhow!(trf(Msg::NoImagesFound, &[&dir.display()])));
+        }
+
+        let prompt = trf(Msg::SelectImage, &[&dir.display()]);
+        let selection = menu::select(&prompt, &labels)?;
+
+        match entries.swap_remove(selection) {
+            Entry::Parent(path) | Entry::Dir(path) => dir = path,
+            Entry::Image(path) => return Ok(path),
+        }
+    }
+}
+
+/// Lets the user choose an image to write: recently flashed images are
+/// offered first, with a fallback to browsing from `start_dir`.
+pub fn choose_image(start_dir: &Path) -> Result<PathBuf> {
+    let recent = history::recent_images(RECENT_LIMIT);
+    if recent.is_empty() {
+        return pick_image(start_dir);
+    }
+
+    let mut labels: Vec<String> = recent
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let name = entry
+                .image
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| entry.image.display().to_string());
+            format!("{}. {} (used {})", i + 1, name, format_age(entry.used))
+        })
+        .collect();
+    labels.push(tr(Msg::BrowseForImage).to_string());
+
+    let selection = menu::select(tr(Msg::SelectRecentImage), &labels)?;
+
+    match recent.into_iter().nth(selection) {
+        Some(entry) => Ok(entry.image),
+        None => pick_image(start_dir),
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::cancel::CancelToken;
+use crate::catalog;
+use crate::config;
+use crate::i18n::{Msg, trf};
+use crate::output::verbose;
+use crate::signature::{self, Signed};
+use crate::write;
+
+/// System-wide policy, set up by an administrator. Users can add
+/// requirements in their own config file, but not lift these.
+const SYSTEM_POLICY_PATH: &str = "/etc/etchr/policy.toml";
+
+/// Restrictions on which images may be written, from the `[policy]`
+/// section of the config file and the system-wide policy file.
+#[derive(Default)]
+pub struct Policy {
+    /// Only images whose hash is in the local catalog may be written.
+    pub require_catalog: bool,
+    /// Images must have a valid detached signature (`image.asc` or `.sig`).
+    pub require_signature: bool,
+    /// Keyring trusted for image signatures, instead of the default one.
+    pub keyring: Option<PathBuf>,
+}
+
+impl Policy {
+    /// Reads the `policy.*` keys of a parsed config file.
+    pub fn from_values(values: &HashMap<String, String>) -> Result<Self> {
+        let flag = |key: &str| {
+            values
+                .get(key)
+                .map(|v| config::parse_bool(key, v))
+                .transpose()
+                .map(|v| v.unwrap_or(false))
+        };
+        Ok(Policy {
+            require_catalog: flag("policy.require_catalog")?,
+            require_signature: flag("policy.require_signature")?,
+            keyring: values.get("policy.keyring").map(|v| config::expand_home(v)),
+        })
+    }
+
+    /// Whether any restriction applies at all.
+    fn is_active(&self) -> bool {
+        self.require_catalog || self.require_signature
+    }
+
+    /// Refuses to write `image` unless it satisfies the policy. `is_file`
+    /// is false for stdin and block devices, which can't be checked.
+    pub fn check(&self, image: &Path, is_file: bool, cancel: &CancelToken) -> Result<()> {
+        if !self.is_active() {
+            return Ok(());
+        }
+        if !is_file {
+            return Err(anyhow!(trf(Msg::PolicyNeedsImageFile, &[&image.display()])));
+        }
+
+        if self.require_signature {
+            let sig = signature::find_detached(image)
+                .ok_or_else(|| anyhow!(trf(Msg::PolicyNoSignature, &[&image.display()])))?;
+            signature::verify(Signed::File(image), &sig, self.keyring.as_deref()).map_err(|e| {
+                anyhow!("{}\n{e}", trf(Msg::PolicyBadSignature, &[&image.display()]))
+            })?;
+            verbose!("Signature {} is valid", sig.display());
+        }
+
+        if self.require_catalog {
+            let hash = write::image_hash(image, cancel)?;
+            let catalog = catalog::load();
+            let entry = catalog::lookup(&catalog, &hash)
+                .ok_or_else(|| anyhow!(trf(Msg::PolicyNotInCatalog, &[&image.display(), &hash])))?;
+            verbose!("{} is {} in the catalog", image.display(), entry.label());
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the effective policy: the user's, tightened by the system-wide
+/// policy file if there is one.
+pub fn load(user: Policy) -> Result<Policy> {
+    let path = Path::new(SYSTEM_POLICY_PATH);
+    if !path.exists() {
+        return Ok(user);
+    }
+
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let system = config::parse(&contents)
+        .and_then(|values| Policy::from_values(&values))
+        .with_context(|| format!("parsing {}", path.display()))?;
+
+    Ok(Policy {
+        require_catalog: system.require_catalog || user.require_catalog,
+        require_signature: system.require_signature || user.require_signature,
+        // Only the administrator decides which keys are trusted, if they chose to
+        keyring: system.keyring.or(user.keyring),
+    })
+}
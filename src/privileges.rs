@@ -0,0 +1,157 @@
+use std::ffi::{CStr, OsStr};
+use std::fs::File;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::output::verbose;
+
+/// The unprivileged user who started etchr through `sudo` or `pkexec`.
+pub struct InvokingUser {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+/// Returns the invoking user if running as root on someone's behalf, i.e.
+/// when there is an unprivileged user to drop to. Plain root logins get None.
+pub fn invoking_user() -> Option<InvokingUser> {
+    if unsafe { libc::geteuid() } != 0 {
+        return None;
+    }
+
+    let env_id = |name: &str| std::env::var(name).ok()?.parse::<u32>().ok();
+    let uid = env_id("SUDO_UID").or_else(|| env_id("PKEXEC_UID"))?;
+    if uid == 0 {
+        return None;
+    }
+    // pkexec doesn't pass the gid, so fall back to the user's primary group
+    let gid = env_id("SUDO_GID").or_else(|| {
+        let pw = unsafe { libc::getpwuid(uid) };
+        (!pw.is_null()).then(|| unsafe { (*pw).pw_gid })
+    })?;
+
+    Some(InvokingUser { uid, gid })
+}
+
+/// Runs `f` with the effective ids of the invoking user, if running as
+/// root on someone's behalf, and as is otherwise. Root is taken back after,
+/// so this is for touching the user's files before or without the drop.
+pub fn as_invoking_user<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    // Effective ids are the process's, so threads take turns
+    static SWITCH: Mutex<()> = Mutex::new(());
+
+    let Some(user) = invoking_user() else {
+        return f();
+    };
+    let _switch = SWITCH.lock().unwrap_or_else(|e| e.into_inner());
+    if unsafe { libc::setegid(user.gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::seteuid(user.uid) } != 0 {
+        let e = io::Error::last_os_error();
+        unsafe { libc::setegid(0) };
+        return Err(e);
+    }
+    let result = f();
+    if unsafe { libc::seteuid(0) } != 0 || unsafe { libc::setegid(0) } != 0 {
+        // Carrying on as the user is safe, but not what was asked for
+        return Err(io::Error::last_os_error());
+    }
+    result
+}
+
+impl InvokingUser {
+    pub fn uid(&self) -> libc::uid_t {
+        self.uid
+    }
+
+    pub fn gid(&self) -> libc::gid_t {
+        self.gid
+    }
+
+    /// Returns the user's home directory, if they have an entry in the
+    /// password database.
+    pub fn home(&self) -> Option<PathBuf> {
+        let pw = unsafe { libc::getpwuid(self.uid) };
+        if pw.is_null() {
+            return None;
+        }
+        let home = unsafe { CStr::from_ptr((*pw).pw_dir) };
+        Some(PathBuf::from(OsStr::from_bytes(home.to_bytes())))
+    }
+
+    /// Hands ownership of a file created as root to the user, so they can
+    /// still use (and delete) it after the drop.
+    pub fn give(&self, file: &File) -> io::Result<()> {
+        if unsafe { libc::fchown(file.as_raw_fd(), self.uid, self.gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Permanently switches the process to the user. Descriptors opened
+    /// before keep their access, so call this once the device and image are
+    /// open. `HOME` is pointed at the user's home directory, so that the
+    /// history is recorded for them.
+    pub fn switch_to(&self) -> Result<()> {
+        let pw = unsafe { libc::getpwuid(self.uid) };
+        let name = if pw.is_null() {
+            None
+        } else {
+            unsafe { Some(CStr::from_ptr((*pw).pw_name).to_owned()) }
+        };
+        let home = self.home();
+
+        // Supplementary groups first, while we still may change them
+        let groups_set = match &name {
+            Some(name) => unsafe { libc::initgroups(name.as_ptr(), self.gid) == 0 },
+            None => false,
+        };
+        if !groups_set && unsafe { libc::setgroups(0, std::ptr::null()) } != 0 {
+            return Err(io::Error::last_os_error()).context("clearing supplementary groups");
+        }
+
+        // glibc applies these to every thread of the process
+        if unsafe { set_gid(self.gid) } != 0 {
+            return Err(io::Error::last_os_error()).context("changing group id");
+        }
+        if unsafe { set_uid(self.uid) } != 0 {
+            return Err(io::Error::last_os_error()).context("changing user id");
+        }
+        // Make sure there is no way back
+        if unsafe { libc::setuid(0) } == 0 {
+            return Err(anyhow!("regained root after dropping privileges"));
+        }
+
+        if let Some(home) = home {
+            // SAFETY: the process is single-threaded apart from helper
+            // threads that never read the environment.
+            unsafe { std::env::set_var("HOME", home) };
+        }
+        verbose!("Dropped privileges to uid {}, gid {}", self.uid, self.gid);
+        Ok(())
+    }
+}
+
+/// Sets the real, effective and saved ids. macOS has no setresuid and
+/// setresgid, but as root its setuid and setgid set all three.
+#[cfg(not(target_os = "macos"))]
+unsafe fn set_gid(gid: libc::gid_t) -> libc::c_int {
+    unsafe { libc::setresgid(gid, gid, gid) }
+}
+#[cfg(not(target_os = "macos"))]
+unsafe fn set_uid(uid: libc::uid_t) -> libc::c_int {
+    unsafe { libc::setresuid(uid, uid, uid) }
+}
+#[cfg(target_os = "macos")]
+unsafe fn set_gid(gid: libc::gid_t) -> libc::c_int {
+    unsafe { libc::setgid(gid) }
+}
+#[cfg(target_os = "macos")]
+unsafe fn set_uid(uid: libc::uid_t) -> libc::c_int {
+    unsafe { libc::setuid(uid) }
+}
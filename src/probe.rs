@@ -0,0 +1,108 @@
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Result, anyhow};
+
+use crate::buffers;
+use crate::device::{self, Device};
+use crate::i18n::{Msg, tr, trf};
+
+// Size of the region sampled by a probe. Large enough to get past the
+// device's write cache burst, small enough to finish in a second or two.
+const PROBE_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+const CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
+
+// O_DIRECT requires buffers to be memory-aligned to the block size
+const BLOCK_SIZE: usize = 512;
+
+/// Measures the sequential read speed of `device` in bytes per second
+/// by reading a small region from its start.
+pub fn measure_read_speed(device: &Path, device_size: u64) -> Result<f64> {
+    let len = std::cmp::min(PROBE_SIZE as u64, device_size) as usize;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(device::O_DIRECT)
+        .open(device)?;
+
+    let memory = buffers::reserve("probing", 1, len);
+    let mut aligned = memory.buffer(BLOCK_SIZE);
+    let buffer = aligned.as_mut_slice();
+
+    let start = Instant::now();
+    for chunk in buffer.chunks_mut(CHUNK_SIZE) {
+        file.read_exact(chunk)?;
+    }
+    rate(len, start)
+}
+
+/// Measures the sequential write speed of `device` in bytes per second.
+/// The probed region is read first and written back unchanged, so the
+/// device contents are preserved. As this runs before the write is
+/// confirmed, a device with a filesystem mounted isn't probed at all: the
+/// filesystem could write the region in between, and lose what it wrote.
+pub fn measure_write_speed(device: &Device) -> Result<f64> {
+    if !device.usage.is_empty() {
+        return Err(anyhow!(tr(Msg::NotProbedMounted)));
+    }
+    let len = std::cmp::min(PROBE_SIZE as u64, device.size_bytes) as usize;
+    // On Linux, an exclusive open also fails if the device is mounted
+    // meanwhile, and keeps it from being mounted until the probe is done
+    #[cfg(not(target_os = "macos"))]
+    let exclusive = libc::O_EXCL;
+    #[cfg(target_os = "macos")]
+    let exclusive = 0;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(device::O_DIRECT | exclusive)
+        .open(&device.path)
+        .map_err(|e| match e.raw_os_error() {
+            Some(libc::EBUSY) => anyhow!(tr(Msg::NotProbedInUse)),
+            _ => e.into(),
+        })?;
+
+    let memory = buffers::reserve("probing", 1, len);
+    let mut aligned = memory.buffer(BLOCK_SIZE);
+    let buffer = aligned.as_mut_slice();
+    for chunk in buffer.chunks_mut(CHUNK_SIZE) {
+        file.read_exact(chunk)?;
+    }
+
+    file.seek(SeekFrom::Start(0))?;
+    let start = Instant::now();
+    for chunk in buffer.chunks(CHUNK_SIZE) {
+        file.write_all(chunk)?;
+    }
+    // Include the time to reach stable storage, not just the cache.
+    file.sync_data()?;
+    rate(len, start)
+}
+
+fn rate(len: usize, start: Instant) -> Result<f64> {
+    let elapsed = start.elapsed().as_secs_f64();
+    if len == 0 || elapsed <= 0.0 {
+        return Err(anyhow!(tr(Msg::ProbeRegionTooSmall)));
+    }
+    Ok(len as f64 / elapsed)
+}
+
+/// Formats a rough duration estimate, e.g. "~14 min (45.2 MiB/s)".
+pub fn describe_estimate(bytes: Option<u64>, rate: f64) -> String {
+    let speed = format!("{:.1} MiB/s", rate / (1024.0 * 1024.0));
+    let Some(bytes) = bytes else {
+        return trf(Msg::UnknownUntilDecompressed, &[&speed]);
+    };
+
+    let secs = bytes as f64 / rate;
+    let duration = if secs < 90.0 {
+        format!("~{:.0} s", secs.max(1.0))
+    } else if secs < 90.0 * 60.0 {
+        format!("~{:.0} min", secs / 60.0)
+    } else {
+        format!("~{:.1} h", secs / 3600.0)
+    };
+    format!("{duration} ({speed})")
+}
@@ -0,0 +1,144 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use console::{measure_text_width, strip_ansi_codes, truncate_str};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, TermLike, WeakProgressBar};
+
+use crate::output;
+
+/// The bars on screen, drawn together so that bars, log lines and prompts
+/// never overwrite each other.
+struct Screen {
+    multi: MultiProgress,
+    bars: Vec<WeakProgressBar>,
+}
+
+static SCREEN: Mutex<Option<Screen>> = Mutex::new(None);
+
+/// Width assumed for serial consoles, which can't report theirs.
+const SERIAL_WIDTH: usize = 80;
+
+/// Draws bars on a serial console: all of them on one line, rewritten
+/// after a carriage return, without colors or cursor movement.
+#[derive(Debug, Default)]
+struct SerialLine {
+    /// The bars of the frame being drawn.
+    frame: Mutex<Vec<String>>,
+    /// The width of the line last drawn, to blank it out.
+    shown: Mutex<usize>,
+}
+
+impl SerialLine {
+    fn draw(&self, text: &str) -> io::Result<()> {
+        let text = truncate_str(text, SERIAL_WIDTH - 1, "…");
+        let width = measure_text_width(&text);
+        let mut shown = self.shown.lock().unwrap();
+        let pad = shown.saturating_sub(width);
+        *shown = width;
+        let mut stderr = io::stderr();
+        write!(stderr, "\r{text}{}", " ".repeat(pad))?;
+        if text.is_empty() {
+            // Leave the cursor where whatever is printed next should start
+            write!(stderr, "\r")?;
+        }
+        stderr.flush()
+    }
+}
+
+impl TermLike for SerialLine {
+    fn width(&self) -> u16 {
+        SERIAL_WIDTH as u16
+    }
+
+    fn move_cursor_up(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        self.write_str(s)
+    }
+
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        let s = strip_ansi_codes(s);
+        if !s.trim().is_empty() {
+            self.frame.lock().unwrap().push(s.trim().to_string());
+        }
+        Ok(())
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        let frame = std::mem::take(&mut *self.frame.lock().unwrap());
+        self.draw(&frame.join(" | "))
+    }
+}
+
+/// Returns the screen if any bar on it is still in use. Once all bars are
+/// dropped their last state stays in the scrollback, and the next bar
+/// starts a new screen below it.
+fn live(screen: &mut Option<Screen>) -> Option<&mut Screen> {
+    if let Some(s) = screen {
+        s.bars.retain(|bar| bar.upgrade().is_some());
+        if s.bars.is_empty() {
+            *screen = None;
+            // Keep the last state of the bars on their line
+            if output::is_serial() && !output::is_quiet() {
+                eprintln!();
+            }
+        }
+    }
+    screen.as_mut()
+}
+
+/// Puts `pb` on screen below the bars already shown. Create bars with a
+/// hidden draw target and add them once styled, so nothing half-set-up is
+/// drawn. Drop a bar once finished so that anything printed afterwards
+/// appears below it; bars that finish while others are still running
+/// should be kept until those are done.
+pub fn add(pb: ProgressBar) -> ProgressBar {
+    let mut screen = SCREEN.lock().unwrap();
+    if live(&mut screen).is_none() {
+        let multi = MultiProgress::new();
+        if output::is_quiet() || output::is_json() {
+            multi.set_draw_target(ProgressDrawTarget::hidden());
+        } else if output::is_serial() {
+            // Serial links are slow, so redraw twice a second at most
+            multi.set_draw_target(ProgressDrawTarget::term_like_with_hz(
+                Box::new(SerialLine::default()),
+                2,
+            ));
+        }
+        *screen = Some(Screen {
+            multi,
+            bars: Vec::new(),
+        });
+    }
+    let screen = screen.as_mut().expect("screen was just created");
+    screen.bars.push(pb.downgrade());
+    screen.multi.add(pb)
+}
+
+/// Hides the bars while `f` runs, then draws them again below whatever it
+/// printed. Used for log lines and prompts.
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    let mut screen = SCREEN.lock().unwrap();
+    match live(&mut screen) {
+        Some(screen) => screen.multi.suspend(f),
+        None => f(),
+    }
+}
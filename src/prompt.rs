@@ -0,0 +1,81 @@
+use std::io::{self, BufRead, Write};
+
+use anyhow::{Result, anyhow};
+use dialoguer::{Confirm, Input, theme::ColorfulTheme};
+
+use crate::i18n::{Msg, tr, trf};
+use crate::output;
+use crate::pause;
+use crate::progress;
+
+/// Fails with `--json`, where a question can't be asked: the program
+/// driving etchr has to answer it on the command line instead.
+pub fn ensure_askable(prompt: &str) -> Result<()> {
+    if output::is_json() {
+        return Err(anyhow!(trf(
+            Msg::NotAskableJson,
+            &[&prompt.trim_end_matches([':', '?', ' '])]
+        )));
+    }
+    Ok(())
+}
+
+/// Prints `prompt` and reads a line of input, without its line ending.
+/// This is how every question is asked on a serial console.
+pub fn read_line(prompt: &str) -> Result<String> {
+    let mut stderr = io::stderr();
+    write!(stderr, "{prompt} ")?;
+    stderr.flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Err(anyhow!(tr(Msg::NoAnswer)));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Runs `f`, which asks a question, with the progress bars hidden and the
+/// keys of a running operation (see [`pause::KeyListener`]) left to it.
+fn ask<R>(f: impl FnOnce() -> R) -> R {
+    progress::suspend(|| pause::suspend_keys(f))
+}
+
+/// Asks a yes/no question, answered with `default` on an empty answer.
+pub fn confirm(prompt: &str, default: bool) -> Result<bool> {
+    ensure_askable(prompt)?;
+    ask(|| {
+        if !output::is_serial() {
+            return Ok(Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .default(default)
+                .interact()?);
+        }
+
+        let hint = if default { "[Y/n]" } else { "[y/N]" };
+        loop {
+            match read_line(&format!("{prompt} {hint}"))?
+                .trim()
+                .to_lowercase()
+                .as_str()
+            {
+                "" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => {}
+            }
+        }
+    })
+}
+
+/// Asks for a line of text, which may be empty.
+pub fn input(prompt: &str) -> Result<String> {
+    ensure_askable(prompt)?;
+    ask(|| {
+        if output::is_serial() {
+            return read_line(&format!("{prompt}:"));
+        }
+        Ok(Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .allow_empty(true)
+            .interact_text()?)
+    })
+}
@@ -1,26 +1,51 @@
-use std::fs::File;
-use std::io::{Read, Write};
-use std::os::unix::io::AsRawFd;
-use std::path::Path;
-use std::time::Instant;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-// Required for .custom_flags(libc::O_DIRECT)
+// Required for .custom_flags(device::O_DIRECT)
 use std::os::unix::fs::OpenOptionsExt;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
 
-use anyhow::{Result, anyhow};
-use indicatif::{ProgressBar, ProgressStyle};
-use nix::ioctl_read;
+use anyhow::{Context, Result, anyhow};
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::buffers;
+use crate::cancel::CancelToken;
+use crate::compress::{self, Choice, Compression, Compressor, ZstdOptions};
+use crate::device;
+use crate::i18n::{Msg, tr, trf};
+use crate::mapfile;
+use crate::output::{self, info, verbose};
+use crate::pause;
+use crate::privileges;
+use crate::probe;
+use crate::progress;
+use crate::sandbox;
+use crate::status::{self, StatusFile};
+use crate::title::TitleMirror;
+use crate::units;
 
 // Use a 1 MiB buffer for I/O operations.
 const BUFFER_SIZE: usize = 1024 * 1024;
 
-// Define the `nix` ioctl for `BLKGETSIZE64` (u64 device size in bytes).
-ioctl_read!(blkgetsize64, 0x12, 114, u64);
+/// The smallest unit a device can fail to read.
+const SECTOR_SIZE: usize = 512;
+
+/// Blocks of the image that are all zeros are left as holes rather than
+/// written, at the granularity most filesystems allocate in.
+const SPARSE_BLOCK: usize = 4096;
+
+/// How often a kept partial read saves how far it got, so that it can be
+/// resumed even if etchr crashes or is killed.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
 
 fn make_progress_bar(len: u64, prefix: &str) -> ProgressBar {
-    let pb = ProgressBar::new(len);
+    let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
     pb.set_prefix(format!("{prefix:<10}"));
     pb.set_style(
         ProgressStyle::default_bar()
@@ -28,76 +53,620 @@ fn make_progress_bar(len: u64, prefix: &str) -> ProgressBar {
             .unwrap()
             .progress_chars("■ "),
     );
-    pb
+    progress::add(pb)
 }
 
-pub fn run(device_path: &Path, image_path: &Path, running: Arc<AtomicBool>) -> Result<()> {
-    println!(
-        "Reading device \"{}\" to image \"{}\"",
-        device_path.display(),
-        image_path.display()
+/// How [`run`] reads a device.
+pub struct Options {
+    /// Sandbox the process (see [`sandbox::enter`]) once the device and
+    /// image are open.
+    pub sandbox: bool,
+    /// Read into `<image>.partial`, renamed once complete, and keep it on
+    /// cancellation or a read error so that the read can be resumed.
+    pub keep_partial: bool,
+    /// Continue a kept partial read instead of starting over.
+    pub resume: bool,
+    /// Written in place of sectors that can't be read.
+    pub fill: FillPattern,
+    /// Where to save a GNU ddrescue mapfile of what was read and which
+    /// sectors were unreadable.
+    pub mapfile: Option<PathBuf>,
+    /// Compress the image as it's read. Can't be combined with
+    /// `keep_partial`, as a compressed stream can't be resumed.
+    pub compress: Option<Choice>,
+    /// Settings for zstd compression.
+    pub zstd: ZstdOptions,
+    /// Train a zstd dictionary on the device, save it here and compress
+    /// with it.
+    pub train_dict: Option<PathBuf>,
+    /// When run through sudo, switch to the invoking user and give them
+    /// the image once the device and image are open.
+    pub drop_privileges: bool,
+    /// Leave blocks of zeros as holes in an uncompressed image file, so
+    /// they take up no space on disk.
+    pub sparse: bool,
+}
+
+/// Bytes repeated over an unreadable sector, given as hex (e.g. `00` or
+/// `DEADBEEF`), so that damaged areas are easy to find in the image.
+#[derive(Clone)]
+pub struct FillPattern(Vec<u8>);
+
+impl Default for FillPattern {
+    /// Zeros, as `--bad-sector-fill` defaults to.
+    fn default() -> Self {
+        FillPattern(vec![0])
+    }
+}
+
+impl FillPattern {
+    /// Repeats the pattern over `buf`.
+    pub fn fill(&self, buf: &mut [u8]) {
+        for (byte, fill) in buf.iter_mut().zip(self.0.iter().cycle()) {
+            *byte = *fill;
+        }
+    }
+}
+
+impl FromStr for FillPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let hex = s.strip_prefix("0x").unwrap_or(s);
+        if hex.is_empty() || !hex.len().is_multiple_of(2) {
+            return Err(trf(Msg::NotHexBytes, &[&s]));
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<_, _>>()
+            .map(FillPattern)
+            .map_err(|_| trf(Msg::NotHex, &[&s]))
+    }
+}
+
+impl fmt::Display for FillPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+/// Reads `buf` from `offset` of `device`. If the device reports an I/O
+/// error, the region is retried in halves, down to single sectors;
+/// sectors that still fail are filled with `fill` and their offsets added
+/// to `bad`. Gives up early, leaving `buf` incomplete, once `cancel` is
+/// cancelled, since each failing read can take seconds.
+fn read_recovering(
+    device: &impl FileExt,
+    offset: u64,
+    buf: &mut [u8],
+    fill: &FillPattern,
+    bad: &mut Vec<u64>,
+    cancel: &CancelToken,
+) -> io::Result<()> {
+    match device.read_exact_at(buf, offset) {
+        Ok(()) => Ok(()),
+        // Anything else, like the device disappearing, isn't a bad sector
+        Err(e) if e.raw_os_error() != Some(libc::EIO) => Err(e),
+        Err(_) if cancel.is_cancelled() => Ok(()),
+        Err(_) if buf.len() <= SECTOR_SIZE => {
+            fill.fill(buf);
+            bad.push(offset);
+            Ok(())
+        }
+        Err(_) => {
+            // Halves stay sector-aligned, as O_DIRECT requires
+            let half = (buf.len() / SECTOR_SIZE).div_ceil(2) * SECTOR_SIZE;
+            let (first, second) = buf.split_at_mut(half);
+            read_recovering(device, offset, first, fill, bad, cancel)?;
+            read_recovering(device, offset + half as u64, second, fill, bad, cancel)
+        }
+    }
+}
+
+/// Writes `data` at the position of `file`, seeking over blocks that are
+/// all zeros instead of writing them, so that they become holes. The file
+/// is only as long as the last block written; the caller sets its length.
+fn write_sparse(file: &mut File, data: &[u8]) -> io::Result<()> {
+    let is_zero = |block: &[u8]| block.iter().all(|&b| b == 0);
+    let mut pos = 0;
+    while pos < data.len() {
+        // A run of blocks that are all zeros, or none of which are
+        let zero = is_zero(&data[pos..(pos + SPARSE_BLOCK).min(data.len())]);
+        let mut end = pos;
+        while end < data.len() {
+            let block = &data[end..(end + SPARSE_BLOCK).min(data.len())];
+            if is_zero(block) != zero {
+                break;
+            }
+            end += block.len();
+        }
+        if zero {
+            file.seek(SeekFrom::Current((end - pos) as i64))?;
+        } else {
+            file.write_all(&data[pos..end])?;
+        }
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Where a kept partial read stopped, saved next to the partial image.
+struct Partial {
+    /// Size of the device being read, so a read is only resumed from a
+    /// device of the same size.
+    device_size: u64,
+    /// Bytes read so far; the partial image holds exactly these.
+    offset: u64,
+    /// Offsets of the sectors that couldn't be read so far.
+    bad_sectors: Vec<u64>,
+}
+
+/// Returns `path` with `suffix` appended to its file name.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path);
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+impl Partial {
+    /// The file the state of the partial image `data` is saved in.
+    fn state_path(data: &Path) -> PathBuf {
+        with_suffix(data, ".state")
+    }
+
+    fn load(data: &Path) -> Result<Self> {
+        let path = Self::state_path(data);
+        let contents = fs::read_to_string(&path)
+            .with_context(|| trf(Msg::NoPartialRead, &[&path.display()]))?;
+        let value = |key: &str| {
+            contents
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| anyhow!(trf(Msg::PartialStateInvalid, &[&path.display(), &key])))
+        };
+        let bad_sectors = contents
+            .lines()
+            .find_map(|line| line.strip_prefix("bad_sectors="))
+            .map(|list| list.split(',').map(str::parse).collect())
+            .transpose()
+            .map_err(|_| {
+                anyhow!(trf(
+                    Msg::PartialStateInvalid,
+                    &[&path.display(), &"bad_sectors"]
+                ))
+            })?
+            .unwrap_or_default();
+        Ok(Partial {
+            device_size: value("device_size")?,
+            offset: value("offset")?,
+            bad_sectors,
+        })
+    }
+
+    /// Saves the state, replacing the old one atomically so that a crash
+    /// while saving leaves one or the other.
+    fn save(&self, data: &Path) -> io::Result<()> {
+        let mut contents = format!("device_size={}\noffset={}\n", self.device_size, self.offset);
+        if !self.bad_sectors.is_empty() {
+            let list: Vec<String> = self.bad_sectors.iter().map(u64::to_string).collect();
+            contents += &format!("bad_sectors={}\n", list.join(","));
+        }
+        let path = Self::state_path(data);
+        let temp = with_suffix(&path, ".tmp");
+        let mut file = File::create(&temp)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp, path)
+    }
+}
+
+/// Returns how far an earlier read into `image_path` with `--keep-partial`
+/// got, if one was cancelled, failed, or died with etchr, leaving its
+/// state behind.
+pub fn interrupted(image_path: &Path) -> Option<u64> {
+    Partial::load(&with_suffix(image_path, ".partial"))
+        .ok()
+        .map(|partial| partial.offset)
+}
+
+/// Deletes what an interrupted read into `image_path` left behind.
+pub fn discard_partial(image_path: &Path) -> io::Result<()> {
+    let partial_path = with_suffix(image_path, ".partial");
+    fs::remove_file(Partial::state_path(&partial_path))?;
+    match fs::remove_file(&partial_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// An image being read into a temp file next to where it goes, deleted
+/// if the read doesn't finish, so that no truncated image is left at the
+/// requested path.
+struct Unfinished {
+    path: Option<PathBuf>,
+}
+
+impl Unfinished {
+    /// Moves the finished image from the temp file to `image_path`.
+    fn finish(mut self, image_path: &Path) -> io::Result<()> {
+        match self.path.take() {
+            Some(path) => fs::rename(path, image_path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Unfinished {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Replaces the contents of `file` with a mapfile (see [`mapfile::write`]).
+fn save_mapfile(file: &mut File, size: u64, done: u64, bad_sectors: &[u64]) -> io::Result<()> {
+    let mut contents = Vec::new();
+    mapfile::write(&mut contents, size, done, bad_sectors, SECTOR_SIZE as u64)?;
+    file.set_len(0)?;
+    file.write_all_at(&contents, 0)
+}
+
+/// Reads `pieces` pieces of `piece_size` bytes (a multiple of the sector
+/// size) spread evenly over `device`, since the start of a device is often
+/// mostly empty.
+fn sample(device: &File, size: u64, pieces: u64, piece_size: usize) -> io::Result<Vec<u8>> {
+    let memory = buffers::reserve("sampling", 1, piece_size);
+    let mut aligned = memory.buffer(SECTOR_SIZE);
+    let buffer = aligned.as_mut_slice();
+    let mut sample = Vec::new();
+    for piece in 0..pieces {
+        let at = size * (2 * piece + 1) / (2 * pieces);
+        let at = at / piece_size as u64 * piece_size as u64;
+        let len = std::cmp::min(piece_size as u64, size - at) as usize;
+        device.read_exact_at(&mut buffer[..len], at)?;
+        sample.extend_from_slice(&buffer[..len]);
+    }
+    Ok(sample)
+}
+
+/// Picks the compression to read `device` with (see [`compress::choose`]),
+/// benchmarked on a few MiB of its contents against its read speed.
+fn choose_compression(device: &File, device_path: &Path, size: u64) -> Result<Compression> {
+    let read_speed = probe::measure_read_speed(device_path, size)?;
+    let sample = sample(device, size, 4, BUFFER_SIZE)?;
+    let chosen = compress::choose(&sample, read_speed)?;
+    let mib = |speed: f64| speed / (1024.0 * 1024.0);
+    info!(
+        "{}",
+        trf(
+            Msg::CompressingWith,
+            &[
+                &chosen.compression,
+                &format!("{:.0}", mib(chosen.speed)),
+                &format!("{:.0}", chosen.ratio * 100.0),
+                &format!("{:.0}", mib(read_speed))
+            ]
+        )
+    );
+    if chosen.speed < read_speed {
+        output::warn(tr(Msg::NoCompressionKeepsUp));
+    }
+    Ok(chosen.compression)
+}
+
+/// Detaches the process's stdout for use as the image output, and points
+/// file descriptor 1 at stderr, so that messages printed anywhere in the
+/// program can't corrupt the image stream.
+pub fn take_stdout() -> io::Result<File> {
+    unsafe {
+        let fd = libc::dup(libc::STDOUT_FILENO);
+        if fd < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(File::from_raw_fd(fd))
+    }
+}
+
+/// Reads the device into `image_path`. If `stream` is given (stdout, see
+/// [`take_stdout`], or another [`sink`](crate::sink)), the image is
+/// streamed there instead and `image_path` is only used for display. With
+/// `options.drop_privileges`, when run through sudo, privileges are
+/// dropped once the device and image are open, and the image is given to
+/// the user.
+pub fn run(
+    device_path: &Path,
+    image_path: &Path,
+    stream: Option<File>,
+    options: &Options,
+    cancel: &CancelToken,
+) -> Result<()> {
+    info!(
+        "{}",
+        trf(
+            Msg::ReadingDevice,
+            &[&device_path.display(), &image_path.display()]
+        )
     );
 
     // Open device for reading
-    let mut device_file = std::fs::OpenOptions::new()
+    let device_file = std::fs::OpenOptions::new()
         .read(true)
         // Use O_DIRECT to bypass the kernel page cache for raw, high-speed I/O.
-        .custom_flags(libc::O_DIRECT)
-        .open(&device_path)?;
+        .custom_flags(device::O_DIRECT)
+        .open(device_path)?;
 
     // Get the device size in bytes using ioctl. This is more reliable
     // than seeking for block devices.
-    let fd = device_file.as_raw_fd();
-    let mut size_bytes: u64 = 0;
-    unsafe {
-        blkgetsize64(fd, &mut size_bytes)?;
-    }
+    let size_bytes = device::file_size(&device_file)?;
+
+    verbose!(
+        "{} reports {}, reading in {BUFFER_SIZE}-byte chunks",
+        device_path.display(),
+        units::bytes(size_bytes)
+    );
 
     // Abort if the device reports zero size (e.g., empty card reader).
     if size_bytes == 0 {
-        return Err(anyhow!("Device size is reported as zero"));
+        return Err(anyhow!(tr(Msg::DeviceSizeZero)));
     }
 
-    let mut image_file = File::create(&image_path)?;
+    let compression = match options.compress {
+        None => None,
+        Some(Choice::Fixed(compression)) => Some(compression),
+        Some(Choice::Auto) => Some(choose_compression(&device_file, device_path, size_bytes)?),
+    };
+    let mut zstd = options.zstd.clone();
+    let dict_file = match &options.train_dict {
+        Some(path) => {
+            // 16 MiB in 16 KiB pieces, the sample size zstd trains well on
+            let piece_size = 16 * 1024;
+            let samples = sample(&device_file, size_bytes, 1024, piece_size)?;
+            let dictionary = compress::train_dictionary(&samples, piece_size)
+                .context(tr(Msg::TrainingDictionary))?;
+            let mut file = File::create(path)?;
+            file.write_all(&dictionary)?;
+            info!(
+                "{}",
+                trf(
+                    Msg::TrainedDictionary,
+                    &[&HumanBytes(dictionary.len() as u64), &path.display()]
+                )
+            );
+            zstd.dictionary = Some(dictionary);
+            Some(file)
+        }
+        None => None,
+    };
+    let streamed = stream.is_some();
+    if let Some(compression) = compression
+        && !streamed
+        && image_path
+            .extension()
+            .is_none_or(|ext| ext != compression.codec.extension())
+    {
+        output::warn(trf(
+            Msg::CodecMismatch,
+            &[
+                &image_path.display(),
+                &compression.codec,
+                &compression.codec.extension(),
+            ],
+        ));
+    }
+    let partial_path =
+        (options.keep_partial && !streamed).then(|| with_suffix(image_path, ".partial"));
+    let unfinished = Unfinished {
+        path: (!streamed && partial_path.is_none()).then(|| with_suffix(image_path, ".tmp")),
+    };
+    let mut start: u64 = 0;
+    let mut bad_sectors = Vec::new();
+    let mut image_file = match (stream, &partial_path.as_ref().or(unfinished.path.as_ref())) {
+        (Some(file), _) => file,
+        (None, Some(partial_path)) if options.resume => {
+            let partial = Partial::load(partial_path)?;
+            if partial.device_size != size_bytes {
+                return Err(anyhow!(trf(
+                    Msg::PartialFromOtherDevice,
+                    &[
+                        &partial_path.display(),
+                        &units::bytes(partial.device_size),
+                        &device_path.display(),
+                        &units::bytes(size_bytes)
+                    ]
+                )));
+            }
+            let mut file = fs::OpenOptions::new().write(true).open(partial_path)?;
+            // Drop anything written after the state was saved
+            file.set_len(partial.offset)?;
+            file.seek(SeekFrom::End(0))?;
+            start = partial.offset;
+            bad_sectors = partial.bad_sectors;
+            info!(
+                "{}",
+                trf(
+                    Msg::ResumingAt,
+                    &[&HumanBytes(start), &HumanBytes(size_bytes)]
+                )
+            );
+            file
+        }
+        (None, Some(path)) => File::create(path)?,
+        (None, None) => unreachable!("the image goes to a stream or a file"),
+    };
+    let mut map_file = options.mapfile.as_ref().map(File::create).transpose()?;
+    let mut status = StatusFile::create("read", device_path, image_path);
 
-    let read_pb = make_progress_bar(size_bytes, "Reading");
+    // Everything that needs root is open; run the rest as the sudo user
+    if let Some(user) = privileges::invoking_user().filter(|_| options.drop_privileges) {
+        if !streamed {
+            user.give(&image_file)?;
+        }
+        for file in map_file.iter().chain(&dict_file) {
+            user.give(file)?;
+        }
+        user.switch_to()?;
+    }
+
+    let mut compressor = compression
+        .map(|compression| {
+            Compressor::spawn(compression, &zstd, BUFFER_SIZE, image_file.try_clone()?)
+        })
+        .transpose()?;
+
+    let sparse = options.sparse && !streamed && compressor.is_none();
+
+    if options.sandbox {
+        // The finished image is renamed into place, and a kept partial
+        // image has its state saved, which needs write access.
+        let image_dir: Vec<PathBuf> = image_path
+            .parent()
+            .filter(|_| !streamed)
+            .map(|dir| {
+                if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                }
+            })
+            .map(Path::to_path_buf)
+            .into_iter()
+            .collect();
+        let writable: Vec<PathBuf> = [status::status_dir()]
+            .into_iter()
+            .chain(image_dir)
+            .collect();
+        sandbox::enter(&writable, &[])?;
+    }
+
+    let read_pb = make_progress_bar(size_bytes, tr(Msg::BarReading));
+    read_pb.set_position(start);
+    // Only measure the speed of what this run reads
+    read_pb.reset_eta();
+    let mut title = TitleMirror::new();
     let start_time = Instant::now();
 
-    // O_DIRECT requires buffers to be memory-aligned to the block size.
-    // We create a buffer with extra capacity and then get an aligned slice from it.
-    let block_size = 512;
-    let mut buf = vec![0u8; BUFFER_SIZE + block_size];
-    let offset = buf.as_ptr().align_offset(block_size);
-    let buffer = &mut buf[offset..offset + BUFFER_SIZE];
+    // O_DIRECT requires buffers to be memory-aligned to the block size
+    let memory = buffers::reserve("reading", 1, BUFFER_SIZE);
+    let mut aligned = memory.buffer(SECTOR_SIZE);
+    let buffer = aligned.as_mut_slice();
+
+    // Records how far the read got, for --resume and in the mapfile
+    let save_progress = |image_file: &mut File,
+                         map_file: &mut Option<File>,
+                         read_total: u64,
+                         bad_sectors: &[u64]|
+     -> Result<()> {
+        if let Some(map_file) = map_file {
+            save_mapfile(map_file, size_bytes, read_total, bad_sectors)?;
+        }
+        if let Some(partial_path) = &partial_path {
+            image_file.sync_all()?;
+            Partial {
+                device_size: size_bytes,
+                offset: read_total,
+                bad_sectors: bad_sectors.to_vec(),
+            }
+            .save(partial_path)?;
+        }
+        Ok(())
+    };
 
-    let mut read_total: u64 = 0;
+    let mut read_total: u64 = start;
+    let mut last_checkpoint = Instant::now();
     while read_total < size_bytes {
-        // Check for Ctrl+C signal for graceful shutdown.
-        if !running.load(Ordering::SeqCst) {
-            read_pb.println("Received exit signal... cleaning up.");
-            read_pb.finish_with_message("❌ Read cancelled.");
-            // Clean up the partial image file on cancellation.
-            std::fs::remove_file(image_path)?;
-            return Err(anyhow!("Operation cancelled by user"));
+        if partial_path.is_some() && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            save_progress(&mut image_file, &mut map_file, read_total, &bad_sectors)?;
+            last_checkpoint = Instant::now();
+        }
+
+        if pause::is_paused() {
+            // Flush what has been dumped so far before idling
+            image_file.flush()?;
+            if !streamed {
+                image_file.sync_all()?;
+            }
+            pause::wait_while_paused(&read_pb, cancel);
+        }
+
+        if let Err(cancelled) = cancel.check(&read_pb, Msg::PhaseRead) {
+            save_progress(&mut image_file, &mut map_file, read_total, &bad_sectors)?;
+            if let Some(partial_path) = &partial_path {
+                return Err(anyhow::Error::new(cancelled).context(trf(
+                    Msg::KeptPartial,
+                    &[&HumanBytes(read_total), &partial_path.display()],
+                )));
+            }
+            // The unfinished image is deleted on the way out
+            return Err(cancelled.into());
         }
 
         let to_read = std::cmp::min(BUFFER_SIZE as u64, size_bytes - read_total) as usize;
 
-        device_file.read_exact(&mut buffer[..to_read])?;
+        let bad_before = bad_sectors.len();
+        let read = read_recovering(
+            &device_file,
+            read_total,
+            &mut buffer[..to_read],
+            &options.fill,
+            &mut bad_sectors,
+            cancel,
+        );
+        for offset in &bad_sectors[bad_before..] {
+            output::warn(trf(Msg::UnreadableSector, &[offset, &options.fill]));
+        }
+        if cancel.is_cancelled() {
+            // The buffer may be incomplete; stop at the check above
+            continue;
+        }
 
         // Write *only* the bytes read. Do not write the full buffer,
         // as the last chunk will be partial and uninitialized data
         // from the buffer would corrupt the image.
-        image_file.write_all(&buffer[..to_read])?;
+        let written = read.and_then(|_| match &mut compressor {
+            Some(compressor) => compressor.write(&buffer[..to_read]),
+            None if sparse => write_sparse(&mut image_file, &buffer[..to_read]),
+            None => image_file.write_all(&buffer[..to_read]),
+        });
+        if let Err(e) = written {
+            // Truncated back to `read_total` when resuming
+            save_progress(&mut image_file, &mut map_file, read_total, &bad_sectors)?;
+            return Err(e.into());
+        }
 
         read_total += to_read as u64;
         read_pb.set_position(read_total);
+        if let Some(compressor) = &compressor {
+            read_pb.set_message(format!("→ {} compressed", HumanBytes(compressor.written())));
+        }
+        status.update("Reading", &read_pb);
+        title.update("Reading", &read_pb);
     }
 
+    if let Some(compressor) = compressor {
+        compressor.finish()?;
+    }
+    if sparse {
+        // Takes in a hole at the end, which was only seeked over
+        image_file.set_len(read_total)?;
+    }
     image_file.flush()?;
+    if let Some(map_file) = &mut map_file {
+        save_mapfile(map_file, size_bytes, read_total, &bad_sectors)?;
+    }
+    if !streamed {
+        image_file.sync_all()?;
+    }
+    unfinished.finish(image_path)?;
+    if let Some(partial_path) = &partial_path {
+        fs::rename(partial_path, image_path)?;
+        fs::remove_file(Partial::state_path(partial_path)).ok();
+    }
 
     let elapsed = start_time.elapsed().as_secs_f64();
-    let avg_speed = (size_bytes as f64 / (1024.0 * 1024.0)) / elapsed;
+    let avg_speed = ((size_bytes - start) as f64 / (1024.0 * 1024.0)) / elapsed;
     read_pb.set_style(
         ProgressStyle::default_bar()
             .template(
@@ -107,17 +676,237 @@ pub fn run(device_path: &Path, image_path: &Path, running: Arc<AtomicBool>) -> R
             .progress_chars("■ "),
     );
     read_pb.finish_with_message(format!(
-        "{avg_speed:.2} MiB/s, {elapsed:.1}s) ✅ Read complete."
+        "{avg_speed:.2} MiB/s, {elapsed:.1}s) {}",
+        tr(Msg::ReadComplete)
     ));
+    drop(read_pb);
 
-    let metadata = image_file.metadata()?;
-    let actual_size = metadata.len();
-    println!(
-        "Read complete: \"{}\" ({} bytes, {:.2} MiB)",
-        image_path.display(),
-        actual_size,
-        actual_size as f64 / (1024.0 * 1024.0)
+    let actual_size = read_total;
+    info!(
+        "{}",
+        trf(
+            Msg::ReadCompleteTo,
+            &[&image_path.display(), &units::bytes(actual_size)]
+        )
     );
+    if compression.is_some() && !streamed {
+        let compressed = image_file.metadata()?.len();
+        info!(
+            "{}",
+            trf(
+                Msg::CompressedTo,
+                &[
+                    &HumanBytes(compressed),
+                    &format!("{:.0}", compressed as f64 * 100.0 / actual_size as f64)
+                ]
+            )
+        );
+    }
+    if sparse {
+        let allocated = image_file.metadata()?.blocks() * 512;
+        if allocated < actual_size {
+            info!("{}", trf(Msg::SparseHoles, &[&HumanBytes(allocated)]));
+        }
+    }
+    if !bad_sectors.is_empty() {
+        let listed = match &options.mapfile {
+            Some(path) => trf(Msg::MappedIn, &[&path.display()]),
+            None => tr(Msg::ListedAbove).to_string(),
+        };
+        output::warn(trf(
+            Msg::UnreadableSectors,
+            &[
+                &bad_sectors.len(),
+                &HumanBytes(bad_sectors.len() as u64 * SECTOR_SIZE as u64),
+                &options.fill,
+                &listed,
+            ],
+        ));
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    /// A device of numbered sectors, some of which fail with an I/O error.
+    struct Device {
+        sectors: u64,
+        bad: Vec<u64>,
+        /// Sectors that fail with another error.
+        gone: Option<u64>,
+        reads: Cell<usize>,
+        /// Cancels this token on the first failed read.
+        cancel_on_error: RefCell<Option<CancelToken>>,
+    }
+
+    impl Device {
+        fn new(sectors: u64, bad: &[u64]) -> Self {
+            Device {
+                sectors,
+                bad: bad.to_vec(),
+                gone: None,
+                reads: Cell::new(0),
+                cancel_on_error: RefCell::new(None),
+            }
+        }
+    }
+
+    impl FileExt for Device {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            self.reads.set(self.reads.get() + 1);
+            let first = offset / SECTOR_SIZE as u64;
+            let last = (offset + buf.len() as u64).div_ceil(SECTOR_SIZE as u64);
+            let failing = |sector: &u64| (first..last).contains(sector);
+            if self.gone.as_ref().is_some_and(failing) {
+                return Err(io::Error::from_raw_os_error(libc::ENODEV));
+            }
+            if self.bad.iter().any(failing) {
+                if let Some(cancel) = self.cancel_on_error.borrow_mut().take() {
+                    cancel.cancel();
+                }
+                return Err(io::Error::from_raw_os_error(libc::EIO));
+            }
+            let len = buf
+                .len()
+                .min((self.sectors * SECTOR_SIZE as u64 - offset) as usize);
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                // Each sector holds its number
+                *byte = ((offset + i as u64) / SECTOR_SIZE as u64) as u8;
+            }
+            Ok(len)
+        }
+
+        fn write_at(&self, _: &[u8], _: u64) -> io::Result<usize> {
+            unreachable!("the device is only read")
+        }
+    }
+
+    fn read(device: &Device, cancel: &CancelToken) -> (io::Result<()>, Vec<u8>, Vec<u64>) {
+        let mut buf = vec![0u8; device.sectors as usize * SECTOR_SIZE];
+        let mut bad = Vec::new();
+        let fill = "ee".parse::<FillPattern>().unwrap();
+        let result = read_recovering(device, 0, &mut buf, &fill, &mut bad, cancel);
+        (result, buf, bad)
+    }
+
+    #[test]
+    fn reads_around_bad_sectors() {
+        let device = Device::new(16, &[3, 4, 15]);
+        let (result, buf, bad) = read(&device, &CancelToken::default());
+        result.unwrap();
+        assert_eq!(bad, [3 * 512, 4 * 512, 15 * 512]);
+        for (sector, data) in buf.chunks(SECTOR_SIZE).enumerate() {
+            let expected = if [3, 4, 15].contains(&sector) {
+                0xee
+            } else {
+                sector as u8
+            };
+            assert!(data.iter().all(|&b| b == expected), "sector {sector}");
+        }
+    }
+
+    #[test]
+    fn narrows_down_a_bad_sector_by_halves() {
+        // Rather than reading each of the 64 sectors on its own, the half
+        // without the bad sector is read at once at every step
+        let device = Device::new(64, &[37]);
+        let (result, _, bad) = read(&device, &CancelToken::default());
+        result.unwrap();
+        assert_eq!(bad, [37 * 512]);
+        assert_eq!(device.reads.get(), 1 + 2 * 6);
+    }
+
+    #[test]
+    fn halves_odd_lengths_at_sector_boundaries() {
+        let device = Device::new(7, &[6]);
+        let (result, buf, bad) = read(&device, &CancelToken::default());
+        result.unwrap();
+        assert_eq!(bad, [6 * 512]);
+        assert_eq!(buf[5 * 512], 5);
+        assert_eq!(buf[6 * 512], 0xee);
+    }
+
+    #[test]
+    fn other_errors_are_not_bad_sectors() {
+        let mut device = Device::new(8, &[2]);
+        device.gone = Some(5);
+        let (result, _, _) = read(&device, &CancelToken::default());
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::ENODEV));
+    }
+
+    #[test]
+    fn cancelling_stops_the_retries() {
+        let device = Device::new(16, &[1]);
+        let cancel = CancelToken::default();
+        *device.cancel_on_error.borrow_mut() = Some(cancel.clone());
+        let (result, _, bad) = read(&device, &cancel);
+        result.unwrap();
+        assert!(bad.is_empty());
+        assert_eq!(device.reads.get(), 1);
+    }
+
+    #[test]
+    fn saves_and_loads_the_state_of_a_partial_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("card.img");
+        let data = with_suffix(&image, ".partial");
+        assert_eq!(interrupted(&image), None);
+
+        let partial = Partial {
+            device_size: 1 << 34,
+            offset: 3 << 30,
+            bad_sectors: vec![512, 1 << 33],
+        };
+        partial.save(&data).unwrap();
+        fs::write(&data, b"").unwrap();
+        let loaded = Partial::load(&data).unwrap();
+        assert_eq!(loaded.device_size, partial.device_size);
+        assert_eq!(loaded.offset, partial.offset);
+        assert_eq!(loaded.bad_sectors, partial.bad_sectors);
+        assert_eq!(interrupted(&image), Some(3 << 30));
+
+        // Without bad sectors, the list is left out
+        let clean = Partial {
+            bad_sectors: Vec::new(),
+            ..partial
+        };
+        clean.save(&data).unwrap();
+        assert!(Partial::load(&data).unwrap().bad_sectors.is_empty());
+
+        discard_partial(&image).unwrap();
+        assert!(!data.exists());
+        assert!(!Partial::state_path(&data).exists());
+        assert_eq!(interrupted(&image), None);
+    }
+
+    #[test]
+    fn refuses_a_damaged_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = dir.path().join("card.img.partial");
+        for contents in [
+            "device_size=100\n",
+            "device_size=100\noffset=ten\n",
+            "device_size=100\noffset=10\nbad_sectors=1,,2\n",
+        ] {
+            fs::write(Partial::state_path(&data), contents).unwrap();
+            assert!(Partial::load(&data).is_err(), "{contents:?}");
+        }
+    }
+
+    #[test]
+    fn parses_fill_patterns() {
+        let pattern: FillPattern = "0xDEad".parse().unwrap();
+        assert_eq!(pattern.to_string(), "DEAD");
+        let mut buf = [0u8; 5];
+        pattern.fill(&mut buf);
+        assert_eq!(buf, [0xde, 0xad, 0xde, 0xad, 0xde]);
+        for bad in ["", "0x", "abc", "zz"] {
+            assert!(bad.parse::<FillPattern>().is_err(), "{bad}");
+        }
+    }
+}
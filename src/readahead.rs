@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread;
+
+use crate::buffers::{self, AlignedBuffer, Reservation};
+use crate::timing::Clock;
+
+/// A chunk of the source, as filled in by the reader thread.
+pub struct Chunk {
+    pub buffer: AlignedBuffer,
+    /// Number of valid bytes at the start of `buffer`.
+    pub len: usize,
+}
+
+/// Reads a file or stream on a separate thread into a small ring of pre-allocated
+/// buffers, so that device writes don't wait on slow image storage (NFS,
+/// spinning disks). At most `depth` chunks are read ahead, fewer if the
+/// memory limit doesn't allow as many.
+pub struct ReadAhead {
+    filled: Receiver<io::Result<Chunk>>,
+    empty: SyncSender<AlignedBuffer>,
+    _memory: Reservation,
+}
+
+impl ReadAhead {
+    /// Starts reading `len` bytes of `file` from `start` in chunks of
+    /// `chunk_size`, counting the time spent reading on `clock`. Reads are
+    /// positioned, so several readers can share the file.
+    pub fn spawn(
+        file: File,
+        start: u64,
+        len: u64,
+        depth: usize,
+        chunk_size: usize,
+        align: usize,
+        clock: &Clock,
+    ) -> Self {
+        let clock = clock.clone();
+        let mut offset = start;
+        let end = start + len;
+        Self::start(depth, chunk_size, align, move |buf| {
+            let to_read = std::cmp::min(buf.len() as u64, end - offset) as usize;
+            clock.time(|| file.read_exact_at(&mut buf[..to_read], offset))?;
+            offset += to_read as u64;
+            Ok(to_read)
+        })
+    }
+
+    /// Starts reading `reader` to its end in chunks of `chunk_size`, e.g.
+    /// a decompressor, which then runs beside the device writes instead of
+    /// between them. Every chunk but the last is full.
+    pub fn stream(
+        mut reader: Box<dyn Read + Send>,
+        depth: usize,
+        chunk_size: usize,
+        align: usize,
+    ) -> Self {
+        Self::start(depth, chunk_size, align, move |buf| {
+            let mut len = 0;
+            while len < buf.len() {
+                match reader.read(&mut buf[len..]) {
+                    Ok(0) => break,
+                    Ok(n) => len += n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(len)
+        })
+    }
+
+    /// Runs `fill` on the reader thread for each buffer until it fills
+    /// none of it or fails.
+    fn start(
+        depth: usize,
+        chunk_size: usize,
+        align: usize,
+        mut fill: impl FnMut(&mut [u8]) -> io::Result<usize> + Send + 'static,
+    ) -> Self {
+        let memory = buffers::reserve("read-ahead", depth, chunk_size);
+        let depth = memory.count();
+        let (filled_tx, filled) = sync_channel(depth);
+        let (empty, empty_rx) = sync_channel::<AlignedBuffer>(depth);
+        for _ in 0..depth {
+            empty.send(memory.buffer(align)).expect("receiver is alive");
+        }
+
+        thread::spawn(move || {
+            loop {
+                // Stops once the consumer is gone (done or cancelled)
+                let Ok(mut buffer) = empty_rx.recv() else {
+                    return;
+                };
+                let result = fill(buffer.as_mut_slice()).map(|len| Chunk { buffer, len });
+                if let Ok(Chunk { len: 0, .. }) = result {
+                    return;
+                }
+                let failed = result.is_err();
+                if filled_tx.send(result).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        Self {
+            filled,
+            empty,
+            _memory: memory,
+        }
+    }
+
+    /// Waits for the next chunk, in file order.
+    pub fn next_chunk(&self) -> io::Result<Chunk> {
+        self.next_chunk_or_end()?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "image ended early"))
+    }
+
+    /// Waits for the next chunk, or `None` once a stream has ended.
+    pub fn next_chunk_or_end(&self) -> io::Result<Option<Chunk>> {
+        self.filled.recv().map_or(Ok(None), |chunk| chunk.map(Some))
+    }
+
+    /// Returns a buffer to the reader once its contents have been written.
+    pub fn recycle(&self, buffer: AlignedBuffer) {
+        // The reader may already have finished, which is fine
+        self.empty.send(buffer).ok();
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::json::quote;
+use crate::status::unix_now;
+
+/// The hash algorithm reports give hashes in.
+const ALGORITHM: &str = "sha256";
+
+/// Finishes `hasher` into the hex digest reports give.
+pub fn hex(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// A range of a device that didn't match, in bytes from its start.
+pub struct Mismatch {
+    pub start: u64,
+    pub end: u64,
+    /// The part of the device it's in, e.g. "partition 2 (rootfs ext4)",
+    /// if the device has a partition table.
+    pub region: Option<String>,
+}
+
+/// What was found for one device.
+#[derive(Default)]
+pub struct DeviceRecord {
+    pub device: String,
+    /// Hash of what was written, the same for every device of a write.
+    pub image_hash: Option<String>,
+    /// Hash of what the device read back, over the image's length.
+    pub device_hash: Option<String>,
+    pub mismatches: Vec<Mismatch>,
+    /// Whether mismatched chunks were re-written until they matched.
+    pub repaired: bool,
+    pub write_time: Option<Duration>,
+    pub verify_time: Option<Duration>,
+    pub error: Option<String>,
+}
+
+impl DeviceRecord {
+    pub fn new(device: &Path) -> Self {
+        DeviceRecord {
+            device: device.display().to_string(),
+            ..DeviceRecord::default()
+        }
+    }
+
+    /// Notes how the device's write or verification ended.
+    pub fn finish(&mut self, result: &Result<()>) {
+        if let Err(e) = result {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// "pass", "fail" (it didn't match), "error" (it failed otherwise), or
+    /// "unverified" (written without verifying).
+    fn outcome(&self) -> &'static str {
+        match (&self.error, &self.verify_time) {
+            (Some(_), _) if !self.mismatches.is_empty() && !self.repaired => "fail",
+            (Some(_), _) => "error",
+            (None, None) => "unverified",
+            (None, Some(_)) => "pass",
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let optional = |value: &Option<String>| value.as_deref().map_or("null".to_string(), quote);
+        let seconds = |time: Option<Duration>| {
+            time.map_or("null".to_string(), |t| format!("{:.3}", t.as_secs_f64()))
+        };
+        let mismatches: Vec<String> = self
+            .mismatches
+            .iter()
+            .map(|m| {
+                format!(
+                    "{{\"start\":{},\"end\":{},\"region\":{}}}",
+                    m.start,
+                    m.end,
+                    optional(&m.region)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"device\":{},\"result\":{},\"device_hash\":{},\"mismatches\":[{}],\"repaired\":{},\"write_seconds\":{},\"verify_seconds\":{},\"error\":{}}}",
+            quote(&self.device),
+            quote(self.outcome()),
+            optional(&self.device_hash),
+            mismatches.join(","),
+            self.repaired,
+            seconds(self.write_time),
+            seconds(self.verify_time),
+            optional(&self.error)
+        )
+    }
+}
+
+/// A record of conformance for a write or verification (`--report`), for
+/// manufacturing execution systems: the hashes of the image and of what
+/// each device holds, the ranges that didn't match, and how long it took.
+pub struct Report {
+    operation: &'static str,
+    /// What the devices were compared with: the image, or the baseline.
+    reference: (&'static str, String),
+    started: u64,
+    pub devices: Vec<DeviceRecord>,
+}
+
+impl Report {
+    /// Starts the report of `operation` ("write" or "verify"), which
+    /// compares devices with `reference`, the `kind` ("image" or "baseline")
+    /// its hash is given as.
+    pub fn new(operation: &'static str, kind: &'static str, reference: &Path) -> Self {
+        Report {
+            operation,
+            reference: (kind, reference.display().to_string()),
+            started: unix_now(),
+            devices: Vec::new(),
+        }
+    }
+
+    /// Writes the report to `path` as JSON.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let (kind, reference) = &self.reference;
+        let hash = self
+            .devices
+            .iter()
+            .find_map(|d| d.image_hash.as_deref())
+            .map_or("null".to_string(), quote);
+        let devices: Vec<String> = self.devices.iter().map(DeviceRecord::to_json).collect();
+        let json = format!(
+            "{{\"tool\":{},\"operation\":{},{}:{},\"hash_algorithm\":{},{}:{hash},\"started\":{},\"finished\":{},\"devices\":[{}]}}\n",
+            quote(&format!("etchr {}", env!("CARGO_PKG_VERSION"))),
+            quote(self.operation),
+            quote(kind),
+            quote(reference),
+            quote(ALGORITHM),
+            quote(&format!("{kind}_hash")),
+            self.started,
+            unix_now(),
+            devices.join(",")
+        );
+        fs::write(path, json).with_context(|| format!("saving the report to {}", path.display()))
+    }
+}
@@ -0,0 +1,274 @@
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::i18n::{Msg, tr, trf};
+use crate::privileges;
+use crate::sink;
+
+/// An object in S3 (or an S3-compatible store), and the credentials to
+/// sign requests for it with, taken from the usual `AWS_*` environment
+/// variables. Requests are signed and sent by curl.
+pub struct Object {
+    /// URL of the object, with the key encoded.
+    url: String,
+    region: String,
+    /// A curl config file holding the credentials, so they don't show
+    /// up in the process list.
+    credentials: NamedTempFile,
+}
+
+impl Object {
+    /// Looks up the object `BUCKET/KEY`, as in an `s3://BUCKET/KEY` URL.
+    pub fn new(location: &str) -> Result<Self> {
+        Self::with_env(location, |name| std::env::var(name).ok())
+    }
+
+    /// Looks up the object `BUCKET/KEY` with the `AWS_*` variables `var`
+    /// returns.
+    fn with_env(location: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let (bucket, key) = location
+            .split_once('/')
+            .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+            .ok_or_else(|| anyhow!(trf(Msg::NotS3Location, &[&location])))?;
+        let env = |name: &str| var(name).filter(|v| !v.is_empty());
+        let (Some(access_key), Some(secret_key)) =
+            (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY"))
+        else {
+            return Err(anyhow!(tr(Msg::S3NeedsCredentials)));
+        };
+        let region = env("AWS_REGION")
+            .or_else(|| env("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        // S3-compatible stores (MinIO, Ceph, ...) are addressed by path
+        let url = match env("AWS_ENDPOINT_URL_S3").or_else(|| env("AWS_ENDPOINT_URL")) {
+            Some(endpoint) => format!(
+                "{}/{bucket}/{}",
+                endpoint.trim_end_matches('/'),
+                uri_encode(key, false)
+            ),
+            None => format!(
+                "https://{bucket}.s3.{region}.amazonaws.com/{}",
+                uri_encode(key, false)
+            ),
+        };
+
+        let mut credentials = NamedTempFile::new()?;
+        writeln!(credentials, "user = \"{access_key}:{secret_key}\"")?;
+        if let Some(token) = env("AWS_SESSION_TOKEN") {
+            writeln!(credentials, "header = \"x-amz-security-token: {token}\"")?;
+        }
+        credentials.flush()?;
+        // curl runs once privileges are dropped
+        if let Some(user) = privileges::invoking_user() {
+            user.give(credentials.as_file())?;
+        }
+        Ok(Object {
+            url,
+            region,
+            credentials,
+        })
+    }
+
+    /// Builds a signed curl request for the object, with `query` appended
+    /// to its URL and a body hashing to `hash`.
+    fn command(&self, method: &str, query: &str, hash: &[u8]) -> Command {
+        let hash: String = hash.iter().map(|b| format!("{b:02x}")).collect();
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--request", method])
+            .arg("--config")
+            .arg(self.credentials.path())
+            .arg("--aws-sigv4")
+            .arg(format!("aws:amz:{}:s3", self.region))
+            .arg("--header")
+            .arg(format!("x-amz-content-sha256: {hash}"))
+            .arg(format!("{}{query}", self.url))
+            .stderr(Stdio::piped());
+        command
+    }
+
+    /// Sends a signed request for the object, with `query` appended to
+    /// its URL, and returns the response's headers and body.
+    pub fn request(
+        &self,
+        method: &str,
+        query: &str,
+        body: Option<&[u8]>,
+    ) -> Result<(String, String)> {
+        let hash = Sha256::digest(body.unwrap_or_default());
+        let mut command = self.command(method, &format!("?{query}"), &hash);
+        command.arg("--include").stdout(Stdio::piped());
+        if body.is_some() {
+            command
+                .args(["--header", "Content-Type: application/octet-stream"])
+                .args(["--data-binary", "@-"])
+                .stdin(Stdio::piped());
+        }
+        let mut curl = command
+            .spawn()
+            .with_context(|| trf(Msg::RunningProgram, &[&"curl"]))?;
+        if let (Some(body), Some(mut stdin)) = (body, curl.stdin.take()) {
+            stdin.write_all(body)?;
+        }
+        let what = format!("{method} {}?{query}", self.url);
+        let response = String::from_utf8_lossy(&sink::wait_for(curl, &what)?).into_owned();
+
+        // Skip the "100 Continue" curl asks for before large parts
+        let mut rest = response.as_str();
+        let (headers, body, status) = loop {
+            let (headers, body) = rest.split_once("\r\n\r\n").unwrap_or((rest, ""));
+            let status = headers.split_whitespace().nth(1).unwrap_or_default();
+            if !status.starts_with('1') {
+                break (headers, body, status);
+            }
+            rest = body;
+        };
+        // Completing an upload can fail with a 200 and an error body
+        if !status.starts_with('2') || body.contains("<Error>") {
+            let message = xml_element(body, "Message").unwrap_or(status);
+            return Err(anyhow!(trf(Msg::ActionFailed, &[&what, &message])));
+        }
+        Ok((headers.to_string(), body.to_string()))
+    }
+
+    /// Starts downloading the object, which curl streams to its stdout.
+    pub fn get(&self) -> Result<Child> {
+        self.command("GET", "", &Sha256::digest([]))
+            .arg("--fail")
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| trf(Msg::RunningProgram, &[&"curl"]))
+    }
+}
+
+/// Returns the text of the first `<name>` element in `xml`.
+pub fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{name}>"))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..end])
+}
+
+/// Percent-encodes `s` as S3 expects in URLs, keeping `/` unless
+/// `slash` is set.
+pub fn uri_encode(s: &str, slash: bool) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !slash => "/".to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use super::*;
+
+    fn lookup(location: &str, vars: &[(&str, &str)]) -> Result<Object> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        Object::with_env(location, |name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    const KEYS: [(&str, &str); 2] = [
+        ("AWS_ACCESS_KEY_ID", "AKID"),
+        ("AWS_SECRET_ACCESS_KEY", "secret"),
+    ];
+
+    #[test]
+    fn addresses_aws_by_bucket_and_region() {
+        let object = lookup("bucket/cards/card 1.img", &KEYS).unwrap();
+        assert_eq!(
+            object.url,
+            "https://bucket.s3.us-east-1.amazonaws.com/cards/card%201.img"
+        );
+        assert_eq!(object.region, "us-east-1");
+
+        let vars = [KEYS[0], KEYS[1], ("AWS_DEFAULT_REGION", "eu-central-1")];
+        assert_eq!(lookup("b/k", &vars).unwrap().region, "eu-central-1");
+        let vars = [
+            KEYS[0],
+            KEYS[1],
+            ("AWS_DEFAULT_REGION", "eu-central-1"),
+            ("AWS_REGION", "eu-west-1"),
+        ];
+        assert_eq!(lookup("b/k", &vars).unwrap().region, "eu-west-1");
+    }
+
+    #[test]
+    fn addresses_other_stores_by_path() {
+        let vars = [
+            KEYS[0],
+            KEYS[1],
+            ("AWS_ENDPOINT_URL", "http://minio:9000/"),
+            ("AWS_REGION", ""),
+        ];
+        let object = lookup("bucket/a+b.img", &vars).unwrap();
+        assert_eq!(object.url, "http://minio:9000/bucket/a%2Bb.img");
+        assert_eq!(object.region, "us-east-1");
+    }
+
+    #[test]
+    fn signs_with_the_credentials_file() {
+        let vars = [KEYS[0], KEYS[1], ("AWS_SESSION_TOKEN", "token")];
+        let object = lookup("b/k", &vars).unwrap();
+        assert_eq!(
+            fs::read_to_string(object.credentials.path()).unwrap(),
+            "user = \"AKID:secret\"\nheader = \"x-amz-security-token: token\"\n"
+        );
+
+        let command = object.command("PUT", "?uploads", &[0xab, 0x01]);
+        let args: Vec<_> = command.get_args().map(|a| a.to_string_lossy()).collect();
+        assert_eq!(
+            args,
+            [
+                "--silent",
+                "--show-error",
+                "--request",
+                "PUT",
+                "--config",
+                &object.credentials.path().to_string_lossy(),
+                "--aws-sigv4",
+                "aws:amz:us-east-1:s3",
+                "--header",
+                "x-amz-content-sha256: ab01",
+                "https://b.s3.us-east-1.amazonaws.com/k?uploads",
+            ]
+        );
+        // The secret stays out of the process list
+        assert!(!args.iter().any(|a| a.contains("secret")));
+    }
+
+    #[test]
+    fn needs_a_bucket_a_key_and_credentials() {
+        for location in ["bucket", "bucket/", "/key"] {
+            assert!(lookup(location, &KEYS).is_err(), "{location}");
+        }
+        assert!(lookup("b/k", &KEYS[..1]).is_err());
+        assert!(lookup("b/k", &[KEYS[0], ("AWS_SECRET_ACCESS_KEY", "")]).is_err());
+    }
+
+    #[test]
+    fn encodes_uris() {
+        assert_eq!(uri_encode("a b/c~d_e.f-g", false), "a%20b/c~d_e.f-g");
+        assert_eq!(uri_encode("a/b=c", true), "a%2Fb%3Dc");
+        assert_eq!(uri_encode("ü", false), "%C3%BC");
+    }
+
+    #[test]
+    fn finds_xml_elements() {
+        let xml = "<R><UploadId>abc</UploadId><Key>k</Key></R>";
+        assert_eq!(xml_element(xml, "UploadId"), Some("abc"));
+        assert_eq!(xml_element(xml, "Key"), Some("k"));
+        assert_eq!(xml_element(xml, "ETag"), None);
+        assert_eq!(xml_element("<Message>cut", "Message"), None);
+    }
+}
@@ -0,0 +1,49 @@
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+
+use crate::i18n::{Msg, trf};
+
+/// Named bundles of safety-related behavior, selected with `--safety`
+/// or `safety = "..."` in the config file.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SafetyProfile {
+    /// Type the device name to confirm, always verify and sandbox, refuse override flags
+    Paranoid,
+    /// Yes/no confirmation, removable devices only
+    #[default]
+    Standard,
+    /// Also offer internal (non-system) disks, for lab automation
+    Relaxed,
+}
+
+impl SafetyProfile {
+    /// Parses a profile name as written in the config file.
+    pub fn from_config(value: &str) -> Result<Self> {
+        Self::from_str(value, true).map_err(|_| {
+            anyhow!("unknown safety profile '{value}' (expected paranoid, standard or relaxed)")
+        })
+    }
+
+    /// Whether the user must type the device name instead of answering yes/no.
+    pub fn requires_typed_confirmation(self) -> bool {
+        self == SafetyProfile::Paranoid
+    }
+
+    /// Whether non-removable disks (other than the system disk) may be selected.
+    pub fn allows_internal_disks(self) -> bool {
+        self == SafetyProfile::Relaxed
+    }
+
+    /// Whether reads and writes are sandboxed even without `--sandbox`.
+    pub fn always_sandboxes(self) -> bool {
+        self == SafetyProfile::Paranoid
+    }
+
+    /// Rejects a flag that weakens safety if the profile forbids overrides.
+    pub fn check_override(self, flag: &str) -> Result<()> {
+        if self == SafetyProfile::Paranoid {
+            return Err(anyhow!(trf(Msg::OverrideForbidden, &[&flag])));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,302 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code, unused_imports))]
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use console::style;
+
+use crate::i18n::{Msg, tr};
+use crate::output::verbose;
+
+// Landlock ABI v1 filesystem access rights (linux/landlock.h).
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+/// Every right known to ABI v1, all of which are denied unless a rule grants them.
+const ACCESS_FS_ALL: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_READ_DIR
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM;
+
+/// Rights for directories the operation keeps updating, e.g. the status file.
+const ACCESS_FS_READ_WRITE: u64 = ACCESS_FS_READ_FILE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_REG;
+
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscalls no part of a read or write should need once its files are open.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+const DENIED_SYSCALLS: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_bind,
+    libc::SYS_mount,
+    libc::SYS_umount2,
+    libc::SYS_pivot_root,
+    libc::SYS_chroot,
+    libc::SYS_unshare,
+    libc::SYS_setns,
+    libc::SYS_bpf,
+    libc::SYS_init_module,
+    libc::SYS_finit_module,
+    libc::SYS_delete_module,
+    libc::SYS_kexec_load,
+    libc::SYS_reboot,
+    libc::SYS_swapon,
+    libc::SYS_swapoff,
+];
+
+/// Restricts the process for the rest of the run. Call once the device and
+/// image are open: descriptors opened before keep working, but afterwards
+/// the filesystem is only reachable beneath `writable` (read/write) and
+/// `removable` (delete files only), and syscalls such as `execve`, `socket`
+/// and `mount` fail with EPERM.
+///
+/// Landlock applies to the calling thread and any threads it spawns later,
+/// which covers decompression. If the kernel lacks Landlock, a warning is
+/// printed and only the syscall filter is applied.
+#[cfg(target_os = "linux")]
+pub fn enter(writable: &[PathBuf], removable: &[PathBuf]) -> Result<()> {
+    // Required for unprivileged Landlock and seccomp, and a good idea anyway.
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error()).context("setting no_new_privs");
+    }
+
+    match restrict_filesystem(writable, removable) {
+        Ok(()) => verbose!("Landlock filesystem restrictions applied"),
+        Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS | libc::EOPNOTSUPP)) => {
+            eprintln!(
+                "{} {}",
+                style(tr(Msg::Warning)).yellow().bold(),
+                tr(Msg::LandlockUnavailable)
+            );
+        }
+        Err(e) => return Err(e).context("applying Landlock rules"),
+    }
+
+    match filter_syscalls() {
+        Ok(()) => verbose!("seccomp filter applied"),
+        Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+            eprintln!(
+                "{} {}",
+                style(tr(Msg::Warning)).yellow().bold(),
+                tr(Msg::SeccompUnavailable)
+            );
+        }
+        Err(e) => return Err(e).context("installing seccomp filter"),
+    }
+    Ok(())
+}
+
+/// Landlock and seccomp are Linux's, so elsewhere `--sandbox` fails rather
+/// than run unconfined.
+#[cfg(not(target_os = "linux"))]
+pub fn enter(_writable: &[PathBuf], _removable: &[PathBuf]) -> Result<()> {
+    Err(anyhow::anyhow!(
+        "--sandbox needs Landlock and seccomp, which only Linux has"
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn restrict_filesystem(writable: &[PathBuf], removable: &[PathBuf]) -> io::Result<()> {
+    let attr = RulesetAttr {
+        handled_access_fs: ACCESS_FS_ALL,
+    };
+    let ruleset = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    };
+    if ruleset < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let ruleset = ruleset as libc::c_int;
+
+    let rules = writable
+        .iter()
+        .map(|dir| (dir, ACCESS_FS_READ_WRITE))
+        .chain(removable.iter().map(|dir| (dir, ACCESS_FS_REMOVE_FILE)));
+
+    let mut result = Ok(());
+    for (dir, access) in rules {
+        // Missing directories simply get no rule
+        if let Err(e) = add_rule(ruleset, dir, access) {
+            verbose!("Not allowing access beneath {}: {e}", dir.display());
+        }
+    }
+    if unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } != 0 {
+        result = Err(io::Error::last_os_error());
+    }
+
+    unsafe { libc::close(ruleset) };
+    result
+}
+
+#[cfg(target_os = "linux")]
+fn add_rule(ruleset: libc::c_int, dir: &Path, access: u64) -> io::Result<()> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset,
+            RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    };
+    let result = if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    };
+
+    unsafe { libc::close(fd) };
+    result
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as u16,
+        jt,
+        jf,
+        k,
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn filter_syscalls() -> io::Result<()> {
+    use libc::{
+        BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K, BPF_LD, BPF_RET, BPF_W, SECCOMP_RET_ALLOW,
+        SECCOMP_RET_ERRNO,
+    };
+
+    let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    // Offsets into struct seccomp_data
+    let nr_offset = 0;
+    let arch_offset = 4;
+
+    let mut filter = vec![
+        // Refuse syscalls made through a foreign ABI, which use other numbers
+        stmt(BPF_LD | BPF_W | BPF_ABS, arch_offset),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET, deny),
+        stmt(BPF_LD | BPF_W | BPF_ABS, nr_offset),
+        // Likewise for x32 syscalls, which share the x86_64 arch value
+        jump(BPF_JMP | BPF_JGE | BPF_K, 0x4000_0000, 0, 1),
+        stmt(BPF_RET, deny),
+    ];
+    for &nr in DENIED_SYSCALLS {
+        filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+        filter.push(stmt(BPF_RET, deny));
+    }
+    filter.push(stmt(BPF_RET, SECCOMP_RET_ALLOW));
+
+    let prog = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    // TSYNC applies the filter to every thread, including the signal and
+    // key listener threads that are already running.
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            libc::SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(
+    target_os = "linux",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+fn filter_syscalls() -> io::Result<()> {
+    // The filter checks syscall numbers, which differ between architectures
+    Err(io::Error::from_raw_os_error(libc::ENOSYS))
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
+use console::style;
+
+use crate::config;
+use crate::history;
+use crate::menu;
+use crate::output::info;
+use crate::prompt;
+use crate::safety::SafetyProfile;
+use crate::spinner;
+
+/// Marks that the wizard was offered, so that it's offered only once
+/// whether or not it was taken up.
+fn offered_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("setup-offered"))
+}
+
+/// Offers the wizard on the first run without a config file, so that new
+/// users find the settings worth changing without reading up on flags.
+pub fn offer() -> Result<()> {
+    let (Some(config), Some(offered)) = (config::config_path(), offered_path()) else {
+        return Ok(());
+    };
+    if config.exists() || offered.exists() {
+        return Ok(());
+    }
+    // Failing to remember only means being asked again next time
+    history::save(&offered, "").ok();
+
+    info!("There's no config file yet, so etchr runs with its defaults.");
+    if !prompt::confirm(
+        "Set up the defaults now? (run 'etchr setup' to do it later)",
+        true,
+    )? {
+        info!();
+        return Ok(());
+    }
+    run()?;
+    info!();
+    Ok(())
+}
+
+/// Asks for the settings most worth changing and writes them to the config
+/// file, replacing it after asking if there is one.
+pub fn run() -> Result<()> {
+    let path =
+        config::config_path().ok_or_else(|| anyhow!("no home directory to keep the config in"))?;
+    if path.exists() && !prompt::confirm(&format!("{} exists. Replace it?", path.display()), false)?
+    {
+        return Ok(());
+    }
+
+    let safety: SafetyProfile = choose("Safety profile")?;
+    // Paranoid always verifies, and refuses to run with it turned off
+    let verify = safety == SafetyProfile::Paranoid
+        || prompt::confirm(
+            "Verify each device against the image after writing it?",
+            true,
+        )?;
+    let image_dir = prompt::input("Directory to pick images from (empty for the current one)")?;
+    let eject = prompt::confirm("Eject each device once it's written?", false)?;
+    let spinner: spinner::Theme = choose("Animation for progress of unknown length")?;
+
+    let mut contents =
+        String::from("# Written by 'etchr setup'; see the README for every setting\n");
+    let image_dir = image_dir.trim();
+    if !image_dir.is_empty() {
+        contents += &format!("image_dir = \"{image_dir}\"\n");
+    }
+    contents += &format!("verify = {verify}\n");
+    contents += &format!("eject = {eject}\n");
+    contents += &format!("spinner = \"{}\"\n", name(spinner));
+    contents += &format!("safety = \"{}\"\n", name(safety));
+    save(&path, &contents)?;
+    info!("✅ Saved to {}", style(path.display()).cyan());
+    Ok(())
+}
+
+/// Asks for one of the values of `T`, described as in `--help` and its
+/// default first.
+fn choose<T: ValueEnum + Default + PartialEq>(prompt: &str) -> Result<T> {
+    let mut values: Vec<&T> = T::value_variants().iter().collect();
+    values.sort_by_key(|value| **value != T::default());
+    let items: Vec<String> = values
+        .iter()
+        .filter_map(|value| value.to_possible_value())
+        .map(|value| match value.get_help() {
+            Some(help) => format!("{}: {help}", value.get_name()),
+            None => value.get_name().to_string(),
+        })
+        .collect();
+    let chosen = menu::select(prompt, &items)?;
+    Ok(values[chosen].clone())
+}
+
+/// The name a value is given by in the config file.
+fn name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn save(path: &Path, contents: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("writing {}", path.display()))
+}
@@ -0,0 +1,116 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::device::Device;
+use crate::i18n::{Msg, trf};
+
+/// NFS exports in effect, as exportfs keeps them, and as configured.
+const NFS_EXPORTS: [&str; 2] = ["/var/lib/nfs/etab", "/etc/exports"];
+
+const SAMBA_CONFIG: &str = "/etc/samba/smb.conf";
+
+/// Processes that run virtual machines, by the start of their name.
+const VM_PROCESSES: [&str; 3] = ["qemu", "VBox", "firecracker"];
+
+/// What else depends on `device`, e.g. "exported over NFS (/media/data)"
+/// or "attached to the virtual machine win10 (qemu-system-x86, pid 4242)":
+/// filesystems on it shared over the network, and virtual machines using
+/// it or one of its partitions as a disk. Overwriting it would corrupt
+/// them from under their users, with errors surfacing far from here.
+pub fn users(device: &Device) -> Vec<String> {
+    let mut users = Vec::new();
+    let mount_points: Vec<&Path> = device
+        .usage
+        .iter()
+        .map(|usage| Path::new(&usage.mount_point))
+        .collect();
+    let on_device = |path: &Path| mount_points.iter().any(|mp| path.starts_with(mp));
+
+    for exports in NFS_EXPORTS {
+        let Ok(contents) = fs::read_to_string(exports) else {
+            continue;
+        };
+        for path in contents
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .filter_map(|line| line.split_whitespace().next())
+        {
+            let description = trf(Msg::ExportedOverNfs, &[&path]);
+            if on_device(Path::new(path)) && !users.contains(&description) {
+                users.push(description);
+            }
+        }
+    }
+
+    if let Ok(contents) = fs::read_to_string(SAMBA_CONFIG) {
+        let mut share = String::new();
+        for line in contents.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                share = name.to_string();
+            } else if let Some((key, value)) = line.split_once('=')
+                && key.trim().eq_ignore_ascii_case("path")
+                && on_device(Path::new(value.trim()))
+            {
+                users.push(trf(Msg::SharedOverSamba, &[&share, &value.trim()]));
+            }
+        }
+    }
+
+    let nodes = device_nodes(device);
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return users;
+    };
+    for process in processes.flatten() {
+        let pid = process.file_name().to_string_lossy().into_owned();
+        if !pid.bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let dir = process.path();
+        let Ok(comm) = fs::read_to_string(dir.join("comm")) else {
+            continue;
+        };
+        let comm = comm.trim();
+        if !VM_PROCESSES.iter().any(|vm| comm.starts_with(vm)) {
+            continue;
+        }
+        // The disks a VM has open; libvirt may pass them as descriptors,
+        // so its command line can't be relied on
+        let uses_device = fs::read_dir(dir.join("fd")).is_ok_and(|fds| {
+            fds.flatten()
+                .filter_map(|fd| fs::read_link(fd.path()).ok())
+                .any(|target| nodes.contains(&target))
+        });
+        if uses_device {
+            let name = vm_name(&dir).map_or(String::new(), |name| format!(" {name}"));
+            users.push(trf(Msg::AttachedToVm, &[&name, &comm, &pid]));
+        }
+    }
+    users
+}
+
+/// The device nodes of `device` and of its partitions.
+fn device_nodes(device: &Device) -> Vec<PathBuf> {
+    let mut nodes = vec![device.path.clone()];
+    if let Ok(entries) = fs::read_dir(Path::new("/sys/block").join(&device.name)) {
+        nodes.extend(
+            entries
+                .flatten()
+                .filter(|entry| entry.path().join("partition").exists())
+                .map(|entry| Path::new("/dev").join(entry.file_name())),
+        );
+    }
+    nodes
+}
+
+/// The name QEMU was given for the VM with `-name`, e.g. by libvirt as
+/// `guest=win10,debug-threads=on`.
+fn vm_name(process: &Path) -> Option<String> {
+    let cmdline = fs::read(process.join("cmdline")).ok()?;
+    let mut args = cmdline
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    args.find(|arg| arg == "-name")?;
+    let value = args.next()?;
+    let name = value.split(',').next()?;
+    Some(name.strip_prefix("guest=").unwrap_or(name).to_string())
+}
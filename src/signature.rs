@@ -0,0 +1,139 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+
+/// What a detached signature is checked against.
+pub enum Signed<'a> {
+    /// A file, read by `gpg` directly. Used for large images.
+    File(&'a Path),
+    /// Data already read from `file`, piped in so that exactly the bytes
+    /// that were checked are the ones used afterwards.
+    Data { file: &'a Path, data: &'a [u8] },
+}
+
+/// Returns the detached signature next to `file`, i.e. `file.asc` or `file.sig`.
+pub fn find_detached(file: &Path) -> Option<PathBuf> {
+    ["asc", "sig"]
+        .iter()
+        .map(|ext| {
+            let mut name = file.as_os_str().to_owned();
+            name.push(".");
+            name.push(ext);
+            PathBuf::from(name)
+        })
+        .find(|p| p.exists())
+}
+
+/// Checks a detached OpenPGP signature with `gpg`. With a `keyring`, only
+/// keys from it are trusted instead of the user's default keyring.
+pub fn verify(signed: Signed, signature: &Path, keyring: Option<&Path>) -> Result<()> {
+    let mut gpg = Command::new("gpg");
+    gpg.arg("--batch");
+    if let Some(keyring) = keyring {
+        gpg.arg("--no-default-keyring")
+            .arg("--keyring")
+            .arg(keyring);
+    }
+    gpg.arg("--verify").arg(signature);
+
+    let (file, data) = match signed {
+        Signed::File(file) => {
+            gpg.arg(file).stdin(Stdio::null());
+            (file, None)
+        }
+        Signed::Data { file, data } => {
+            gpg.arg("-").stdin(Stdio::piped());
+            (file, Some(data))
+        }
+    };
+
+    let mut child = gpg
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("running gpg (is it installed?)")?;
+    if let (Some(mut stdin), Some(data)) = (child.stdin.take(), data) {
+        // gpg may exit early on a bad signature; that's reported below
+        stdin.write_all(data).ok();
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "signature check of {} failed:\n{}",
+            file.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// A key to sign images with, from the `[signing]` section of the config.
+pub enum SigningKey {
+    /// A GPG key ID (or anything `gpg --local-user` accepts).
+    Gpg(String),
+    /// A minisign secret key file.
+    Minisign(PathBuf),
+}
+
+impl SigningKey {
+    /// Builds the key from `signing.tool` (`gpg` if not given) and
+    /// `signing.key` in the config file.
+    pub fn from_config(tool: Option<&str>, key: &str) -> Result<Self> {
+        match tool.unwrap_or("gpg") {
+            "gpg" => Ok(SigningKey::Gpg(key.to_string())),
+            "minisign" => Ok(SigningKey::Minisign(crate::config::expand_home(key))),
+            tool => Err(anyhow!(
+                "unknown signing tool '{tool}' (expected gpg or minisign)"
+            )),
+        }
+    }
+}
+
+/// Signs `file` with `key`, writing a detached signature next to it:
+/// `file.asc` with GPG or `file.minisig` with minisign. Returns the
+/// signature's path. The tools may ask for the key's passphrase.
+pub fn sign(file: &Path, key: &SigningKey) -> Result<PathBuf> {
+    let with_extension = |ext: &str| {
+        let mut name = file.as_os_str().to_owned();
+        name.push(".");
+        name.push(ext);
+        PathBuf::from(name)
+    };
+
+    let (tool, mut command, signature) = match key {
+        SigningKey::Gpg(id) => {
+            let signature = with_extension("asc");
+            let mut gpg = Command::new("gpg");
+            gpg.args(["--yes", "--armor", "--detach-sign", "--local-user"])
+                .arg(id)
+                .arg("--output")
+                .arg(&signature)
+                .arg(file);
+            ("gpg", gpg, signature)
+        }
+        SigningKey::Minisign(secret_key) => {
+            let signature = with_extension("minisig");
+            let mut minisign = Command::new("minisign");
+            minisign
+                .arg("-S")
+                .arg("-s")
+                .arg(secret_key)
+                .arg("-m")
+                .arg(file)
+                .arg("-x")
+                .arg(&signature);
+            ("minisign", minisign, signature)
+        }
+    };
+
+    let status = command
+        .status()
+        .with_context(|| format!("running {tool} (is it installed?)"))?;
+    if !status.success() {
+        return Err(anyhow!("signing {} with {tool} failed", file.display()));
+    }
+    Ok(signature)
+}
@@ -0,0 +1,477 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::TcpStream;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use indicatif::HumanBytes;
+
+use crate::i18n::{Msg, tr, trf};
+use crate::output::verbose;
+use crate::read;
+use crate::s3;
+
+/// How often storing a part of an S3 upload is tried before giving up.
+const ATTEMPTS: u32 = 4;
+
+/// Pause before the first retry of a part; it doubles with each retry.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Size of the first parts of an S3 upload. S3 takes at most 10,000
+/// parts, so every 1000 parts the size doubles, which covers any disk.
+const FIRST_PART_SIZE: usize = 16 * 1024 * 1024;
+
+/// Somewhere other than a local file that a read streams its image to.
+/// The read writes the image to the stream returned with the sink (see
+/// [`open`]) as it would to stdout, and closes it when done.
+pub trait Sink {
+    /// Waits until the image written to the stream is stored, once the
+    /// read succeeded and closed the stream.
+    fn finish(self: Box<Self>) -> Result<()>;
+
+    /// Discards what was stored after the read failed with `error`, and
+    /// returns the error to report: the sink's own, if it failed first
+    /// and so made the read fail.
+    fn abandon(self: Box<Self>, error: anyhow::Error) -> anyhow::Error {
+        error
+    }
+}
+
+/// Whether `image` names a sink rather than a local file.
+pub fn is_stream(image: &Path) -> bool {
+    let image = image.to_string_lossy();
+    image == "-"
+        || ["tcp://", "unix:", "http://", "https://", "s3://"]
+            .iter()
+            .any(|scheme| image.starts_with(scheme))
+}
+
+/// Claims stdout for the image if `image` is `-`, so that nothing else
+/// printed ends up in it. Call it before printing anything, and pass the
+/// result to [`open`].
+pub fn claim_stdout(image: &Path) -> Result<Option<File>> {
+    if image != Path::new("-") {
+        return Ok(None);
+    }
+    Ok(Some(read::take_stdout()?))
+}
+
+/// Fails if the sink `image` names can't be used in the sandbox: the HTTP
+/// and S3 sinks run curl, which `sandbox` forbids.
+pub fn check(image: &Path, sandbox: bool) -> Result<()> {
+    if runs_curl(&image.to_string_lossy()) && sandbox {
+        return Err(anyhow!(tr(Msg::UploadSandboxed)));
+    }
+    Ok(())
+}
+
+fn runs_curl(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://") || name.starts_with("s3://")
+}
+
+/// Opens the sink `image` names: `-` for stdout (claimed earlier with
+/// [`claim_stdout`]), `tcp://HOST:PORT` or `unix:PATH` for a socket, an
+/// `http(s)://` URL to PUT the image to, or `s3://BUCKET/KEY` for a
+/// multipart upload to S3. Returns `None` for a local file. Open it only
+/// once the read is confirmed: an upload that starts replaces the object
+/// at its URL, even with nothing.
+pub fn open(
+    image: &Path,
+    stdout: Option<File>,
+    sandbox: bool,
+) -> Result<Option<(File, Box<dyn Sink>)>> {
+    check(image, sandbox)?;
+    let name = image.to_string_lossy();
+    let opened: (File, Box<dyn Sink>) = if let Some(stdout) = stdout {
+        (stdout, Box::new(Stream))
+    } else if let Some(address) = name.strip_prefix("tcp://") {
+        let socket =
+            TcpStream::connect(address).with_context(|| trf(Msg::ConnectingTo, &[&address]))?;
+        (File::from(OwnedFd::from(socket)), Box::new(Stream))
+    } else if let Some(path) = name.strip_prefix("unix:") {
+        let socket = UnixStream::connect(path).with_context(|| trf(Msg::ConnectingTo, &[&path]))?;
+        (File::from(OwnedFd::from(socket)), Box::new(Stream))
+    } else if let Some(location) = name.strip_prefix("s3://") {
+        let (stream, upload) = S3Upload::start(location)?;
+        (stream, Box::new(upload))
+    } else if runs_curl(&name) {
+        let (stream, put) = HttpPut::start(&name)?;
+        (stream, Box::new(put))
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(opened))
+}
+
+/// Stdout or a socket: the image is stored once it's all written.
+struct Stream;
+
+impl Sink for Stream {
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Waits for `child` and turns a failure into an error with its stderr.
+pub fn wait_for(child: Child, what: &str) -> Result<Vec<u8>> {
+    let output = child
+        .wait_with_output()
+        .with_context(|| trf(Msg::RunningCurlTo, &[&what]))?;
+    if !output.status.success() {
+        return Err(anyhow!(trf(
+            Msg::ActionFailed,
+            &[&what, &String::from_utf8_lossy(&output.stderr).trim()]
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// A single HTTP PUT of the whole image as it's read. The image is never
+/// held anywhere, so unlike S3 parts it can't be retried.
+struct HttpPut {
+    url: String,
+    /// `None` once curl is waited for.
+    curl: Option<Child>,
+}
+
+impl HttpPut {
+    fn start(url: &str) -> Result<(File, Self)> {
+        let mut curl = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location"])
+            .args(["--header", "Expect:", "--upload-file", "-"])
+            .arg(url)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| trf(Msg::RunningProgram, &[&"curl"]))?;
+        let stdin = curl.stdin.take().expect("curl's stdin is piped");
+        let url = url.to_string();
+        let put = HttpPut {
+            url,
+            curl: Some(curl),
+        };
+        Ok((File::from(OwnedFd::from(stdin)), put))
+    }
+}
+
+impl HttpPut {
+    /// Cuts the upload off if curl is still running, so the server
+    /// doesn't keep a truncated image. Returns whether it was.
+    fn stop(&mut self) -> bool {
+        let Some(curl) = &mut self.curl else {
+            return false;
+        };
+        if let Ok(None) = curl.try_wait() {
+            curl.kill().ok();
+            curl.wait().ok();
+            self.curl = None;
+            return true;
+        }
+        false
+    }
+}
+
+impl Sink for HttpPut {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let curl = self.curl.take().expect("curl is waited for once");
+        wait_for(curl, &trf(Msg::UploadingTo, &[&self.url]))?;
+        Ok(())
+    }
+
+    fn abandon(mut self: Box<Self>, error: anyhow::Error) -> anyhow::Error {
+        if self.stop() {
+            return error;
+        }
+        let curl = self.curl.take().expect("curl is waited for once");
+        wait_for(curl, &trf(Msg::UploadingTo, &[&self.url]))
+            .err()
+            .unwrap_or(error)
+    }
+}
+
+impl Drop for HttpPut {
+    fn drop(&mut self) {
+        // Dropped without finishing, e.g. on an error before the read
+        // started: stop curl before it stores an empty image
+        self.stop();
+    }
+}
+
+/// An S3 multipart upload, fed through a pipe. A thread cuts what the read
+/// writes into parts and stores each, retrying failed parts.
+struct S3Upload {
+    uploader: Option<JoinHandle<Result<Uploaded>>>,
+    /// Set when the upload is abandoned, to stop storing parts.
+    abandoned: Arc<AtomicBool>,
+}
+
+/// What the upload thread stored.
+struct Uploaded {
+    object: s3::Object,
+    /// `None` until the first part is stored.
+    upload_id: Option<String>,
+    /// The ETag of each stored part, in order.
+    etags: Vec<String>,
+}
+
+impl Uploaded {
+    /// Deletes the parts stored so far.
+    fn abort(&self) {
+        let Some(upload_id) = &self.upload_id else {
+            return;
+        };
+        let query = format!("uploadId={}", s3::uri_encode(upload_id, true));
+        if let Err(e) = self.object.request("DELETE", &query, None) {
+            verbose!("Aborting the S3 upload failed: {e:#}");
+        }
+    }
+}
+
+impl S3Upload {
+    fn start(location: &str) -> Result<(File, Self)> {
+        let object = s3::Object::new(location)?;
+        let (reader, writer) = io::pipe()?;
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let stop = abandoned.clone();
+        let uploader = thread::spawn(move || {
+            let mut uploaded = Uploaded {
+                object,
+                upload_id: None,
+                etags: Vec::new(),
+            };
+            match upload(&mut uploaded, reader, &stop) {
+                Ok(()) => Ok(uploaded),
+                Err(e) => {
+                    uploaded.abort();
+                    Err(e)
+                }
+            }
+        });
+        let upload = S3Upload {
+            uploader: Some(uploader),
+            abandoned,
+        };
+        Ok((File::from(OwnedFd::from(writer)), upload))
+    }
+
+    /// Waits for the upload thread, once the stream is closed.
+    fn join(&mut self) -> Result<Uploaded> {
+        let uploader = self.uploader.take().expect("the upload is joined once");
+        uploader
+            .join()
+            .map_err(|_| anyhow!(tr(Msg::S3UploadPanicked)))?
+    }
+}
+
+impl Sink for S3Upload {
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let uploaded = self.join()?;
+        let upload_id = uploaded
+            .upload_id
+            .as_deref()
+            .expect("the first part is always stored");
+        let mut parts = String::from("<CompleteMultipartUpload>");
+        for (i, etag) in uploaded.etags.iter().enumerate() {
+            parts.push_str(&format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{etag}</ETag></Part>",
+                i + 1
+            ));
+        }
+        parts.push_str("</CompleteMultipartUpload>");
+        let query = format!("uploadId={}", s3::uri_encode(upload_id, true));
+        let completed = uploaded
+            .object
+            .request("POST", &query, Some(parts.as_bytes()))
+            .context(tr(Msg::CompletingS3Upload));
+        if completed.is_err() {
+            uploaded.abort();
+        }
+        completed.map(|_| ())
+    }
+
+    fn abandon(mut self: Box<Self>, error: anyhow::Error) -> anyhow::Error {
+        self.abandoned.store(true, Ordering::Relaxed);
+        match self.join() {
+            Ok(uploaded) => {
+                uploaded.abort();
+                error
+            }
+            // The thread aborted the upload already
+            Err(e) => e,
+        }
+    }
+}
+
+impl Drop for S3Upload {
+    fn drop(&mut self) {
+        // Dropped before the read started (e.g. it was cancelled at the
+        // prompt): nothing is stored yet, and the thread ends with the
+        // stream or the process
+        self.abandoned.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Reads parts from `reader` until it's closed and stores them, starting
+/// the multipart upload with the first.
+fn upload(uploaded: &mut Uploaded, mut reader: io::PipeReader, stop: &AtomicBool) -> Result<()> {
+    let mut part = Vec::new();
+    loop {
+        let number = uploaded.etags.len() + 1;
+        let size = FIRST_PART_SIZE << (uploaded.etags.len() / 1000);
+        part.clear();
+        (&mut reader).take(size as u64).read_to_end(&mut part)?;
+        if stop.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        // The first part is stored even if empty, to upload an empty image
+        if part.is_empty() && number > 1 {
+            return Ok(());
+        }
+
+        let upload_id = match &uploaded.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let (_, body) = uploaded
+                    .object
+                    .request("POST", "uploads", Some(&[]))
+                    .context(tr(Msg::StartingS3Upload))?;
+                let upload_id = s3::xml_element(&body, "UploadId")
+                    .ok_or_else(|| anyhow!(tr(Msg::S3NoUploadId)))?
+                    .to_string();
+                uploaded.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+        let query = format!(
+            "partNumber={number}&uploadId={}",
+            s3::uri_encode(&upload_id, true)
+        );
+
+        let mut delay = RETRY_DELAY;
+        let mut attempt = 1;
+        let headers = loop {
+            match uploaded.object.request("PUT", &query, Some(&part)) {
+                Ok((headers, _)) => break headers,
+                Err(e) if attempt < ATTEMPTS && !stop.load(Ordering::Relaxed) => {
+                    verbose!("Storing part {number} failed (attempt {attempt}): {e:#}");
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    return Err(e.context(trf(
+                        Msg::StoringS3Part,
+                        &[&number, &HumanBytes(part.len() as u64)],
+                    )));
+                }
+            }
+        };
+        let etag = headers
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("etag").then(|| value.trim())
+            })
+            .ok_or_else(|| anyhow!(trf(Msg::S3NoEtag, &[&number])))?;
+        verbose!(
+            "Stored part {number} ({}) of the S3 upload",
+            HumanBytes(part.len() as u64)
+        );
+        uploaded.etags.push(etag.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn tells_sinks_from_files() {
+        for image in [
+            "-",
+            "tcp://host:9000",
+            "unix:/run/etchr.sock",
+            "http://host/card.img",
+            "https://host/card.img",
+            "s3://bucket/card.img",
+        ] {
+            assert!(is_stream(Path::new(image)), "{image}");
+        }
+        for image in ["card.img", "./-", "scp://host/card.img", "unix"] {
+            assert!(!is_stream(Path::new(image)), "{image}");
+        }
+    }
+
+    #[test]
+    fn uploads_are_refused_in_the_sandbox() {
+        for image in ["http://host/card.img", "https://host/a", "s3://bucket/a"] {
+            assert!(check(Path::new(image), true).is_err(), "{image}");
+            assert!(check(Path::new(image), false).is_ok(), "{image}");
+        }
+        for image in ["-", "tcp://host:9000", "unix:/run/etchr.sock", "card.img"] {
+            assert!(check(Path::new(image), true).is_ok(), "{image}");
+        }
+    }
+
+    #[test]
+    fn local_files_are_no_sink() {
+        assert!(claim_stdout(Path::new("card.img")).unwrap().is_none());
+        assert!(open(Path::new("card.img"), None, false).unwrap().is_none());
+    }
+
+    #[test]
+    fn streams_to_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sock");
+        let unix = UnixListener::bind(&path).unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        let images = [
+            format!("unix:{}", path.display()),
+            format!("tcp://{}", tcp.local_addr().unwrap()),
+        ];
+        for image in images {
+            let (mut stream, sink) = open(Path::new(&image), None, true).unwrap().unwrap();
+            stream.write_all(b"image").unwrap();
+            drop(stream);
+            sink.finish().unwrap();
+        }
+        let mut received = String::new();
+        unix.accept()
+            .unwrap()
+            .0
+            .read_to_string(&mut received)
+            .unwrap();
+        tcp.accept()
+            .unwrap()
+            .0
+            .read_to_string(&mut received)
+            .unwrap();
+        assert_eq!(received, "imageimage");
+    }
+
+    #[test]
+    fn dropping_an_upload_stops_curl() {
+        // Accepts the connection but never answers, so curl waits
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/card.img", server.local_addr().unwrap());
+        let Ok((_stream, put)) = HttpPut::start(&url) else {
+            return; // No curl to run
+        };
+        let pid = put.curl.as_ref().unwrap().id();
+        drop(put);
+        // Killed and reaped, so not even a zombie is left
+        assert!(!Path::new(&format!("/proc/{pid}")).exists());
+    }
+}
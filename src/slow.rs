@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use indicatif::HumanBytes;
+
+use crate::config;
+
+/// Stretch of time spent writing over which the speed is measured, long
+/// enough to get past any fast cache on the card.
+const WINDOW: Duration = Duration::from_secs(15);
+
+/// When a device writes so slowly that something is likely wrong with it
+/// (`[slow_write]` section of the config file).
+pub struct SlowWrite {
+    /// Percentage of the write speed a card's speed class guarantees that
+    /// it must at least reach.
+    threshold: u32,
+    /// Speed in bytes per second any device must at least reach, for
+    /// devices whose class isn't known, like cards behind USB readers.
+    min_speed: Option<u64>,
+}
+
+impl Default for SlowWrite {
+    fn default() -> Self {
+        SlowWrite {
+            threshold: 50,
+            min_speed: None,
+        }
+    }
+}
+
+impl SlowWrite {
+    pub fn from_values(values: &HashMap<String, String>) -> Result<Self> {
+        let threshold = values
+            .get("slow_write.threshold")
+            .map(|v| {
+                v.trim()
+                    .trim_end_matches('%')
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|t| *t <= 100)
+                    .ok_or_else(|| {
+                        anyhow!("slow_write.threshold: '{v}' is not a percentage (e.g. 50)")
+                    })
+            })
+            .transpose()?;
+        Ok(SlowWrite {
+            threshold: threshold.unwrap_or(Self::default().threshold),
+            min_speed: values
+                .get("slow_write.min_speed")
+                .map(|v| config::parse_size("slow_write.min_speed", v))
+                .transpose()?,
+        })
+    }
+
+    /// Starts watching the writes to a device whose class guarantees
+    /// `guaranteed` bytes per second, if known. Returns `None` if there's
+    /// no speed to hold it to.
+    pub fn monitor(&self, guaranteed: Option<u64>) -> Option<Monitor> {
+        let from_class = guaranteed
+            .map(|speed| speed * u64::from(self.threshold) / 100)
+            .filter(|floor| *floor > 0);
+        // The higher of the two floors applies
+        let (floor, reason) = match (from_class, self.min_speed) {
+            (Some(floor), Some(min_speed)) if min_speed > floor => {
+                (min_speed, "slow_write.min_speed".to_string())
+            }
+            (Some(floor), _) => (
+                floor,
+                format!(
+                    "{}% of the {}/s its speed class guarantees",
+                    self.threshold,
+                    HumanBytes(guaranteed.unwrap_or_default())
+                ),
+            ),
+            (None, Some(min_speed)) => (min_speed, "slow_write.min_speed".to_string()),
+            (None, None) => return None,
+        };
+        Some(Monitor {
+            floor,
+            reason,
+            bytes: 0,
+            time: Duration::ZERO,
+            slowest: None,
+        })
+    }
+}
+
+/// Measures how fast a device sustains writes, over successive windows.
+pub struct Monitor {
+    /// Bytes per second below which the device is too slow.
+    floor: u64,
+    /// Where `floor` comes from.
+    reason: String,
+    /// Written in the current window, and the time it took.
+    bytes: u64,
+    time: Duration,
+    /// The slowest speed of a window below `floor`, once there has been one.
+    slowest: Option<u64>,
+}
+
+impl Monitor {
+    /// Records `bytes` written in `time`. Returns a warning the first time
+    /// a window is too slow.
+    pub fn record(&mut self, bytes: u64, time: Duration) -> Option<String> {
+        self.bytes += bytes;
+        self.time += time;
+        if self.time < WINDOW {
+            return None;
+        }
+        let speed = (self.bytes as f64 / self.time.as_secs_f64()) as u64;
+        self.bytes = 0;
+        self.time = Duration::ZERO;
+        if speed >= self.floor {
+            return None;
+        }
+        let first = self.slowest.is_none();
+        self.slowest = Some(self.slowest.map_or(speed, |slowest| slowest.min(speed)));
+        first.then(|| {
+            format!(
+                "the device sustains only {}/s, under {}/s ({}); \
+                 it may be counterfeit, worn out, or in a failing reader",
+                HumanBytes(speed),
+                HumanBytes(self.floor),
+                self.reason
+            )
+        })
+    }
+
+    /// Describes how slow the device was, if it ever was too slow, for the
+    /// report at the end.
+    pub fn summary(&self) -> Option<String> {
+        self.slowest.map(|speed| {
+            format!(
+                "as slow as {}/s, under {}/s ({})",
+                HumanBytes(speed),
+                HumanBytes(self.floor),
+                self.reason
+            )
+        })
+    }
+}
@@ -0,0 +1,267 @@
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::i18n::{Msg, trf};
+use crate::privileges;
+use crate::s3;
+
+/// Whether `image` names a stream rather than a local file: stdin (`-`)
+/// or a URL.
+pub fn is_stream(image: &Path) -> bool {
+    let image = image.to_string_lossy();
+    image == "-"
+        || ["http://", "https://", "s3://", "scp://", "sftp://"]
+            .iter()
+            .any(|scheme| image.starts_with(scheme))
+}
+
+/// Whether `image` is a magnet link or a `.torrent` file. Builds with the
+/// `torrent` feature download these before writing (see `torrent::fetch`).
+pub fn is_torrent(image: &Path) -> bool {
+    image.to_string_lossy().starts_with("magnet:")
+        || image
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("torrent"))
+}
+
+/// The size of the download `image` names, if it's an `http(s)://` URL
+/// whose server tells, so the download's progress can be shown against it.
+pub fn download_len(image: &Path) -> Option<u64> {
+    let name = image.to_string_lossy();
+    if !name.starts_with("http://") && !name.starts_with("https://") {
+        return None;
+    }
+    let output = Command::new("curl")
+        .args([
+            "--fail",
+            "--silent",
+            "--head",
+            "--location",
+            "--max-time",
+            "30",
+        ])
+        .arg(name.as_ref())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Redirects come first, each with its own headers; the last response
+    // is the image's
+    let mut len = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.starts_with("HTTP/") {
+            len = None;
+        } else if let Some((header, value)) = line.split_once(':')
+            && header.eq_ignore_ascii_case("content-length")
+        {
+            len = value.trim().parse().ok();
+        }
+    }
+    len
+}
+
+/// Opens the stream `image` names, the write side's counterpart to
+/// [`sink::open`](crate::sink::open): `-` for stdin, an `http(s)://` URL,
+/// `s3://BUCKET/KEY`, or `scp://` or `sftp://[USER@]HOST[:PORT]/PATH`.
+/// Returns `None` for a local file. A stream can't be read twice, so it
+/// goes through the same pipeline as stdin: decompressed by its magic
+/// bytes, hashed on the way, and kept in a temp file for verification.
+pub fn open(image: &Path) -> Result<Option<Box<dyn Read + Send>>> {
+    let name = image.to_string_lossy();
+    let stream: Box<dyn Read + Send> = if name == "-" {
+        Box::new(io::stdin())
+    } else if name.starts_with("http://") || name.starts_with("https://") {
+        let mut curl = Command::new("curl");
+        curl.args(["--fail", "--silent", "--show-error", "--location"])
+            .arg(name.as_ref());
+        Box::new(Download::start(curl, &name, None)?)
+    } else if let Some(location) = name.strip_prefix("s3://") {
+        let object = s3::Object::new(location)?;
+        let curl = object.get()?;
+        Box::new(Download::new(curl, &name, Some(object)))
+    } else if let Some(location) = name
+        .strip_prefix("scp://")
+        .or_else(|| name.strip_prefix("sftp://"))
+    {
+        Box::new(Download::start(ssh_cat(location)?, &name, None)?)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(stream))
+}
+
+/// Builds an ssh command printing the file at `location`, i.e.
+/// `[USER@]HOST[:PORT]/PATH`, with a `PATH` starting with `~/` taken
+/// relative to the remote home directory. ssh runs as the user behind
+/// sudo, so it finds their keys and known hosts, and never prompts.
+fn ssh_cat(location: &str) -> Result<Command> {
+    let (host, path) = location
+        .split_once('/')
+        .filter(|(host, path)| !host.is_empty() && !path.is_empty())
+        .ok_or_else(|| anyhow!(trf(Msg::NotSshLocation, &[&location])))?;
+    let (host, port) = match host.rsplit_once(':') {
+        Some((host, port)) => (host, Some(port)),
+        None => (host, None),
+    };
+    let path = match path.strip_prefix("~/") {
+        Some(relative) => relative.to_string(),
+        None => format!("/{path}"),
+    };
+
+    let mut ssh = Command::new("ssh");
+    ssh.args(["-o", "BatchMode=yes"]);
+    if let Some(port) = port {
+        ssh.args(["-p", port]);
+    }
+    // The remote shell splits the command, so quote the path for it
+    ssh.args([host, "--", "cat", "--"])
+        .arg(format!("'{}'", path.replace('\'', r"'\''")));
+    if let Some(user) = privileges::invoking_user() {
+        ssh.uid(user.uid()).gid(user.gid());
+        if let Some(home) = user.home() {
+            ssh.env("HOME", home);
+        }
+    }
+    Ok(ssh)
+}
+
+/// The output of curl or ssh fetching an image. A download that fails
+/// half way ends in an error rather than looking like the end of the image.
+struct Download {
+    child: Child,
+    stdout: ChildStdout,
+    what: String,
+    /// Holds the credentials curl reads, for an S3 download.
+    _object: Option<s3::Object>,
+}
+
+impl Download {
+    fn start(mut command: Command, what: &str, object: Option<s3::Object>) -> Result<Self> {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| trf(Msg::RunningProgram, &[&program]))?;
+        Ok(Download::new(child, what, object))
+    }
+
+    fn new(mut child: Child, what: &str, object: Option<s3::Object>) -> Self {
+        let stdout = child.stdout.take().expect("the download's stdout is piped");
+        Download {
+            child,
+            stdout,
+            what: what.to_string(),
+            _object: object,
+        }
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = self.child.stderr.take() {
+                    pipe.read_to_string(&mut stderr).ok();
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    trf(Msg::DownloadingFailed, &[&self.what, &stderr.trim()]),
+                ));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        // Stop a download the write no longer needs, e.g. when cancelled
+        if let Ok(None) = self.child.try_wait() {
+            self.child.kill().ok();
+            self.child.wait().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh_args(location: &str) -> Vec<String> {
+        let ssh = ssh_cat(location).unwrap();
+        ssh.get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn tells_streams_from_files() {
+        for image in [
+            "-",
+            "http://host/os.img.xz",
+            "https://host/os.img",
+            "s3://bucket/os.img",
+            "scp://host/os.img",
+            "sftp://user@host:22/os.img",
+        ] {
+            assert!(is_stream(Path::new(image)), "{image}");
+        }
+        for image in ["os.img", "./-", "/srv/http://x", "ftp://host/os.img"] {
+            assert!(!is_stream(Path::new(image)), "{image}");
+        }
+    }
+
+    #[test]
+    fn tells_torrents() {
+        assert!(is_torrent(Path::new("magnet:?xt=urn:btih:abc")));
+        assert!(is_torrent(Path::new("/srv/os.img.TORRENT")));
+        assert!(!is_torrent(Path::new("os.torrent.img")));
+        assert!(!is_torrent(Path::new("https://host/os.img")));
+    }
+
+    #[test]
+    fn fetches_with_ssh_cat() {
+        assert_eq!(
+            ssh_args("pi@host/srv/images/os.img"),
+            [
+                "-o",
+                "BatchMode=yes",
+                "pi@host",
+                "--",
+                "cat",
+                "--",
+                "'/srv/images/os.img'"
+            ]
+        );
+        assert_eq!(
+            ssh_args("host:2222/~/os.img")[2..],
+            ["-p", "2222", "host", "--", "cat", "--", "'os.img'"]
+        );
+    }
+
+    #[test]
+    fn quotes_the_path_for_the_remote_shell() {
+        assert_eq!(
+            ssh_args("host/it's $HOME; rm -rf ~").last().unwrap(),
+            r"'/it'\''s $HOME; rm -rf ~'"
+        );
+    }
+
+    #[test]
+    fn needs_a_host_and_a_path() {
+        for location in ["host", "host/", "/path"] {
+            assert!(ssh_cat(location).is_err(), "{location}");
+        }
+    }
+}
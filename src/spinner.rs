@@ -0,0 +1,92 @@
+use std::sync::OnceLock;
+
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use console::{Color, Style};
+use indicatif::ProgressStyle;
+
+/// Animations for progress of unknown length, selected with
+/// `spinner = "..."` in the config file.
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Theme {
+    /// A row of squares sliding back and forth
+    #[default]
+    Bounce,
+    /// A wave of rising and falling bars
+    Wave,
+    /// A single rotating braille dot, for narrow terminals
+    Dots,
+}
+
+/// Theme used by every spinner, set once from the config file.
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+impl Theme {
+    /// Parses a theme name as written in the config file.
+    pub fn from_config(value: &str) -> Result<Self> {
+        Self::from_str(value, true)
+            .map_err(|_| anyhow!("unknown spinner '{value}' (expected bounce, wave or dots)"))
+    }
+
+    /// The animation frames, `width` columns wide, in `color`.
+    fn frames(self, width: usize, color: Color) -> Vec<String> {
+        let frames = match self {
+            Theme::Bounce => bounce(width),
+            Theme::Wave => wave(width),
+            Theme::Dots => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏".chars().map(String::from).collect(),
+        };
+        let style = Style::new().fg(color);
+        frames
+            .iter()
+            .map(|frame| style.apply_to(frame).to_string())
+            .collect()
+    }
+}
+
+/// Seven squares, three columns apart, moving from the left edge to the
+/// right and back.
+fn bounce(width: usize) -> Vec<String> {
+    let squares = ["■"; 7].join("  ");
+    let travel = width.saturating_sub(squares.chars().count());
+    let frame = |offset: usize| {
+        format!(
+            "{}{squares}{}",
+            " ".repeat(offset),
+            " ".repeat(travel - offset)
+        )
+    };
+    // Forward including both ends, back without them, so it loops smoothly
+    (0..=travel).chain((1..travel).rev()).map(frame).collect()
+}
+
+/// Bars of increasing and decreasing height, shifting one column per frame.
+fn wave(width: usize) -> Vec<String> {
+    let levels: Vec<char> = "▁▂▃▄▅▆▇█▇▆▅▄▃▂".chars().collect();
+    (0..levels.len())
+        .map(|shift| {
+            (0..width)
+                .map(|col| levels[(col + levels.len() - shift) % levels.len()])
+                .collect()
+        })
+        .collect()
+}
+
+/// Selects the theme for all spinners. Only the first call has an effect.
+pub fn set_theme(theme: Theme) {
+    THEME.set(theme).ok();
+}
+
+/// A spinner style of the configured theme, `width` columns wide, for a
+/// template containing `{spinner}`.
+pub fn style(template: &str, width: usize, color: Color) -> ProgressStyle {
+    let frames = THEME
+        .get()
+        .copied()
+        .unwrap_or_default()
+        .frames(width, color);
+    let frames: Vec<&str> = frames.iter().map(String::as_str).collect();
+    ProgressStyle::default_spinner()
+        .tick_strings(&frames)
+        .template(template)
+        .unwrap()
+}
@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use console::Color;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::buffers;
+use crate::cancel::{CancelToken, Cancelled};
+use crate::i18n::{Msg, tr, trf};
+use crate::progress;
+use crate::spinner;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Where the image in a spool comes from, as shown on its progress bar.
+#[derive(Clone, Copy)]
+pub enum Feed {
+    /// A download, of this size if known.
+    Download(Option<u64>),
+    /// Decompression of a local image, of this compressed size.
+    Decompression(u64),
+}
+
+impl Feed {
+    fn prefix(self) -> &'static str {
+        match self {
+            Feed::Download(_) => tr(Msg::BarDownload),
+            Feed::Decompression(_) => tr(Msg::BarDecompress),
+        }
+    }
+
+    fn noun(self) -> &'static str {
+        match self {
+            Feed::Download(_) => tr(Msg::PhaseDownload),
+            Feed::Decompression(_) => tr(Msg::PhaseDecompression),
+        }
+    }
+}
+
+/// How much of the image is in the spool file so far.
+#[derive(Default)]
+struct Filled {
+    len: u64,
+    /// Set once the source has ended, with the error it ended with if it
+    /// didn't end cleanly.
+    end: Option<Result<(), String>>,
+    /// Set when the spool is dropped, to stop filling it.
+    abandoned: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    filled: Mutex<Filled>,
+    grown: Condvar,
+}
+
+/// A compressed or streamed image, decompressed (and downloaded) into a
+/// temp file in the background while the device is written from what has
+/// arrived so far. Decompression or a slow download and the write then
+/// overlap, so the whole takes about as long as the slower of the two
+/// rather than both added up, and the complete temp file is there
+/// afterwards to re-write chunks that fail verification and to write
+/// further devices from.
+pub struct Spool {
+    shared: Arc<Shared>,
+    file: File,
+    filler: Option<JoinHandle<()>>,
+}
+
+impl Spool {
+    /// Starts filling `file` from `reader`, failing once it grows beyond
+    /// `limit`. `consumed` counts the bytes received, shown in a progress
+    /// bar for `feed` beside the device's.
+    pub fn start(
+        reader: Box<dyn Read + Send>,
+        file: File,
+        limit: u64,
+        consumed: Arc<AtomicU64>,
+        feed: Feed,
+        cancel: &CancelToken,
+    ) -> io::Result<Self> {
+        let shared = Arc::new(Shared::default());
+        let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
+        pb.set_prefix(format!("{:<10}", feed.prefix()));
+        match feed {
+            Feed::Download(None) => pb.set_style(spinner::style(
+                "{prefix} [{elapsed_precise}] [{spinner}] {bytes} ({bytes_per_sec}) {msg}",
+                46,
+                Color::Blue,
+            )),
+            // Progress through the compressed file or download is known
+            // exactly
+            Feed::Download(Some(len)) | Feed::Decompression(len) => {
+                pb.set_length(len);
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "{prefix} [{elapsed_precise}] [{bar:40.blue/black}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}",
+                    )
+                    .unwrap()
+                    .progress_chars("■ "),
+                );
+            }
+        }
+        let pb = progress::add(pb);
+        pb.enable_steady_tick(Duration::from_millis(100));
+
+        let filler = {
+            let shared = shared.clone();
+            let file = file.try_clone()?;
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                let end = fill(reader, &file, limit, &consumed, &pb, &shared, &cancel);
+                match &end {
+                    Ok(()) => pb.finish_with_message(trf(Msg::PhaseComplete, &[&feed.noun()])),
+                    Err(_) => pb.abandon_with_message(trf(Msg::PhaseFailed, &[&feed.noun()])),
+                }
+                shared.filled.lock().unwrap().end = Some(end);
+                shared.grown.notify_all();
+            })
+        };
+        Ok(Spool {
+            shared,
+            file,
+            filler: Some(filler),
+        })
+    }
+
+    /// Returns a reader of the image, which waits for the spool to fill
+    /// up and ends where the source did.
+    pub fn reader(&self, cancel: &CancelToken) -> io::Result<SpoolReader> {
+        Ok(SpoolReader {
+            shared: self.shared.clone(),
+            file: self.file.try_clone()?,
+            position: 0,
+            cancel: cancel.clone(),
+        })
+    }
+
+    /// The spool file, complete once a [`SpoolReader`] has reached its end.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Waits for the source to end, and returns the complete spool file
+    /// and the length of the image in it.
+    pub fn finish(mut self) -> Result<(File, u64)> {
+        if let Some(filler) = self.filler.take() {
+            filler.join().map_err(|_| anyhow!(tr(Msg::SpoolPanicked)))?;
+        }
+        let filled = self.shared.filled.lock().unwrap();
+        match &filled.end {
+            Some(Err(e)) => Err(anyhow!("{e}")),
+            _ => Ok((self.file.try_clone()?, filled.len)),
+        }
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        // The write failed; the thread stops at its next buffer, or with
+        // the process if the source has stalled
+        self.shared.filled.lock().unwrap().abandoned = true;
+    }
+}
+
+/// Copies `reader` into `file` until it ends, publishing its progress.
+fn fill(
+    mut reader: Box<dyn Read + Send>,
+    file: &File,
+    limit: u64,
+    consumed: &AtomicU64,
+    pb: &ProgressBar,
+    shared: &Shared,
+    cancel: &CancelToken,
+) -> Result<(), String> {
+    let memory = buffers::reserve("spooling", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(1);
+    let buffer = buffer.as_mut_slice();
+    let mut len: u64 = 0;
+    loop {
+        if cancel.is_cancelled() || shared.filled.lock().unwrap().abandoned {
+            return Err(Cancelled.to_string());
+        }
+        let n = match reader.read(buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.to_string()),
+        };
+        if len + n as u64 > limit {
+            return Err(trf(Msg::SpoolFull, &[&HumanBytes(limit)]));
+        }
+        file.write_all_at(&buffer[..n], len)
+            .map_err(|e| trf(Msg::SpoolingFailed, &[&e]))?;
+        len += n as u64;
+        shared.filled.lock().unwrap().len = len;
+        shared.grown.notify_all();
+        pb.set_position(consumed.load(Ordering::Relaxed));
+        pb.set_message(trf(Msg::OfImage, &[&HumanBytes(len)]));
+    }
+}
+
+/// Reads the image from a [`Spool`] as it fills.
+pub struct SpoolReader {
+    shared: Arc<Shared>,
+    file: File,
+    position: u64,
+    cancel: CancelToken,
+}
+
+impl Read for SpoolReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = self.shared.filled.lock().unwrap();
+        while filled.len <= self.position {
+            match &filled.end {
+                Some(Ok(())) => return Ok(0),
+                Some(Err(e)) => return Err(io::Error::other(e.clone())),
+                None if self.cancel.is_cancelled() => return Err(io::Error::other(Cancelled)),
+                None => {
+                    // Wake up now and then to notice a cancellation
+                    filled = self
+                        .shared
+                        .grown
+                        .wait_timeout(filled, Duration::from_millis(200))
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+        let available = (filled.len - self.position).min(buf.len() as u64) as usize;
+        drop(filled);
+        let n = self.file.read_at(&mut buf[..available], self.position)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
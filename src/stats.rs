@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use console::style;
+use indicatif::HumanBytes;
+
+use crate::history;
+use crate::i18n::{Msg, tr, trf};
+use crate::status::unix_now;
+
+/// Where every write of a device is noted, for `etchr stats`: one line
+/// each, of when it ended (seconds since the epoch), `ok` or `failed`,
+/// the bytes written, the milliseconds it took and the image's file name.
+/// It stays on this machine.
+fn stats_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("stats"))
+}
+
+/// A write, as noted in the stats file.
+struct Entry {
+    time: u64,
+    ok: bool,
+    bytes: u64,
+    millis: u64,
+    image: String,
+}
+
+/// Reads all entries, oldest first. Malformed lines are skipped.
+fn read_entries() -> Vec<Entry> {
+    let Some(contents) = stats_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(5, '\t');
+            Some(Entry {
+                time: fields.next()?.parse().ok()?,
+                ok: fields.next()? == "ok",
+                bytes: fields.next()?.parse().ok()?,
+                millis: fields.next()?.parse().ok()?,
+                image: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Notes a write of `image` to one device, which wrote `result`'s bytes
+/// or failed, in `elapsed` including verification. Writes of several
+/// devices at once append their lines side by side.
+pub fn record(image: &Path, result: &Result<u64>, elapsed: Duration) -> io::Result<()> {
+    let Some(path) = stats_path() else {
+        return Ok(());
+    };
+    let (outcome, bytes) = match result {
+        Ok(bytes) => ("ok", *bytes),
+        Err(_) => ("failed", 0),
+    };
+    let name = image.file_name().unwrap_or(image.as_os_str());
+    let line = format!(
+        "{}\t{outcome}\t{bytes}\t{}\t{}\n",
+        unix_now(),
+        elapsed.as_millis(),
+        name.to_string_lossy().replace(['\n', '\t'], " ")
+    );
+    history::append(&path, &line)
+}
+
+/// The local date of `secs` since the epoch, e.g. "2025-03-14".
+fn local_date(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday
+    )
+}
+
+/// Prints the writes of the last `days` days: how many succeeded and
+/// failed each day, the average throughput, and how often each image was
+/// written.
+pub fn show(days: u32) -> Result<()> {
+    let since = unix_now().saturating_sub(u64::from(days) * 24 * 60 * 60);
+    let entries: Vec<Entry> = read_entries()
+        .into_iter()
+        .filter(|entry| entry.time >= since)
+        .collect();
+    if entries.is_empty() {
+        println!("{}", trf(Msg::NoWrites, &[&days]));
+        return Ok(());
+    }
+
+    let mut per_day: BTreeMap<String, (u32, u32)> = BTreeMap::new();
+    let mut per_image: BTreeMap<&str, u32> = BTreeMap::new();
+    for entry in &entries {
+        let (ok, failed) = per_day.entry(local_date(entry.time)).or_default();
+        if entry.ok {
+            *ok += 1;
+        } else {
+            *failed += 1;
+        }
+        *per_image.entry(&entry.image).or_default() += 1;
+    }
+
+    println!("{}", style(tr(Msg::WritesPerDay)).bold());
+    let ok_label = tr(Msg::WritesOk);
+    for (day, (ok, failed)) in &per_day {
+        match failed {
+            0 => println!("  {day}  {ok:>5} {ok_label}"),
+            n => println!(
+                "  {day}  {ok:>5} {ok_label}  {}",
+                style(trf(Msg::WritesFailed, &[n])).red()
+            ),
+        }
+    }
+
+    let total = entries.len();
+    let failed = entries.iter().filter(|entry| !entry.ok).count();
+    let (bytes, millis) = entries
+        .iter()
+        .filter(|entry| entry.ok)
+        .fold((0, 0), |(bytes, millis), entry| {
+            (bytes + entry.bytes, millis + entry.millis)
+        });
+    println!();
+    println!(
+        "{}",
+        trf(
+            Msg::WritesSummary,
+            &[
+                &days,
+                &total,
+                &failed,
+                &format!("{:.1}", failed as f64 * 100.0 / total as f64)
+            ]
+        )
+    );
+    if let Some(rate) = (bytes * 1000).checked_div(millis) {
+        println!("{}", trf(Msg::AverageThroughput, &[&HumanBytes(rate)]));
+    }
+
+    let mut images: Vec<(&str, u32)> = per_image.into_iter().collect();
+    images.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    println!();
+    println!("{}", style(tr(Msg::WrittenImages)).bold());
+    for (image, count) in images {
+        println!("  {count:>5}  {image}");
+    }
+    Ok(())
+}
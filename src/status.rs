@@ -0,0 +1,377 @@
+use std::collections::hash_map::RandomState;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use console::{Term, style};
+use indicatif::{HumanBytes, HumanDuration, ProgressBar};
+
+use crate::cancel::CancelToken;
+use crate::events;
+use crate::notify;
+use crate::output::verbose;
+use crate::privileges;
+
+/// How often a running operation rewrites its status file.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Where the status directories of each user are kept. Only root can
+/// create anything in its parent, so nobody else can put something there
+/// first.
+#[cfg(not(target_os = "macos"))]
+const STATUS_ROOT: &str = "/run/etchr";
+#[cfg(target_os = "macos")]
+const STATUS_ROOT: &str = "/var/run/etchr";
+
+/// The user operations run for: the one who ran sudo, if any.
+fn status_owner() -> (libc::uid_t, libc::gid_t) {
+    match privileges::invoking_user() {
+        Some(user) => (user.uid(), user.gid()),
+        None => unsafe { (libc::geteuid(), libc::getegid()) },
+    }
+}
+
+/// Directory where running operations publish their progress, one for each
+/// user, so that `etchr watch` shows theirs without root, whether or not
+/// they were started through sudo.
+pub fn status_dir() -> PathBuf {
+    Path::new(STATUS_ROOT).join(status_owner().0.to_string())
+}
+
+/// Creates the status directory if need be, which takes root, and checks
+/// that it's a directory only its user can get into. Root hands one it
+/// creates to that user, so that their operations can still update their
+/// status once privileges are dropped.
+fn prepare_dir() -> io::Result<PathBuf> {
+    let (uid, gid) = status_owner();
+    let dir = status_dir();
+    if unsafe { libc::geteuid() } == 0 {
+        match DirBuilder::new().mode(0o755).create(STATUS_ROOT) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        check_dir(Path::new(STATUS_ROOT), 0)?;
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => std::os::unix::fs::lchown(&dir, Some(uid), Some(gid))?,
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            Err(_) => {}
+        }
+    }
+    check_dir(&dir, uid)?;
+    Ok(dir)
+}
+
+/// Fails unless `dir` is a directory (not a link to one) owned by `uid`
+/// that nobody else may write to.
+fn check_dir(dir: &Path, uid: libc::uid_t) -> io::Result<()> {
+    let meta = fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o022 != 0 {
+        return Err(io::Error::other(format!(
+            "{} isn't a directory only its owner can write to",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Publishes the progress of a running operation to a small key=value file,
+/// so other processes (e.g., `etchr watch`) can observe it.
+/// The file is removed when the `StatusFile` is dropped.
+pub struct StatusFile {
+    path: Option<PathBuf>,
+    op: String,
+    device: String,
+    image: String,
+    started: u64,
+    last_update: Option<Instant>,
+    /// Set for a device that isn't the one published (see `detached`).
+    detached: bool,
+}
+
+impl StatusFile {
+    /// Registers a new operation. Failing to create the status file is not
+    /// fatal; the operation simply won't be visible to `etchr watch`. Call
+    /// it before privileges are dropped, which its directory may need.
+    pub fn create(op: &str, device: &Path, image: &Path) -> Self {
+        let path = match prepare_dir() {
+            Ok(dir) => Some(dir.join(format!("{}.status", std::process::id()))),
+            Err(e) => {
+                verbose!("Not publishing the status for 'etchr watch': {e}");
+                None
+            }
+        };
+        events::start(op, &device.display().to_string());
+        notify::status(&format!("Starting {op} on {}", device.display()));
+
+        Self {
+            path,
+            op: op.to_string(),
+            device: device.display().to_string(),
+            image: image.display().to_string(),
+            started: unix_now(),
+            last_update: None,
+            detached: false,
+        }
+    }
+
+    /// Follows a further device written alongside the one `create`d for.
+    /// Its progress only goes to notifications, which name the device; the
+    /// status file and event stream follow the first device.
+    pub fn detached(device: &Path) -> Self {
+        Self {
+            path: None,
+            op: String::new(),
+            device: device.display().to_string(),
+            image: String::new(),
+            started: unix_now(),
+            last_update: None,
+            detached: true,
+        }
+    }
+
+    /// Switches to the next device of a queued operation.
+    pub fn set_device(&mut self, device: &Path) {
+        self.device = device.display().to_string();
+        self.last_update = None;
+        events::device(&self.device);
+    }
+
+    /// Records the current state of `pb`. Updates are throttled, so this is
+    /// cheap enough to call once per buffer.
+    pub fn update(&mut self, phase: &str, pb: &ProgressBar) {
+        if let Some(last) = self.last_update
+            && last.elapsed() < UPDATE_INTERVAL
+        {
+            return;
+        }
+        self.last_update = Some(Instant::now());
+        events::notify(events::Event::Progress {
+            device: &self.device,
+            phase,
+            done: pb.position(),
+            total: pb.length().unwrap_or(0),
+            rate: pb.per_sec() as u64,
+        });
+        if !self.detached {
+            events::progress(
+                phase,
+                pb.position(),
+                pb.length().unwrap_or(0),
+                pb.per_sec() as u64,
+            );
+        }
+        notify::progress(
+            &self.device,
+            phase,
+            pb.position(),
+            pb.length().unwrap_or(0),
+            pb.per_sec() as u64,
+        );
+
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let contents = format!(
+            "pid={}\nop={}\ndevice={}\nimage={}\nphase={}\ndone={}\ntotal={}\nrate={}\nstarted={}\n",
+            std::process::id(),
+            self.op,
+            self.device,
+            self.image,
+            phase,
+            pb.position(),
+            pb.length().unwrap_or(0),
+            pb.per_sec() as u64,
+            self.started,
+        );
+
+        // Write to a temp file and rename so readers never see a partial
+        // file. It's made afresh under a name nobody can guess, and never
+        // through a link.
+        let tmp = path.with_extension(format!(
+            "{:016x}.tmp",
+            RandomState::new().build_hasher().finish()
+        ));
+        let result = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o644)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, path));
+        if result.is_err() {
+            fs::remove_file(&tmp).ok();
+        }
+    }
+}
+
+impl Drop for StatusFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// A snapshot of an operation as read back from its status file.
+struct Snapshot {
+    pid: i32,
+    op: String,
+    device: String,
+    image: String,
+    phase: String,
+    done: u64,
+    total: u64,
+    rate: u64,
+    started: u64,
+}
+
+impl Snapshot {
+    fn parse(contents: &str) -> Option<Self> {
+        let mut snap = Snapshot {
+            pid: 0,
+            op: String::new(),
+            device: String::new(),
+            image: String::new(),
+            phase: String::new(),
+            done: 0,
+            total: 0,
+            rate: 0,
+            started: 0,
+        };
+
+        for line in contents.lines() {
+            let (key, value) = line.split_once('=')?;
+            match key {
+                "pid" => snap.pid = value.parse().ok()?,
+                "op" => snap.op = value.to_string(),
+                "device" => snap.device = value.to_string(),
+                "image" => snap.image = value.to_string(),
+                "phase" => snap.phase = value.to_string(),
+                "done" => snap.done = value.parse().ok()?,
+                "total" => snap.total = value.parse().ok()?,
+                "rate" => snap.rate = value.parse().ok()?,
+                "started" => snap.started = value.parse().ok()?,
+                _ => {}
+            }
+        }
+
+        (snap.pid > 0).then_some(snap)
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        if self.rate == 0 || self.done >= self.total {
+            return None;
+        }
+        Some(Duration::from_secs((self.total - self.done) / self.rate))
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Checks whether a process is still alive without signalling it.
+fn pid_alive(pid: i32) -> bool {
+    let ret = unsafe { libc::kill(pid, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Collects the snapshots of all live operations, removing stale files
+/// left behind by processes that were killed.
+fn collect_snapshots() -> Vec<Snapshot> {
+    let Ok(entries) = fs::read_dir(status_dir()) else {
+        return Vec::new();
+    };
+
+    let mut snapshots = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("status") {
+            continue;
+        }
+
+        let Some(snap) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| Snapshot::parse(&c))
+        else {
+            continue;
+        };
+
+        if !pid_alive(snap.pid) {
+            fs::remove_file(&path).ok();
+            continue;
+        }
+        snapshots.push(snap);
+    }
+
+    snapshots.sort_by_key(|s| s.pid);
+    snapshots
+}
+
+fn render(term: &Term, snapshots: &[Snapshot]) -> io::Result<()> {
+    let mut out = String::new();
+    out.push_str(&format!(
+        "{} {} operation(s) in progress. Press Ctrl+C to exit.\n\n",
+        style("etchr watch").bold(),
+        snapshots.len()
+    ));
+    out.push_str(&format!(
+        "  {:<8} {:<6} {:<12} {:<10} {:>6} {:>21} {:>12} {:>9} {:>9}  IMAGE\n",
+        "PID", "OP", "DEVICE", "PHASE", "DONE", "BYTES", "SPEED", "ELAPSED", "ETA"
+    ));
+
+    let now = unix_now();
+    for snap in snapshots {
+        let percent = if snap.total > 0 {
+            snap.done as f64 * 100.0 / snap.total as f64
+        } else {
+            0.0
+        };
+        let eta = snap
+            .eta()
+            .map(|d| HumanDuration(d).to_string())
+            .unwrap_or_else(|| "-".to_string());
+        out.push_str(&format!(
+            "  {:<8} {:<6} {:<12} {:<10} {:>5.1}% {:>21} {:>10}/s {:>9} {:>9}  {}\n",
+            snap.pid,
+            snap.op,
+            snap.device,
+            snap.phase,
+            percent,
+            format!("{}/{}", HumanBytes(snap.done), HumanBytes(snap.total)),
+            HumanBytes(snap.rate).to_string(),
+            HumanDuration(Duration::from_secs(now.saturating_sub(snap.started))).to_string(),
+            eta,
+            snap.image,
+        ));
+    }
+
+    term.clear_screen()?;
+    term.write_str(&out)?;
+    term.flush()
+}
+
+/// Shows a live, `top`-like table of all running etchr operations
+/// until the user presses Ctrl+C.
+pub fn watch(cancel: &CancelToken) -> Result<()> {
+    let term = Term::stdout();
+    term.hide_cursor()?;
+
+    while !cancel.is_cancelled() {
+        render(&term, &collect_snapshots())?;
+        std::thread::sleep(Duration::from_secs(1));
+    }
+
+    term.show_cursor()?;
+    Ok(())
+}
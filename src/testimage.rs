@@ -0,0 +1,249 @@
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow};
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::cancel::CancelToken;
+use crate::compress::{Compression, Compressor, ZstdOptions};
+use crate::config;
+use crate::i18n::Msg;
+use crate::progress;
+use crate::read::FillPattern;
+
+/// Images are generated in chunks of this size, which is also where the
+/// first partition starts.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+const SECTOR_SIZE: usize = 512;
+
+/// The MBR partition type given to every partition (Linux).
+const PARTITION_TYPE: u8 = 0x83;
+
+/// What a test image is filled with.
+#[derive(Clone)]
+pub enum Fill {
+    /// All zeros, like a freshly created image.
+    Zero,
+    /// Incompressible pseudo-random data.
+    Random,
+    /// Each sector starts with its own offset in text, so misplaced or
+    /// missing writes show up with `hexdump`.
+    Sectors,
+    /// Mostly zeros, with stretches of random data and stamped sectors,
+    /// roughly like a real OS image.
+    Mixed,
+    /// Hex bytes repeated over the whole image.
+    Pattern(FillPattern),
+}
+
+impl FromStr for Fill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "zero" => Ok(Fill::Zero),
+            "random" => Ok(Fill::Random),
+            "sectors" => Ok(Fill::Sectors),
+            "mixed" => Ok(Fill::Mixed),
+            _ => s.parse().map(Fill::Pattern).map_err(|_| {
+                format!("'{s}' is not zero, random, sectors, mixed or hex bytes (e.g. DEADBEEF)")
+            }),
+        }
+    }
+}
+
+/// The size of a partition of a test image.
+#[derive(Clone, Copy)]
+pub enum PartitionSize {
+    Bytes(u64),
+    /// Whatever is left up to the end of the image.
+    Rest,
+}
+
+impl FromStr for PartitionSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s == "rest" {
+            return Ok(PartitionSize::Rest);
+        }
+        config::parse_size("--partitions", s)
+            .map(PartitionSize::Bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Parses an image size such as `256M`.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    config::parse_size("--size", s)
+        .map_err(|e| e.to_string())
+        .and_then(|size| {
+            if size.is_multiple_of(SECTOR_SIZE as u64) && size > 0 {
+                Ok(size)
+            } else {
+                Err(format!("'{s}' is not a whole number of 512-byte sectors"))
+            }
+        })
+}
+
+/// What [`generate`] makes.
+pub struct Spec {
+    pub size: u64,
+    /// Partitions to lay out one after another, from 1 MiB on, in an MBR.
+    pub partitions: Vec<PartitionSize>,
+    pub fill: Fill,
+    /// Seed of the pseudo-random data, so the same image can be made again.
+    pub seed: u64,
+    pub compression: Option<Compression>,
+}
+
+/// A small, fast xorshift generator; test data needs no better.
+struct Rng(u64);
+
+impl Rng {
+    fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            chunk.copy_from_slice(&self.0.to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
+
+/// Stamps each sector of `buf`, which starts at `offset` in the image,
+/// with that sector's offset.
+fn stamp_sectors(buf: &mut [u8], offset: u64) {
+    for (i, sector) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+        sector.fill(0);
+        let stamp = format!(
+            "etchr test sector at {:#014x}\n",
+            offset + (i * SECTOR_SIZE) as u64
+        );
+        let len = stamp.len().min(sector.len());
+        sector[..len].copy_from_slice(&stamp.as_bytes()[..len]);
+    }
+}
+
+/// Fills the chunk of the image at `offset`.
+fn fill_chunk(fill: &Fill, rng: &mut Rng, buf: &mut [u8], offset: u64) {
+    match fill {
+        Fill::Zero => buf.fill(0),
+        Fill::Random => rng.fill(buf),
+        Fill::Sectors => stamp_sectors(buf, offset),
+        Fill::Pattern(pattern) => pattern.fill(buf),
+        Fill::Mixed => {
+            // Draw the kind of chunk from the generator too, so the layout
+            // is as reproducible as the data
+            let mut kind = [0u8; 1];
+            rng.fill(&mut kind);
+            match kind[0] % 8 {
+                0 | 1 => rng.fill(buf),
+                2 => stamp_sectors(buf, offset),
+                _ => buf.fill(0),
+            }
+        }
+    }
+}
+
+/// Lays out `sizes` one after another from 1 MiB on in an image of
+/// `size` bytes, and returns the MBR describing them.
+fn mbr(sizes: &[PartitionSize], size: u64) -> Result<[u8; SECTOR_SIZE]> {
+    if sizes.len() > 4 {
+        return Err(anyhow!("an MBR holds at most 4 partitions"));
+    }
+    let mut sector = [0u8; SECTOR_SIZE];
+    let mut start = CHUNK_SIZE as u64;
+    for (i, partition) in sizes.iter().enumerate() {
+        let len = match partition {
+            PartitionSize::Bytes(len) => *len,
+            PartitionSize::Rest if i == sizes.len() - 1 => size.saturating_sub(start),
+            PartitionSize::Rest => return Err(anyhow!("only the last partition can be 'rest'")),
+        };
+        if len == 0 || start + len > size {
+            return Err(anyhow!(
+                "partition {} doesn't fit in the {} image",
+                i + 1,
+                HumanBytes(size)
+            ));
+        }
+        let sectors = |bytes: u64| -> Result<[u8; 4]> {
+            u32::try_from(bytes / SECTOR_SIZE as u64)
+                .map(u32::to_le_bytes)
+                .map_err(|_| anyhow!("partition {} is beyond the 2 TiB an MBR can address", i + 1))
+        };
+        let entry = &mut sector[446 + i * 16..446 + (i + 1) * 16];
+        entry[4] = PARTITION_TYPE;
+        entry[8..12].copy_from_slice(&sectors(start)?);
+        entry[12..16].copy_from_slice(&sectors(len)?);
+        // Keep the partitions aligned to 1 MiB, as partitioning tools do
+        start = (start + len).div_ceil(CHUNK_SIZE as u64) * CHUNK_SIZE as u64;
+    }
+    sector[510] = 0x55;
+    sector[511] = 0xaa;
+    Ok(sector)
+}
+
+/// Generates the test image described by `spec` at `path`.
+pub fn generate(path: &Path, spec: &Spec, cancel: &CancelToken) -> Result<()> {
+    let table = if spec.partitions.is_empty() {
+        None
+    } else {
+        Some(mbr(&spec.partitions, spec.size)?)
+    };
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut compressor = spec
+        .compression
+        .map(|compression| {
+            Compressor::spawn(
+                compression,
+                &ZstdOptions::default(),
+                CHUNK_SIZE,
+                file.try_clone()?,
+            )
+        })
+        .transpose()?;
+
+    let pb = ProgressBar::with_draw_target(Some(spec.size), ProgressDrawTarget::hidden());
+    pb.set_prefix(format!("{:<10}", "Generating"));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} [{elapsed_precise}] [{bar:40.cyan/black}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}")
+            .unwrap()
+            .progress_chars("■ "),
+    );
+    let pb = progress::add(pb);
+
+    // Zero would stay zero, so the seed is mixed with a constant
+    let mut rng = Rng(spec.seed ^ 0x9e37_79b9_7f4a_7c15);
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let mut offset = 0u64;
+    while offset < spec.size {
+        cancel.check(&pb, Msg::PhaseGenerating)?;
+        let len = (spec.size - offset).min(CHUNK_SIZE as u64) as usize;
+        let chunk = &mut buf[..len];
+        fill_chunk(&spec.fill, &mut rng, chunk, offset);
+        if let (0, Some(table)) = (offset, &table) {
+            chunk[..SECTOR_SIZE.min(len)].copy_from_slice(&table[..SECTOR_SIZE.min(len)]);
+        }
+        match &mut compressor {
+            Some(compressor) => compressor.write(chunk)?,
+            None => file.write_all_at(chunk, offset)?,
+        }
+        offset += len as u64;
+        pb.set_position(offset);
+    }
+
+    let mut file = match compressor {
+        Some(compressor) => compressor.finish()?,
+        None => file,
+    };
+    file.flush()?;
+    file.sync_all()?;
+    pb.finish_and_clear();
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::output::info;
+
+/// A running total of time that several threads can add to.
+#[derive(Clone, Default)]
+pub struct Clock(Arc<AtomicU64>);
+
+impl Clock {
+    /// Runs `f`, adding the time it takes to the total.
+    pub fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.0
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts the time spent reading from `inner` on a [`Clock`].
+pub struct TimedReader<R> {
+    inner: R,
+    clock: Clock,
+}
+
+impl<R> TimedReader<R> {
+    pub fn new(inner: R, clock: &Clock) -> Self {
+        Self {
+            inner,
+            clock: clock.clone(),
+        }
+    }
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.clock.time(|| self.inner.read(buf))
+    }
+}
+
+/// Where the time of a write went, phase by phase.
+#[derive(Default)]
+pub struct Timings {
+    /// Reading the image from its storage (or stdin), in any phase.
+    pub source_read: Clock,
+    /// Part of `source_read` that happened while decompressing.
+    pub source_read_decompressing: Duration,
+    /// Decompressing into the temp file, excluding reading the source.
+    pub decompression: Duration,
+    /// Writes to the device.
+    pub device_write: Duration,
+    /// Time the device sat idle waiting for the next chunk of the image.
+    pub waiting_for_source: Duration,
+    /// Flushing the device's caches after writing.
+    pub sync: Duration,
+    /// Reading back and comparing.
+    pub verify: Duration,
+    /// Devices that wrote suspiciously slowly, and how slowly.
+    pub slow_devices: Vec<String>,
+}
+
+impl Timings {
+    /// The phase that cost the most time, with a hint for speeding it up.
+    /// Reads that overlapped device writes only count while the device
+    /// had to wait for them.
+    fn bottleneck(&self) -> (&'static str, &'static str) {
+        let phases = [
+            (
+                self.source_read_decompressing + self.waiting_for_source,
+                "source read",
+                "copy the image to faster local storage, or raise --queue-depth",
+            ),
+            (
+                self.decompression,
+                "decompression",
+                "zstd decompresses much faster than xz, or decompress the image once beforehand",
+            ),
+            (
+                self.device_write,
+                "device write",
+                "the device itself is the limit; a faster card or reader helps",
+            ),
+            (
+                self.sync,
+                "sync",
+                "the device caches a lot of data; nothing to tune on this side",
+            ),
+            (
+                self.verify,
+                "verify",
+                "--no-verify skips verification, at the cost of not catching bad writes",
+            ),
+        ];
+        let (_, name, hint) = phases
+            .into_iter()
+            .max_by_key(|(time, _, _)| *time)
+            .expect("phases is not empty");
+        (name, hint)
+    }
+
+    /// Takes in the timings of a device written alongside. The devices'
+    /// phases overlap, so each counts for as long as the slowest device's.
+    pub fn merge(&mut self, other: Timings) {
+        self.device_write = self.device_write.max(other.device_write);
+        self.waiting_for_source = self.waiting_for_source.max(other.waiting_for_source);
+        self.sync = self.sync.max(other.sync);
+        self.verify = self.verify.max(other.verify);
+        self.slow_devices.extend(other.slow_devices);
+    }
+
+    /// Prints the breakdown and the bottleneck.
+    pub fn print(&self) {
+        let rows = [
+            ("Source read", self.source_read.total()),
+            ("Decompression", self.decompression),
+            ("Device write", self.device_write),
+            ("  waiting for source", self.waiting_for_source),
+            ("Sync", self.sync),
+            ("Verify", self.verify),
+        ];
+        info!("Time spent:");
+        for (name, time) in rows {
+            if !time.is_zero() {
+                info!("  {name:<22}{:>8.1}s", time.as_secs_f64());
+            }
+        }
+        let (name, hint) = self.bottleneck();
+        info!("  Bottleneck: {name} ({hint})");
+        for device in &self.slow_devices {
+            info!("  Slow device: {device}");
+        }
+    }
+}
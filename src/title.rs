@@ -0,0 +1,103 @@
+use std::io::{IsTerminal, Write, stderr};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use indicatif::ProgressBar;
+
+use crate::launcher;
+
+/// Whether progress should be mirrored into the terminal title.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns on title mirroring for all subsequent operations.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+fn set_title(title: &str) {
+    let mut err = stderr();
+    // OSC 2 sets the window title (and the pane title inside tmux).
+    let mut seq = format!("\x1b]2;{title}\x07");
+    // tmux only renames the window on its own escape sequence.
+    if std::env::var_os("TMUX").is_some() {
+        seq.push_str(&format!("\x1bk{title}\x1b\\"));
+    }
+    err.write_all(seq.as_bytes()).ok();
+    err.flush().ok();
+}
+
+/// Mirrors the progress of an operation into the terminal title, so it
+/// stays visible on the tab or tmux window while the pane is in the background,
+/// and onto the terminal's launcher icon if that is enabled.
+/// Both are cleared when the mirror is dropped.
+pub struct TitleMirror {
+    title: bool,
+    launcher: bool,
+    last: Option<(String, u64)>,
+}
+
+impl Default for TitleMirror {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TitleMirror {
+    pub fn new() -> Self {
+        Self {
+            title: ENABLED.load(Ordering::SeqCst) && stderr().is_terminal(),
+            launcher: launcher::is_enabled(),
+            last: None,
+        }
+    }
+
+    /// A mirror that shows nothing, for devices written alongside the one
+    /// whose progress the title shows.
+    pub fn disabled() -> Self {
+        Self {
+            title: false,
+            launcher: false,
+            last: None,
+        }
+    }
+
+    /// Updates the title whenever the phase or the whole percentage changes.
+    pub fn update(&mut self, phase: &str, pb: &ProgressBar) {
+        if !self.title && !self.launcher {
+            return;
+        }
+
+        let percent = match pb.length() {
+            Some(len) if len > 0 => pb.position() * 100 / len,
+            _ => 0,
+        };
+        if self
+            .last
+            .as_ref()
+            .is_some_and(|(p, pct)| p == phase && *pct == percent)
+        {
+            return;
+        }
+
+        if self.title {
+            set_title(&format!("etchr: {phase} {percent}%"));
+        }
+        if self.launcher {
+            launcher::set_progress(Some(percent as f64 / 100.0));
+        }
+        self.last = Some((phase.to_string(), percent));
+    }
+}
+
+impl Drop for TitleMirror {
+    fn drop(&mut self) {
+        if self.last.is_none() {
+            return;
+        }
+        if self.title {
+            set_title("");
+        }
+        if self.launcher {
+            launcher::set_progress(None);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use console::Color;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget};
+use tempfile::TempDir;
+
+use crate::cancel::CancelToken;
+use crate::i18n::Msg;
+use crate::output::info;
+use crate::privileges;
+use crate::progress;
+use crate::spinner;
+
+/// An image downloaded over BitTorrent, deleted with its temp directory
+/// once dropped.
+pub struct Download {
+    _dir: TempDir,
+    image: PathBuf,
+}
+
+impl Download {
+    /// The downloaded image, a local file like any other.
+    pub fn image(&self) -> &Path {
+        &self.image
+    }
+}
+
+/// Downloads the image that `torrent`, a magnet link or `.torrent` file,
+/// describes into a temp directory, running aria2c as the user behind sudo.
+/// Every piece is checked against its hash in the torrent's metadata as it
+/// arrives, and the whole download again once complete, so the image is
+/// exactly what the torrent describes. Of several files in the torrent,
+/// the largest is taken as the image; checksums and signatures beside it
+/// are small.
+pub fn fetch(torrent: &Path, cancel: &CancelToken) -> Result<Download> {
+    let dir = tempfile::Builder::new().prefix("etchr-torrent").tempdir()?;
+    let user = privileges::invoking_user();
+    if let Some(user) = &user {
+        user.give(&File::open(dir.path())?)?;
+    }
+
+    let mut aria2c = Command::new("aria2c");
+    aria2c.arg(format!("--dir={}", dir.path().display())).args([
+        "--seed-time=0",
+        "--check-integrity=true",
+        "--file-allocation=none",
+        "--bt-save-metadata=false",
+        "--summary-interval=0",
+        "--show-console-readout=false",
+        "--console-log-level=error",
+        "--download-result=hide",
+    ]);
+    let name = torrent.to_string_lossy();
+    if name.starts_with("magnet:") {
+        aria2c.arg(name.as_ref());
+    } else {
+        aria2c.arg(format!(
+            "--torrent-file={}",
+            torrent
+                .canonicalize()
+                .with_context(|| format!("opening {}", torrent.display()))?
+                .display()
+        ));
+    }
+    if let Some(user) = &user {
+        aria2c.uid(user.uid()).gid(user.gid());
+        if let Some(home) = user.home() {
+            aria2c.env("HOME", home);
+        }
+    }
+    let mut child = aria2c
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("running aria2c (is it installed?)")?;
+    // Errors go to stdout; drain it so aria2c never blocks on a full pipe
+    let mut stdout = child.stdout.take().expect("aria2c's stdout is piped");
+    let output = thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).ok();
+        output
+    });
+
+    let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
+    pb.set_prefix(format!("{:<10}", "Torrent"));
+    pb.set_style(spinner::style(
+        "{prefix} [{elapsed_precise}] [{spinner}] {bytes} ({bytes_per_sec}) {msg}",
+        46,
+        Color::Blue,
+    ));
+    let pb = progress::add(pb);
+    pb.enable_steady_tick(Duration::from_millis(100));
+
+    let status = loop {
+        if let Err(cancelled) = cancel.check(&pb, Msg::PhaseDownload) {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(cancelled.into());
+        }
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        // Pieces land all over the files, so count what's been stored
+        pb.set_position(stored(dir.path()));
+        thread::sleep(Duration::from_millis(200));
+    };
+    if !status.success() {
+        pb.abandon_with_message("❌ Download failed.");
+        let mut errors = output.join().unwrap_or_default();
+        if let Some(mut stderr) = child.stderr.take() {
+            stderr.read_to_string(&mut errors).ok();
+        }
+        return Err(anyhow!(
+            "downloading {name} failed ({status}): {}",
+            errors.trim()
+        ));
+    }
+
+    let (image, len) =
+        largest_file(dir.path())?.ok_or_else(|| anyhow!("the torrent {name} contains no files"))?;
+    pb.set_position(len);
+    pb.finish_with_message("✅ Download complete.");
+    info!(
+        "Downloaded {} ({}) from the torrent",
+        image.file_name().unwrap_or_default().to_string_lossy(),
+        HumanBytes(len)
+    );
+    Ok(Download { _dir: dir, image })
+}
+
+/// The bytes stored in the files under `dir` so far.
+fn stored(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => stored(&entry.path()),
+            Ok(meta) => meta.blocks() * 512,
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// The largest file under `dir`, with its size.
+fn largest_file(dir: &Path) -> Result<Option<(PathBuf, u64)>> {
+    let mut largest: Option<(PathBuf, u64)> = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        let candidate = if meta.is_dir() {
+            largest_file(&entry.path())?
+        } else {
+            Some((entry.path(), meta.len()))
+        };
+        if let Some((path, len)) = candidate
+            && largest.as_ref().is_none_or(|(_, max)| len > *max)
+        {
+            largest = Some((path, len));
+        }
+    }
+    Ok(largest)
+}
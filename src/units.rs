@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use indicatif::HumanBytes;
+
+use crate::i18n;
+
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Prints byte counts as plain numbers for the rest of the run, for
+/// scripts that parse the output (`--raw-sizes`).
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::SeqCst);
+}
+
+/// Writes `n` with its digits grouped in threes, using the separator of
+/// the current language, e.g. `1,234,567,890`.
+pub fn grouped(n: u64) -> String {
+    let digits = n.to_string();
+    let separator = i18n::thousands_separator();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(separator);
+        }
+        text.push(digit);
+    }
+    text
+}
+
+/// Describes an exact byte count along with its size in binary units,
+/// e.g. `1,234,567,890 bytes (1.15 GiB)`, or as a plain number with
+/// `--raw-sizes`.
+pub fn bytes(n: u64) -> String {
+    if RAW.load(Ordering::SeqCst) {
+        return n.to_string();
+    }
+    if n < 1024 {
+        return format!("{} bytes", grouped(n));
+    }
+    format!("{} bytes ({})", grouped(n), HumanBytes(n))
+}
@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::device::Device;
+use crate::exclude;
+use crate::history;
+use crate::status::unix_now;
+
+/// How long an unlock lasts unless `unlock_minutes` says otherwise.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Where unlocks are kept: one line per device, its identifier and when
+/// it was unlocked, in seconds since the epoch.
+fn unlocks_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("unlocked"))
+}
+
+/// What a device is recognized by across replugs: its serial number, world
+/// wide name or port, or else its kernel name.
+fn identity(device: &Device) -> String {
+    exclude::identifiers(&device.name)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| device.name.clone())
+}
+
+/// The unlocks still within `window`, by device.
+fn load(window: Duration) -> Vec<(String, u64)> {
+    let now = unix_now();
+    let contents = unlocks_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (id, time) = line.rsplit_once(' ')?;
+            Some((id.to_string(), time.parse().ok()?))
+        })
+        .filter(|(_, time)| now.saturating_sub(*time) < window.as_secs())
+        .collect()
+}
+
+/// Unlocks `device` for destructive commands for the next `window`, with
+/// `require_unlock` set.
+pub fn unlock(device: &Device, window: Duration) -> Result<()> {
+    let path = unlocks_path().ok_or_else(|| anyhow!("no state directory to keep unlocks in"))?;
+    let id = identity(device);
+    let mut unlocks = load(window);
+    unlocks.retain(|(other, _)| *other != id);
+    unlocks.push((id, unix_now()));
+    let contents: String = unlocks
+        .iter()
+        .map(|(id, time)| format!("{id} {time}\n"))
+        .collect();
+    history::save(&path, &contents).with_context(|| format!("writing {}", path.display()))
+}
+
+/// Fails unless `device` was unlocked within the last `window`: with
+/// `require_unlock` set, overwriting a device takes two separate steps.
+pub fn check(device: &Device, window: Duration) -> Result<()> {
+    let id = identity(device);
+    if load(window).iter().any(|(other, _)| *other == id) {
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{} is locked; unlock it with 'etchr unlock {}' first (it stays unlocked for {} minutes)",
+        device.path.display(),
+        device.name,
+        window.as_secs() / 60
+    ))
+}
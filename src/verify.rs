@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow};
+use console::style;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use sha2::{Digest, Sha256};
+
+use crate::cancel::CancelToken;
+use crate::device;
+use crate::i18n::Msg;
+use crate::output::{info, verbose};
+use crate::partition::{self, Region};
+use crate::progress;
+use crate::report::{self, DeviceRecord, Mismatch, Report};
+use crate::status::StatusFile;
+use crate::write;
+
+const BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
+
+/// Differences are told apart down to the sector.
+const SECTOR_SIZE: usize = 512;
+
+/// Changed ranges listed for each region; the rest are only counted.
+const SHOWN_RANGES: usize = 3;
+
+fn make_progress_bar(len: u64, prefix: &str) -> ProgressBar {
+    let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
+    pb.set_prefix(format!("{prefix:<10}"));
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{prefix} [{elapsed_precise}] [{bar:40.magenta/black}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}")
+            .unwrap()
+            .progress_chars("■ "),
+    );
+    progress::add(pb)
+}
+
+/// What changed in one region since the baseline.
+#[derive(Default)]
+struct Changes {
+    bytes: u64,
+    /// Number of separate changed ranges.
+    count: usize,
+    /// The first few ranges, as start and end offsets.
+    shown: Vec<(u64, u64)>,
+    /// Where the last range ends, to extend it if the next one follows.
+    end: u64,
+}
+
+impl Changes {
+    fn add(&mut self, start: u64, end: u64) {
+        self.bytes += end - start;
+        if self.count > 0 && start == self.end {
+            if let Some(last) = self.shown.last_mut().filter(|last| last.1 == start) {
+                last.1 = end;
+            }
+        } else {
+            self.count += 1;
+            if self.shown.len() < SHOWN_RANGES {
+                self.shown.push((start, end));
+            }
+        }
+        self.end = end;
+    }
+
+    fn describe(&self) -> String {
+        let ranges: Vec<String> = self
+            .shown
+            .iter()
+            .map(|(start, end)| format!("{start:#x}–{end:#x}"))
+            .collect();
+        let more = if self.count > self.shown.len() {
+            format!(", and {} more", self.count - self.shown.len())
+        } else {
+            String::new()
+        };
+        let noun = if self.count == 1 { "range" } else { "ranges" };
+        format!(
+            "{} changed in {} {noun} ({}{more})",
+            HumanBytes(self.bytes),
+            self.count,
+            ranges.join(", ")
+        )
+    }
+}
+
+/// Reads from `reader` until `buf` is full or the reader ends.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Compares `target`, a device or image, against `baseline`, an earlier
+/// dump of it (compressed or not), and reports what changed by the region
+/// of `target`'s partition table it's in: e.g. that only the root
+/// partition changed, while the boot partition is as it was. Fails if
+/// anything differs, so that it can be scripted. With `report`, both are
+/// hashed and the result is saved there as well.
+pub fn run(
+    target: &Path,
+    baseline: &Path,
+    report: Option<&Path>,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let mut record = report.map(|_| DeviceRecord::new(target));
+    let result = compare(target, baseline, record.as_mut(), cancel);
+    if let (Some(path), Some(mut record)) = (report, record) {
+        record.finish(&result);
+        let mut report = Report::new("verify", "baseline", baseline);
+        report.devices.push(record);
+        report.save(path)?;
+        info!("Saved the report to {}", path.display());
+    }
+    result
+}
+
+fn compare(
+    target: &Path,
+    baseline: &Path,
+    mut record: Option<&mut DeviceRecord>,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let start = Instant::now();
+    let file = File::open(target).with_context(|| format!("opening {}", target.display()))?;
+    let len = device::file_size(&file)?;
+    let table = partition::read_table(&file)
+        .inspect_err(|e| verbose!("No partition table on {}: {e}", target.display()))
+        .ok();
+    let mut reader = write::decompressed_reader(baseline)?;
+
+    info!(
+        "Comparing {} with the baseline {}",
+        style(target.display()).cyan(),
+        style(baseline.display()).cyan()
+    );
+    let pb = make_progress_bar(len, "Comparing");
+    let mut status = StatusFile::create("verify", target, baseline);
+    let mut changes: BTreeMap<Region, Changes> = BTreeMap::new();
+    let mut current = vec![0u8; BUFFER_SIZE];
+    let mut previous = vec![0u8; BUFFER_SIZE];
+    let mut offset: u64 = 0;
+    let mut baseline_ended = false;
+    let mut target_hasher = record.is_some().then(Sha256::new);
+    let mut baseline_hasher = record.is_some().then(Sha256::new);
+    while offset < len {
+        cancel.check(&pb, Msg::PhaseComparison)?;
+        let to_read = (len - offset).min(BUFFER_SIZE as u64) as usize;
+        file.read_exact_at(&mut current[..to_read], offset)
+            .with_context(|| format!("reading {}", target.display()))?;
+        let n = read_full(&mut reader, &mut previous[..to_read])
+            .with_context(|| format!("reading {}", baseline.display()))?;
+        if let (Some(now), Some(then)) = (&mut target_hasher, &mut baseline_hasher) {
+            now.update(&current[..n]);
+            then.update(&previous[..n]);
+        }
+
+        if current[..n] != previous[..n] {
+            for (i, (now, then)) in current[..n]
+                .chunks(SECTOR_SIZE)
+                .zip(previous[..n].chunks(SECTOR_SIZE))
+                .enumerate()
+            {
+                if now != then {
+                    let start = offset + (i * SECTOR_SIZE) as u64;
+                    let region = table
+                        .as_ref()
+                        .map_or(Region::Unpartitioned, |table| table.region(start));
+                    let end = start + now.len() as u64;
+                    changes.entry(region).or_default().add(start, end);
+                    if let Some(record) = record.as_deref_mut() {
+                        let region = table.as_ref().map(|_| region.to_string());
+                        match record.mismatches.last_mut() {
+                            Some(last) if last.end == start && last.region == region => {
+                                last.end = end
+                            }
+                            _ => record.mismatches.push(Mismatch { start, end, region }),
+                        }
+                    }
+                }
+            }
+        }
+        offset += n as u64;
+        pb.set_position(offset);
+        status.update("Comparing", &pb);
+        if n < to_read {
+            baseline_ended = true;
+            break;
+        }
+    }
+    // The baseline may go on past the end of the target
+    let beyond = match &mut baseline_hasher {
+        _ if baseline_ended => 0,
+        Some(hasher) => io::copy(&mut reader, hasher)?,
+        None => io::copy(&mut reader, &mut io::sink())?,
+    };
+    pb.finish_and_clear();
+    if let Some(record) = record {
+        record.device_hash = target_hasher.map(report::hex);
+        record.image_hash = baseline_hasher.map(report::hex);
+        record.verify_time = Some(start.elapsed());
+    }
+
+    if baseline_ended {
+        info!(
+            "The baseline ends after {}; the rest of {} wasn't compared",
+            HumanBytes(offset),
+            target.display()
+        );
+    }
+    if changes.is_empty() && beyond == 0 {
+        println!("✅ {} matches the baseline", style(target.display()).cyan());
+        return Ok(());
+    }
+
+    let changed: u64 = changes.values().map(|c| c.bytes).sum();
+    let amount = if changed > 0 {
+        format!(" in {}", HumanBytes(changed))
+    } else {
+        String::new()
+    };
+    println!(
+        "❌ {} differs from the baseline{amount}:",
+        style(target.display()).cyan()
+    );
+    for (region, changes) in &changes {
+        let size = match (region, &table) {
+            (Region::Partition(number), Some(table)) => table
+                .get(*number)
+                .map(|p| match partition::contents(&file, &p) {
+                    Some(contents) => format!(" ({}, {contents})", HumanBytes(p.len)),
+                    None => format!(" ({})", HumanBytes(p.len)),
+                })
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+        println!("  {region}{size}: {}", changes.describe());
+    }
+    if beyond > 0 {
+        println!(
+            "  the baseline goes on for {} past the end of {}",
+            HumanBytes(beyond),
+            target.display()
+        );
+    }
+    if let Some(table) = &table {
+        let unchanged: Vec<String> = std::iter::once(Region::Table)
+            .chain(table.partitions.iter().map(|p| Region::Partition(p.number)))
+            .filter(|region| !changes.contains_key(region))
+            .map(|region| region.to_string())
+            .collect();
+        if !unchanged.is_empty() {
+            println!("  unchanged: {}", unchanged.join(", "));
+        }
+    }
+    Err(anyhow!(
+        "{} differs from the baseline {}",
+        target.display(),
+        baseline.display()
+    ))
+}
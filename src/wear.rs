@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::device;
+use crate::history;
+
+/// Number of writes after which a card is flagged as possibly worn out,
+/// unless `wear_warning` is set in the config file.
+pub const DEFAULT_WARNING: u32 = 1000;
+
+fn burns_path() -> Option<PathBuf> {
+    history::state_dir().map(|d| d.join("burns"))
+}
+
+/// Reads how often each card or stick was written, keyed by serial
+/// number. Malformed lines are skipped.
+fn read_counts() -> Vec<(String, u32)> {
+    let Some(contents) = burns_path().and_then(|p| fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (serial, count) = line.split_once('\t')?;
+            Some((serial.to_string(), count.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Returns how many times etchr has written the disk `device_name`, or
+/// `None` if it has no serial number to recognize it by.
+pub fn count(device_name: &str) -> Option<u32> {
+    let serial = device::serial(device_name)?;
+    Some(
+        read_counts()
+            .into_iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(&serial))
+            .map_or(0, |(_, count)| count),
+    )
+}
+
+/// Counts another write of the disk `device_name`, if it has a serial
+/// number.
+pub fn record(device_name: &str) -> io::Result<()> {
+    let (Some(path), Some(serial)) = (burns_path(), device::serial(device_name)) else {
+        return Ok(());
+    };
+
+    let mut counts = read_counts();
+    match counts
+        .iter_mut()
+        .find(|(s, _)| s.eq_ignore_ascii_case(&serial))
+    {
+        Some((_, count)) => *count += 1,
+        None => counts.push((serial, 1)),
+    }
+
+    let mut contents = String::new();
+    for (serial, count) in &counts {
+        contents.push_str(&format!("{serial}\t{count}\n"));
+    }
+    history::save(&path, &contents)
+}
@@ -1,40 +1,130 @@
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
-use std::os::unix::fs::OpenOptionsExt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tempfile::{NamedTempFile, TempPath};
 
-use anyhow::{Result, anyhow};
-use console::style;
-use flate2::read::GzDecoder;
-use indicatif::{ProgressBar, ProgressStyle};
+use anyhow::{Context, Result, anyhow};
+use console::Color;
+use flate2::read::MultiGzDecoder;
+use indicatif::{HumanBytes, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use sha2::{Digest, Sha256};
 use xz2::read::XzDecoder;
 use zstd::stream::read::Decoder as ZstdDecoder;
 
+use crate::buffers::{self, AlignedBuffer};
+use crate::cancel::CancelToken;
+use crate::checksum::Checksum;
+use crate::compress;
+use crate::compression;
+use crate::device::{self, ConfirmMode};
+use crate::history;
+use crate::i18n::{Msg, tr, trf};
+use crate::link::Link;
+use crate::output::{self, info, verbose};
+use crate::partition::{self, Region};
+use crate::pause;
+use crate::privileges;
+use crate::progress;
+use crate::prompt;
+use crate::readahead::{Chunk, ReadAhead};
+use crate::report::{self, DeviceRecord, Mismatch, Report};
+use crate::sandbox;
+use crate::slow::SlowWrite;
+use crate::source;
+use crate::spinner;
+use crate::spool::{Feed, Spool};
+use crate::stats;
+use crate::status::{self, StatusFile};
+use crate::timing::{Clock, TimedReader, Timings};
+use crate::title::TitleMirror;
+use crate::units;
+use crate::zip;
+
 const BUFFER_SIZE: usize = 1024 * 1024; // 1 MiB
 
-/// Manages the lifetime of a decompressed image file.
-/// If the image was decompressed to a temp file, `_temp_handle` will
-/// hold the `TempPath`, and the file will be deleted on drop.
-/// If it was an uncompressed image, `_temp_handle` is None.
-struct DecompressedImage {
-    path: PathBuf,
-    _temp_handle: Option<TempPath>,
+/// An image opened for writing, before anything has been decompressed.
+/// Opening is split from decompression so that the sandbox can be applied
+/// in between, with every file the operation needs already open.
+enum ImageSource {
+    /// An uncompressed image, written as is.
+    Raw(File),
+    /// A compressed image, and the temp file it will be decompressed into.
+    /// The temp file has no name, so it is gone once closed, however the
+    /// process ends.
+    Compressed {
+        reader: Box<dyn Read + Send>,
+        temp_file: File,
+        /// Size of the compressed file or download, unknown for stdin.
+        compressed_len: Option<u64>,
+        /// Size of the decompressed image, if the format records it.
+        uncompressed_len: Option<u64>,
+        /// Compressed bytes read so far.
+        consumed: Arc<AtomicU64>,
+        /// Hashes a streamed image as it's read, for [`image_hash`].
+        hasher: Option<Arc<Mutex<Sha256>>>,
+    },
+}
+
+/// Counts the bytes read from `inner`, to tell how far through the
+/// compressed input decompression is.
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
 }
 
-/// Allows `DecompressedImage` to be used as a simple `&Path`.
-impl AsRef<Path> for DecompressedImage {
-    fn as_ref(&self) -> &Path {
-        &self.path
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
     }
 }
 
+/// Reports decompression errors as a corrupt or truncated image, with how
+/// far into the compressed input they were found, so a damaged download
+/// is recognized as such.
+struct ArchiveReader {
+    inner: Box<dyn Read + Send>,
+    consumed: Arc<AtomicU64>,
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| {
+            let what = match e.kind() {
+                io::ErrorKind::UnexpectedEof => "truncated",
+                io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::Other => {
+                    "corrupt"
+                }
+                _ => return e,
+            };
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "the source archive is {what} near byte {} ({e})",
+                    self.consumed.load(Ordering::Relaxed)
+                ),
+            )
+        })
+    }
+}
+
+/// The image ready to be written: either the original file (if
+/// uncompressed) or the temp file it was decompressed into.
+struct DecompressedImage {
+    file: File,
+    /// The part of `file` to write: all of it, or the partition given with
+    /// `--partition`.
+    start: u64,
+    len: u64,
+}
+
 fn make_progress_bar(len: u64, prefix: &str, color: &str) -> ProgressBar {
-    let pb = ProgressBar::new(len);
+    let pb = ProgressBar::with_draw_target(Some(len), ProgressDrawTarget::hidden());
     pb.set_prefix(format!("{prefix:<10}"));
     pb.set_style(
         ProgressStyle::default_bar()
@@ -42,331 +132,1226 @@ fn make_progress_bar(len: u64, prefix: &str, color: &str) -> ProgressBar {
             .unwrap()
             .progress_chars("■ "),
     );
-    pb
+    progress::add(pb)
 }
 
-/// Decompresses an image file to a temporary file if needed.
-/// Returns a `DecompressedImage` struct which points to either
-/// the original file (if uncompressed) or the new temp file.
-fn decompress_image(input_path: &Path, running: Arc<AtomicBool>) -> io::Result<DecompressedImage> {
-    let ext = input_path
+/// Sizes measured by [`decompressed_size`], so that the image review
+/// shows them without decompressing the image again.
+static SIZE_CACHE: Mutex<Vec<(PathBuf, u64)>> = Mutex::new(Vec::new());
+
+/// Returns the size of the data that will be written to the device,
+/// if it can be known without decompressing the image (or it already was).
+pub fn uncompressed_size(image_path: &Path) -> Option<u64> {
+    let cache = SIZE_CACHE.lock().unwrap();
+    if let Some((_, size)) = cache.iter().find(|(path, _)| path == image_path) {
+        return Some(*size);
+    }
+    drop(cache);
+
+    let ext = image_path
         .extension()
         .and_then(|e| e.to_str())
         .unwrap_or("")
         .to_lowercase();
 
-    let input_file = File::open(input_path)?;
+    match ext.as_str() {
+        "gz" | "gzip" | "xz" | "zst" | "zstd" | "zip" => {
+            compression::uncompressed_size(image_path, &ext)
+        }
+        _ => File::open(image_path)
+            .and_then(|f| device::file_size(&f))
+            .ok(),
+    }
+}
+
+/// Returns the size a gzip, xz or zstd image decompresses to, for when the
+/// file doesn't record it (gzip never does, and zstd compressed from a pipe
+/// doesn't), by decompressing it without keeping the result. `None` for
+/// other formats.
+pub fn decompressed_size(
+    image_path: &Path,
+    dictionary: Option<&[u8]>,
+    cancel: &CancelToken,
+) -> Result<Option<u64>> {
+    let ext = image_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let file = File::open(image_path)?;
+    let len = device::file_size(&file)?;
+    let consumed = Arc::new(AtomicU64::new(0));
+    let input = BufReader::new(CountingReader {
+        inner: file,
+        count: consumed.clone(),
+    });
+    let Some(reader) = decoder_for(&ext, input, dictionary)? else {
+        return Ok(None);
+    };
+    let mut reader = ArchiveReader {
+        inner: reader,
+        consumed: consumed.clone(),
+    };
+
+    let pb = make_progress_bar(len, tr(Msg::BarMeasuring), "cyan");
+    let memory = buffers::reserve("measuring", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(1);
+    let buf = buffer.as_mut_slice();
+    let mut total = 0u64;
+    loop {
+        cancel.check(&pb, Msg::PhaseMeasuring)?;
+        let n = reader.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        total += n as u64;
+        pb.set_position(consumed.load(Ordering::Relaxed));
+    }
+    pb.finish_and_clear();
+    let mut cache = SIZE_CACHE.lock().unwrap();
+    cache.push((image_path.to_path_buf(), total));
+    Ok(Some(total))
+}
+
+/// Hashes computed so far, so that the policy check and the image review
+/// don't hash the same (possibly large) image twice.
+static HASH_CACHE: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+/// The hash [`image_hash`] already computed for the image, if any.
+pub fn cached_hash(image_path: &Path) -> Option<String> {
+    let cache = HASH_CACHE.lock().unwrap();
+    cache
+        .iter()
+        .find(|(path, _)| path == image_path)
+        .map(|(_, hash)| hash.clone())
+}
+
+/// Computes the SHA-256 of the image file as stored on disk (i.e., before
+/// decompression) as lowercase hex.
+pub fn image_hash(image_path: &Path, cancel: &CancelToken) -> Result<String> {
+    let mut cache = HASH_CACHE.lock().unwrap();
+    if let Some((_, hash)) = cache.iter().find(|(path, _)| path == image_path) {
+        return Ok(hash.clone());
+    }
+
+    let mut file = File::open(image_path)?;
+    let len = device::file_size(&file)?;
+    let pb = make_progress_bar(len, tr(Msg::BarHashing), "cyan");
+    let mut hasher = Sha256::new();
+    let memory = buffers::reserve("hashing", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(1);
+    let buf = buffer.as_mut_slice();
+
+    loop {
+        cancel.check(&pb, Msg::PhaseHashing)?;
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        pb.inc(n as u64);
+    }
+    pb.finish_and_clear();
+
+    let hex: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    cache.push((image_path.to_path_buf(), hex.clone()));
+    Ok(hex)
+}
+
+/// Creates a zstd decoder that accepts images compressed with any window
+/// size (see `read --long`), using `dictionary` if given. Like the gzip
+/// and xz decoders, it carries on through concatenated frames to the end
+/// of the file.
+fn zstd_decoder<R: BufRead>(
+    input: R,
+    dictionary: Option<&[u8]>,
+) -> io::Result<ZstdDecoder<'static, R>> {
+    let mut decoder = ZstdDecoder::with_dictionary(input, dictionary.unwrap_or_default())?;
+    decoder.window_log_max(compress::MAX_WINDOW_LOG)?;
+    Ok(decoder)
+}
+
+/// Hashes what is read from `inner`, so that an image read as a stream
+/// is hashed without reading it twice.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Arc<Mutex<Sha256>>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.lock().unwrap().update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Creates a reader for an image streamed from stdin or a URL (see
+/// [`source::open`]), detecting the compression format from its magic
+/// bytes since there may be no extension.
+fn stream_reader(
+    stream: Box<dyn Read + Send>,
+    clock: &Clock,
+    dictionary: Option<&[u8]>,
+    consumed: Arc<AtomicU64>,
+    hasher: Arc<Mutex<Sha256>>,
+) -> io::Result<Box<dyn Read + Send>> {
+    let counted = CountingReader {
+        inner: HashingReader {
+            inner: stream,
+            hasher,
+        },
+        count: consumed,
+    };
+    decoder(BufReader::new(TimedReader::new(counted, clock)), dictionary)
+}
+
+/// Wraps `input` in the decompressor its magic bytes call for, if any.
+fn decoder<R: BufRead + Send + 'static>(
+    mut input: R,
+    dictionary: Option<&[u8]>,
+) -> io::Result<Box<dyn Read + Send>> {
+    let magic = input.fill_buf()?;
+    Ok(if magic.starts_with(&[0x1f, 0x8b]) {
+        Box::new(MultiGzDecoder::new(input))
+    } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Box::new(XzDecoder::new_multi_decoder(input))
+    } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Box::new(zstd_decoder(input, dictionary)?)
+    } else {
+        Box::new(input)
+    })
+}
+
+/// Wraps `input` in the decompressor the extension `ext` calls for, or
+/// `None` if it names no compressed format (or a ZIP archive, which is
+/// read through [`zip`]).
+fn decoder_for<R: BufRead + Send + 'static>(
+    ext: &str,
+    input: R,
+    dictionary: Option<&[u8]>,
+) -> io::Result<Option<Box<dyn Read + Send>>> {
+    Ok(match ext {
+        "gz" | "gzip" => Some(Box::new(MultiGzDecoder::new(input))),
+        "xz" => Some(Box::new(XzDecoder::new_multi_decoder(input))),
+        "zst" | "zstd" => Some(Box::new(zstd_decoder(input, dictionary)?)),
+        _ => None,
+    })
+}
+
+/// Opens the image file at `path` for reading it through once, as it would
+/// be written: decompressed if its magic bytes say it's compressed.
+pub fn decompressed_reader(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let file = File::open(path).with_context(|| trf(Msg::OpeningFile, &[&path.display()]))?;
+    Ok(decoder(BufReader::new(file), None)?)
+}
+
+/// Opens the image for writing, picking a decompressor from the extension.
+/// An `input_path` of `-` or a URL reads the image as a stream (see
+/// [`source::open`]), which is always buffered to a temp file so it can
+/// be read again for verification. Reads of a compressed source are timed
+/// on `clock`. zstd images are decompressed with `dictionary` if given.
+fn open_image(input_path: &Path, clock: &Clock, dictionary: Option<&[u8]>) -> Result<ImageSource> {
+    let consumed = Arc::new(AtomicU64::new(0));
+    let stream = source::open(input_path)?;
+    let streamed = stream.is_some();
+    let hasher = streamed.then(|| Arc::new(Mutex::new(Sha256::new())));
+    let open = || {
+        File::open(input_path).map(|f| {
+            let counted = CountingReader {
+                inner: f,
+                count: consumed.clone(),
+            };
+            BufReader::new(TimedReader::new(counted, clock))
+        })
+    };
+    let ext = input_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
 
     // Create a reader based on the file extension
-    let mut reader: Box<dyn Read> = match ext.as_str() {
-        "gz" | "gzip" => Box::new(GzDecoder::new(BufReader::new(input_file))),
-        "xz" => Box::new(XzDecoder::new(BufReader::new(input_file))),
-        "zst" | "zstd" => Box::new(ZstdDecoder::new(BufReader::new(input_file))?),
-        // Not a compressed file, return a path to the original
-        _ => {
+    let mut zip_entry = None;
+    let reader: Box<dyn Read + Send> = match (stream, ext.as_str()) {
+        (Some(stream), _) => stream_reader(
+            stream,
+            clock,
+            dictionary,
+            consumed.clone(),
+            hasher.clone().expect("streams are hashed"),
+        )?,
+        (None, "gz" | "gzip" | "xz" | "zst" | "zstd") => {
+            decoder_for(&ext, open()?, dictionary)?.expect("a compressed format")
+        }
+        (None, "zip") => {
+            let mut file = File::open(input_path)?;
+            let entry = zip::find_image(&mut file, input_path)?;
+            info!("{}", trf(Msg::WritingFromArchive, &[&entry.name]));
+            entry.seek_to_data(&mut file)?;
+            let counted = CountingReader {
+                inner: file.take(entry.compressed_len),
+                count: consumed.clone(),
+            };
+            let reader = entry.reader(BufReader::new(TimedReader::new(counted, clock)));
+            zip_entry = Some(entry);
+            reader
+        }
+        // Not a compressed file, write the original
+        (None, _) => return Ok(ImageSource::Raw(File::open(input_path)?)),
+    };
+
+    verbose!("Decompressing {}", input_path.display());
+    let (compressed_len, uncompressed_len) = if streamed {
+        (source::download_len(input_path), None)
+    } else if let Some(entry) = zip_entry {
+        (Some(entry.compressed_len), Some(entry.uncompressed_len))
+    } else {
+        (
+            Some(std::fs::metadata(input_path)?.len()),
+            compression::uncompressed_size(input_path, &ext),
+        )
+    };
+    let reader = Box::new(ArchiveReader {
+        inner: reader,
+        consumed: consumed.clone(),
+    });
+    Ok(ImageSource::Compressed {
+        reader,
+        temp_file: tempfile::tempfile()?,
+        compressed_len,
+        uncompressed_len,
+        consumed,
+        hasher,
+    })
+}
+
+/// Decompresses the image to its temp file if needed, failing once that
+/// grows beyond `limit`. Only needed to find a partition in the image;
+/// otherwise the temp file is filled while the device is written (see
+/// [`Spool`]). Returns a `DecompressedImage` holding either the
+/// original file (if uncompressed) or the temp file, positioned at the
+/// start of the image data.
+fn decompress_image(
+    source: ImageSource,
+    limit: u64,
+    cancel: &CancelToken,
+) -> Result<DecompressedImage> {
+    let (mut reader, mut temp_file, compressed_len, uncompressed_len, consumed) = match source {
+        ImageSource::Raw(file) => {
+            let len = device::file_size(&file)?;
             return Ok(DecompressedImage {
-                path: input_path.to_path_buf(),
-                _temp_handle: None,
+                file,
+                start: 0,
+                len,
             });
         }
+        ImageSource::Compressed {
+            reader,
+            temp_file,
+            compressed_len,
+            uncompressed_len,
+            consumed,
+            ..
+        } => (
+            reader,
+            temp_file,
+            compressed_len,
+            uncompressed_len,
+            consumed,
+        ),
     };
 
-    let decompress_pb = ProgressBar::new_spinner();
-    decompress_pb.set_prefix("Decompress");
-    // A custom spinner animation for decompression
-    decompress_pb.set_style(
-        ProgressStyle::default_spinner()
-            .tick_strings(&[
-                &style("■  ■  ■  ■  ■  ■  ■                           ")
-                    .blue()
-                    .to_string(),
-                &style(" ■  ■  ■  ■  ■  ■  ■                          ")
-                    .blue()
-                    .to_string(),
-                &style("  ■  ■  ■  ■  ■  ■  ■                         ")
-                    .blue()
-                    .to_string(),
-                &style("   ■  ■  ■  ■  ■  ■  ■                        ")
-                    .blue()
-                    .to_string(),
-                &style("    ■  ■  ■  ■  ■  ■  ■                       ")
-                    .blue()
-                    .to_string(),
-                &style("     ■  ■  ■  ■  ■  ■  ■                      ")
-                    .blue()
-                    .to_string(),
-                &style("      ■  ■  ■  ■  ■  ■  ■                     ")
-                    .blue()
-                    .to_string(),
-                &style("       ■  ■  ■  ■  ■  ■  ■                    ")
-                    .blue()
-                    .to_string(),
-                &style("        ■  ■  ■  ■  ■  ■  ■                   ")
-                    .blue()
-                    .to_string(),
-                &style("         ■  ■  ■  ■  ■  ■  ■                  ")
-                    .blue()
-                    .to_string(),
-                &style("          ■  ■  ■  ■  ■  ■  ■                 ")
-                    .blue()
-                    .to_string(),
-                &style("           ■  ■  ■  ■  ■  ■  ■                ")
-                    .blue()
-                    .to_string(),
-                &style("            ■  ■  ■  ■  ■  ■  ■               ")
-                    .blue()
-                    .to_string(),
-                &style("             ■  ■  ■  ■  ■  ■  ■              ")
-                    .blue()
-                    .to_string(),
-                &style("              ■  ■  ■  ■  ■  ■  ■             ")
-                    .blue()
-                    .to_string(),
-                &style("               ■  ■  ■  ■  ■  ■  ■            ")
-                    .blue()
-                    .to_string(),
-                &style("                ■  ■  ■  ■  ■  ■  ■           ")
-                    .blue()
-                    .to_string(),
-                &style("                 ■  ■  ■  ■  ■  ■  ■          ")
-                    .blue()
-                    .to_string(),
-                &style("                  ■  ■  ■  ■  ■  ■  ■         ")
-                    .blue()
-                    .to_string(),
-                &style("                   ■  ■  ■  ■  ■  ■  ■        ")
-                    .blue()
-                    .to_string(),
-                &style("                    ■  ■  ■  ■  ■  ■  ■       ")
-                    .blue()
-                    .to_string(),
-                &style("                     ■  ■  ■  ■  ■  ■  ■      ")
-                    .blue()
-                    .to_string(),
-                &style("                      ■  ■  ■  ■  ■  ■  ■     ")
-                    .blue()
-                    .to_string(),
-                &style("                       ■  ■  ■  ■  ■  ■  ■    ")
-                    .blue()
-                    .to_string(),
-                &style("                        ■  ■  ■  ■  ■  ■  ■   ")
-                    .blue()
-                    .to_string(),
-                &style("                         ■  ■  ■  ■  ■  ■  ■  ")
-                    .blue()
-                    .to_string(),
-                &style("                          ■  ■  ■  ■  ■  ■  ■ ")
-                    .blue()
-                    .to_string(),
-                &style("                           ■  ■  ■  ■  ■  ■  ■")
-                    .blue()
-                    .to_string(),
-                &style("                          ■  ■  ■  ■  ■  ■  ■ ")
-                    .blue()
-                    .to_string(),
-                &style("                         ■  ■  ■  ■  ■  ■  ■  ")
-                    .blue()
-                    .to_string(),
-                &style("                        ■  ■  ■  ■  ■  ■  ■   ")
-                    .blue()
-                    .to_string(),
-                &style("                       ■  ■  ■  ■  ■  ■  ■    ")
-                    .blue()
-                    .to_string(),
-                &style("                      ■  ■  ■  ■  ■  ■  ■     ")
-                    .blue()
-                    .to_string(),
-                &style("                     ■  ■  ■  ■  ■  ■  ■      ")
-                    .blue()
-                    .to_string(),
-                &style("                    ■  ■  ■  ■  ■  ■  ■       ")
-                    .blue()
-                    .to_string(),
-                &style("                   ■  ■  ■  ■  ■  ■  ■        ")
-                    .blue()
-                    .to_string(),
-                &style("                  ■  ■  ■  ■  ■  ■  ■         ")
-                    .blue()
-                    .to_string(),
-                &style("                 ■  ■  ■  ■  ■  ■  ■          ")
-                    .blue()
-                    .to_string(),
-                &style("                ■  ■  ■  ■  ■  ■  ■           ")
-                    .blue()
-                    .to_string(),
-                &style("               ■  ■  ■  ■  ■  ■  ■            ")
-                    .blue()
-                    .to_string(),
-                &style("              ■  ■  ■  ■  ■  ■  ■             ")
-                    .blue()
-                    .to_string(),
-                &style("             ■  ■  ■  ■  ■  ■  ■              ")
-                    .blue()
-                    .to_string(),
-                &style("            ■  ■  ■  ■  ■  ■  ■               ")
-                    .blue()
-                    .to_string(),
-                &style("           ■  ■  ■  ■  ■  ■  ■                ")
-                    .blue()
-                    .to_string(),
-                &style("          ■  ■  ■  ■  ■  ■  ■                 ")
-                    .blue()
-                    .to_string(),
-                &style("         ■  ■  ■  ■  ■  ■  ■                  ")
-                    .blue()
-                    .to_string(),
-                &style("        ■  ■  ■  ■  ■  ■  ■                   ")
-                    .blue()
-                    .to_string(),
-                &style("       ■  ■  ■  ■  ■  ■  ■                    ")
-                    .blue()
-                    .to_string(),
-                &style("      ■  ■  ■  ■  ■  ■  ■                     ")
-                    .blue()
-                    .to_string(),
-                &style("     ■  ■  ■  ■  ■  ■  ■                      ")
-                    .blue()
-                    .to_string(),
-                &style("    ■  ■  ■  ■  ■  ■  ■                       ")
-                    .blue()
-                    .to_string(),
-                &style("   ■  ■  ■  ■  ■  ■  ■                        ")
-                    .blue()
-                    .to_string(),
-                &style("  ■  ■  ■  ■  ■  ■  ■                         ")
-                    .blue()
-                    .to_string(),
-                &style(" ■  ■  ■  ■  ■  ■  ■                          ")
-                    .blue()
-                    .to_string(),
-            ])
-            .template("{prefix} [{elapsed_precise}] [{spinner}] {bytes} ({bytes_per_sec}) {msg}")
-            .unwrap(),
-    );
+    let decompress_pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
+    decompress_pb.set_prefix(tr(Msg::BarDecompress));
+    decompress_pb.set_style(spinner::style(
+        "{prefix} [{elapsed_precise}] [{spinner}] {bytes} ({bytes_per_sec}) {msg}",
+        46,
+        Color::Blue,
+    ));
+    if let Some(len) = uncompressed_len {
+        verbose!("The image decompresses to {}", units::bytes(len));
+        decompress_pb.set_length(len);
+        decompress_pb.set_style(
+            ProgressStyle::with_template(
+                "{prefix} [{elapsed_precise}] [{bar:40.blue/black}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta}) {msg}",
+            )
+            .unwrap()
+            .progress_chars("■ "),
+        );
+    } else if compressed_len.is_some() {
+        // The total is estimated from the compression ratio so far
+        decompress_pb.set_style(
+            ProgressStyle::with_template(
+                "{prefix} [{elapsed_precise}] [{bar:40.blue/black}] {bytes}/~{total_bytes} ({bytes_per_sec}, {eta}) {msg}",
+            )
+            .unwrap()
+            .progress_chars("■ "),
+        );
+    }
+    let decompress_pb = progress::add(decompress_pb);
     decompress_pb.enable_steady_tick(Duration::from_millis(100));
 
-    // Decompress to a named temp file
-    let mut temp_file = NamedTempFile::new()?;
+    // Decompress to the temp file, which has no name, so the system removes
+    // it however etchr exits
     {
         let mut writer = BufWriter::new(&mut temp_file);
         let mut buffer = [0u8; 8192];
         let mut total: u64 = 0;
 
         loop {
-            if !running.load(Ordering::SeqCst) {
-                decompress_pb.println("Received exit signal... cleaning up.");
-                decompress_pb.finish_with_message("❌ Decompression cancelled.");
-                // Return an Interrupted error. This will cause the NamedTempFile
-                // to be dropped, cleaning up the file automatically.
-                return Err(io::Error::new(
-                    io::ErrorKind::Interrupted,
-                    "Operation cancelled by user",
-                ));
-            }
+            pause::wait_while_paused(&decompress_pb, cancel);
+            // Returning closes the temp file, which frees its space
+            cancel.check(&decompress_pb, Msg::PhaseDecompression)?;
 
             let n = reader.read(&mut buffer)?;
             if n == 0 {
                 break;
             }
-            writer.write_all(&buffer[..n])?;
             total += n as u64;
+            if total > limit {
+                return Err(anyhow!(trf(Msg::TempSpaceExceeded, &[&HumanBytes(limit)])));
+            }
+            writer.write_all(&buffer[..n])?;
+            let consumed = consumed.load(Ordering::Relaxed);
+            // The ratio of the first few blocks says little about the rest
+            if let Some(compressed_len) = compressed_len
+                && uncompressed_len.is_none()
+                && consumed > 0
+                && consumed * 100 >= compressed_len
+            {
+                let ratio = total as f64 / consumed as f64;
+                let estimate = (compressed_len as f64 * ratio) as u64;
+                decompress_pb.set_length(estimate.max(total));
+            }
             decompress_pb.set_position(total);
         }
         writer.flush()?;
+        // A file cut off between streams still decodes cleanly
+        if let Some(len) = uncompressed_len
+            && total != len
+        {
+            return Err(anyhow!(trf(
+                Msg::ArchiveTruncated,
+                &[&units::bytes(total), &units::bytes(len)]
+            )));
+        }
     }
 
     decompress_pb.set_style(
         indicatif::ProgressStyle::with_template(
-            "Decompress [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes} ({bytes_per_sec}) {msg}",
+            "{prefix} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes} ({bytes_per_sec}) {msg}",
         )
         .unwrap()
         .progress_chars("■■"),
     );
 
-    // Ensure the progress bar finishes at 100%
-    if let Some(len) = decompress_pb.length() {
-        decompress_pb.set_position(len);
-    }
+    // Ensure the progress bar finishes at 100%, whatever was estimated
+    let total = decompress_pb.position();
+    decompress_pb.set_length(total);
 
-    decompress_pb.finish_with_message("✅ Decompression complete.");
+    decompress_pb.finish_with_message(tr(Msg::DecompressionComplete));
 
-    // Hand over ownership of the temp file to the DecompressedImage struct
-    let temp_path = temp_file.into_temp_path();
+    temp_file.seek(SeekFrom::Start(0))?;
     Ok(DecompressedImage {
-        path: temp_path.to_path_buf(),
-        _temp_handle: Some(temp_path),
+        file: temp_file,
+        start: 0,
+        len: total,
     })
 }
 
+/// Returns the space available to unprivileged users on the filesystem
+/// holding `dir`.
+fn free_space(dir: &Path) -> io::Result<u64> {
+    let path = std::ffi::CString::new(dir.as_os_str().as_encoded_bytes())?;
+    // statvfs fails with EOVERFLOW on 32-bit hosts for large filesystems;
+    // macOS has no statvfs64, but its statvfs is 64-bit anyway
+    #[cfg(target_os = "macos")]
+    use libc::statvfs as statvfs64;
+    #[cfg(not(target_os = "macos"))]
+    use libc::statvfs64;
+    let mut stat: statvfs64 = unsafe { std::mem::zeroed() };
+    if unsafe { statvfs64(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// The most an image may take up once decompressed to a temp file: the
+/// space free where temp files go, or `max_temp_size` if that's less.
+fn temp_limit(max_temp_size: Option<u64>) -> u64 {
+    let free = free_space(&std::env::temp_dir()).unwrap_or(u64::MAX);
+    max_temp_size.map_or(free, |max| max.min(free))
+}
+
+/// What a device is written from.
+enum Source<'a> {
+    /// An image file, or the temp file it was decompressed into.
+    Image(&'a DecompressedImage),
+    /// A decompressor, read as the device is written. The image can't be
+    /// read again, so chunks that fail verification can't be re-written.
+    Stream {
+        reader: Box<dyn Read + Send>,
+        /// The decompressed size, if the format records it.
+        len: Option<u64>,
+    },
+    /// A streamed image, written as it's spooled to its temp file.
+    Spooled {
+        spool: &'a Spool,
+        /// The decompressed size, if the format records it.
+        len: Option<u64>,
+    },
+}
+
+/// The chunks a device is written from, in order, read ahead on their
+/// own thread.
+enum Chunks {
+    File(ReadAhead),
+    /// A decompressor or spool, which ends where the image does.
+    Stream(ReadAhead),
+}
+
+impl Chunks {
+    /// Returns the next chunk, of `BUFFER_SIZE` bytes unless it's the last,
+    /// or `None` at the end of a stream.
+    fn next(&mut self) -> io::Result<Option<Chunk>> {
+        match self {
+            Chunks::File(reader) => reader.next_chunk().map(Some),
+            Chunks::Stream(reader) => reader.next_chunk_or_end(),
+        }
+    }
+
+    /// Hands a written chunk's buffer back to be filled again.
+    fn recycle(&mut self, buffer: AlignedBuffer) {
+        match self {
+            Chunks::File(reader) | Chunks::Stream(reader) => reader.recycle(buffer),
+        }
+    }
+}
+
+/// How [`run`] writes an image.
+pub struct Options {
+    /// Compare the device against the image after writing.
+    pub verify: bool,
+    /// Sandbox the process (see [`sandbox::enter`]) once the image, device
+    /// and temp file are open, before any decompression.
+    pub sandbox: bool,
+    /// Number of image buffers read ahead of the device.
+    pub queue_depth: usize,
+    /// When run through sudo, switch to the invoking user once the image and
+    /// device are open (see [`privileges::InvokingUser::switch_to`]).
+    pub drop_privileges: bool,
+    /// The zstd dictionary the image was compressed with, if any.
+    pub dictionary: Option<Vec<u8>>,
+    /// How to confirm re-writing chunks that fail verification.
+    pub confirm: ConfirmMode,
+    /// Write only this partition of the image.
+    pub partition: Option<u32>,
+    /// Write into this partition of each device, instead of from its start.
+    pub to_partition: Option<u32>,
+    /// Write a compressed image straight from the decompressor, without
+    /// keeping it in a temp file.
+    pub stream: bool,
+    /// The most a temp file may take up, besides the free space there.
+    pub max_temp_size: Option<u64>,
+    /// When to warn that a device writes too slowly.
+    pub slow_write: SlowWrite,
+    /// Write and verify every device at once, each on its own thread,
+    /// rather than one after another.
+    pub parallel: bool,
+    /// Save a report of the hashes and mismatches of each device here.
+    pub report: Option<PathBuf>,
+    /// The checksum a streamed image must have, checked once it has
+    /// arrived. Image files are checked before the write.
+    pub checksum: Option<Checksum>,
+    /// Cut the image off at the end of the device (or partition), rather
+    /// than refuse an image larger than that.
+    pub truncate: bool,
+}
+
+/// A device that failed while others were written alongside it.
+pub struct Failure {
+    pub device: PathBuf,
+    pub error: anyhow::Error,
+}
+
+/// A target device, with every handle the write and verification need.
+struct Target<'a> {
+    path: &'a Path,
+    device_file: File,
+    /// O_DIRECT can only write whole sectors; an unaligned tail goes
+    /// through the page cache instead of being padded with zeros.
+    tail_file: File,
+    /// Opened up front so verification still works inside the sandbox.
+    verify_file: Option<File>,
+    /// Where on the device the image goes, and how much room there is: to
+    /// the end of the device, or of the partition it goes into.
+    start: u64,
+    room: u64,
+    /// Write speed the device guarantees, if it's a card that tells.
+    guaranteed_speed: Option<u64>,
+    /// Shown before each progress bar, to tell apart devices written at
+    /// once.
+    label: Option<String>,
+}
+
+impl<'a> Target<'a> {
+    /// Opens the device at `path`, locating partition `to_partition` of it
+    /// if given.
+    fn open(path: &'a Path, verify: bool, to_partition: Option<u32>) -> Result<Self> {
+        let partition = to_partition
+            .map(|number| partition::find(&File::open(path)?, number, &path.display().to_string()))
+            .transpose()?;
+        let guaranteed_speed = path
+            .canonicalize()
+            .ok()
+            .and_then(|path| Link::read(&path.file_name()?.to_string_lossy()))
+            .and_then(|link| link.guaranteed_write_speed());
+        let device_file = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(device::O_DIRECT) // Use O_DIRECT for unbuffered I/O
+            .open(path)?;
+        let room = match partition {
+            Some(partition) => partition.len,
+            None => device::file_size(&device_file)?,
+        };
+        Ok(Target {
+            path,
+            guaranteed_speed,
+            label: None,
+            start: partition.map_or(0, |p| p.start),
+            room,
+            device_file,
+            tail_file: std::fs::OpenOptions::new().write(true).open(path)?,
+            verify_file: if verify {
+                Some(File::open(path)?)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// Writes the image to each device in turn, or all at once with
+/// `options.parallel`, and optionally verifies it. A compressed image is
+/// only decompressed once, however many devices there are. Written one
+/// after another, the first device to fail stops the queue; written at
+/// once, every device is seen through and those that failed are returned.
 pub fn run(
     image_path: &Path,
-    device_path: &Path,
-    verify: bool,
-    running: Arc<AtomicBool>,
-) -> Result<()> {
-    println!(
-        "Writing image \"{}\" to device \"{}\"",
-        image_path.display(),
-        device_path.display()
-    );
+    device_paths: &[PathBuf],
+    options: &Options,
+    cancel: &CancelToken,
+) -> Result<Vec<Failure>> {
+    if device_paths.is_empty() {
+        return Err(anyhow!(tr(Msg::NoDevicesToWrite)));
+    }
+    let mut timings = Timings::default();
+    let source = open_image(
+        image_path,
+        &timings.source_read,
+        options.dictionary.as_deref(),
+    )?;
+    let hasher = match &source {
+        ImageSource::Compressed { hasher, .. } => hasher.clone(),
+        ImageSource::Raw(_) => None,
+    };
+    let targets = device_paths
+        .iter()
+        .map(|path| Target::open(path, options.verify, options.to_partition))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Stream images that won't fit where temp files go, rather than fill it
+    let limit = temp_limit(options.max_temp_size);
+    let too_large = match &source {
+        ImageSource::Raw(_) => false,
+        ImageSource::Compressed {
+            uncompressed_len, ..
+        } => uncompressed_len.is_some_and(|len| len > limit),
+    };
+    // The temp file is only needed to read the image again: to re-write
+    // chunks that fail verification, for further devices, or to find the
+    // partition to write
+    let needs_temp_file = options.verify || targets.len() > 1 || options.partition.is_some();
+    let stream = matches!(source, ImageSource::Compressed { .. })
+        && (options.stream || too_large || !needs_temp_file);
+    if stream && too_large && !options.stream {
+        info!(
+            "{}",
+            trf(Msg::StreamingPastTempSpace, &[&HumanBytes(limit)])
+        );
+    }
+    if stream && (options.stream || too_large) {
+        if options.partition.is_some() {
+            return Err(anyhow!(tr(Msg::PartitionNeedsTempFile)));
+        }
+        if targets.len() > 1 {
+            return Err(anyhow!(tr(Msg::SeveralDevicesNeedTempFile)));
+        }
+    }
+
+    let mut status = StatusFile::create("write", &device_paths[0], image_path);
+
+    // Everything that needs root is open; run the rest as the sudo user
+    if let Some(user) = privileges::invoking_user().filter(|_| options.drop_privileges) {
+        user.switch_to()?;
+    }
 
-    let image = match decompress_image(image_path, running.clone()) {
-        Ok(img) => img,
-        Err(e) if e.kind() == io::ErrorKind::Interrupted => {
-            // This is our custom cancellation error
-            return Err(anyhow!("Operation cancelled by user"));
+    let mut title = TitleMirror::new();
+
+    if options.sandbox {
+        // The status and history files are still updated
+        let report_dir = options.report.as_deref().map(|path| match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        });
+        let writable: Vec<PathBuf> = [Some(status::status_dir()), history::state_dir(), report_dir]
+            .into_iter()
+            .flatten()
+            .collect();
+        sandbox::enter(&writable, &[])?;
+    }
+
+    let (mut image, mut stream, mut spool) = match source {
+        ImageSource::Compressed {
+            reader,
+            uncompressed_len,
+            ..
+        } if stream => (None, Some((reader, uncompressed_len)), None),
+        // Decompress (or download) into the temp file while the device is
+        // written from it, rather than before
+        ImageSource::Compressed {
+            reader,
+            temp_file,
+            uncompressed_len,
+            consumed,
+            compressed_len,
+            ..
+        } if options.partition.is_none() => {
+            // Only streams are hashed as they're read
+            let feed = match compressed_len {
+                Some(len) if hasher.is_none() => Feed::Decompression(len),
+                len => Feed::Download(len),
+            };
+            let spool = Spool::start(reader, temp_file, limit, consumed, feed, cancel)?;
+            (None, None, Some((spool, uncompressed_len)))
         }
-        Err(e) => {
-            // This is a real IO error
-            return Err(e.into());
+        source => {
+            let decompress_start = Instant::now();
+            let image = decompress_image(source, limit, cancel)
+                .context(tr(Msg::DecompressingBeforeWrite))?;
+            timings.source_read_decompressing = timings.source_read.total();
+            timings.decompression = decompress_start
+                .elapsed()
+                .saturating_sub(timings.source_read_decompressing);
+            check_download(hasher.as_deref(), options)?;
+            (Some(image), None, None)
         }
     };
+    let direct = stream.is_some();
+    if let (Some(image), Some(number)) = (&mut image, options.partition) {
+        let partition = partition::find(&image.file, number, "the image")?;
+        info!(
+            "{}",
+            trf(
+                Msg::WritingPartition,
+                &[&number, &HumanBytes(partition.len), &partition.start]
+            )
+        );
+        image.start = partition.start;
+        image.len = partition.len;
+    }
 
-    let mut image_file = File::open(&image)?;
-    let image_len = image_file.metadata()?.len();
+    let count = targets.len();
+    let mut failures = Vec::new();
+    let mut report = options
+        .report
+        .as_ref()
+        .map(|_| Report::new("write", "image", image_path));
+    if options.parallel && count > 1 {
+        let names: Vec<String> = targets.iter().map(|t| label(t.path)).collect();
+        info!(
+            "{}",
+            trf(
+                Msg::WritingToDevicesAtOnce,
+                &[&image_path.display(), &count, &names.join(", ")]
+            )
+        );
+        let source = match (&image, &spool) {
+            (Some(image), _) => Source::Image(image),
+            (None, Some((spool, len))) => Source::Spooled { spool, len: *len },
+            (None, None) => unreachable!("a stream is only written to one device"),
+        };
+        let results = write_parallel(
+            image_path,
+            &source,
+            targets,
+            options,
+            &mut timings,
+            &mut status,
+            &mut title,
+            cancel,
+            report.is_some(),
+        );
+        if let Some((spool, expected_len)) = spool.take() {
+            finish_spool(spool, expected_len)?;
+            check_download(hasher.as_deref(), options)?;
+        }
+        for (path, result, record) in results {
+            if let Some(report) = &mut report {
+                report.devices.push(record);
+            }
+            if let Err(error) = result {
+                // Stopping every device is one failure, not one per device
+                if cancel.is_cancelled() {
+                    return Err(error);
+                }
+                failures.push(Failure {
+                    device: path,
+                    error,
+                });
+            }
+        }
+    } else {
+        for (i, target) in targets.into_iter().enumerate() {
+            let position = if count > 1 {
+                format!("[{}/{count}] ", i + 1)
+            } else {
+                String::new()
+            };
+            info!(
+                "{}",
+                trf(
+                    Msg::WritingToDevice,
+                    &[&position, &image_path.display(), &target.path.display()]
+                )
+            );
+            status.set_device(target.path);
+            let path = target.path;
+            let source = match (&image, &spool, stream.take()) {
+                (Some(image), _, _) => Source::Image(image),
+                (None, Some((spool, len)), _) => Source::Spooled { spool, len: *len },
+                (None, None, Some((reader, len))) => Source::Stream { reader, len },
+                (None, None, None) => unreachable!("a stream is only written to one device"),
+            };
+            let mut record = DeviceRecord::new(path);
+            let started = Instant::now();
+            let result = write_target(
+                source,
+                target,
+                options,
+                &mut timings,
+                &mut status,
+                &mut title,
+                cancel,
+                report.is_some().then_some(&mut record),
+            );
+            if !cancel.is_cancelled() {
+                stats::record(image_path, &result, started.elapsed()).ok();
+            }
+            let result = result.map(drop);
+            record.finish(&result);
+            if let Some(report) = &mut report {
+                report.devices.push(record);
+                // A device that failed is part of the record too
+                if result.is_err() {
+                    save_report(report, options).ok();
+                }
+            }
+            // With a queue, say which device failed and how many were done
+            if count > 1 {
+                result
+                    .with_context(|| trf(Msg::WritingDeviceOf, &[&path.display(), &i, &count]))?;
+            } else {
+                result?;
+            }
+            // Further devices are written from the complete temp file
+            if let Some((spool, expected_len)) = spool.take() {
+                image = Some(finish_spool(spool, expected_len)?);
+                check_download(hasher.as_deref(), options)?;
+            }
+        }
+    }
 
-    let mut device_file = std::fs::OpenOptions::new()
-        .write(true)
-        .custom_flags(libc::O_DIRECT) // Use O_DIRECT for unbuffered I/O
-        .open(device_path)?;
+    timings.print();
+    // A stream written straight from the decompressor has only now ended
+    if direct {
+        check_download(hasher.as_deref(), options)?;
+    }
+    // A stream can't be hashed again later, so keep what was hashed on the way
+    if let Some(hasher) = hasher {
+        let hex = report::hex(hasher.lock().unwrap().clone());
+        HASH_CACHE
+            .lock()
+            .unwrap()
+            .push((image_path.to_path_buf(), hex));
+    }
+    if let Some(report) = &report {
+        save_report(report, options)?;
+    }
+    Ok(failures)
+}
 
-    let write_pb = make_progress_bar(image_len, "Writing", "green");
+/// Fails if the streamed image, hashed by `hasher` as it arrived, doesn't
+/// match its checksum. By then it has been written, at least in part: the
+/// device holds something else than what was published, and mustn't be
+/// used.
+fn check_download(hasher: Option<&Mutex<Sha256>>, options: &Options) -> Result<()> {
+    let (Some(hasher), Some(checksum)) = (hasher, &options.checksum) else {
+        return Ok(());
+    };
+    let actual = report::hex(hasher.lock().unwrap().clone());
+    if actual != checksum.sha256 {
+        return Err(anyhow!(trf(
+            Msg::ChecksumMismatch,
+            &[&actual, &checksum.from, &checksum.sha256]
+        )));
+    }
+    info!("{}", trf(Msg::ChecksumMatches, &[&checksum.from]));
+    Ok(())
+}
+
+/// Saves `report` where `--report` asked for it. A failure is reported
+/// here too, as the caller may be failing with another error already.
+fn save_report(report: &Report, options: &Options) -> Result<()> {
+    let Some(path) = &options.report else {
+        return Ok(());
+    };
+    report
+        .save(path)
+        .inspect_err(|e| output::warn(format_args!("{e:#}")))?;
+    info!("{}", trf(Msg::SavedReport, &[&path.display()]));
+    Ok(())
+}
+
+/// Waits for a spool to fill, and returns the image in it.
+fn finish_spool(spool: Spool, expected_len: Option<u64>) -> Result<DecompressedImage> {
+    let (file, len) = spool.finish()?;
+    // A file cut off between streams still decodes cleanly
+    if let Some(expected_len) = expected_len
+        && len != expected_len
+    {
+        return Err(anyhow!(trf(
+            Msg::ArchiveTruncated,
+            &[&units::bytes(len), &units::bytes(expected_len)]
+        )));
+    }
+    Ok(DecompressedImage {
+        file,
+        start: 0,
+        len,
+    })
+}
+
+/// The kernel name of a device, e.g. "sdb" for /dev/sdb.
+fn label(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Writes `source` to every target at once, each on its own thread, and
+/// returns how each device fared, with a record for a report if
+/// `reporting`. The first device keeps the status file and terminal title;
+/// the others only show their progress bars.
+#[allow(clippy::too_many_arguments)]
+fn write_parallel(
+    image_path: &Path,
+    source: &Source,
+    targets: Vec<Target>,
+    options: &Options,
+    timings: &mut Timings,
+    status: &mut StatusFile,
+    title: &mut TitleMirror,
+    cancel: &CancelToken,
+    reporting: bool,
+) -> Vec<(PathBuf, Result<()>, DeviceRecord)> {
+    let mut first = Some((status, title));
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = targets
+            .into_iter()
+            .map(|mut target| {
+                let path = target.path.to_path_buf();
+                target.label = Some(label(target.path));
+                let source = match source {
+                    Source::Image(image) => Source::Image(image),
+                    Source::Spooled { spool, len } => Source::Spooled { spool, len: *len },
+                    Source::Stream { .. } => unreachable!("a stream is only written to one device"),
+                };
+                let mut own_timings = Timings {
+                    source_read: timings.source_read.clone(),
+                    ..Timings::default()
+                };
+                let shared = first.take();
+                let thread = scope.spawn(move || {
+                    let mut detached = StatusFile::detached(target.path);
+                    let mut no_title = TitleMirror::disabled();
+                    let (status, title) = shared.unwrap_or((&mut detached, &mut no_title));
+                    let mut record = DeviceRecord::new(target.path);
+                    let started = Instant::now();
+                    let result = write_target(
+                        source,
+                        target,
+                        options,
+                        &mut own_timings,
+                        status,
+                        title,
+                        cancel,
+                        reporting.then_some(&mut record),
+                    );
+                    if !cancel.is_cancelled() {
+                        stats::record(image_path, &result, started.elapsed()).ok();
+                    }
+                    let result = result.map(drop);
+                    record.finish(&result);
+                    (result, own_timings, record)
+                });
+                (path, thread)
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|(path, thread)| match thread.join() {
+                Ok((result, device_timings, record)) => {
+                    timings.merge(device_timings);
+                    (path, result, record)
+                }
+                Err(_) => {
+                    let result = Err(anyhow!(trf(Msg::WriterPanicked, &[&path.display()])));
+                    let mut record = DeviceRecord::new(&path);
+                    record.finish(&result);
+                    (path, result, record)
+                }
+            })
+            .collect()
+    })
+}
+
+/// Writes the image to one device and optionally verifies it, noting the
+/// hashes and mismatches in `record` for a report, if given. Returns the
+/// bytes written.
+#[allow(clippy::too_many_arguments)]
+fn write_target(
+    source: Source,
+    target: Target,
+    options: &Options,
+    timings: &mut Timings,
+    status: &mut StatusFile,
+    title: &mut TitleMirror,
+    cancel: &CancelToken,
+    mut record: Option<&mut DeviceRecord>,
+) -> Result<u64> {
+    let Target {
+        path: device_path,
+        mut device_file,
+        tail_file,
+        verify_file,
+        start: device_start,
+        room,
+        guaranteed_speed,
+        label,
+    } = target;
+    // Written alongside other devices, each bar says which one it's for
+    let prefix = |phase: &str| match &label {
+        Some(label) => format!("{label:<8} {phase:<9}"),
+        None => phase.to_string(),
+    };
+    // The length of a stream is only known for sure once it has ended
+    let expected_len = match &source {
+        Source::Image(image) => Some(image.len),
+        Source::Stream { len, .. } | Source::Spooled { len, .. } => *len,
+    };
+    let target = match options.to_partition {
+        Some(number) => trf(Msg::PartitionOfDevice, &[&number, &device_path.display()]),
+        None => device_path.display().to_string(),
+    };
+    let expected_len = match expected_len {
+        Some(len) if len > room && options.truncate => {
+            output::warn(trf(Msg::ImageCutOffBy, &[&HumanBytes(len - room), &target]));
+            Some(room)
+        }
+        Some(len) if len > room => {
+            return Err(anyhow!(trf(
+                Msg::ImageDoesNotFit,
+                &[&HumanBytes(len), &target, &HumanBytes(room)]
+            )));
+        }
+        len => len,
+    };
+    device_file.seek(SeekFrom::Start(device_start))?;
+    verbose!(
+        "Opened {} with O_DIRECT, writing in {BUFFER_SIZE}-byte chunks",
+        device_path.display()
+    );
+
+    let write_pb = match expected_len {
+        Some(len) => make_progress_bar(len, &prefix(tr(Msg::BarWriting)), "green"),
+        None => {
+            let pb = ProgressBar::with_draw_target(None, ProgressDrawTarget::hidden());
+            pb.set_prefix(format!("{:<10}", prefix(tr(Msg::BarWriting))));
+            pb.set_style(spinner::style(
+                "{prefix} [{elapsed_precise}] [{spinner}] {bytes} ({bytes_per_sec}) {msg}",
+                46,
+                Color::Green,
+            ));
+            progress::add(pb)
+        }
+    };
     let start_time = Instant::now();
 
-    // Align buffer to 512 bytes for O_DIRECT compatibility
+    // Buffers are aligned to 512 bytes for O_DIRECT compatibility
     let block_size = 512;
-    let mut buf = vec![0u8; BUFFER_SIZE + block_size];
-    let offset = buf.as_ptr().align_offset(block_size);
-    let buffer = &mut buf[offset..offset + BUFFER_SIZE];
+    let (mut chunks, image, image_start) = match source {
+        Source::Image(image) => {
+            let reader = ReadAhead::spawn(
+                image.file.try_clone()?,
+                image.start,
+                // Less than all of it, if it's cut off
+                expected_len.unwrap_or(image.len),
+                options.queue_depth,
+                BUFFER_SIZE,
+                block_size,
+                &timings.source_read,
+            );
+            (Chunks::File(reader), Some(&image.file), image.start)
+        }
+        Source::Stream { reader, .. } => (
+            Chunks::Stream(ReadAhead::stream(
+                reader,
+                options.queue_depth,
+                BUFFER_SIZE,
+                block_size,
+            )),
+            None,
+            0,
+        ),
+        // The spool is complete by the time chunks are re-written
+        Source::Spooled { spool, .. } => (
+            Chunks::Stream(ReadAhead::stream(
+                Box::new(spool.reader(cancel)?),
+                options.queue_depth,
+                BUFFER_SIZE,
+                block_size,
+            )),
+            Some(spool.file()),
+            0,
+        ),
+    };
 
+    let mut monitor = options.slow_write.monitor(guaranteed_speed);
+    let mut checksums = Checksums::new(expected_len.unwrap_or_default(), image_start, device_start);
+    let mut image_hasher = record.is_some().then(Sha256::new);
     let mut written: u64 = 0;
-    while written < image_len {
-        if !running.load(Ordering::SeqCst) {
-            write_pb.println("Received exit signal... cleaning up.");
-            write_pb.finish_with_message("❌ Write cancelled.");
-            // Return error. 'image' will be dropped, cleaning up the temp file.
-            return Err(anyhow!("Operation cancelled by user"));
-        }
-
-        let to_read = std::cmp::min(BUFFER_SIZE as u64, image_len - written) as usize;
-        image_file.read_exact(&mut buffer[..to_read])?;
-
-        // Ensure the data chunk is a multiple of the block size
-        let padded_size = if to_read % block_size != 0 {
-            let pad = to_read.div_ceil(block_size) * block_size;
-            buffer[to_read..pad].fill(0);
-            pad
-        } else {
-            to_read
+    while expected_len.is_none_or(|len| written < len) {
+        if pause::is_paused() {
+            // Flush what has been written so far before idling
+            device_file.sync_all()?;
+            pause::wait_while_paused(&write_pb, cancel);
+        }
+
+        if let Err(cancelled) = cancel.check(&write_pb, Msg::PhaseWrite) {
+            // Leave nothing in the device's cache, so it can be unplugged
+            device_file.sync_all()?;
+            let total = expected_len.map_or("?".to_string(), |len| HumanBytes(len).to_string());
+            return Err(anyhow::Error::new(cancelled).context(trf(
+                Msg::PartiallyWritten,
+                &[&device_path.display(), &HumanBytes(written), &total],
+            )));
+        }
+
+        let wait_start = Instant::now();
+        let Some(mut chunk) = chunks.next()? else {
+            break;
         };
+        timings.waiting_for_source += wait_start.elapsed();
+        let mut to_read = chunk.len;
+        // Only a stream of unknown length gets here with more than fits
+        let cut_off = written + to_read as u64 > room;
+        if cut_off {
+            if !options.truncate {
+                device_file.sync_all()?;
+                return Err(anyhow!(trf(
+                    Msg::ImageOverflowed,
+                    &[&target, &HumanBytes(room), &device_path.display()]
+                )));
+            }
+            to_read = (room - written) as usize;
+        }
+        let buffer = chunk.buffer.as_mut_slice();
+        checksums.push(&buffer[..to_read]);
+        if let Some(hasher) = &mut image_hasher {
+            hasher.update(&buffer[..to_read]);
+        }
 
-        device_file.write_all(&buffer[..padded_size])?;
+        // Write the sector-aligned part directly, and any remainder (only
+        // possible in the last chunk) without O_DIRECT, so that the bytes
+        // following the image on the device are left untouched.
+        let aligned = to_read / block_size * block_size;
+        let write_start = Instant::now();
+        device_file.write_all(&buffer[..aligned])?;
+        if aligned < to_read {
+            verbose!(
+                "Writing the last {} bytes without O_DIRECT",
+                to_read - aligned
+            );
+            tail_file.write_all_at(
+                &buffer[aligned..to_read],
+                device_start + written + aligned as u64,
+            )?;
+            tail_file.sync_all()?;
+        }
+        let write_time = write_start.elapsed();
+        timings.device_write += write_time;
+        if let Some(warning) = monitor
+            .as_mut()
+            .and_then(|monitor| monitor.record(to_read as u64, write_time))
+        {
+            output::warn(format_args!("{}: {warning}", device_path.display()));
+        }
+        chunks.recycle(chunk.buffer);
         written += to_read as u64;
         write_pb.set_position(written);
+        status.update("Writing", &write_pb);
+        title.update("Writing", &write_pb);
+        if cut_off {
+            output::warn(trf(Msg::ImageCutOff, &[&target]));
+            break;
+        }
     }
 
-    device_file.flush()?;
+    // Make sure nothing is left in the device's own cache
+    let sync_start = Instant::now();
+    device_file.sync_all()?;
+    timings.sync += sync_start.elapsed();
 
+    if let Some(summary) = monitor.and_then(|monitor| monitor.summary()) {
+        timings
+            .slow_devices
+            .push(format!("{} {summary}", device_path.display()));
+    }
+
+    let image_len = written;
+    if expected_len.is_some_and(|len| image_len < len) {
+        return Err(anyhow!(trf(
+            Msg::ImageEndedEarly,
+            &[
+                &units::bytes(image_len),
+                &units::bytes(expected_len.unwrap_or_default()),
+                &device_path.display()
+            ]
+        )));
+    }
+    write_pb.set_length(image_len);
+
+    if let Some(record) = record.as_deref_mut() {
+        record.image_hash = image_hasher.map(report::hex);
+        record.write_time = Some(start_time.elapsed());
+    }
     let write_elapsed = start_time.elapsed().as_secs_f64();
     let write_avg_speed = (image_len as f64 / (1024.0 * 1024.0)) / write_elapsed;
     write_pb.set_style(
@@ -378,69 +1363,536 @@ pub fn run(
             .progress_chars("■ "),
     );
     write_pb.finish_with_message(format!(
-        "{write_avg_speed:6.2} MiB/s, {write_elapsed:5.1}s) ✅ Write complete."
+        "{write_avg_speed:6.2} MiB/s, {write_elapsed:5.1}s) {}",
+        tr(Msg::WriteComplete)
     ));
+    // Leave the finished bar in the scrollback before printing below it
+    drop(write_pb);
 
-    println!();
+    info!();
 
     // --- Verification ---
-    if verify {
-        let mut image_file = File::open(&image)?;
-        let mut device_file = File::open(device_path)?;
-
-        let verify_pb = make_progress_bar(image_len, "Verifying", "magenta");
+    if let Some(verify_file) = verify_file {
+        let verification_start = Instant::now();
+        let verify_pb = make_progress_bar(image_len, &prefix(tr(Msg::BarVerifying)), "magenta");
         let verify_start = Instant::now();
-
-        let mut image_hasher = Sha256::new();
-        let mut device_hasher = Sha256::new();
-
-        let mut image_buf = vec![0u8; BUFFER_SIZE];
-        let mut device_buf = vec![0u8; BUFFER_SIZE];
-
-        let mut remaining = image_len;
-        while remaining > 0 {
-            if !running.load(Ordering::SeqCst) {
-                verify_pb.println("Received exit signal... cleaning up.");
-                verify_pb.finish_with_message("❌ Verification cancelled.");
-                // Return error. 'image' will be dropped, cleaning up the temp file.
-                return Err(anyhow!("Operation cancelled by user"));
-            }
-
-            let chunk = std::cmp::min(BUFFER_SIZE as u64, remaining) as usize;
-            image_file.read_exact(&mut image_buf[..chunk])?;
-            device_file.read_exact(&mut device_buf[..chunk])?;
-
-            image_hasher.update(&image_buf[..chunk]);
-            device_hasher.update(&device_buf[..chunk]);
-
-            verify_pb.inc(chunk as u64);
-            remaining -= chunk as u64;
+        let mut device_hasher = record.is_some().then(Sha256::new);
+        let mut mismatched = mismatched_chunks(
+            &verify_file,
+            &checksums,
+            0..checksums.count(),
+            &verify_pb,
+            status,
+            title,
+            cancel,
+            device_hasher.as_mut(),
+        )?;
+        if let Some(record) = record.as_deref_mut() {
+            record.mismatches = mismatch_ranges(&verify_file, &checksums, &mismatched);
+            record.device_hash = device_hasher.map(report::hex);
         }
 
+        timings.verify += verify_start.elapsed();
         let verify_elapsed = verify_start.elapsed().as_secs_f64();
         let verify_avg_speed = (image_len as f64 / (1024.0 * 1024.0)) / verify_elapsed;
 
-        let hash1 = image_hasher.finalize();
-        let hash2 = device_hasher.finalize();
-
         verify_pb.set_style(
             ProgressStyle::default_bar()
                 .template("{prefix} [{elapsed_precise}] [{bar:40.magenta/black}] {total_bytes} (avg {msg}")
                 .unwrap()
                 .progress_chars("■ "),
         );
-
-        if hash1 == hash2 {
-            verify_pb.finish_with_message(format!(
-                "{verify_avg_speed:6.2} MiB/s, {verify_elapsed:5.1}s) ✅ Verification successful."
-            ));
+        let outcome = tr(if mismatched.is_empty() {
+            Msg::VerificationSuccessful
         } else {
-            return Err(anyhow!(
-                "❌ Verification failed: hash mismatch. (avg {:.2} MiB/s)",
-                verify_avg_speed
-            ));
+            Msg::VerificationFailed
+        });
+        verify_pb.finish_with_message(format!(
+            "{verify_avg_speed:6.2} MiB/s, {verify_elapsed:5.1}s) {outcome}"
+        ));
+        drop(verify_pb);
+
+        // Give the device a few chances before giving up on it
+        let mut attempts = 0;
+        while !mismatched.is_empty() {
+            let mut failure = format!(
+                "{} of {} ({} of {}, {} each) don't match the image",
+                describe_chunks(&mismatched),
+                device_path.display(),
+                mismatched.len(),
+                checksums.count(),
+                HumanBytes(BUFFER_SIZE as u64)
+            );
+            for place in locate_chunks(&verify_file, &checksums, &mismatched) {
+                failure.push_str(&format!("\n  mismatch inside {place}"));
+            }
+            attempts += 1;
+            // A streamed image is gone, so there's nothing to re-write from
+            let Some(image) = image else {
+                return Err(anyhow!(trf(Msg::VerificationFailedWith, &[&failure])));
+            };
+            if attempts > MAX_REWRITES || !confirm_rewrite(&failure, options.confirm) {
+                return Err(anyhow!(trf(Msg::VerificationFailedWith, &[&failure])));
+            }
+
+            let len = mismatched
+                .iter()
+                .map(|&i| checksums.chunk(i).2 as u64)
+                .sum();
+            let rewrite_pb = make_progress_bar(len, &prefix(tr(Msg::BarRewriting)), "green");
+            let write_start = Instant::now();
+            rewrite_chunks(
+                image,
+                &device_file,
+                &tail_file,
+                &checksums,
+                &mismatched,
+                &rewrite_pb,
+                cancel,
+            )?;
+            timings.device_write += write_start.elapsed();
+            rewrite_pb.finish_with_message(tr(Msg::RewriteComplete));
+            drop(rewrite_pb);
+
+            let verify_pb = make_progress_bar(len, &prefix(tr(Msg::BarVerifying)), "magenta");
+            let verify_start = Instant::now();
+            mismatched = mismatched_chunks(
+                &verify_file,
+                &checksums,
+                mismatched.clone(),
+                &verify_pb,
+                status,
+                title,
+                cancel,
+                None,
+            )?;
+            timings.verify += verify_start.elapsed();
+            verify_pb.finish_with_message(tr(if mismatched.is_empty() {
+                Msg::VerificationSuccessful
+            } else {
+                Msg::VerificationFailed
+            }));
+            drop(verify_pb);
+        }
+
+        if let Some(record) = record {
+            // The device changed since it was hashed, so hash it again
+            if attempts > 0 {
+                let hash_pb = make_progress_bar(image_len, &prefix(tr(Msg::BarHashing)), "magenta");
+                let mut hasher = Sha256::new();
+                mismatched_chunks(
+                    &verify_file,
+                    &checksums,
+                    0..checksums.count(),
+                    &hash_pb,
+                    status,
+                    title,
+                    cancel,
+                    Some(&mut hasher),
+                )?;
+                hash_pb.finish_and_clear();
+                record.device_hash = Some(report::hex(hasher));
+                record.repaired = true;
+            }
+            record.verify_time = Some(verification_start.elapsed());
         }
     }
 
+    Ok(image_len)
+}
+
+/// How many times chunks that fail verification may be re-written.
+const MAX_REWRITES: u32 = 3;
+
+/// Checksums of each chunk of the image, recorded as it's written so that
+/// verification only needs to read the device.
+struct Checksums {
+    sums: Vec<u32>,
+    /// The length of the chunks pushed so far.
+    image_len: u64,
+    /// Where chunk 0 is in the image file and on the device.
+    image_start: u64,
+    device_start: u64,
+}
+
+impl Checksums {
+    /// Starts recording an image expected to be `expected_len` bytes long.
+    fn new(expected_len: u64, image_start: u64, device_start: u64) -> Self {
+        Self {
+            sums: Vec::with_capacity(expected_len.div_ceil(BUFFER_SIZE as u64) as usize),
+            image_len: 0,
+            image_start,
+            device_start,
+        }
+    }
+
+    /// Records the next chunk, of `BUFFER_SIZE` bytes unless it's the last.
+    fn push(&mut self, chunk: &[u8]) {
+        self.sums.push(crc32fast::hash(chunk));
+        self.image_len += chunk.len() as u64;
+    }
+
+    fn count(&self) -> usize {
+        self.sums.len()
+    }
+
+    /// The offsets of chunk `i` in the image file and on the device, and
+    /// its length.
+    fn chunk(&self, i: usize) -> (u64, u64, usize) {
+        // In u64 before multiplying, as usize is 32 bits on armv7
+        let offset = i as u64 * BUFFER_SIZE as u64;
+        let len = std::cmp::min(BUFFER_SIZE as u64, self.image_len - offset) as usize;
+        (self.image_start + offset, self.device_start + offset, len)
+    }
+}
+
+/// Reads the given chunks back from the device and returns the indices of
+/// those whose checksum differs from the one recorded while writing. Only
+/// the device is read, as the checksums stand in for the image. What's
+/// read is also fed to `hasher`, if given.
+#[allow(clippy::too_many_arguments)]
+fn mismatched_chunks(
+    device_file: &File,
+    checksums: &Checksums,
+    chunks: impl IntoIterator<Item = usize>,
+    pb: &ProgressBar,
+    status: &mut StatusFile,
+    title: &mut TitleMirror,
+    cancel: &CancelToken,
+    mut hasher: Option<&mut Sha256>,
+) -> Result<Vec<usize>> {
+    let memory = buffers::reserve("verification", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(1);
+    let buf = buffer.as_mut_slice();
+    let mut mismatched = Vec::new();
+    for i in chunks {
+        pause::wait_while_paused(pb, cancel);
+        cancel.check(pb, Msg::PhaseVerification)?;
+
+        let (_, offset, len) = checksums.chunk(i);
+        device_file.read_exact_at(&mut buf[..len], offset)?;
+        if let Some(hasher) = hasher.as_deref_mut() {
+            hasher.update(&buf[..len]);
+        }
+        if crc32fast::hash(&buf[..len]) != checksums.sums[i] {
+            mismatched.push(i);
+        }
+
+        pb.inc(len as u64);
+        status.update("Verifying", pb);
+        title.update("Verifying", pb);
+    }
+    Ok(mismatched)
+}
+
+/// Shows which chunks failed verification and asks whether to re-write
+/// them, unless `mode` is [`ConfirmMode::AssumeYes`].
+fn confirm_rewrite(failure: &str, mode: ConfirmMode) -> bool {
+    // Devices written at once ask one at a time
+    static ASKING: Mutex<()> = Mutex::new(());
+    let _asking = ASKING.lock().unwrap_or_else(|e| e.into_inner());
+    output::warn(failure);
+    if mode == ConfirmMode::AssumeYes {
+        return true;
+    }
+    // Without a terminal to ask on, leave the device as is
+    prompt::confirm(tr(Msg::ConfirmRewrite), true).unwrap_or(false)
+}
+
+/// Writes the given chunks of the image to the device again, as the write
+/// loop does, and syncs them.
+fn rewrite_chunks(
+    image: &File,
+    device_file: &File,
+    tail_file: &File,
+    checksums: &Checksums,
+    chunks: &[usize],
+    pb: &ProgressBar,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let block_size = 512;
+    let memory = buffers::reserve("re-writing", 1, BUFFER_SIZE);
+    let mut buffer = memory.buffer(block_size);
+    let buf = buffer.as_mut_slice();
+    for &i in chunks {
+        cancel.check(pb, Msg::PhaseRewrite)?;
+
+        let (image_offset, offset, len) = checksums.chunk(i);
+        image.read_exact_at(&mut buf[..len], image_offset)?;
+        let aligned = len / block_size * block_size;
+        device_file.write_all_at(&buf[..aligned], offset)?;
+        if aligned < len {
+            tail_file.write_all_at(&buf[aligned..len], offset + aligned as u64)?;
+            tail_file.sync_all()?;
+        }
+        pb.inc(len as u64);
+    }
+    device_file.sync_all()?;
     Ok(())
 }
+
+/// The device ranges the given chunks cover, merged where they're next to
+/// each other, with the region of the partition table each starts in.
+fn mismatch_ranges(device_file: &File, checksums: &Checksums, chunks: &[usize]) -> Vec<Mismatch> {
+    let table = partition::read_table(device_file).ok();
+    let mut ranges: Vec<Mismatch> = Vec::new();
+    for &i in chunks {
+        let (_, start, len) = checksums.chunk(i);
+        let end = start + len as u64;
+        match ranges.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => ranges.push(Mismatch {
+                start,
+                end,
+                region: table.as_ref().map(|table| table.region(start).to_string()),
+            }),
+        }
+    }
+    ranges
+}
+
+/// Says where on the device the given chunks are, by its partition table:
+/// one line per partition (or the table, or unpartitioned space), e.g.
+/// "partition 2 (rootfs ext4) at LBA 81920–86015". Empty if the device
+/// has no partition table to go by.
+fn locate_chunks(device_file: &File, checksums: &Checksums, chunks: &[usize]) -> Vec<String> {
+    let Ok(table) = partition::read_table(device_file) else {
+        return Vec::new();
+    };
+    let sector_size = table.sector_size();
+    let mut regions: BTreeMap<Region, Vec<(u64, u64)>> = BTreeMap::new();
+    for &i in chunks {
+        let (_, offset, len) = checksums.chunk(i);
+        let first = offset / sector_size;
+        let last = (offset + len as u64 - 1) / sector_size;
+        let ranges = regions.entry(table.region(offset)).or_default();
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == first => *end = last,
+            _ => ranges.push((first, last)),
+        }
+    }
+    regions
+        .into_iter()
+        .map(|(region, ranges)| {
+            let contents = match region {
+                Region::Partition(number) => table
+                    .get(number)
+                    .and_then(|p| partition::contents(device_file, &p)),
+                _ => None,
+            };
+            let region = match contents {
+                Some(contents) => format!("{region} ({contents})"),
+                None => region.to_string(),
+            };
+            let mut shown: Vec<String> = ranges
+                .iter()
+                .take(3)
+                .map(|&(first, last)| format!("{first}–{last}"))
+                .collect();
+            if ranges.len() > shown.len() {
+                shown.push(format!("and {} more", ranges.len() - shown.len()));
+            }
+            format!("{region} at LBA {}", shown.join(", "))
+        })
+        .collect()
+}
+
+/// Describes sorted chunk indices as ranges, e.g. "chunks 812–815 and 900".
+fn describe_chunks(chunks: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &chunk in chunks {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == chunk => *end = chunk,
+            _ => ranges.push((chunk, chunk)),
+        }
+    }
+    let mut parts: Vec<String> = ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}–{end}")
+            }
+        })
+        .collect();
+    let noun = if chunks.len() == 1 { "chunk" } else { "chunks" };
+    match parts.pop() {
+        Some(last) if !parts.is_empty() => format!("{noun} {} and {last}", parts.join(", ")),
+        Some(last) => format!("{noun} {last}"),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use flate2::write::GzEncoder;
+    use xz2::write::XzEncoder;
+
+    use super::*;
+
+    /// Two parts of an image, each compressed on its own, as `cat`
+    /// joins images made with `pigz` or `xz -T0`.
+    fn parts() -> [Vec<u8>; 2] {
+        let first = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let second = (0..200_000u32).map(|i| (i * 7 % 253) as u8).collect();
+        [first, second]
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = XzEncoder::new(Vec::new(), 1);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        zstd::encode_all(data, 1).unwrap()
+    }
+
+    /// Decodes `compress` applied to each part, concatenated, and checks
+    /// that both parts come out, whether the format is told by magic bytes
+    /// or by extension, and that the whole is measured.
+    fn check_concatenated(compress: fn(&[u8]) -> Vec<u8>, ext: &str) {
+        let parts = parts();
+        let expected = parts.concat();
+        let joined: Vec<u8> = parts.iter().flat_map(|part| compress(part)).collect();
+
+        let mut decoded = Vec::new();
+        decoder(Cursor::new(joined.clone()), None)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded == expected, "{ext}: decoded by magic bytes differs");
+
+        let mut decoded = Vec::new();
+        decoder_for(ext, Cursor::new(joined.clone()), None)
+            .unwrap()
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert!(decoded == expected, "{ext}: decoded by extension differs");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(format!("image.img.{ext}"));
+        std::fs::write(&path, &joined).unwrap();
+        let size = decompressed_size(&path, None, &CancelToken::default()).unwrap();
+        assert_eq!(size, Some(expected.len() as u64), "{ext}: measured size");
+    }
+
+    #[test]
+    fn decodes_concatenated_gzip() {
+        check_concatenated(gzip, "gz");
+    }
+
+    #[test]
+    fn decodes_concatenated_xz() {
+        check_concatenated(xz, "xz");
+    }
+
+    #[test]
+    fn decodes_concatenated_zstd() {
+        check_concatenated(zstd, "zst");
+    }
+
+    const MIB: u64 = BUFFER_SIZE as u64;
+    const GIB: u64 = 1024 * MIB;
+
+    /// Checksums of an image of zeros a few chunks past 4 GiB, whose last
+    /// chunk is `tail` bytes long, written `device_start` bytes into the
+    /// device.
+    fn large_checksums(tail: u64, device_start: u64) -> Checksums {
+        let chunks = (4 * GIB / MIB + 6) as usize;
+        let mut sums = vec![crc32fast::hash(&[0; BUFFER_SIZE]); chunks];
+        sums[chunks - 1] = crc32fast::hash(&vec![0; tail as usize]);
+        Checksums {
+            sums,
+            image_len: (chunks as u64 - 1) * MIB + tail,
+            image_start: 0,
+            device_start,
+        }
+    }
+
+    #[test]
+    fn chunk_offsets_past_4_gib() {
+        let checksums = large_checksums(1000, 3 * MIB);
+        let last = checksums.count() - 1;
+        assert_eq!(last, 4101);
+
+        let (image, device, len) = checksums.chunk(4096);
+        assert_eq!(
+            (image, device, len),
+            (4 * GIB, 4 * GIB + 3 * MIB, BUFFER_SIZE)
+        );
+        let (image, device, len) = checksums.chunk(last);
+        assert_eq!(image, 4 * GIB + 5 * MIB);
+        assert_eq!(device, 4 * GIB + 8 * MIB);
+        assert_eq!(len, 1000);
+    }
+
+    /// A chunk past 4 GiB that differs on a sparse, file-backed device is
+    /// the one verification reports, and re-writing it from the image
+    /// (including a last chunk that isn't a whole number of sectors)
+    /// makes the device match.
+    #[test]
+    fn verifies_and_rewrites_chunks_past_4_gib() {
+        let device_start = 3 * MIB;
+        let checksums = large_checksums(1000, device_start);
+        let last = checksums.count() - 1;
+
+        let image = tempfile::tempfile().unwrap();
+        image.set_len(checksums.image_len).unwrap();
+        let device = tempfile::tempfile().unwrap();
+        device.set_len(device_start + checksums.image_len).unwrap();
+        // Damage chunk 4097 and the last one on the device only
+        for i in [4097, last] {
+            let (_, offset, _) = checksums.chunk(i);
+            device.write_all_at(&[0xa5; 300], offset + 200).unwrap();
+        }
+
+        let pb = ProgressBar::hidden();
+        let mut status = StatusFile::detached(Path::new("/dev/null"));
+        let mut title = TitleMirror::disabled();
+        let cancel = CancelToken::default();
+        let verify = |status: &mut StatusFile, title: &mut TitleMirror| {
+            mismatched_chunks(
+                &device,
+                &checksums,
+                4094..=last,
+                &pb,
+                status,
+                title,
+                &cancel,
+                None,
+            )
+            .unwrap()
+        };
+        let mismatched = verify(&mut status, &mut title);
+        assert_eq!(mismatched, [4097, last]);
+
+        rewrite_chunks(
+            &image,
+            &device,
+            &device,
+            &checksums,
+            &mismatched,
+            &pb,
+            &cancel,
+        )
+        .unwrap();
+        assert!(verify(&mut status, &mut title).is_empty());
+        // Nothing before the image on the device was touched
+        let mut before = [1u8; 512];
+        device
+            .read_exact_at(&mut before, device_start - 512)
+            .unwrap();
+        assert_eq!(before, [0; 512]);
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use flate2::Crc;
+use flate2::bufread::DeflateDecoder;
+
+use crate::compression::le;
+
+/// Signatures of the records of a ZIP archive.
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const ZIP64_END_LOCATOR: u32 = 0x0706_4b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x0606_4b50;
+const CENTRAL_DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+
+/// Compression methods an entry can be read with.
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+/// Entries with these extensions are disk images.
+const IMAGE_EXTENSIONS: &[&str] = &["img", "iso", "raw", "bin", "wic", "sdcard", "hddimg"];
+
+/// The disk image in a ZIP archive.
+pub struct Entry {
+    pub name: String,
+    method: u16,
+    crc: u32,
+    pub compressed_len: u64,
+    pub uncompressed_len: u64,
+    local_header: u64,
+}
+
+impl Entry {
+    /// Positions `file` at the start of the entry's data.
+    pub fn seek_to_data(&self, file: &mut File) -> Result<()> {
+        let mut header = [0u8; 30];
+        file.seek(SeekFrom::Start(self.local_header))?;
+        file.read_exact(&mut header)?;
+        if le(&header[0..4]) as u32 != LOCAL_HEADER {
+            return Err(anyhow!(
+                "the archive is damaged: no entry where {} should be",
+                self.name
+            ));
+        }
+        // The local header's name and extra field may differ from the
+        // central directory's
+        let skip = le(&header[26..28]) + le(&header[28..30]);
+        file.seek(SeekFrom::Current(skip as i64))?;
+        Ok(())
+    }
+
+    /// Decompresses the entry's data, read from `input`, checking it
+    /// against the CRC-32 in the archive once it ends.
+    pub fn reader<R: BufRead + Send + 'static>(&self, input: R) -> Box<dyn Read + Send> {
+        let decoded: Box<dyn Read + Send> = match self.method {
+            DEFLATED => Box::new(DeflateDecoder::new(input)),
+            _ => Box::new(input),
+        };
+        Box::new(Checked {
+            inner: decoded,
+            crc: Crc::new(),
+            expected: self.crc,
+            name: self.name.clone(),
+        })
+    }
+}
+
+/// Checks what's read against a CRC-32 once it ends, so a damaged archive
+/// fails rather than write a damaged image.
+struct Checked {
+    inner: Box<dyn Read + Send>,
+    crc: Crc,
+    expected: u32,
+    name: String,
+}
+
+impl Read for Checked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        if n == 0 && !buf.is_empty() && self.crc.sum() != self.expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} in the archive is damaged (CRC-32 mismatch)", self.name),
+            ));
+        }
+        Ok(n)
+    }
+}
+
+/// Finds the disk image in the ZIP archive `file`: its only file, or its
+/// largest file with an image extension (`.img`, `.iso`, ...), as vendor
+/// archives often carry a README, checksum or boot files beside it. Fails
+/// if that doesn't single one out, naming the candidates.
+pub fn find_image(file: &mut File, archive: &Path) -> Result<Entry> {
+    let entries = read_directory(file)
+        .map_err(|e| anyhow!("reading the ZIP archive {}: {e}", archive.display()))?;
+    let files: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| !e.name.ends_with('/') && !e.name.starts_with("__MACOSX/"))
+        .collect();
+    let images: Vec<&Entry> = files
+        .iter()
+        .copied()
+        .filter(|e| {
+            Path::new(&e.name)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    let largest = images.iter().map(|e| e.uncompressed_len).max();
+    let largest: Vec<&Entry> = images
+        .iter()
+        .copied()
+        .filter(|e| Some(e.uncompressed_len) == largest)
+        .collect();
+    let chosen = match (files.as_slice(), largest.as_slice()) {
+        ([], _) => return Err(anyhow!("the ZIP archive {} is empty", archive.display())),
+        ([only], _) | (_, [only]) => only.local_header,
+        _ => {
+            let candidates = if largest.is_empty() { &files } else { &largest };
+            let names: Vec<&str> = candidates.iter().map(|e| e.name.as_str()).collect();
+            return Err(anyhow!(
+                "the ZIP archive {} holds several files that could be the image: {}; unpack the one to write",
+                archive.display(),
+                names.join(", ")
+            ));
+        }
+    };
+    let entry = entries
+        .into_iter()
+        .find(|e| e.local_header == chosen)
+        .expect("the entry is in the directory");
+    match entry.method {
+        STORED | DEFLATED => Ok(entry),
+        method => Err(anyhow!(
+            "{} in {} is compressed with method {method}, which etchr can't decompress (only stored and deflated entries); unpack it first",
+            entry.name,
+            archive.display()
+        )),
+    }
+}
+
+/// Reads the archive's central directory, from the record at its end.
+fn read_directory(file: &mut File) -> io::Result<Vec<Entry>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    let len = file.seek(SeekFrom::End(0))?;
+    // The end record is 22 bytes, followed by a comment of up to 64 KiB
+    let tail_len = len.min(22 + 65535);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&i| le(&tail[i..i + 4]) as u32 == END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| invalid("not a ZIP archive"))?;
+    let record = &tail[end..];
+    let mut count = le(&record[10..12]);
+    let mut size = le(&record[12..16]);
+    let mut offset = le(&record[16..20]);
+
+    // Archives past 4 GiB or 65535 entries keep the real values in a
+    // ZIP64 record, found through a locator just before the end record
+    if (count == 0xffff || size == 0xffff_ffff || offset == 0xffff_ffff) && end >= 20 {
+        let locator = &tail[end - 20..end];
+        if le(&locator[0..4]) as u32 == ZIP64_END_LOCATOR {
+            let mut record = [0u8; 56];
+            file.seek(SeekFrom::Start(le(&locator[8..16])))?;
+            file.read_exact(&mut record)?;
+            if le(&record[0..4]) as u32 != ZIP64_END_OF_CENTRAL_DIRECTORY {
+                return Err(invalid("damaged ZIP64 end of central directory"));
+            }
+            count = le(&record[32..40]);
+            size = le(&record[40..48]);
+            offset = le(&record[48..56]);
+        }
+    }
+
+    // Both come from the archive, so make sure they're within it before
+    // allocating the directory
+    if offset.checked_add(size).is_none_or(|end| end > len) {
+        return Err(invalid("damaged end of central directory"));
+    }
+    let mut directory = vec![0u8; size as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut directory)?;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    for _ in 0..count {
+        let header = directory
+            .get(pos..pos + 46)
+            .ok_or_else(|| invalid("truncated central directory"))?;
+        if le(&header[0..4]) as u32 != CENTRAL_DIRECTORY_ENTRY {
+            return Err(invalid("damaged central directory"));
+        }
+        let flags = le(&header[8..10]);
+        let name_len = le(&header[28..30]) as usize;
+        let extra_len = le(&header[30..32]) as usize;
+        let comment_len = le(&header[32..34]) as usize;
+        let name = directory
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| invalid("truncated central directory"))?;
+        let extra = directory
+            .get(pos + 46 + name_len..pos + 46 + name_len + extra_len)
+            .ok_or_else(|| invalid("truncated central directory"))?;
+        let mut entry = Entry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: le(&header[10..12]) as u16,
+            crc: le(&header[16..20]) as u32,
+            compressed_len: le(&header[20..24]),
+            uncompressed_len: le(&header[24..28]),
+            local_header: le(&header[42..46]),
+        };
+        if flags & 1 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} is encrypted", entry.name),
+            ));
+        }
+        zip64_sizes(&mut entry, extra);
+        entries.push(entry);
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// Takes the sizes and offset that don't fit in 32 bits from the entry's
+/// ZIP64 extra field, where they're given in this order, each only if
+/// its 32-bit field is all ones.
+fn zip64_sizes(entry: &mut Entry, mut extra: &[u8]) {
+    while extra.len() >= 4 {
+        let id = le(&extra[0..2]);
+        let len = (le(&extra[2..4]) as usize).min(extra.len() - 4);
+        if id == 1 {
+            let mut values = extra[4..4 + len].chunks_exact(8).map(le);
+            for field in [
+                &mut entry.uncompressed_len,
+                &mut entry.compressed_len,
+                &mut entry.local_header,
+            ] {
+                if *field == 0xffff_ffff {
+                    match values.next() {
+                        Some(value) => *field = value,
+                        None => return,
+                    }
+                }
+            }
+            return;
+        }
+        extra = &extra[4 + len..];
+    }
+}